@@ -0,0 +1,251 @@
+use std::{borrow::Cow, panic};
+
+use futures::future::{self, Ready};
+use tokio::{runtime::Handle, sync::watch, time::Instant};
+use tracing::{debug, Span};
+
+use crate::{metrics, Actor, Inbox, Message, ProgramState};
+
+/// An actor that answers without awaiting anything, taking as long as it needs to without holding up the runtime.
+/// It's spawned as a [`Blocking`], whose loop runs on a thread of its own.
+pub trait BlockingActor: Sized + Send + 'static {
+    type Input: Send + Sync;
+    type Output: Send + Sync;
+
+    fn answer(&mut self, i: Self::Input) -> Self::Output;
+
+    fn close(self) {}
+
+    /// What the actor's called in logs. Its type's name, unless it says otherwise.
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Owned(metrics::short_type_name::<Self>())
+    }
+
+    /// Tells apart actors with the same name in logs.
+    fn id(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Runs a [`BlockingActor`] on one of the runtime's blocking threads, so that managers and mailboxes take it like any
+/// other actor.
+pub struct Blocking<B>(pub B);
+
+impl<B: BlockingActor> Actor for Blocking<B> {
+    type Input = B::Input;
+    type Output = B::Output;
+
+    type Response<'a> = Ready<B::Output>;
+    type CloseFuture<'a> = Ready<()>;
+
+    fn close<'a>(self) -> Self::CloseFuture<'a> {
+        self.0.close();
+        future::ready(())
+    }
+
+    /// Answers in place, blocking whatever awaits it. Only for actors answered outside of their loop.
+    fn answer(&mut self, i: Self::Input) -> Self::Response<'_> {
+        future::ready(self.0.answer(i))
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        self.0.name()
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.0.id()
+    }
+
+    async fn run_async_loop(
+        self,
+        inbox: Inbox<Self::Input, Self::Output>,
+        program_state: watch::Receiver<ProgramState>,
+    ) {
+        let runtime = Handle::current();
+        let span = Span::current();
+
+        let ended = tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            run_blocking(self.0, &inbox, program_state, &runtime);
+        })
+        .await;
+
+        if let Err(e) = ended {
+            if e.is_panic() {
+                panic::resume_unwind(e.into_panic());
+            }
+        }
+    }
+}
+
+fn run_blocking<B: BlockingActor>(
+    mut actor: B,
+    inbox: &Inbox<B::Input, B::Output>,
+    mut program_state: watch::Receiver<ProgramState>,
+    runtime: &Handle,
+) {
+    // waiting is the only thing that's awaited, answering happens on this thread
+    while let Some(message) = runtime.block_on(async {
+        tokio::select! {
+            biased;
+            _ = program_state.changed() => None,
+            Some(message) = inbox.recv() => Some(message),
+            else => None
+        }
+    }) {
+        respond(&mut actor, inbox, message);
+    }
+
+    // an answer can't be interrupted here, so the deadline's only checked between them
+    let state = *program_state.borrow();
+    if let ProgramState::Draining { until } = state {
+        while Instant::now() < until {
+            let Some(message) = inbox.try_recv() else {
+                break;
+            };
+            respond(&mut actor, inbox, message);
+        }
+    }
+
+    actor.close();
+}
+
+fn respond<B: BlockingActor>(
+    actor: &mut B,
+    inbox: &Inbox<B::Input, B::Output>,
+    message: Message<B::Input, B::Output>,
+) {
+    let Message { value, output } = message;
+    let Some(value) = inbox.unless_abandoned(value, &output) else {
+        return;
+    };
+
+    let started = Instant::now();
+    let result = actor.answer(value);
+    inbox.metrics().handled(started.elapsed());
+    // like an actor's own loop, there's nothing to hand off once it's answered
+    if output.send(result).is_err() {
+        debug!("nobody was waiting for the answer anymore");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        time::Duration,
+    };
+
+    use tracing::Span;
+
+    use super::*;
+    use crate::{ActorManager, RequestError};
+
+    /// Keeps a running total, calling `before` ahead of adding each input, and hands it over once closed.
+    struct Total {
+        sum: u64,
+        before: Box<dyn FnMut() + Send>,
+        closed: Arc<Mutex<Option<u64>>>,
+    }
+
+    impl BlockingActor for Total {
+        type Input = u64;
+        type Output = u64;
+
+        fn answer(&mut self, i: u64) -> u64 {
+            (self.before)();
+            self.sum += i;
+            self.sum
+        }
+
+        fn close(self) {
+            *self.closed.lock().unwrap() = Some(self.sum);
+        }
+    }
+
+    fn total(before: impl FnMut() + Send + 'static) -> (Blocking<Total>, Arc<Mutex<Option<u64>>>) {
+        let closed = Arc::new(Mutex::new(None));
+        let total = Total {
+            sum: 0,
+            before: Box::new(before),
+            closed: Arc::clone(&closed),
+        };
+
+        (Blocking(total), closed)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn answers_off_the_runtime() {
+        // the actor can only answer once a task on the only worker thread lets it, which it couldn't if it was on it
+        let (go, wait) = mpsc::channel();
+        let (actor, closed) = total(move || {
+            wait.recv_timeout(Duration::from_secs(5))
+                .expect("the actor held up the runtime");
+        });
+        let (mut manager, mailbox) = ActorManager::new(4);
+        manager.spawn_actor(actor, Span::none());
+
+        let first = mailbox.deferred_request(1).await;
+        let second = mailbox.deferred_request(2).await;
+        tokio::spawn(async move {
+            go.send(()).unwrap();
+            go.send(()).unwrap();
+        });
+
+        assert_eq!(first.await, Ok(1));
+        assert_eq!(second.await, Ok(3));
+
+        let summary = manager.close_and_join().await;
+        assert_eq!(summary.joined, 1);
+        assert_eq!(*closed.lock().unwrap(), Some(3));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stops_draining_once_the_deadline_passes() {
+        // the first answer holds on until the manager's started draining
+        let (started, answering) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let (actor, closed) = total(move || {
+            let _ = started.send(());
+            let _ = released.recv();
+        });
+        let (mut manager, mailbox) = ActorManager::new(8);
+        manager.spawn_actor(actor, Span::none());
+
+        let mut pending = Vec::new();
+        for _ in 0..6 {
+            pending.push(mailbox.deferred_request(1).await);
+        }
+        answering.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // an answer can't be cut short, so the one underway finishes, but nothing's taken after the deadline
+        let (summary, ()) = tokio::join!(manager.drain_and_join(Duration::ZERO), async move {
+            release.send(()).unwrap();
+        });
+        assert_eq!(summary.joined, 1);
+
+        let answers = futures::future::join_all(pending).await;
+        assert_eq!(answers[0], Ok(1));
+        assert!(answers[1..]
+            .iter()
+            .all(|answer| *answer == Err(RequestError::Dropped)));
+        assert_eq!(*closed.lock().unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn drains_everything_before_the_deadline() {
+        let (actor, closed) = total(|| {});
+        let (mut manager, mailbox) = ActorManager::new(8);
+        manager.spawn_actor(actor, Span::none());
+
+        let mut pending = Vec::new();
+        for _ in 0..4 {
+            pending.push(mailbox.deferred_request(1).await);
+        }
+
+        manager.drain_and_join(Duration::from_secs(5)).await;
+        let answers = futures::future::join_all(pending).await;
+        assert_eq!(answers, [Ok(1), Ok(2), Ok(3), Ok(4)]);
+        assert_eq!(*closed.lock().unwrap(), Some(4));
+    }
+}
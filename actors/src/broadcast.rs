@@ -0,0 +1,119 @@
+use std::fmt::{self, Debug};
+
+use futures::future::{self, Future};
+
+use crate::{Actor, Mailbox, RequestError};
+
+/// Hands every input to each of the mailboxes subscribed to it, answering with all of their answers.
+pub struct Broadcast<A: Actor> {
+    subscribers: Vec<Mailbox<A>>,
+}
+
+impl<A: Actor> Default for Broadcast<A> {
+    fn default() -> Self {
+        Broadcast {
+            subscribers: Vec::new(),
+        }
+    }
+}
+
+impl<A: Actor> Clone for Broadcast<A> {
+    fn clone(&self) -> Self {
+        Broadcast {
+            subscribers: self.subscribers.clone(),
+        }
+    }
+}
+
+impl<A: Actor> Debug for Broadcast<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Broadcast")
+            .field("subscribers", &self.subscribers)
+            .finish()
+    }
+}
+
+impl<A: Actor> FromIterator<Mailbox<A>> for Broadcast<A> {
+    fn from_iter<T: IntoIterator<Item = Mailbox<A>>>(iter: T) -> Self {
+        Broadcast {
+            subscribers: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<A: Actor + 'static> Broadcast<A>
+where
+    A::Input: Clone,
+{
+    pub fn new() -> Broadcast<A> {
+        Broadcast::default()
+    }
+
+    pub fn subscribe(&mut self, mailbox: Mailbox<A>) {
+        self.subscribers.push(mailbox);
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Sends `input` to every subscriber, returning their answers to wait on, in the order they subscribed.
+    pub async fn deferred_request(
+        &self,
+        input: A::Input,
+    ) -> Vec<impl Future<Output = Result<A::Output, RequestError>> + Send + Sync> {
+        let mut pending = Vec::with_capacity(self.subscribers.len());
+        for mailbox in &self.subscribers {
+            pending.push(mailbox.deferred_request(input.clone()).await);
+        }
+
+        pending
+    }
+
+    /// Sends `input` to every subscriber, and waits for all of them to answer.
+    pub async fn request(&self, input: A::Input) -> Vec<Result<A::Output, RequestError>> {
+        future::join_all(self.deferred_request(input).await).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::Span;
+
+    use super::*;
+    use crate::{tests::echo, ActorManager};
+
+    #[tokio::test]
+    async fn answers_with_every_subscriber() {
+        let (mut first, first_mailbox) = ActorManager::new(4);
+        let (mut second, second_mailbox) = ActorManager::new(4);
+        first.spawn_actor(echo(), Span::none());
+        second.spawn_actor(echo(), Span::none());
+
+        let mut broadcast = Broadcast::new();
+        broadcast.subscribe(first_mailbox);
+        broadcast.subscribe(second_mailbox);
+        assert_eq!(broadcast.len(), 2);
+        assert_eq!(broadcast.request(5).await, [Ok(5), Ok(5)]);
+
+        // one subscriber closing doesn't keep the others from answering
+        second.close_and_join().await;
+        assert_eq!(
+            broadcast.request(6).await,
+            [Ok(6), Err(RequestError::Dropped)]
+        );
+
+        first.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn answers_nothing_without_subscribers() {
+        let broadcast = Broadcast::<crate::tests::Echo>::new();
+        assert!(broadcast.is_empty());
+        assert!(broadcast.request(1).await.is_empty());
+    }
+}
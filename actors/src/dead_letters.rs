@@ -0,0 +1,76 @@
+//! Messages that were sent, but never made it to an actor: handed to a callback rather than dropped without a word.
+
+use std::{
+    fmt::{self, Display},
+    sync::RwLock,
+};
+
+use tracing::debug;
+
+use crate::{Actor, ActorManager};
+
+/// A message no actor will answer, and why. Its request fails once it's handed off.
+#[derive(Debug)]
+pub struct DeadLetter<I> {
+    pub value: I,
+    pub reason: DeadLetterReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    /// The mailbox was full, and rejects what's sent while it is.
+    Rejected,
+    /// The mailbox was full, and dropped it to make room for a newer message.
+    Overflowed,
+    /// The actors stopped before they got to it.
+    Closed,
+    /// Whoever sent it stopped waiting for its answer before an actor got to it, so it wasn't answered.
+    Abandoned,
+}
+
+impl Display for DeadLetterReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeadLetterReason::Rejected => "rejected",
+            DeadLetterReason::Overflowed => "overflowed",
+            DeadLetterReason::Closed => "closed",
+            DeadLetterReason::Abandoned => "abandoned",
+        })
+    }
+}
+
+type Callback<I> = Box<dyn Fn(DeadLetter<I>) + Send + Sync>;
+
+/// Where a manager's dead letters go, shared by its inbox and mailboxes.
+pub(crate) struct DeadLetters<I> {
+    callback: RwLock<Option<Callback<I>>>,
+}
+
+impl<I> DeadLetters<I> {
+    pub(crate) fn new() -> DeadLetters<I> {
+        DeadLetters {
+            callback: RwLock::new(None),
+        }
+    }
+
+    /// Hands `value` to the callback, or logs that it was dropped if there isn't one.
+    pub(crate) fn send(&self, value: I, reason: DeadLetterReason) {
+        match &*self.callback.read().unwrap() {
+            Some(callback) => callback(DeadLetter { value, reason }),
+            None => debug!("dropped a message no actor answered ({reason})"),
+        }
+    }
+}
+
+impl<A: Actor> ActorManager<A> {
+    /// Has `callback` called with every message sent to this manager that its actors won't answer, in place of any
+    /// callback set before. To retry them later, it can send them on to a channel of their own.
+    ///
+    /// The callback's called from whatever sent or closed, so it shouldn't block.
+    pub fn on_dead_letter<F>(&self, callback: F)
+    where
+        F: Fn(DeadLetter<A::Input>) + Send + Sync + 'static,
+    {
+        *self.inbox.dead_letters.callback.write().unwrap() = Some(Box::new(callback));
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use tokio::{sync::watch, task::AbortHandle};
+use tracing::Span;
+
+use crate::{Actor, ActorManager, ProgramState};
+
+/// One of the actors a manager spawned, to stop or replace without closing the others.
+#[derive(Debug)]
+pub struct ActorHandle {
+    pub(crate) state: Arc<watch::Sender<ProgramState>>,
+    pub(crate) task: AbortHandle,
+}
+
+impl ActorHandle {
+    /// Tells the actor to close once it's answered what it's answering. What's still in the mailbox is left to the
+    /// other actors.
+    pub fn stop(&self) {
+        self.state.send_replace(ProgramState::Closing);
+    }
+
+    /// Stops the actor where it is, without closing it. Whatever it was answering is dropped.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl<A: Actor + Send + 'static> ActorManager<A> {
+    /// Stops the actor `handle` is for, and spawns `actor` to answer the mailbox in its place, like after its config
+    /// changed.
+    pub fn replace(&mut self, handle: ActorHandle, actor: A, span: Span) -> ActorHandle {
+        handle.stop();
+        self.spawn_actor(actor, span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future, time::Duration};
+
+    use tracing::Span;
+
+    use crate::{Actor, ActorManager};
+
+    /// Answers with its own tag.
+    struct Tagged(u32);
+
+    impl Actor for Tagged {
+        type Input = ();
+        type Output = u32;
+
+        type Response<'a> = future::Ready<u32>;
+        type CloseFuture<'a> = future::Ready<()>;
+
+        fn close<'a>(self) -> Self::CloseFuture<'a> {
+            future::ready(())
+        }
+
+        fn answer(&mut self, _: ()) -> Self::Response<'_> {
+            future::ready(self.0)
+        }
+    }
+
+    async fn until_finished(handle: &super::ActorHandle) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !handle.is_finished() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_one_actor_of_many() {
+        let (mut manager, mailbox) = ActorManager::new(8);
+        let first = manager.spawn_actor(Tagged(1), Span::none());
+        manager.spawn_actor(Tagged(2), Span::none());
+
+        first.stop();
+        until_finished(&first).await;
+
+        for _ in 0..4 {
+            assert_eq!(mailbox.request(()).await, 2);
+        }
+
+        let summary = manager.close_and_join().await;
+        assert_eq!(summary.joined, 2);
+    }
+
+    #[tokio::test]
+    async fn replaces_an_actor() {
+        let (mut manager, mailbox) = ActorManager::new(8);
+        let first = manager.spawn_actor(Tagged(1), Span::none());
+        assert_eq!(mailbox.request(()).await, 1);
+
+        let second = manager.replace(first, Tagged(2), Span::none());
+        // the first actor may still answer until it notices it was stopped
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while mailbox.request(()).await != 2 {}
+        })
+        .await
+        .unwrap();
+
+        for _ in 0..4 {
+            assert_eq!(mailbox.request(()).await, 2);
+        }
+
+        second.abort();
+        until_finished(&second).await;
+        let summary = manager.close_and_join().await;
+        assert_eq!(summary.joined, 1);
+        assert_eq!(summary.cancelled, 1);
+    }
+}
@@ -1,28 +1,51 @@
 #![feature(return_position_impl_trait_in_trait)]
 
 use std::{
-    fmt::Debug,
-    future::Future,
+    any::Any,
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Debug, Display},
+    future::{self, Future},
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
     },
+    time::Duration,
 };
 
-use futures::FutureExt;
+use flume::TrySendError;
+use futures::{future::Either, FutureExt};
 use tokio::{
     sync::{oneshot, watch, Notify},
     task::JoinSet,
+    time::Instant,
 };
-use tracing::{Instrument, Span};
+use tracing::{debug, error, info_span, Instrument, Span};
 
-pub static TASK_COUNT: AtomicUsize = AtomicUsize::new(0);
+mod blocking;
+mod broadcast;
+mod dead_letters;
+mod handle;
+pub mod metrics;
+mod supervisor;
+
+pub use blocking::{Blocking, BlockingActor};
+pub use broadcast::Broadcast;
+use dead_letters::DeadLetters;
+pub use dead_letters::{DeadLetter, DeadLetterReason};
+pub use handle::ActorHandle;
+pub use metrics::{Metrics, MetricsSnapshot};
+pub use supervisor::RestartPolicy;
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ProgramState {
     Running,
     Closing,
+    /// Closing once what's already in the mailbox is answered, or the deadline passes.
+    Draining {
+        until: Instant,
+    },
 }
 
 pub trait Actor: Sized + Send + 'static {
@@ -39,21 +62,36 @@ pub trait Actor: Sized + Send + 'static {
     fn close<'a>(self) -> Self::CloseFuture<'a>;
     fn answer(&mut self, i: Self::Input) -> Self::Response<'_>;
 
-    fn run_async_loop<'a>(
+    /// What the actor's called in logs. Its type's name, unless it says otherwise.
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Owned(metrics::short_type_name::<Self>())
+    }
+
+    /// Tells apart actors with the same name in logs.
+    fn id(&self) -> Option<usize> {
+        None
+    }
+
+    fn run_async_loop(
         mut self,
-        rx: flume::Receiver<Message<Self::Input, Self::Output>>,
+        inbox: Inbox<Self::Input, Self::Output>,
         mut program_state: watch::Receiver<ProgramState>,
     ) -> impl Future<Output = ()> + Send {
         async move {
             loop {
+                // once it's told to close, it stops taking messages, rather than taking the ones that keep coming
                 tokio::select! {
-                    Ok(Message { value, output }) = rx.recv_async() => {
-                        let result = self.answer(value).in_current_span().await;
-                        let _ = output.send(result);
-                    },
+                    biased;
                     _ = program_state.changed() => {
+                        let state = *program_state.borrow();
+                        if let ProgramState::Draining { until } = state {
+                            drain(&mut self, &inbox, until).await;
+                        }
                         break
                     },
+                    Some(message) = inbox.recv() => {
+                        respond(&mut self, &inbox, message).await;
+                    },
                     else => break
                 }
             }
@@ -94,53 +132,390 @@ pub trait Actor: Sized + Send + 'static {
 //     }
 // }
 
+async fn respond<A: Actor>(
+    actor: &mut A,
+    inbox: &Inbox<A::Input, A::Output>,
+    message: Message<A::Input, A::Output>,
+) {
+    let Message { value, output } = message;
+    let Some(value) = inbox.unless_abandoned(value, &output) else {
+        return;
+    };
+
+    let started = Instant::now();
+    let result = actor.answer(value).in_current_span().await;
+    inbox.metrics().handled(started.elapsed());
+    // it was answered, so there's nothing to hand off and retry: only the answer's lost
+    if output.send(result).is_err() {
+        debug!("nobody was waiting for the answer anymore");
+    }
+}
+
+/// Answers what's waiting in the inbox until it's empty or `until` passes. An answer that's still coming when it does is
+/// given up on.
+async fn drain<A: Actor>(actor: &mut A, inbox: &Inbox<A::Input, A::Output>, until: Instant) {
+    while let Some(message) = inbox.try_recv() {
+        if tokio::time::timeout_at(until, respond(actor, inbox, message))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// The span an actor runs in, under `parent`, naming it.
+fn actor_span<A: Actor>(actor: &A, id: Option<usize>, parent: &Span) -> Span {
+    info_span!(parent: parent, "actor", name = %actor.name(), id)
+}
+
+/// What a panic was raised with, if it was a message.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(not a message)")
+}
+
 pub struct Message<I, O> {
     pub value: I,
     pub output: oneshot::Sender<O>,
 }
 
+// what goes through a mailbox's channels, dated to tell how long it waited
+struct Envelope<I, O> {
+    message: Message<I, O>,
+    sent_at: Instant,
+}
+
+/// Counts a request as resolved once it's dropped, whether it was answered or given up on.
+struct Resolving {
+    metrics: Arc<Metrics>,
+    notify: Arc<Notify>,
+}
+
+impl Drop for Resolving {
+    fn drop(&mut self) {
+        self.metrics.resolved();
+        self.notify.notify_waiters();
+    }
+}
+
+/// How soon a message is answered. High priority messages, like seed urls, go ahead of every normal one that's waiting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+}
+
+/// What sending to a mailbox that's full does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Waits until there's room.
+    #[default]
+    Wait,
+    /// Turns the message away, failing its request with [`RequestError::Rejected`].
+    Reject,
+    /// Makes room by dropping the message that's waited longest, failing its request with [`RequestError::Dropped`].
+    DropOldest,
+    /// Queues the message past capacity, in an unbounded queue taken from once the mailbox's own runs out.
+    Spill,
+}
+
+/// Why a request wasn't answered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestError {
+    /// The mailbox was full, and rejects what's sent while it is.
+    Rejected,
+    /// The message was dropped before an actor answered it, by a full mailbox making room or by the actors stopping.
+    Dropped,
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Rejected => f.write_str("the mailbox was full"),
+            RequestError::Dropped => f.write_str("the message was dropped before it was answered"),
+        }
+    }
+}
+
+impl Error for RequestError {}
+
+// one priority's messages: its bounded queue, and the unbounded one that queue spills into
+struct Lane<T> {
+    queue: T,
+    spill: T,
+}
+
+type Queue<I, O> = flume::Receiver<Envelope<I, O>>;
+type Sending<I, O> = Lane<flume::Sender<Envelope<I, O>>>;
+type Receiving<I, O> = Lane<Queue<I, O>>;
+// the high and normal queues, as the receiving ends a mailbox drops the oldest message from
+type OldestQueues<I, O> = (Queue<I, O>, Queue<I, O>);
+
+fn lane<I, O>(capacity: usize) -> (Sending<I, O>, Receiving<I, O>) {
+    let (queue_tx, queue_rx) = flume::bounded(capacity);
+    let (spill_tx, spill_rx) = flume::unbounded();
+
+    (
+        Lane {
+            queue: queue_tx,
+            spill: spill_tx,
+        },
+        Lane {
+            queue: queue_rx,
+            spill: spill_rx,
+        },
+    )
+}
+
+impl<T: Clone> Clone for Lane<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+            spill: self.spill.clone(),
+        }
+    }
+}
+
+impl<I, O> Receiving<I, O> {
+    fn try_recv(&self) -> Option<Envelope<I, O>> {
+        self.queue
+            .try_recv()
+            .or_else(|_| self.spill.try_recv())
+            .ok()
+    }
+}
+
+/// The receiving end of a [`Mailbox`], shared by the actors a manager runs.
+pub struct Inbox<I, O> {
+    high: Receiving<I, O>,
+    normal: Receiving<I, O>,
+    metrics: Arc<Metrics>,
+    dead_letters: Arc<DeadLetters<I>>,
+    // whether the manager's joined its actors. Mailboxes that drop the oldest message hold on to the receivers too, so
+    // they check this to tell whether anyone's still listening
+    closed: Arc<AtomicBool>,
+}
+
+impl<I, O> Clone for Inbox<I, O> {
+    fn clone(&self) -> Self {
+        Self {
+            high: self.high.clone(),
+            normal: self.normal.clone(),
+            metrics: Arc::clone(&self.metrics),
+            dead_letters: Arc::clone(&self.dead_letters),
+            closed: Arc::clone(&self.closed),
+        }
+    }
+}
+
+impl<I, O> Inbox<I, O> {
+    /// Waits for the next message, taking high priority ones first. Returns `None` once every mailbox is dropped and
+    /// there's nothing left to take.
+    pub async fn recv(&self) -> Option<Message<I, O>> {
+        // spilled messages were sent after the ones still in the queue, so they wait for those
+        let Envelope { message, sent_at } = tokio::select! {
+            biased;
+            Ok(envelope) = self.high.queue.recv_async() => envelope,
+            Ok(envelope) = self.high.spill.recv_async() => envelope,
+            Ok(envelope) = self.normal.queue.recv_async() => envelope,
+            Ok(envelope) = self.normal.spill.recv_async() => envelope,
+            else => return None
+        };
+        self.metrics.received(sent_at.elapsed());
+
+        Some(message)
+    }
+
+    /// Takes the next message if there's one waiting, high priority ones first.
+    pub fn try_recv(&self) -> Option<Message<I, O>> {
+        let Envelope { message, sent_at } = self.take()?;
+        self.metrics.received(sent_at.elapsed());
+
+        Some(message)
+    }
+
+    fn take(&self) -> Option<Envelope<I, O>> {
+        self.high.try_recv().or_else(|| self.normal.try_recv())
+    }
+
+    /// Hands `value` to the dead letters if whoever sent it has stopped waiting for `output`, and gives it back
+    /// otherwise. Actors with their own loop check this before working on a message.
+    pub fn unless_abandoned(&self, value: I, output: &oneshot::Sender<O>) -> Option<I> {
+        if output.is_closed() {
+            self.dead_letters.send(value, DeadLetterReason::Abandoned);
+            return None;
+        }
+
+        Some(value)
+    }
+
+    /// The counters of the mailbox this is the other end of.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Whether every mailbox sending here is dropped.
+    pub fn is_disconnected(&self) -> bool {
+        self.normal.queue.is_disconnected()
+    }
+}
+
+/// How the actors an [`ActorManager`] ran ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JoinSummary {
+    /// Actors that closed, or returned, on their own.
+    pub joined: usize,
+    /// Actors whose tasks panicked.
+    pub panicked: usize,
+    /// Actors whose tasks were aborted.
+    pub cancelled: usize,
+}
+
 pub struct ActorManager<A: Actor> {
     tasks: JoinSet<()>,
-    state: watch::Sender<ProgramState>,
-    pub rx: flume::Receiver<Message<A::Input, A::Output>>,
+    /// Each live actor's own state, so that one can be stopped without the rest.
+    states: Vec<Arc<watch::Sender<ProgramState>>>,
+    pub inbox: Inbox<A::Input, A::Output>,
 }
 
 impl<A: Actor + Send + 'static> ActorManager<A> {
     pub fn new(capacity: usize) -> (ActorManager<A>, Mailbox<A>) {
-        let (tx, rx) = flume::bounded(capacity);
-        let (state, _) = watch::channel(ProgramState::Running);
+        Self::with_backpressure(capacity, Backpressure::Wait)
+    }
+
+    /// Makes a manager whose mailbox holds up to `capacity` messages of each priority, doing what `backpressure` says
+    /// once it's full.
+    pub fn with_backpressure(
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> (ActorManager<A>, Mailbox<A>) {
+        let (high_tx, high) = lane(capacity);
+        let (normal_tx, normal) = lane(capacity);
+        let metrics = Metrics::register::<A>();
+        let dead_letters = Arc::new(DeadLetters::new());
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let oldest = (backpressure == Backpressure::DropOldest)
+            .then(|| (high.queue.clone(), normal.queue.clone()));
 
         (
             ActorManager {
                 tasks: JoinSet::new(),
-                rx,
-                state,
+                inbox: Inbox {
+                    high,
+                    normal,
+                    metrics: Arc::clone(&metrics),
+                    dead_letters: Arc::clone(&dead_letters),
+                    closed: Arc::clone(&closed),
+                },
+                states: Vec::new(),
             },
             Mailbox {
                 notify: Arc::new(Notify::const_new()),
-                tx,
+                high: high_tx,
+                normal: normal_tx,
+                backpressure,
+                oldest,
+                closed: Arc::downgrade(&closed),
+                metrics,
+                dead_letters,
             },
         )
     }
 
-    pub async fn close_and_join(&mut self) {
-        self.state.send(ProgramState::Closing).unwrap();
-        while (self.tasks.join_next().await).is_some() {}
+    /// Tells every actor to close, and waits for them to. Actors that panicked are logged with what they panicked with,
+    /// and counted in the summary.
+    pub async fn close_and_join(&mut self) -> JoinSummary {
+        self.set_state(ProgramState::Closing);
+        self.join().await
     }
 
-    pub fn get_rx(&self) -> flume::Receiver<Message<A::Input, A::Output>> {
-        self.rx.clone()
+    /// Like [`ActorManager::close_and_join`], but has the actors answer what's already in their mailbox first, for up
+    /// to `deadline`, rather than leaving it there.
+    pub async fn drain_and_join(&mut self, deadline: Duration) -> JoinSummary {
+        self.set_state(ProgramState::Draining {
+            until: Instant::now() + deadline,
+        });
+        self.join().await
+    }
+
+    fn set_state(&self, state: ProgramState) {
+        // actors that already stopped aren't listening anymore, so the state's set whether anyone hears it or not
+        for actor in &self.states {
+            actor.send_replace(state);
+        }
+    }
+
+    /// Makes the state for an actor about to be spawned.
+    fn attach(
+        &mut self,
+    ) -> (
+        Arc<watch::Sender<ProgramState>>,
+        watch::Receiver<ProgramState>,
+    ) {
+        self.states.retain(|state| !state.is_closed());
+
+        let (state, receiver) = watch::channel(ProgramState::Running);
+        let state = Arc::new(state);
+        self.states.push(Arc::clone(&state));
+
+        (state, receiver)
+    }
+
+    async fn join(&mut self) -> JoinSummary {
+        let mut summary = JoinSummary::default();
+        while let Some(ended) = self.tasks.join_next().await {
+            match ended {
+                Ok(()) => summary.joined += 1,
+                Err(e) if e.is_panic() => {
+                    error!("actor panicked: {}", panic_message(&*e.into_panic()));
+                    summary.panicked += 1;
+                }
+                Err(_) => summary.cancelled += 1,
+            }
+        }
+
+        // no actor's left to answer what's still waiting, or what's sent from now on, which would otherwise keep its
+        // requests waiting forever
+        self.inbox.closed.store(true, Ordering::Release);
+        while let Some(Envelope { message, .. }) = self.inbox.take() {
+            self.inbox.metrics.unsent();
+            self.inbox
+                .dead_letters
+                .send(message.value, DeadLetterReason::Closed);
+        }
+
+        summary
+    }
+
+    pub fn get_inbox(&self) -> Inbox<A::Input, A::Output> {
+        self.inbox.clone()
     }
 }
 
 pub struct Mailbox<A: Actor> {
-    tx: flume::Sender<Message<A::Input, A::Output>>,
+    high: Sending<A::Input, A::Output>,
+    normal: Sending<A::Input, A::Output>,
+    backpressure: Backpressure,
+    /// The high and normal queues' receiving ends, to drop the oldest message from.
+    oldest: Option<OldestQueues<A::Input, A::Output>>,
+    closed: Weak<AtomicBool>,
+    metrics: Arc<Metrics>,
+    dead_letters: Arc<DeadLetters<A::Input>>,
     notify: Arc<Notify>,
 }
 
 impl<A: Actor> Debug for Mailbox<A> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Mailbox").field("tx", &self.tx).finish()
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mailbox")
+            .field("tx", &self.normal.queue)
+            .field("backpressure", &self.backpressure)
+            .finish()
     }
 }
 
@@ -148,58 +523,552 @@ impl<A: Actor> Clone for Mailbox<A> {
     fn clone(&self) -> Self {
         Self {
             notify: Arc::clone(&self.notify),
-            tx: self.tx.clone(),
+            high: self.high.clone(),
+            normal: self.normal.clone(),
+            backpressure: self.backpressure,
+            oldest: self.oldest.clone(),
+            closed: Weak::clone(&self.closed),
+            metrics: Arc::clone(&self.metrics),
+            dead_letters: Arc::clone(&self.dead_letters),
         }
     }
 }
 
 impl<A: Actor + Send + 'static> ActorManager<A> {
-    pub fn spawn_actor(&mut self, actor: A, span: Span) {
-        let rx = self.rx.clone();
+    /// Spawns `actor`, running in an `actor` span under `span` with its name and id.
+    pub fn spawn_actor(&mut self, actor: A, span: Span) -> ActorHandle {
+        let id = actor.id();
+        self.spawn_in(actor, id, &span)
+    }
 
-        self.tasks.spawn(
+    /// Spawns `n` actors made by `factory`, which is handed each one's index, all answering the same mailbox. Actors
+    /// without an id of their own are labelled with their index instead.
+    pub fn spawn_pool<F>(&mut self, n: usize, mut factory: F, span: Span) -> Vec<ActorHandle>
+    where
+        F: FnMut(usize) -> A,
+    {
+        (0..n)
+            .map(|idx| {
+                let actor = factory(idx);
+                let id = actor.id().or(Some(idx));
+                self.spawn_in(actor, id, &span)
+            })
+            .collect()
+    }
+
+    fn spawn_in(&mut self, actor: A, id: Option<usize>, parent: &Span) -> ActorHandle {
+        let span = actor_span(&actor, id, parent);
+        let (state, receiver) = self.attach();
+        let task = self.tasks.spawn(
             actor
-                .run_async_loop(rx, self.state.subscribe())
+                .run_async_loop(self.inbox.clone(), receiver)
                 .instrument(span),
         );
+
+        ActorHandle { state, task }
     }
 }
 
 impl<A: Actor + 'static> Mailbox<A> {
     pub fn len(&self) -> usize {
-        self.tx.len()
+        [&self.high, &self.normal]
+            .iter()
+            .map(|lane| lane.queue.len() + lane.spill.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn subscribe(&self) -> Arc<Notify> {
         Arc::clone(&self.notify)
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     pub async fn deferred_request(
         &self,
         input: A::Input,
-    ) -> impl Future<Output = Result<A::Output, oneshot::error::RecvError>> + Send + Sync {
-        TASK_COUNT.fetch_add(1, Ordering::Release);
+    ) -> impl Future<Output = Result<A::Output, RequestError>> + Send + Sync {
+        self.deferred_request_with(input, Priority::Normal).await
+    }
+
+    pub async fn deferred_request_with(
+        &self,
+        input: A::Input,
+        priority: Priority,
+    ) -> impl Future<Output = Result<A::Output, RequestError>> + Send + Sync {
+        self.metrics.sent();
+        // resolved however the request ends, including by being dropped before it's answered
+        let resolving = Resolving {
+            metrics: Arc::clone(&self.metrics),
+            notify: Arc::clone(&self.notify),
+        };
 
         let (oneshot_tx, oneshot_rx) = oneshot::channel();
-        let notifier = Arc::clone(&self.notify);
-        notifier.notify_waiters();
+        self.notify.notify_waiters();
 
-        let _ = self
-            .tx
-            .send_async(Message {
+        let envelope = Envelope {
+            message: Message {
                 value: input,
                 output: oneshot_tx,
-            })
-            .await;
+            },
+            sent_at: Instant::now(),
+        };
+
+        if let Err((envelope, reason)) = self.deliver(envelope, priority).await {
+            self.metrics.unsent();
+            // handing it off drops its answer's sender, failing the request
+            self.dead_letters.send(envelope.message.value, reason);
+
+            if reason == DeadLetterReason::Rejected {
+                return Either::Left(future::ready(Err(RequestError::Rejected)));
+            }
+        }
 
-        oneshot_rx.inspect(move |_| {
-            TASK_COUNT.fetch_sub(1, Ordering::Release);
-            notifier.notify_waiters();
-        })
+        Either::Right(oneshot_rx.map(move |answer| {
+            drop(resolving);
+            answer.map_err(|_| RequestError::Dropped)
+        }))
     }
 
+    async fn deliver(
+        &self,
+        envelope: Envelope<A::Input, A::Output>,
+        priority: Priority,
+    ) -> Result<(), (Envelope<A::Input, A::Output>, DeadLetterReason)> {
+        let lane = match priority {
+            Priority::High => &self.high,
+            Priority::Normal => &self.normal,
+        };
+        let listening = self
+            .closed
+            .upgrade()
+            .is_some_and(|closed| !closed.load(Ordering::Acquire));
+        if !listening {
+            return Err((envelope, DeadLetterReason::Closed));
+        }
+
+        match self.backpressure {
+            Backpressure::Wait => lane
+                .queue
+                .send_async(envelope)
+                .await
+                .map_err(|e| (e.into_inner(), DeadLetterReason::Closed)),
+            Backpressure::Reject => lane.queue.try_send(envelope).map_err(|e| match e {
+                TrySendError::Full(envelope) => (envelope, DeadLetterReason::Rejected),
+                TrySendError::Disconnected(envelope) => (envelope, DeadLetterReason::Closed),
+            }),
+            Backpressure::DropOldest => {
+                let oldest = match (priority, &self.oldest) {
+                    (Priority::High, Some((high, _))) => high,
+                    (Priority::Normal, Some((_, normal))) => normal,
+                    (_, None) => {
+                        unreachable!("mailboxes that drop the oldest message keep its queue")
+                    }
+                };
+
+                let mut envelope = envelope;
+                loop {
+                    match lane.queue.try_send(envelope) {
+                        Ok(()) => return Ok(()),
+                        Err(TrySendError::Full(unsent)) => match oldest.try_recv() {
+                            Ok(dropped) => {
+                                self.metrics.unsent();
+                                self.dead_letters
+                                    .send(dropped.message.value, DeadLetterReason::Overflowed);
+                                envelope = unsent;
+                            }
+                            // full with nothing in it, like with a capacity of 0, so there's nothing to drop and
+                            // waiting's all that's left
+                            Err(_) => {
+                                return lane
+                                    .queue
+                                    .send_async(unsent)
+                                    .await
+                                    .map_err(|e| (e.into_inner(), DeadLetterReason::Closed))
+                            }
+                        },
+                        Err(TrySendError::Disconnected(envelope)) => {
+                            return Err((envelope, DeadLetterReason::Closed))
+                        }
+                    }
+                }
+            }
+            Backpressure::Spill => {
+                // once anything's spilled, what comes after it spills too, so it isn't answered ahead
+                let envelope = if lane.spill.is_empty() {
+                    match lane.queue.try_send(envelope) {
+                        Ok(()) => return Ok(()),
+                        Err(TrySendError::Full(envelope)) => envelope,
+                        Err(TrySendError::Disconnected(envelope)) => {
+                            return Err((envelope, DeadLetterReason::Closed))
+                        }
+                    }
+                } else {
+                    envelope
+                };
+
+                lane.spill
+                    .send(envelope)
+                    .map_err(|e| (e.into_inner(), DeadLetterReason::Closed))
+            }
+        }
+    }
+
+    /// Sends `input`, and waits for an actor to answer it.
+    ///
+    /// # Panics
+    /// If the message is rejected or dropped, rather than answered.
     pub async fn request(&self, input: A::Input) -> A::Output {
-        let v = self.deferred_request(input).await;
+        self.request_with(input, Priority::Normal).await
+    }
+
+    pub async fn request_with(&self, input: A::Input, priority: Priority) -> A::Output {
+        let v = self.deferred_request_with(input, priority).await;
         v.await.unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use futures::future::BoxFuture;
+    use tracing::Span;
+
+    use super::*;
+
+    /// Answers with its input, after `delay`.
+    pub(crate) struct Echo {
+        pub(crate) delay: Duration,
+    }
+
+    impl Actor for Echo {
+        type Input = u32;
+        type Output = u32;
+
+        type Response<'a> = BoxFuture<'a, u32>;
+        type CloseFuture<'a> = future::Ready<()>;
+
+        fn close<'a>(self) -> Self::CloseFuture<'a> {
+            future::ready(())
+        }
+
+        fn answer(&mut self, i: u32) -> Self::Response<'_> {
+            let delay = self.delay;
+            async move {
+                tokio::time::sleep(delay).await;
+                i
+            }
+            .boxed()
+        }
+    }
+
+    pub(crate) fn echo() -> Echo {
+        Echo {
+            delay: Duration::from_millis(10),
+        }
+    }
+
+    /// Panics on 0, and answers with its input otherwise.
+    pub(crate) struct Fragile;
+
+    impl Actor for Fragile {
+        type Input = u32;
+        type Output = u32;
+
+        type Response<'a> = future::Ready<u32>;
+        type CloseFuture<'a> = future::Ready<()>;
+
+        fn close<'a>(self) -> Self::CloseFuture<'a> {
+            future::ready(())
+        }
+
+        fn answer(&mut self, i: u32) -> Self::Response<'_> {
+            assert_ne!(i, 0, "asked to break");
+            future::ready(i)
+        }
+    }
+
+    /// Answers with its input, writing down the order it answered in.
+    struct Record(Arc<Mutex<Vec<u32>>>);
+
+    impl Actor for Record {
+        type Input = u32;
+        type Output = u32;
+
+        type Response<'a> = future::Ready<u32>;
+        type CloseFuture<'a> = future::Ready<()>;
+
+        fn close<'a>(self) -> Self::CloseFuture<'a> {
+            future::ready(())
+        }
+
+        fn answer(&mut self, i: u32) -> Self::Response<'_> {
+            self.0.lock().unwrap().push(i);
+            future::ready(i)
+        }
+    }
+
+    type DeadLetterLog = Arc<Mutex<Vec<(u32, DeadLetterReason)>>>;
+
+    /// A mailbox of `capacity` with nobody answering it yet, and the dead letters it hands off.
+    fn unanswered(
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> (ActorManager<Echo>, Mailbox<Echo>, DeadLetterLog) {
+        let (manager, mailbox) = ActorManager::with_backpressure(capacity, backpressure);
+        let dead = Arc::new(Mutex::new(Vec::new()));
+        let letters = Arc::clone(&dead);
+        manager.on_dead_letter(move |letter| {
+            letters.lock().unwrap().push((letter.value, letter.reason))
+        });
+
+        (manager, mailbox, dead)
+    }
+
+    #[tokio::test]
+    async fn counts_actors_that_panicked() {
+        let (mut manager, mailbox) = ActorManager::new(4);
+        manager.spawn_actor(Fragile, Span::none());
+        manager.spawn_actor(Fragile, Span::none());
+
+        assert_eq!(
+            mailbox.deferred_request(0).await.await,
+            Err(RequestError::Dropped)
+        );
+        assert_eq!(mailbox.request(5).await, 5);
+
+        let summary = manager.close_and_join().await;
+        assert_eq!(summary.joined, 1);
+        assert_eq!(summary.panicked, 1);
+        assert_eq!(summary.cancelled, 0);
+    }
+
+    #[tokio::test]
+    async fn answers_high_priority_first() {
+        let answered = Arc::new(Mutex::new(Vec::new()));
+        let (mut manager, mailbox) = ActorManager::new(8);
+
+        let mut pending = Vec::new();
+        for (i, priority) in [
+            (1, Priority::Normal),
+            (2, Priority::Normal),
+            (3, Priority::Normal),
+            (10, Priority::High),
+            (11, Priority::High),
+        ] {
+            pending.push(mailbox.deferred_request_with(i, priority).await);
+        }
+
+        manager.spawn_actor(Record(Arc::clone(&answered)), Span::none());
+        for request in pending {
+            request.await.unwrap();
+        }
+
+        assert_eq!(*answered.lock().unwrap(), [10, 11, 1, 2, 3]);
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn counts_what_is_sent_and_answered() {
+        let (mut manager, mailbox) = ActorManager::new(8);
+
+        let mut pending = Vec::new();
+        for i in 0..3 {
+            pending.push(mailbox.deferred_request(i).await);
+        }
+
+        let waiting = mailbox.metrics().snapshot();
+        assert_eq!((waiting.depth, waiting.in_flight), (3, 3));
+        assert_eq!((waiting.received, waiting.handled), (0, 0));
+
+        manager.spawn_actor(echo(), Span::none());
+        for request in pending {
+            request.await.unwrap();
+        }
+
+        let answered = mailbox.metrics().snapshot();
+        assert_eq!((answered.depth, answered.in_flight), (0, 0));
+        assert_eq!((answered.received, answered.handled), (3, 3));
+        assert_eq!(answered.name, "Echo");
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_room() {
+        let (mut manager, mailbox, dead) = unanswered(1, Backpressure::Wait);
+
+        let first = mailbox.deferred_request(1).await;
+        let full =
+            tokio::time::timeout(Duration::from_millis(50), mailbox.deferred_request(2)).await;
+        assert!(full.is_err());
+
+        manager.spawn_actor(echo(), Span::none());
+        assert_eq!(first.await, Ok(1));
+        assert_eq!(mailbox.request(2).await, 2);
+        assert!(dead.lock().unwrap().is_empty());
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_when_full() {
+        let (mut manager, mailbox, dead) = unanswered(1, Backpressure::Reject);
+
+        let first = mailbox.deferred_request(1).await;
+        assert_eq!(
+            mailbox.deferred_request(2).await.await,
+            Err(RequestError::Rejected)
+        );
+        assert_eq!(*dead.lock().unwrap(), [(2, DeadLetterReason::Rejected)]);
+
+        manager.spawn_actor(echo(), Span::none());
+        assert_eq!(first.await, Ok(1));
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_when_full() {
+        let (mut manager, mailbox, dead) = unanswered(1, Backpressure::DropOldest);
+
+        let first = mailbox.deferred_request(1).await;
+        let second = mailbox.deferred_request(2).await;
+        assert_eq!(first.await, Err(RequestError::Dropped));
+        assert_eq!(*dead.lock().unwrap(), [(1, DeadLetterReason::Overflowed)]);
+
+        manager.spawn_actor(echo(), Span::none());
+        assert_eq!(second.await, Ok(2));
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn spills_past_capacity_in_order() {
+        let answered = Arc::new(Mutex::new(Vec::new()));
+        let (mut manager, mailbox) = ActorManager::with_backpressure(1, Backpressure::Spill);
+
+        let mut pending = Vec::new();
+        for i in 1..=4 {
+            pending.push(mailbox.deferred_request(i).await);
+        }
+        assert_eq!(mailbox.len(), 4);
+
+        manager.spawn_actor(Record(Arc::clone(&answered)), Span::none());
+        for request in pending {
+            request.await.unwrap();
+        }
+
+        assert_eq!(*answered.lock().unwrap(), [1, 2, 3, 4]);
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drains_until_the_deadline() {
+        let (mut manager, mailbox, dead) = unanswered(8, Backpressure::Wait);
+        manager.spawn_actor(
+            Echo {
+                delay: Duration::from_millis(50),
+            },
+            Span::none(),
+        );
+
+        let mut pending = Vec::new();
+        for i in 0..6 {
+            pending.push(mailbox.deferred_request(i).await);
+        }
+
+        // two answers fit in before the deadline, and the third is given up on partway through
+        let summary = manager.drain_and_join(Duration::from_millis(120)).await;
+        assert_eq!(summary.joined, 1);
+
+        let answers = futures::future::join_all(pending).await;
+        assert_eq!(answers[..2], [Ok(0), Ok(1)]);
+        assert!(answers[2..]
+            .iter()
+            .all(|answer| *answer == Err(RequestError::Dropped)));
+
+        // what was never taken went to the dead letters
+        let mut dead = dead.lock().unwrap().clone();
+        dead.sort_unstable_by_key(|(value, _)| *value);
+        assert_eq!(
+            dead,
+            [3, 4, 5].map(|value| (value, DeadLetterReason::Closed))
+        );
+    }
+
+    #[tokio::test]
+    async fn drains_everything_before_the_deadline() {
+        let (mut manager, mailbox) = ActorManager::new(8);
+        manager.spawn_actor(echo(), Span::none());
+
+        let mut pending = Vec::new();
+        for i in 0..4 {
+            pending.push(mailbox.deferred_request(i).await);
+        }
+
+        manager.drain_and_join(Duration::from_secs(5)).await;
+        let answers = futures::future::join_all(pending).await;
+        assert_eq!(answers, [Ok(0), Ok(1), Ok(2), Ok(3)]);
+    }
+
+    #[tokio::test]
+    async fn closed_mailboxes_dead_letter_what_is_sent() {
+        let (mut manager, mailbox, dead) = unanswered(4, Backpressure::Wait);
+        manager.spawn_actor(echo(), Span::none());
+        manager.close_and_join().await;
+
+        assert_eq!(
+            mailbox.deferred_request(7).await.await,
+            Err(RequestError::Dropped)
+        );
+        assert_eq!(*dead.lock().unwrap(), [(7, DeadLetterReason::Closed)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_requests_are_not_in_flight() {
+        let (mut manager, mailbox) = ActorManager::new(8);
+        manager.spawn_actor(
+            Echo {
+                delay: Duration::from_secs(60),
+            },
+            Span::none(),
+        );
+
+        // like a caller that times out waiting
+        let answer = tokio::time::timeout(Duration::from_secs(1), mailbox.request(1)).await;
+        assert!(answer.is_err());
+        assert_eq!(mailbox.metrics().snapshot().in_flight, 0);
+
+        let unwanted = mailbox.deferred_request(2).await;
+        drop(unwanted);
+        assert_eq!(mailbox.metrics().snapshot().in_flight, 0);
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn dead_letters_what_nobody_waits_for_anymore() {
+        let (mut manager, mailbox, dead) = unanswered(4, Backpressure::Wait);
+
+        let abandoned = mailbox.deferred_request(1).await;
+        let wanted = mailbox.deferred_request(2).await;
+        drop(abandoned);
+
+        manager.spawn_actor(echo(), Span::none());
+        assert_eq!(wanted.await, Ok(2));
+        assert_eq!(*dead.lock().unwrap(), [(1, DeadLetterReason::Abandoned)]);
+        manager.close_and_join().await;
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_without_capacity() {
+        let (mut manager, mailbox) = ActorManager::with_backpressure(0, Backpressure::DropOldest);
+        manager.spawn_actor(echo(), Span::none());
+
+        let answered = tokio::time::timeout(Duration::from_secs(5), mailbox.request(3)).await;
+        assert_eq!(answered, Ok(3));
+        manager.close_and_join().await;
+    }
+}
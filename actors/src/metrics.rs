@@ -0,0 +1,162 @@
+//! What the actors are up to: how much is waiting for them, how long it waits, and how long they take to answer it.
+
+use std::{
+    any,
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+static REGISTRY: Mutex<Vec<Weak<Metrics>>> = Mutex::new(Vec::new());
+
+/// Counters for one manager's mailbox, and the actors answering it.
+#[derive(Debug)]
+pub struct Metrics {
+    name: String,
+    /// Sent, but not taken by an actor yet.
+    depth: AtomicUsize,
+    /// Sent, and not answered or given up on yet.
+    in_flight: AtomicUsize,
+    received: AtomicU64,
+    handled: AtomicU64,
+    wait_nanos: AtomicU64,
+    handle_nanos: AtomicU64,
+}
+
+impl Metrics {
+    /// Makes the counters for a mailbox of `A`, named after it, and adds them to the registry.
+    pub(crate) fn register<A>() -> Arc<Metrics> {
+        let metrics = Arc::new(Metrics {
+            name: short_type_name::<A>(),
+            depth: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            received: AtomicU64::new(0),
+            handled: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            handle_nanos: AtomicU64::new(0),
+        });
+
+        let mut registry = REGISTRY.lock().unwrap();
+        registry.retain(|metrics| metrics.strong_count() > 0);
+        registry.push(Arc::downgrade(&metrics));
+
+        metrics
+    }
+
+    pub(crate) fn sent(&self) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Release);
+    }
+
+    /// Takes back a message that couldn't be sent after all.
+    pub(crate) fn unsent(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, waited: Duration) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.wait_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn resolved(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Release);
+    }
+
+    /// Records a message as answered, after `took`. Actors with their own loop call this themselves.
+    pub fn handled(&self, took: Duration) {
+        self.handled.fetch_add(1, Ordering::Relaxed);
+        self.handle_nanos
+            .fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mean = |nanos: &AtomicU64, count: u64| {
+            Duration::from_nanos(nanos.load(Ordering::Relaxed) / count.max(1))
+        };
+        let received = self.received.load(Ordering::Relaxed);
+        let handled = self.handled.load(Ordering::Relaxed);
+
+        MetricsSnapshot {
+            name: self.name.clone(),
+            depth: self.depth.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Acquire),
+            received,
+            handled,
+            mean_wait: mean(&self.wait_nanos, received),
+            mean_handle: mean(&self.handle_nanos, handled),
+        }
+    }
+}
+
+/// `A`'s name without the paths of it and its generics: `evergarden_client::client::HttpClient` reads better as
+/// `HttpClient`, and `actors::Blocking<evergarden::Index>` as `Blocking<Index>`.
+pub(crate) fn short_type_name<A: ?Sized>() -> String {
+    let name = any::type_name::<A>();
+    let mut short = String::with_capacity(name.len());
+
+    let mut rest = name;
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(rest.len());
+        let path = &rest[..end];
+        short.push_str(path.rsplit("::").next().unwrap_or(path));
+
+        // then whatever separates it from the next path
+        let mut after = rest[end..].chars();
+        short.extend(after.next());
+        rest = after.as_str();
+    }
+
+    short
+}
+
+/// A mailbox's counters at one point in time.
+#[derive(Clone, Debug)]
+pub struct MetricsSnapshot {
+    /// The type of the actors answering the mailbox.
+    pub name: String,
+    pub depth: usize,
+    pub in_flight: usize,
+    pub received: u64,
+    pub handled: u64,
+    pub mean_wait: Duration,
+    pub mean_handle: Duration,
+}
+
+impl Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} queued, {} in flight, {} answered, waiting {:?} and answering in {:?} on average",
+            self.name, self.depth, self.in_flight, self.handled, self.mean_wait, self.mean_handle
+        )
+    }
+}
+
+/// Every live mailbox's counters.
+pub fn snapshot() -> Vec<MetricsSnapshot> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|metrics| metrics.snapshot())
+        .collect()
+}
+
+/// How many requests, to any actor, haven't been answered yet. Nothing's left to do once it's zero.
+pub fn in_flight() -> usize {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|metrics| metrics.in_flight.load(Ordering::Acquire))
+        .sum()
+}
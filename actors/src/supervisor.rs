@@ -0,0 +1,203 @@
+use std::{panic, time::Duration};
+
+use tokio::{sync::watch, task::AbortHandle};
+use tracing::{error, warn, Instrument, Span};
+
+use crate::{actor_span, panic_message, Actor, ActorHandle, ActorManager, Inbox, ProgramState};
+
+/// How a supervised actor is brought back when it stops before its manager closes.
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    /// How many times the actor is re-created before the supervisor gives up on it.
+    pub max_restarts: usize,
+    /// How long to wait before the first restart. Every restart after it waits twice as long as the last one did.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl<A: Actor + Send + 'static> ActorManager<A> {
+    /// Spawns an actor made by `factory`, and makes a new one whenever it panics or its loop returns while the manager
+    /// is still running, rather than leaving the pool a worker short.
+    ///
+    /// Once the policy's restarts are used up the supervisor gives up, passing the last panic on, if there was one.
+    pub fn spawn_supervised<F>(
+        &mut self,
+        factory: F,
+        policy: RestartPolicy,
+        span: Span,
+    ) -> ActorHandle
+    where
+        F: Fn() -> A + Send + 'static,
+    {
+        let inbox = self.inbox.clone();
+        let (state, receiver) = self.attach();
+        let task = self
+            .tasks
+            .spawn(supervise(factory, policy, inbox, receiver).instrument(span));
+
+        ActorHandle { state, task }
+    }
+}
+
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn supervise<A, F>(
+    factory: F,
+    policy: RestartPolicy,
+    inbox: Inbox<A::Input, A::Output>,
+    state: watch::Receiver<ProgramState>,
+) where
+    A: Actor + Send + 'static,
+    F: Fn() -> A,
+{
+    let mut restarts = 0;
+    let mut backoff = policy.backoff;
+
+    loop {
+        // run as its own task, so that a panic ends up here instead of taking the supervisor down with it
+        let actor = factory();
+        let span = actor_span(&actor, actor.id(), &Span::current());
+        let task = tokio::spawn(
+            actor
+                .run_async_loop(inbox.clone(), state.clone())
+                .instrument(span),
+        );
+        // aborting the supervisor aborts the actor with it
+        let _abort = AbortOnDrop(task.abort_handle());
+        let ended = task.await;
+
+        let panicked = match ended {
+            Ok(()) => None,
+            Err(e) if e.is_panic() => Some(e.into_panic()),
+            Err(_) => return,
+        };
+
+        // closing the manager or dropping every mailbox stops actors on purpose
+        if *state.borrow() != ProgramState::Running || inbox.is_disconnected() {
+            if let Some(payload) = panicked {
+                panic::resume_unwind(payload);
+            }
+            return;
+        }
+
+        match &panicked {
+            Some(payload) => error!("actor panicked: {}", panic_message(&**payload)),
+            None => warn!("actor stopped while its manager was still running"),
+        }
+
+        if restarts == policy.max_restarts {
+            error!("giving up on the actor after {restarts} restarts");
+            if let Some(payload) = panicked {
+                panic::resume_unwind(payload);
+            }
+            return;
+        }
+
+        tokio::time::sleep(backoff).await;
+        restarts += 1;
+        backoff = (backoff * 2).min(policy.max_backoff);
+        warn!(
+            "restarting the actor ({restarts} of {})",
+            policy.max_restarts
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio::time::Instant;
+    use tracing::Span;
+
+    use super::*;
+    use crate::{tests::Fragile, Mailbox, RequestError};
+
+    fn supervised(
+        policy: RestartPolicy,
+    ) -> (ActorManager<Fragile>, Mailbox<Fragile>, Arc<AtomicUsize>) {
+        let (mut manager, mailbox) = ActorManager::new(8);
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&created);
+        manager.spawn_supervised(
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Fragile
+            },
+            policy,
+            Span::none(),
+        );
+
+        (manager, mailbox, created)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_with_backoff() {
+        let (mut manager, mailbox, created) = supervised(RestartPolicy {
+            max_restarts: 5,
+            backoff: Duration::from_millis(30),
+            max_backoff: Duration::from_millis(30),
+        });
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(
+                mailbox.deferred_request(0).await.await,
+                Err(RequestError::Dropped)
+            );
+        }
+        assert_eq!(mailbox.request(7).await, 7);
+
+        // 30ms each time, rather than the 210ms it would take if it kept doubling. the clock only moves for the backoffs
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(90), "waited {waited:?}");
+        assert!(waited < Duration::from_millis(210), "waited {waited:?}");
+        assert_eq!(created.load(Ordering::SeqCst), 4);
+
+        let summary = manager.close_and_join().await;
+        assert_eq!(summary.joined, 1);
+        assert_eq!(created.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_restarts() {
+        let (mut manager, mailbox, created) = supervised(RestartPolicy {
+            max_restarts: 1,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        });
+
+        for _ in 0..2 {
+            assert_eq!(
+                mailbox.deferred_request(0).await.await,
+                Err(RequestError::Dropped)
+            );
+        }
+
+        let summary = manager.close_and_join().await;
+        assert_eq!(summary.panicked, 1);
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+    }
+}
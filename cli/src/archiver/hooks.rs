@@ -0,0 +1,272 @@
+//! Reporting a crawl's progress to webhooks and commands, so that crawls nobody's watching still say how they went.
+
+use std::{path::PathBuf, process::Stdio};
+
+use evergarden_client::{
+    budget::{Budget, BudgetLimit},
+    config::{CrawlEvent, HooksConfig},
+};
+use evergarden_common::{EvergardenResult, Filter, Storage};
+use hyper::{client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::{io::AsyncWriteExt, process::Command, time::timeout};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// What a hook is told about the crawl.
+#[derive(Serialize)]
+struct Payload {
+    event: CrawlEvent,
+    /// A line summing the event up, which is what chat webhooks show.
+    text: String,
+    crawl_id: Uuid,
+    output: PathBuf,
+    seeds: usize,
+    #[serde(with = "time::serde::rfc3339")]
+    started_at: OffsetDateTime,
+    #[serde(
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    finished_at: Option<OffsetDateTime>,
+    /// What's stored, once the crawl's over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The limit the crawl reached, and what it spent getting there.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budget: Option<BudgetLimit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requests: Option<u64>,
+}
+
+impl Payload {
+    /// Adds what the crawl's stored so far. That reads through the whole index, so it's done off the runtime.
+    async fn sum_up(&mut self, storage: &Storage) {
+        let storage = storage.clone();
+        match tokio::task::spawn_blocking(move || summarize(&storage)).await {
+            Ok(Ok((records, bytes, failures))) => {
+                self.records = Some(records);
+                self.bytes = Some(bytes);
+                self.failures = Some(failures);
+            }
+            Ok(Err(e)) => warn!("couldn't sum the crawl up for its hooks: {e}"),
+            Err(e) => warn!("couldn't sum the crawl up for its hooks: {e}"),
+        }
+    }
+}
+
+pub(crate) struct Hooks {
+    config: HooksConfig,
+    client: Client<HttpsConnector<HttpConnector>>,
+    crawl_id: Uuid,
+    output: PathBuf,
+    seeds: usize,
+    started_at: OffsetDateTime,
+}
+
+impl Hooks {
+    pub(crate) fn new(
+        config: HooksConfig,
+        crawl_id: Uuid,
+        output: PathBuf,
+        seeds: usize,
+        started_at: OffsetDateTime,
+    ) -> Hooks {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Hooks {
+            config,
+            client: Client::builder().build(connector),
+            crawl_id,
+            output,
+            seeds,
+            started_at,
+        }
+    }
+
+    pub(crate) async fn started(&self) {
+        let text = format!(
+            "crawl {} started from {} seeds, into {}",
+            self.crawl_id,
+            self.seeds,
+            self.output.display()
+        );
+        self.fire(self.payload(CrawlEvent::Started, text)).await;
+    }
+
+    /// Reports the crawl as over, with what it stored, and the error that ended it if it failed.
+    pub(crate) async fn ended(&self, storage: &Storage, error: Option<String>) {
+        let event = match error {
+            Some(_) => CrawlEvent::Failed,
+            None => CrawlEvent::Finished,
+        };
+        if !self.config.reports(event) {
+            return;
+        }
+
+        let mut payload = self.payload(event, String::new());
+        payload.finished_at = Some(OffsetDateTime::now_utc());
+        payload.sum_up(storage).await;
+
+        payload.text = match &error {
+            Some(error) => format!("crawl {} failed: {error}", self.crawl_id),
+            None => format!(
+                "crawl {} finished, storing {} records ({} bytes) with {} failed urls, into {}",
+                self.crawl_id,
+                payload.records.unwrap_or_default(),
+                payload.bytes.unwrap_or_default(),
+                payload.failures.unwrap_or_default(),
+                self.output.display()
+            ),
+        };
+        payload.error = error;
+
+        self.fire(payload).await;
+    }
+
+    /// Reports the crawl reaching `limit`, with what it's stored so far. It still reports finishing once it's stopped.
+    pub(crate) async fn budget_exhausted(
+        &self,
+        storage: &Storage,
+        budget: &Budget,
+        limit: BudgetLimit,
+    ) {
+        if !self.config.reports(CrawlEvent::BudgetExhausted) {
+            return;
+        }
+
+        let (requests, read) = budget.spent();
+        let mut payload = self.payload(
+            CrawlEvent::BudgetExhausted,
+            format!(
+                "crawl {} reached its {limit} budget after {requests} requests ({read} bytes), and is stopping",
+                self.crawl_id
+            ),
+        );
+        payload.budget = Some(limit);
+        payload.requests = Some(requests);
+        payload.sum_up(storage).await;
+
+        self.fire(payload).await;
+    }
+
+    fn payload(&self, event: CrawlEvent, text: String) -> Payload {
+        Payload {
+            event,
+            text,
+            crawl_id: self.crawl_id,
+            output: self.output.clone(),
+            seeds: self.seeds,
+            started_at: self.started_at,
+            finished_at: None,
+            records: None,
+            bytes: None,
+            failures: None,
+            error: None,
+            budget: None,
+            requests: None,
+        }
+    }
+
+    // hooks only report on the crawl, so one that fails is logged rather than stopping it
+    async fn fire(&self, payload: Payload) {
+        if !self.config.reports(payload.event) {
+            return;
+        }
+
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("couldn't serialize the hook payload: {e}");
+                return;
+            }
+        };
+
+        for url in &self.config.webhooks {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(url.as_str())
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.clone()));
+            let request = match request {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("can't send to webhook {url}: {e}");
+                    continue;
+                }
+            };
+
+            match timeout(self.config.timeout, self.client.request(request)).await {
+                Ok(Ok(res)) if res.status().is_success() => {
+                    info!("reported {:?} to {url}", payload.event)
+                }
+                Ok(Ok(res)) => warn!("webhook {url} answered {}", res.status()),
+                Ok(Err(e)) => warn!("webhook {url} failed: {e}"),
+                Err(_) => warn!("webhook {url} timed out"),
+            }
+        }
+
+        if let Some(command) = &self.config.command {
+            match timeout(
+                self.config.timeout,
+                run_command(command, &self.config.args, &payload, &body),
+            )
+            .await
+            {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("hook command {command} failed: {e}"),
+                Err(_) => warn!("hook command {command} timed out"),
+            }
+        }
+    }
+}
+
+async fn run_command(
+    command: &str,
+    args: &[String],
+    payload: &Payload,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let mut child = Command::new(command)
+        .args(args)
+        .env(
+            "EVERGARDEN_EVENT",
+            serde_json::to_value(payload.event)?
+                .as_str()
+                .unwrap_or_default(),
+        )
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body).await?;
+    }
+
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("exited with {status}")));
+    }
+
+    Ok(())
+}
+
+/// How many records the crawl stored, their bodies' total length, and how many urls failed.
+fn summarize(storage: &Storage) -> EvergardenResult<(usize, u64, usize)> {
+    let stats = storage.stats(Filter::default(), 0)?;
+    let failures = storage.list_failures().count();
+
+    Ok((stats.records, stats.bytes, failures))
+}
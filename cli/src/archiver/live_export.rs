@@ -0,0 +1,160 @@
+//! Writing a crawl into a WACZ package as responses are stored, so that it's ready as soon as the crawl is.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use evergarden_client::extract;
+use evergarden_common::{CrawlInfo, EvergardenResult, RecordSink, ResponseMetadata};
+use tempfile::tempfile;
+use ubyte::ByteUnit;
+use zip::ZipWriter;
+
+use crate::export::{
+    cdxj::{CDXRecord, CDXWriter},
+    metadata::PackageMetadata,
+    pages::PagesWriter,
+    run::{create_package, decode_html, finish_package, is_noindex},
+    warc::{RotatingWarcRecorder, WarcFormat, WarcInfo, WarcRecorder},
+};
+
+struct LivePackage {
+    recorder: RotatingWarcRecorder<ZipWriter<BufWriter<File>>>,
+    // in the order responses were stored, until they're sorted for the finished package
+    index: Vec<CDXRecord>,
+    pages: PagesWriter<File>,
+}
+
+/// Writes every stored response into a WACZ package. Its WARC files are written into the package as responses come
+/// in; its index and pages wait until the crawl's done, when the package is finished.
+///
+/// The package has what this crawl stored, as it was stored: annotations added to a page afterwards aren't in it, and
+/// revisits of captures from earlier crawls of the same output point at records it doesn't have.
+pub(crate) struct LiveExport {
+    path: PathBuf,
+    entry_points: Vec<String>,
+    user_agent: String,
+    package: Mutex<Option<LivePackage>>,
+}
+
+impl LiveExport {
+    /// Starts the package at `path`, for the crawl `info`.
+    pub(crate) fn create(
+        path: impl AsRef<Path>,
+        info: &CrawlInfo,
+        warc_info: WarcInfo,
+        user_agent: String,
+    ) -> EvergardenResult<LiveExport> {
+        let path = path.as_ref().to_path_buf();
+        let recorder = RotatingWarcRecorder::starting_at(
+            create_package(&path)?,
+            "archive/",
+            ByteUnit::Gigabyte(1).as_u64(),
+            warc_info,
+            WarcFormat::default(),
+            0,
+        )?;
+
+        let mut entry_points = info.entry_points.clone();
+        entry_points.sort();
+
+        Ok(LiveExport {
+            path,
+            entry_points,
+            user_agent,
+            package: Mutex::new(Some(LivePackage {
+                recorder,
+                index: Vec::new(),
+                pages: PagesWriter::new(tempfile()?, tempfile()?)?,
+            })),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Finishes the package, adding its index, now sorted, and pages.
+    pub(crate) fn finish(&self) -> Result<(), Box<dyn Error>> {
+        let Some(LivePackage {
+            recorder,
+            mut index,
+            pages,
+        }) = self.package.lock().unwrap().take()
+        else {
+            return Ok(());
+        };
+
+        let (entries, package) = recorder.finalize()?;
+
+        index.sort_by(|a, b| (&a.key, a.time).cmp(&(&b.key, b.time)));
+        let mut cdx_writer = CDXWriter::new(tempfile()?, tempfile()?)?;
+        cdx_writer.write_batch(index)?;
+
+        finish_package(
+            package,
+            cdx_writer,
+            pages,
+            entries,
+            false,
+            &PackageMetadata::default(),
+        )?;
+
+        Ok(())
+    }
+}
+
+impl RecordSink for LiveExport {
+    fn write_record(
+        &self,
+        key: &str,
+        meta: &ResponseMetadata,
+        body: Option<&mut dyn Read>,
+    ) -> EvergardenResult<()> {
+        let mut package = self.package.lock().unwrap();
+        let Some(LivePackage {
+            recorder,
+            index,
+            pages,
+        }) = package.as_mut()
+        else {
+            return Err(io::Error::other("the crawl's WACZ package is already finished").into());
+        };
+
+        let mut empty = io::empty();
+        let mut body = body.unwrap_or(&mut empty);
+        // only html with robots directives has to be read in whole, for the check below
+        let mut html = None;
+
+        let cdx = match (&meta.resource, &meta.revisit) {
+            (Some(resource), _) => {
+                index.push(recorder.write_resource(key, meta, resource, &mut body)?);
+                return Ok(());
+            }
+            (None, Some(revisit)) => recorder.write_revisit(key, meta, revisit)?,
+            (None, None) if extract::is_html(meta) && meta.robots.is_some() => {
+                let mut received = Vec::new();
+                body.read_to_end(&mut received)?;
+                html = decode_html(meta, &received);
+                recorder.write_warc(key, meta, &mut &received[..])?
+            }
+            (None, None) => recorder.write_warc(key, meta, &mut body)?,
+        };
+        index.push(cdx);
+
+        // revisits come without a body, so only their headers can keep them out of the pages
+        if !is_noindex(meta, html.as_deref(), &self.user_agent) {
+            pages.add_entry(
+                meta,
+                self.entry_points.binary_search(&key.to_owned()).is_ok(),
+                None,
+            )?;
+        }
+
+        Ok(())
+    }
+}
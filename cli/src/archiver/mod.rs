@@ -1,27 +1,87 @@
-use std::{error::Error, path::PathBuf, sync::atomic::Ordering, time::Duration};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use actors::ActorManager;
+use actors::{ActorManager, Blocking, Priority, RestartPolicy};
 use evergarden_client::{
-    client::{HttpClient, HttpRateLimiter},
-    config::{FullConfig, GlobalState},
+    budget::Budget,
+    client::{CanonicalAliases, HttpClient, HttpRateLimiter},
+    config::{FullConfig, GlobalState, HooksConfig},
+    extract::Discovery,
+    frontier::Blocklist,
     scripting::script::ScriptManager,
 };
-use evergarden_common::{surt, CrawlInfo, Storage, UrlInfo};
+use evergarden_common::{
+    surt_stripping, CrawlInfo, EvergardenResult, FailedFetch, Filter, ScopeKind, Storage,
+    StorageBackend, UrlInfo,
+};
 use futures_util::{stream::FuturesUnordered, StreamExt};
-use tracing::{info, info_span, metadata::LevelFilter};
+use time::OffsetDateTime;
+use tracing::{debug, error, info, info_span, metadata::LevelFilter, warn};
 
 use clap::builder::TypedValueParser;
-use tracing_subscriber::{filter::Targets, fmt::format, prelude::*};
+use tracing_subscriber::{
+    filter::Targets,
+    fmt::{format, writer::BoxMakeWriter},
+    prelude::*,
+};
 use url::Url;
+use uuid::Uuid;
+
+mod hooks;
+mod live_export;
+mod report;
+mod warc_sink;
+
+use crate::{config::OverrideArgs, export::warc::WarcInfo};
+use hooks::Hooks;
+use live_export::LiveExport;
+use report::CrawlReport;
+use warc_sink::WarcSink;
+
+/// How long storing what's still queued when the crawl's over may take.
+const STORAGE_DRAIN: Duration = Duration::from_secs(30);
+
+/// How long the HTTP actor may take to queue what the scripts sent it, once the budget's spent.
+const HTTP_QUEUE_WAIT: Duration = Duration::from_secs(30);
 
 #[derive(clap::Args, Debug)]
 pub(crate) struct ArchiverArgs {
-    #[arg(short, long, help = "crawl configuration")]
-    config: PathBuf,
-    #[arg(short, long, help = "output folder")]
-    output: PathBuf,
-    #[arg(long, help = "Doesn't overwrite existing records in <output>, except for seed urls.")]
+    #[arg(
+        short,
+        long,
+        help = "crawl configuration",
+        required_unless_present = "resume"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        help = "output folder",
+        required_unless_present = "dry_run"
+    )]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Doesn't overwrite existing records in <output>, except for seed urls."
+    )]
     no_clobber: bool,
+    #[arg(
+        long,
+        help = "Re-fetch urls already in <output>, recording unchanged bodies as revisits of the earlier capture. Implies --no-clobber."
+    )]
+    refresh: bool,
+    #[arg(
+        long,
+        help = "Continue an interrupted crawl in <output>, with its original seeds and (unless -c is given) configuration.",
+        conflicts_with_all = ["refresh", "seed_urls", "seeds_file"]
+    )]
+    resume: bool,
     #[arg(
         long,
         help = "Logging level for HTTP tasks",
@@ -38,7 +98,39 @@ pub(crate) struct ArchiverArgs {
             .map(|s| s.parse::<LevelFilter>().unwrap()),
     )]
     script_log: LevelFilter,
-    #[arg(help = "URLs for start of crawl", required = true)]
+    #[arg(
+        long,
+        help = "What the crawl of each seed should stay in; leaving it counts as a hop",
+        default_value = "none",
+        value_parser = clap::builder::PossibleValuesParser::new(["none", "host", "prefix"])
+            .map(|s| s.parse::<ScopeKind>().unwrap()),
+    )]
+    scope: ScopeKind,
+    #[arg(
+        long,
+        value_name = "WACZ",
+        help = "Also write what's stored into a WACZ package as the crawl goes, so it's ready as soon as the crawl is"
+    )]
+    live_export: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Crawl as usual, but only read the bodies that scripts and extractors look for links in, store nothing, and print the urls that would be archived",
+        conflicts_with_all = ["resume", "refresh", "no_clobber", "live_export"]
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        visible_alias = "seeds",
+        value_name = "FILE",
+        help = "Also start from the URLs in this file, or stdin if it's -, one per line. Lines starting with # are skipped."
+    )]
+    seeds_file: Option<PathBuf>,
+    #[command(flatten)]
+    overrides: OverrideArgs,
+    #[arg(
+        help = "URLs for start of crawl. Prefix with host= or prefix= to override --scope for a single seed.",
+        required_unless_present_any = ["resume", "seeds_file"]
+    )]
     seed_urls: Vec<String>,
 }
 
@@ -46,14 +138,23 @@ pub(crate) async fn run_archiver(
     args: ArchiverArgs,
     log_level: LevelFilter,
 ) -> Result<(), Box<dyn Error>> {
+    // a dry run's urls are printed on stdout
+    let log_writer = if args.dry_run {
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::fmt::layer().event_format(
-                format()
-                    .pretty()
-                    .with_line_number(false)
-                    .with_source_location(false),
-            ),
+            tracing_subscriber::fmt::layer()
+                .with_writer(log_writer)
+                .event_format(
+                    format()
+                        .pretty()
+                        .with_line_number(false)
+                        .with_source_location(false),
+                ),
         )
         .with(
             Targets::new()
@@ -63,105 +164,450 @@ pub(crate) async fn run_archiver(
         )
         .init();
 
-    let cfg: FullConfig = toml::from_str(&tokio::fs::read_to_string(args.config).await?)?;
-    let storage: Storage = Storage::new(args.output, !args.no_clobber)?;
+    // checked before anything in the output is touched
+    let config_file = match &args.config {
+        Some(path) => Some(
+            args.overrides
+                .parse(&tokio::fs::read_to_string(path).await?)?,
+        ),
+        None => None,
+    };
+    if let Some(problems) = config_file.as_ref().map(FullConfig::problems) {
+        for problem in &problems {
+            error!("{problem}");
+        }
+        if !problems.is_empty() {
+            return Err(format!("found {} problems in the config", problems.len()).into());
+        }
+    }
+
+    // dry runs crawl into a scratch folder, which is gone once they're done
+    let scratch = if args.dry_run {
+        Some(tempfile::tempdir()?)
+    } else {
+        None
+    };
+    let output = match (&scratch, &args.output) {
+        (Some(scratch), _) => scratch.path().to_path_buf(),
+        (None, Some(output)) => output.clone(),
+        (None, None) => unreachable!("clap requires an output unless dry running"),
+    };
+
+    let report_path = output.join("failed-urls.jsonl");
+    let crawl_report_path = output.join("crawl-report.json");
+    let warc_dir = output.join("warc");
+    let clobber = !(args.no_clobber || args.refresh || args.resume);
+    if clobber {
+        let _ = std::fs::remove_dir_all(&warc_dir);
+    }
+
+    let storage: Storage = Storage::new(&output, clobber)?;
+    let started_at = OffsetDateTime::now_utc();
+
+    // read once and passed around from here, so the log, the hooks and the WARC files all agree on the crawl's id
+    let (cfg, info, queued_urls) = if args.resume {
+        let info = storage.read_info_sync()?;
+        // crawls saved before they had an id are given one when they're read, which is kept from now on
+        storage.write_info(&info).await?;
 
-    let seed_urls: Vec<Url> = args
-        .seed_urls
-        .into_iter()
-        .filter_map(|v| v.parse::<Url>().ok())
-        .collect();
+        let cfg = match config_file {
+            Some(cfg) => cfg,
+            None => args
+                .overrides
+                .apply(serde_json::from_value(info.saved_config()?)?)?,
+        };
 
-    storage
-        .write_info(&CrawlInfo {
+        // urls that were stored before the crawl stopped are answered from storage, so only the rest get fetched
+        let queued = storage
+            .list_queued()
+            .collect::<EvergardenResult<Vec<UrlInfo>>>()?;
+        info!(
+            "resuming crawl {} with {} queued urls",
+            info.id,
+            queued.len()
+        );
+
+        (cfg, info, queued)
+    } else {
+        let cfg = config_file.expect("clap requires a config unless resuming");
+        let mut seeds = args.seed_urls.clone();
+        if let Some(path) = &args.seeds_file {
+            seeds.extend(read_seeds(path)?);
+        }
+
+        let seed_urls: Vec<UrlInfo> = seeds
+            .iter()
+            .filter_map(|v| parse_seed(v, args.scope))
+            .map(|mut seed| {
+                seed.url = cfg.rewrite.apply(seed.url);
+                seed.discovered_in = seed.url.clone();
+                seed
+            })
+            .collect();
+
+        let info = CrawlInfo {
+            id: Uuid::new_v4(),
             config: serde_json::to_string(&cfg)?,
-            entry_points: seed_urls.iter().cloned().map(surt).collect(),
-        })
-        .await?;
+            entry_points: seed_urls
+                .iter()
+                .map(|s| surt_stripping(s.url.clone(), &cfg.storage.strip_params))
+                .collect(),
+            seeds: seed_urls.clone(),
+        };
+        storage.write_info(&info).await?;
 
-    for url in seed_urls.iter().cloned().map(surt) {
-        storage.del_by_key(&url).await?;
-    }
+        // refreshing re-fetches everything anyway, and needs the previous captures to compare against
+        if !args.refresh {
+            for url in seed_urls
+                .iter()
+                .map(|s| surt_stripping(s.url.clone(), &cfg.storage.strip_params))
+            {
+                storage.del_by_key(&url).await?;
+            }
+        }
+
+        (cfg, info, Vec::new())
+    };
+    let seed_urls = info.seeds.clone();
 
     let FullConfig {
         general,
         ratelimiter,
         http,
+        skip,
+        rewrite,
+        extract,
+        storage: mut storage_config,
+        mut hooks,
         scripts,
     } = cfg;
 
-    let rate_limiter = HttpRateLimiter::new(ratelimiter);
+    // nothing's stored anywhere but the scratch folder, and there's nothing to report
+    if args.dry_run {
+        storage_config.backend = StorageBackend::Local;
+        hooks = HooksConfig::default();
+    }
 
-    let (mut http_manager, http_mailbox) = ActorManager::new(10_000);
-    let (mut script_runner, script_mailbox) = ActorManager::new(256);
-    let (mut storage_manager, storage_mailbox) = ActorManager::new(256);
+    let crawl_id = info.id;
+    let seeds = seed_urls.iter().map(|s| s.url.clone()).collect::<Vec<_>>();
+    let hooks = Hooks::new(hooks, crawl_id, output.clone(), seed_urls.len(), started_at);
+    hooks.started().await;
 
-    storage_manager.spawn_actor(
-        storage,
-        info_span!(target: "evergarden::storage", "Storage"),
-    );
+    let hook_storage = storage.clone();
+    let crawled: Result<(), Box<dyn Error>> = async {
+        // the warc backend writes responses out as they're stored, rather than keeping them for export
+        let warc_sink = match storage_config.backend {
+            StorageBackend::Warc => Some(Arc::new(WarcSink::open(
+                &warc_dir,
+                WarcInfo::new(&info, general.operator.clone()),
+            )?)),
+            _ => None,
+        };
 
-    http_manager.spawn_actor(
-        HttpClient::new(
+        let live_export = match &args.live_export {
+            Some(_) if warc_sink.is_some() => {
+                return Err(
+                    "the warc storage backend already writes WARC files as the crawl goes, and can't also write a package"
+                        .into(),
+                );
+            }
+            Some(path) => Some(Arc::new(LiveExport::create(
+                path,
+                &info,
+                WarcInfo::new(&info, general.operator.clone()),
+                http.robots.user_agent.clone(),
+            )?)),
+            None => None,
+        };
+
+        let mut storage = storage.with_config(storage_config)?;
+        if let Some(sink) = &warc_sink {
+            storage = storage.with_sink(sink.clone());
+        }
+        if let Some(sink) = &live_export {
+            storage = storage.with_sink(sink.clone());
+        }
+
+        let skip = Arc::new(skip);
+
+        let rate_limiter = HttpRateLimiter::new(ratelimiter);
+
+        let (mut http_manager, http_mailbox) = ActorManager::new(10_000);
+        let (mut script_runner, script_mailbox) = ActorManager::new(256);
+        let (mut storage_manager, storage_mailbox) = ActorManager::new(256);
+
+        // everything else waits on storage, so it's brought back if it dies rather than stalling the crawl. It answers on
+        // a blocking thread, since writing compresses and hashes bodies
+        let storage_actor = storage.clone();
+        storage_manager.spawn_supervised(
+            move || Blocking(storage_actor.clone()),
+            RestartPolicy::default(),
+            info_span!(target: "evergarden::storage", "Storage"),
+        );
+
+        let blocklist = Blocklist::default();
+        let canonical_aliases = CanonicalAliases::default();
+        let budget = Budget::new(general.budget.clone());
+        let mut http_client = HttpClient::new(
             &http,
             rate_limiter,
+            Arc::clone(&skip),
+            Arc::new(rewrite),
             storage_mailbox.clone(),
             script_mailbox.clone(),
-        )?,
-        info_span!(target: "evergarden::http", "HTTP"),
-    );
+        )?
+        .with_queue(http_mailbox.clone())
+        .with_blocklist(blocklist.clone())
+        .with_canonical_aliases(canonical_aliases.clone())
+        .with_budget(budget.clone());
 
-    let global_state = GlobalState {
-        config: general,
-        client: http_mailbox.clone(),
-    };
+        if args.refresh {
+            http_client = http_client.refresh_since(started_at);
+        }
+        if args.dry_run {
+            http_client = http_client.dry_run(Discovery::new(&extract, &scripts));
+        }
 
-    let script_span = info_span!(target: "evergarden::scripting", "Scripts");
-    script_runner.spawn_actor(ScriptManager::new(scripts, &global_state)?, script_span);
-
-    let mail = http_mailbox.clone();
-    let submitter_task = tokio::task::spawn(async move {
-        let mut futures = seed_urls
-            .into_iter()
-            .map(|v| UrlInfo {
-                url: v.clone(),
-                discovered_in: v,
-                hops: 0,
-            })
-            .map(|u| mail.request(u))
-            .collect::<FuturesUnordered<_>>();
+        http_manager.spawn_actor(http_client, info_span!(target: "evergarden::http", "HTTP"));
+        // urls the http actor never took aren't in the stored queue yet, so a resume won't pick them up either. abandoned
+        // ones were, and stay there
+        http_manager.on_dead_letter(|letter| {
+            warn!(
+                url = letter.value.url.as_str(),
+                "url was dropped before it was fetched ({})", letter.reason
+            )
+        });
+
+        let global_state = GlobalState {
+            config: general,
+            skip,
+            canonical: http.canonical,
+            extract: Arc::new(extract),
+            robots: Arc::new(http.robots.clone()),
+            client: http_mailbox.clone(),
+            storage: storage_mailbox.clone(),
+            blocklist,
+            canonical_aliases,
+        };
+
+        let script_span = info_span!(target: "evergarden::scripting", "Scripts");
+        let script_manager = ScriptManager::new(scripts, &global_state).await?;
+        let script_counters = script_manager.counters();
+        script_runner.spawn_actor(script_manager, script_span);
 
-        while futures.next().await.is_some() {}
-    });
+        let mail = http_mailbox.clone();
+        let submitter_task = tokio::task::spawn(async move {
+            // seeds go ahead of the urls a resumed crawl had queued
+            let mut futures = seed_urls
+                .into_iter()
+                .map(|u| (u, Priority::High))
+                .chain(queued_urls.into_iter().map(|u| (u, Priority::Normal)))
+                .map(|(u, priority)| mail.request_with(u, priority))
+                .collect::<FuturesUnordered<_>>();
 
-    let mut ticker = tokio::time::interval(Duration::from_millis(200));
-    ticker.tick().await;
+            while futures.next().await.is_some() {}
+        });
 
-    let queue_notifier = http_mailbox.subscribe();
+        let mut ticker = tokio::time::interval(Duration::from_millis(200));
+        ticker.tick().await;
+
+        let queue_notifier = http_mailbox.subscribe();
+        let http_queue = http_mailbox.clone();
+
+        let queue_task = tokio::task::spawn(async move {
+            loop {
+                queue_notifier.notified().await;
+                info!(
+                    "HTTP Queue Size {} | Actor System Queue Size {}",
+                    http_mailbox.len(),
+                    actors::metrics::in_flight()
+                );
+                for snapshot in actors::metrics::snapshot() {
+                    debug!("{snapshot}");
+                }
+            }
+        });
 
-    let queue_task = tokio::task::spawn(async move {
         loop {
-            queue_notifier.notified().await;
-            info!(
-                "HTTP Queue Size {} | Actor System Queue Size {}",
-                http_mailbox.len(),
-                actors::TASK_COUNT.load(Ordering::Acquire)
-            );
+            ticker.tick().await;
+
+            if submitter_task.is_finished() && actors::metrics::in_flight() == 0 {
+                break;
+            }
+
+            // the client's stopped fetching, so the queued urls will never be answered
+            if let Some(limit) = budget.exhausted() {
+                warn!("the crawl reached its {limit} budget, stopping");
+                hooks.budget_exhausted(&hook_storage, &budget, limit).await;
+                break;
+            }
         }
-    });
 
-    loop {
-        ticker.tick().await;
+        let scripts_ended = script_runner.close_and_join().await;
+        // the client still queues what it's sent once the budget's spent, so the urls the scripts found are kept for a
+        // resume
+        if budget.exhausted().is_some() {
+            // an actor that stopped taking from its mailbox, like one that panicked, would otherwise be waited on forever
+            let queued = tokio::time::timeout(HTTP_QUEUE_WAIT, async {
+                while !http_queue.is_empty() {
+                    ticker.tick().await;
+                }
+            })
+            .await;
+            if queued.is_err() {
+                warn!(
+                    "{} urls weren't queued for a resume, as the HTTP actor stopped taking them",
+                    http_queue.len()
+                );
+            }
+        }
+
+        let ended = [
+            ("script", scripts_ended),
+            ("HTTP", http_manager.close_and_join().await),
+            // anything still waiting to be stored is, unless it takes too long
+            (
+                "storage",
+                storage_manager.drain_and_join(STORAGE_DRAIN).await,
+            ),
+        ];
+
+        queue_task.abort();
+
+        if let Some(sink) = warc_sink {
+            sink.finish()?;
+            info!("wrote WARC files to {}", sink.dir().display());
+        }
+
+        if let Some(export) = live_export {
+            export.finish()?;
+            info!("wrote WACZ package to {}", export.path().display());
+        }
 
-        if submitter_task.is_finished() && actors::TASK_COUNT.load(Ordering::Acquire) == 0 {
-            break;
+        if args.dry_run {
+            let count = write_dry_run(&storage)?;
+            info!("{count} urls would be archived");
+        } else {
+            write_failure_report(&storage, &report_path)?;
+
+            // a resumed crawl's earlier records were fetched before this run started
+            let report = CrawlReport::collect(
+                &storage,
+                crawl_id,
+                seeds,
+                started_at,
+                (!args.resume).then_some(started_at),
+                &script_counters,
+            )?;
+            report.write(&crawl_report_path)?;
+            report.summarize();
+            info!("wrote crawl report to {}", crawl_report_path.display());
+        }
+
+        // an actor that died partway through took whatever it was working on with it, so the crawl's incomplete even
+        // though what it did store is written out
+        if let Some((actor, _)) = ended.iter().find(|(_, summary)| summary.panicked > 0) {
+            return Err(format!("the {actor} actor panicked during the crawl").into());
+        }
+
+        Ok(())
+    }
+    .await;
+
+    hooks
+        .ended(&hook_storage, crawled.as_ref().err().map(|e| e.to_string()))
+        .await;
+
+    crawled
+}
+
+// seeds are urls, optionally prefixed with the scope to crawl them in, like `prefix=https://example.com/blog/`
+fn parse_seed(seed: &str, default_scope: ScopeKind) -> Option<UrlInfo> {
+    let (scope, url) = match seed.split_once('=') {
+        Some((scope, url)) if scope.chars().all(|c| c.is_ascii_alphabetic()) => {
+            match scope.parse::<ScopeKind>() {
+                Ok(scope) => (scope, url),
+                Err(e) => {
+                    warn!("ignoring seed {seed}: {e}");
+                    return None;
+                }
+            }
+        }
+        _ => (default_scope, seed),
+    };
+
+    match url.parse::<Url>() {
+        Ok(url) => Some(UrlInfo::scoped_seed(url, scope)),
+        Err(e) => {
+            warn!("ignoring seed {seed}: {e}");
+            None
+        }
+    }
+}
+
+/// Reads seeds from a file, or stdin for `-`, skipping blank lines and `#` comments.
+fn read_seeds(path: &Path) -> io::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    let mut seeds = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let seed = line.trim();
+        if !seed.is_empty() && !seed.starts_with('#') {
+            seeds.push(seed.to_owned());
         }
     }
 
-    script_runner.close_and_join().await;
-    http_manager.close_and_join().await;
+    Ok(seeds)
+}
+
+/// Writes the urls a dry run would have archived to stdout, with their status and content type, and returns how many
+/// there were.
+fn write_dry_run(storage: &Storage) -> Result<usize, Box<dyn Error>> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut count = 0;
+    for record in storage.query(Filter::default()) {
+        let (_, _, meta) = record?;
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            meta.url.url,
+            meta.status.as_u16(),
+            meta.mime().unwrap_or_default()
+        )?;
+        count += 1;
+    }
+    out.flush()?;
 
-    queue_task.abort();
+    Ok(count)
+}
+
+/// Writes every url that failed during the crawl as json lines, so they can be inspected or re-seeded.
+fn write_failure_report(storage: &Storage, path: &Path) -> Result<(), Box<dyn Error>> {
+    let failures = storage
+        .list_failures()
+        .collect::<EvergardenResult<Vec<FailedFetch>>>()?;
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    for failure in &failures {
+        serde_json::to_writer(&mut out, failure)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+
+    warn!(
+        "{} urls could not be archived, see {}",
+        failures.len(),
+        path.display()
+    );
 
     Ok(())
 }
@@ -0,0 +1,150 @@
+//! A report on how a crawl went, written next to what it stored, for whatever runs crawls to read, and summed up in the
+//! log for whoever's watching.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+
+use evergarden_client::scripting::script::ScriptCounters;
+use evergarden_common::{CrawlStats, EvergardenResult, FailedFetch, FailureClass, Filter, Storage};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::info;
+use ubyte::ToByteUnit;
+use url::Url;
+use uuid::Uuid;
+
+/// How many of the largest records the report lists.
+const LARGEST: usize = 10;
+
+#[derive(Serialize)]
+pub(crate) struct CrawlReport {
+    crawl_id: Uuid,
+    seeds: Vec<Url>,
+    #[serde(with = "time::serde::rfc3339")]
+    started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    finished_at: OffsetDateTime,
+    /// In seconds.
+    duration: f64,
+    /// What this crawl stored, every host and content type included.
+    stored: CrawlStats,
+    failures: FailureReport,
+    /// How each script did, by name.
+    scripts: BTreeMap<Arc<str>, ScriptReport>,
+}
+
+#[derive(Default, Serialize)]
+struct FailureReport {
+    total: usize,
+    by_class: BTreeMap<FailureClass, usize>,
+    by_host: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct ScriptReport {
+    /// How many responses it was handed.
+    runs: usize,
+    /// How many of those it failed on.
+    failures: usize,
+}
+
+impl CrawlReport {
+    /// Sums up the records fetched since `since`, or all of them, and the failures the crawl recorded.
+    pub(crate) fn collect(
+        storage: &Storage,
+        crawl_id: Uuid,
+        seeds: Vec<Url>,
+        started_at: OffsetDateTime,
+        since: Option<OffsetDateTime>,
+        scripts: &[(Arc<str>, Arc<ScriptCounters>)],
+    ) -> EvergardenResult<CrawlReport> {
+        let finished_at = OffsetDateTime::now_utc();
+        let stored = storage.stats(
+            Filter {
+                since,
+                ..Filter::default()
+            },
+            LARGEST,
+        )?;
+
+        let mut failures = FailureReport::default();
+        for failure in storage.list_failures() {
+            let FailedFetch { url, class, .. } = failure?;
+            failures.total += 1;
+            *failures.by_class.entry(class).or_default() += 1;
+            *failures
+                .by_host
+                .entry(url.url.host_str().unwrap_or_default().to_owned())
+                .or_default() += 1;
+        }
+
+        let scripts = scripts
+            .iter()
+            .map(|(name, counters)| {
+                (
+                    Arc::clone(name),
+                    ScriptReport {
+                        runs: counters.runs.load(Ordering::Relaxed),
+                        failures: counters.failures.load(Ordering::Relaxed),
+                    },
+                )
+            })
+            .collect();
+
+        Ok(CrawlReport {
+            crawl_id,
+            seeds,
+            started_at,
+            finished_at,
+            duration: (finished_at - started_at).as_seconds_f64(),
+            stored,
+            failures,
+            scripts,
+        })
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, self)?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+
+        Ok(())
+    }
+
+    /// Logs the report's gist.
+    pub(crate) fn summarize(&self) {
+        info!(
+            "crawl {} took {}, storing {} records ({}) from {} hosts",
+            self.crawl_id,
+            humantime::format_duration(std::time::Duration::from_secs(self.duration as u64)),
+            self.stored.records,
+            self.stored.bytes.bytes(),
+            self.stored.hosts.len()
+        );
+
+        if self.failures.total > 0 {
+            let classes = self
+                .failures
+                .by_class
+                .iter()
+                .map(|(class, count)| format!("{count} {class}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            info!("{} urls failed: {classes}", self.failures.total);
+        }
+
+        for (name, script) in &self.scripts {
+            info!(
+                "script {name} ran on {} responses, failing on {}",
+                script.runs, script.failures
+            );
+        }
+    }
+}
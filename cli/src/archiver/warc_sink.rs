@@ -0,0 +1,114 @@
+//! Writing responses to WARC files as they're stored, for crawls using the `warc` storage backend.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use evergarden_common::{EvergardenResult, RecordSink, ResponseMetadata};
+use ubyte::ByteUnit;
+
+use crate::export::warc::{RotatingWarcRecorder, WarcDir, WarcFormat, WarcInfo, WarcRecorder};
+
+const INDEX_FILE: &str = "index.cdxj";
+
+/// Appends every stored response to rotating `.warc.gz` files in a directory, and its CDXJ line to `index.cdxj` there.
+pub(crate) struct WarcSink {
+    dir: PathBuf,
+    writers: Mutex<Option<(RotatingWarcRecorder<WarcDir>, BufWriter<File>)>>,
+}
+
+impl WarcSink {
+    /// Opens `dir`, adding to the WARC files and index already in it.
+    pub(crate) fn open(dir: impl AsRef<Path>, info: WarcInfo) -> io::Result<WarcSink> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        // files from earlier crawls into the same output are left as they are, and numbering carries on after them
+        let existing = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .filter(|file| file.file_name().to_string_lossy().ends_with(".warc.gz"))
+            .count();
+
+        let recorder = RotatingWarcRecorder::starting_at(
+            WarcDir::new(&dir),
+            "",
+            ByteUnit::Gigabyte(1).as_u64(),
+            info,
+            WarcFormat::default(),
+            existing,
+        )?;
+        let index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(INDEX_FILE))?;
+
+        Ok(WarcSink {
+            dir,
+            writers: Mutex::new(Some((recorder, BufWriter::new(index)))),
+        })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Finishes the last WARC file, and sorts the index, which is in the order responses were stored until then.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        let Some((recorder, mut index)) = self.writers.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        recorder.finalize()?;
+        index.flush()?;
+        drop(index);
+
+        let path = self.dir.join(INDEX_FILE);
+        let mut lines = BufReader::new(File::open(&path)?)
+            .lines()
+            .collect::<io::Result<Vec<String>>>()?;
+        lines.sort_unstable();
+
+        let mut out = BufWriter::new(File::create(&path)?);
+        for line in lines {
+            writeln!(out, "{line}")?;
+        }
+        out.flush()
+    }
+}
+
+impl RecordSink for WarcSink {
+    fn write_record(
+        &self,
+        key: &str,
+        meta: &ResponseMetadata,
+        body: Option<&mut dyn Read>,
+    ) -> EvergardenResult<()> {
+        let mut writers = self.writers.lock().unwrap();
+        let Some((recorder, index)) = writers.as_mut() else {
+            return Err(io::Error::other("the crawl's WARC files are already finished").into());
+        };
+
+        let cdx = match (&meta.resource, &meta.revisit) {
+            (Some(resource), _) => recorder.write_resource(
+                key,
+                meta,
+                resource,
+                &mut body.unwrap_or(&mut io::empty()),
+            )?,
+            (None, Some(revisit)) => recorder.write_revisit(key, meta, revisit)?,
+            (None, None) => {
+                recorder.write_warc(key, meta, &mut body.unwrap_or(&mut io::empty()))?
+            }
+        };
+
+        // flushed as it goes, so that an interrupted crawl still leaves an index of what it wrote
+        index.write_all(&cdx.to_line())?;
+        index.write_all(b"\n")?;
+        index.flush()?;
+
+        Ok(())
+    }
+}
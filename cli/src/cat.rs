@@ -0,0 +1,86 @@
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use evergarden_common::{encoding::decode_body, Filter, Storage, StorageBackend};
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use tracing_subscriber::filter::LevelFilter;
+use url::Url;
+
+use crate::export::warc::HttpResponseWriter;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct CatArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(help = "The url of the record, or the SURT key it's stored under")]
+    record: String,
+    #[arg(long, help = "Write the status line and headers before the body")]
+    headers: bool,
+    #[arg(
+        long,
+        help = "Undo the body's Content-Encoding, like gzip, instead of writing it as received"
+    )]
+    decode: bool,
+}
+
+// urls are turned into their SURT, and anything else, like the `urn:` keys of resources, is looked up as it is
+fn record_key(storage: &Storage, record: String) -> String {
+    match Url::parse(&record) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => storage.surt(url),
+        _ => record,
+    }
+}
+
+pub(crate) fn cat(args: CatArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    // the record itself goes to stdout
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    let (storage, storage_config) = Storage::open_crawl(&args.input)?;
+    if matches!(storage_config.backend, StorageBackend::Warc) {
+        return Err(
+            "this crawl's responses were written to WARC files, which hold their bodies instead"
+                .into(),
+        );
+    }
+
+    let key = record_key(&storage, args.record);
+    let (_, hash, mut meta) = storage
+        .query(Filter {
+            keys: vec![key.clone()],
+            ..Filter::default()
+        })
+        .next()
+        .ok_or_else(|| format!("nothing is stored under {key}"))??;
+
+    // revisits share the body of the capture they revisit
+    let mut body = Vec::new();
+    storage
+        .read_body_sync(hash, meta.compression)?
+        .ok_or_else(|| format!("the body of {key} is missing"))?
+        .read_to_end(&mut body)?;
+
+    if args.decode {
+        body = decode_body(&meta.headers, &body)?.into_owned();
+        // the headers describe the body as it's written
+        meta.headers.remove(CONTENT_ENCODING);
+        if meta.headers.contains_key(CONTENT_LENGTH) {
+            meta.headers
+                .insert(CONTENT_LENGTH, body.len().to_string().parse().unwrap());
+        }
+    }
+
+    let mut out = io::stdout().lock();
+    if args.headers {
+        out.write_http_head(&meta)?;
+    }
+    out.write_all(&body)?;
+    out.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::builder::TypedValueParser;
+use evergarden_common::{Compression, Storage, StorageBackend};
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use ubyte::ToByteUnit;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct CompactArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(
+        long,
+        help = "Recompress bodies with this algorithm, instead of the crawl's",
+        value_parser = clap::builder::PossibleValuesParser::new(["lz4", "zstd", "none"])
+            .map(|s| match s.as_str() {
+                "zstd" => Compression::Zstd,
+                "none" => Compression::None,
+                _ => Compression::Lz4,
+            }),
+    )]
+    compression: Option<Compression>,
+    #[arg(long, help = "The zstd compression level to recompress with")]
+    level: Option<i32>,
+}
+
+pub(crate) fn compact(args: CompactArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let (storage, mut storage_config) = Storage::open_crawl(&args.input)?;
+    if matches!(storage_config.backend, StorageBackend::Warc) {
+        return Err(
+            "this crawl's responses were written to WARC files, and there are no bodies to compact"
+                .into(),
+        );
+    }
+
+    if let Some(compression) = args.compression {
+        storage_config.compression = compression;
+        storage_config.level = None;
+    }
+    if let Some(level) = args.level {
+        storage_config.level = Some(level);
+    }
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let (storage, stats) = rt.block_on(storage.compact(storage_config))?;
+    // bodies in an object store aren't part of the directory, so the ones left behind are removed separately
+    let removed = storage.gc()?;
+
+    info!(
+        "compacted {} records from {} to {}",
+        stats.entries,
+        stats.before.bytes(),
+        stats.after.bytes()
+    );
+    if removed.bodies > 0 {
+        info!(
+            "removed {} unreferenced bodies from the object store, freeing {}",
+            removed.bodies,
+            removed.bytes.bytes()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,405 @@
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::Subcommand;
+use evergarden_client::config::FullConfig;
+use toml::{Table, Value};
+use tracing::{error, info};
+use tracing_subscriber::filter::LevelFilter;
+
+const STARTER_CONFIG: &str = include_str!("../../configs/starter.toml");
+
+/// What environment variables overriding config values start with.
+const ENV_PREFIX: &str = "EVERGARDEN_";
+
+/// Config values to override, for crawls run from CI and the like, where templating config files is a chore.
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct OverrideArgs {
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        value_parser = parse_override,
+        help = "Override a config value, like `http.timeout=30s` or `general.max_hops=2`. Values are read as TOML, or as strings if they aren't valid TOML. Can be given more than once, and wins over EVERGARDEN_* environment variables."
+    )]
+    sets: Vec<(String, Value)>,
+}
+
+impl OverrideArgs {
+    /// Every override, those from `EVERGARDEN_*` environment variables first, so that `--set` wins over them.
+    ///
+    /// Variables name a key with `__` between its levels, like `EVERGARDEN_HTTP__TIMEOUT` for `http.timeout`. Every key
+    /// is at least two levels deep, so variables without a `__`, like the `EVERGARDEN_EVENT` hooks get, aren't
+    /// overrides.
+    fn overrides(&self) -> Vec<(String, Value)> {
+        let mut overrides = env::vars()
+            .filter_map(|(name, value)| {
+                let key = name.strip_prefix(ENV_PREFIX)?;
+                key.contains("__").then(|| {
+                    (
+                        key.split("__")
+                            .map(str::to_lowercase)
+                            .collect::<Vec<_>>()
+                            .join("."),
+                        parse_value(&value),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        overrides.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        overrides.extend(self.sets.iter().cloned());
+        overrides
+    }
+
+    /// Parses a TOML config, with the overrides applied over it.
+    pub(crate) fn parse(&self, config: &str) -> Result<FullConfig, Box<dyn Error>> {
+        let overrides = self.overrides();
+        // parsed straight into a config when there's nothing to override, so that errors point at the file's lines
+        if overrides.is_empty() {
+            return Ok(toml::from_str(config)?);
+        }
+
+        apply(toml::from_str(config)?, overrides)
+    }
+
+    /// Applies the overrides over a config that's already parsed, like a resumed crawl's.
+    pub(crate) fn apply(&self, config: FullConfig) -> Result<FullConfig, Box<dyn Error>> {
+        let overrides = self.overrides();
+        if overrides.is_empty() {
+            return Ok(config);
+        }
+
+        match Value::try_from(config)? {
+            Value::Table(table) => apply(table, overrides),
+            _ => unreachable!("configs are tables"),
+        }
+    }
+}
+
+fn apply(mut table: Table, overrides: Vec<(String, Value)>) -> Result<FullConfig, Box<dyn Error>> {
+    for (key, value) in &overrides {
+        set(&mut table, key, value.clone())?;
+    }
+
+    let config: FullConfig = Value::Table(table).try_into()?;
+    // configs ignore keys they don't know, so a misspelled override would otherwise be dropped without a word
+    let Value::Table(known) = Value::try_from(&config)? else {
+        unreachable!("configs are tables")
+    };
+    if let Some((key, _)) = overrides.iter().find(|(key, _)| get(&known, key).is_none()) {
+        return Err(format!("can't set {key}: there's no such setting").into());
+    }
+
+    Ok(config)
+}
+
+fn get<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let mut names = key.split('.');
+    let first = table.get(names.next()?)?;
+    names.try_fold(first, |value, name| value.as_table()?.get(name))
+}
+
+/// Sets a dotted key, like `http.robots.user_agent`, creating the tables on the way to it if they're missing.
+fn set(table: &mut Table, key: &str, value: Value) -> Result<(), String> {
+    let (path, last) = match key.rsplit_once('.') {
+        Some((path, last)) => (path.split('.').collect::<Vec<_>>(), last),
+        None => (Vec::new(), key),
+    };
+
+    let mut table = table;
+    for (depth, name) in path.iter().enumerate() {
+        table = match table
+            .entry(*name)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(inner) => inner,
+            _ => {
+                return Err(format!(
+                    "can't set {key}: {} isn't a table",
+                    path[..=depth].join(".")
+                ))
+            }
+        };
+    }
+
+    table.insert(last.to_owned(), value);
+    Ok(())
+}
+
+fn parse_override(arg: &str) -> Result<(String, Value), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), parse_value(value.trim())))
+        }
+        _ => Err(format!("expected KEY=VALUE, got {arg}")),
+    }
+}
+
+// `30` and `true` are a number and a boolean, but `30s` isn't valid TOML, and is taken as the string it'd have to be.
+// So is anything that goes on past the one value, like `1\nother = 2`
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {value}"))
+        .ok()
+        .filter(|table| table.len() == 1)
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Checks a crawl config, and prints it with every default filled in.
+    Check {
+        #[arg(help = "The config file to check")]
+        file: PathBuf,
+        #[command(flatten)]
+        overrides: OverrideArgs,
+    },
+    /// Writes a commented starter config.
+    Init {
+        #[arg(help = "Where to write the config, instead of stdout")]
+        file: Option<PathBuf>,
+        #[arg(long, help = "Overwrite the file if it exists")]
+        force: bool,
+    },
+}
+
+pub(crate) fn config(args: ConfigArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    // configs are written to stdout
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    match args.command {
+        ConfigCommand::Check { file, overrides } => check(file, overrides),
+        ConfigCommand::Init { file, force } => init(file, force),
+    }
+}
+
+fn check(file: PathBuf, overrides: OverrideArgs) -> Result<(), Box<dyn Error>> {
+    let config = match overrides.parse(&fs::read_to_string(&file)?) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{e}");
+            return Err(format!("{} isn't a valid config", file.display()).into());
+        }
+    };
+
+    let problems = config.problems();
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{problem}");
+        }
+        return Err(format!("found {} problems in {}", problems.len(), file.display()).into());
+    }
+
+    io::stdout().write_all(toml::to_string_pretty(&config)?.as_bytes())?;
+    info!("{} is valid", file.display());
+
+    Ok(())
+}
+
+fn init(file: Option<PathBuf>, force: bool) -> Result<(), Box<dyn Error>> {
+    let Some(file) = file else {
+        io::stdout().write_all(STARTER_CONFIG.as_bytes())?;
+        return Ok(());
+    };
+
+    let mut out = if force {
+        File::create(&file)?
+    } else {
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(&file)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => {
+                    format!(
+                        "{} exists already, use --force to overwrite it",
+                        file.display()
+                    )
+                }
+                _ => e.to_string(),
+            })?
+    };
+    out.write_all(STARTER_CONFIG.as_bytes())?;
+    info!("wrote a starter config to {}", file.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn sets_nested_keys() {
+        let mut config = table("[http]\ntimeout = \"30s\"\n[http.robots]\nenabled = true");
+
+        set(&mut config, "http.robots.enabled", Value::Boolean(false)).unwrap();
+        set(&mut config, "http.dns.hosts_file", parse_value("hosts")).unwrap();
+        set(&mut config, "operator", parse_value("someone")).unwrap();
+
+        assert_eq!(
+            config,
+            table(
+                "operator = \"someone\"\n[http]\ntimeout = \"30s\"\n[http.robots]\nenabled = false\n[http.dns]\nhosts_file = \"hosts\""
+            )
+        );
+    }
+
+    #[test]
+    fn sets_arrays_whole() {
+        let mut config = table("[skip]\nextensions = [\"iso\"]");
+
+        set(
+            &mut config,
+            "skip.extensions",
+            parse_value(r#"["exe", "zip"]"#),
+        )
+        .unwrap();
+        set(
+            &mut config,
+            "http.headers",
+            parse_value(r#"[{ name = "User-Agent", value = "evergarden" }]"#),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            table(
+                "[skip]\nextensions = [\"exe\", \"zip\"]\n[http]\nheaders = [{ name = \"User-Agent\", value = \"evergarden\" }]"
+            )
+        );
+
+        // arrays can't be reached into, only replaced
+        assert_eq!(
+            set(&mut config, "skip.extensions.0", parse_value("iso")),
+            Err(String::from(
+                "can't set skip.extensions.0: skip.extensions isn't a table"
+            ))
+        );
+        assert_eq!(
+            set(&mut config, "http.headers.name.x", parse_value("x")),
+            Err(String::from(
+                "can't set http.headers.name.x: http.headers isn't a table"
+            ))
+        );
+    }
+
+    #[test]
+    fn coerces_values() {
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(parse_value("false"), Value::Boolean(false));
+        assert_eq!(parse_value("30"), Value::Integer(30));
+        assert_eq!(parse_value("-2"), Value::Integer(-2));
+        assert_eq!(parse_value("0.5"), Value::Float(0.5));
+        assert_eq!(parse_value("\"30\""), Value::String(String::from("30")));
+        assert_eq!(parse_value("'true'"), Value::String(String::from("true")));
+        // what isn't valid TOML is taken as a string
+        assert_eq!(parse_value("30s"), Value::String(String::from("30s")));
+        assert_eq!(parse_value("True"), Value::String(String::from("True")));
+        assert_eq!(
+            parse_value("https://example.com"),
+            Value::String(String::from("https://example.com"))
+        );
+        assert_eq!(parse_value(""), Value::String(String::new()));
+        // only one value is read, so what follows it doesn't get cut off
+        assert_eq!(
+            parse_value("1\nother = 2"),
+            Value::String(String::from("1\nother = 2"))
+        );
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!(
+            parse_override(" general.max_hops = 2 "),
+            Ok((String::from("general.max_hops"), Value::Integer(2)))
+        );
+        assert_eq!(
+            parse_override("http.headers=a=b"),
+            Ok((
+                String::from("http.headers"),
+                Value::String(String::from("a=b"))
+            ))
+        );
+        assert!(parse_override("general.max_hops").is_err());
+        assert!(parse_override(" =2").is_err());
+    }
+
+    #[test]
+    fn applies_overrides_over_a_config() {
+        let config = apply(
+            table(STARTER_CONFIG),
+            vec![
+                (String::from("general.max_hops"), parse_value("5")),
+                (String::from("http.timeout"), parse_value("10s")),
+                (String::from("http.robots.enabled"), parse_value("false")),
+                (String::from("skip.extensions"), parse_value(r#"["iso"]"#)),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.general.max_hops, 5);
+        assert_eq!(config.http.timeout, time::Duration::seconds(10));
+        assert!(!config.http.robots.enabled);
+        assert_eq!(config.skip.extensions, ["iso"]);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let unknown = |key: &str| {
+            apply(
+                table(STARTER_CONFIG),
+                vec![(String::from(key), parse_value("1"))],
+            )
+            .err()
+            .map(|e| e.to_string())
+        };
+
+        assert_eq!(
+            unknown("http.timout"),
+            Some(String::from(
+                "can't set http.timout: there's no such setting"
+            ))
+        );
+        assert_eq!(
+            unknown("http.robots.nonsense"),
+            Some(String::from(
+                "can't set http.robots.nonsense: there's no such setting"
+            ))
+        );
+        assert_eq!(
+            unknown("nonsense"),
+            Some(String::from("can't set nonsense: there's no such setting"))
+        );
+        // settings left out of the config are known all the same
+        assert_eq!(unknown("http.max_body_length"), None);
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let result = apply(
+            table(STARTER_CONFIG),
+            vec![(String::from("general.max_hops"), parse_value("lots"))],
+        );
+
+        assert!(result.is_err());
+    }
+}
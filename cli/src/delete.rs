@@ -0,0 +1,95 @@
+use std::{
+    error::Error,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use evergarden_common::{KeyPattern, Storage};
+use regex::Regex;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use ubyte::ToByteUnit;
+use url::Url;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct DeleteArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(
+        long,
+        required_unless_present = "prefix",
+        conflicts_with = "prefix",
+        help = "Delete records whose SURT key matches this regex"
+    )]
+    regex: Option<Regex>,
+    #[arg(
+        long,
+        help = "Delete records under this SURT prefix, like `com,example)/private/`, or under this url"
+    )]
+    prefix: Option<String>,
+    #[arg(long, help = "Only list the records that would be deleted")]
+    dry_run: bool,
+    #[arg(short, long, help = "Delete without asking for confirmation")]
+    yes: bool,
+}
+
+// urls are turned into their SURT, so that `https://example.com/private/` works as well as the key it's stored under
+fn surt_prefix(storage: &Storage, prefix: String) -> String {
+    match Url::parse(&prefix) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => storage.surt(url),
+        _ => prefix,
+    }
+}
+
+fn confirm(count: usize) -> io::Result<bool> {
+    eprint!("delete {count} records? [y/N] ");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+pub(crate) fn delete(args: DeleteArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    // the records to delete are listed on stdout
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    let (storage, _) = Storage::open_crawl(&args.input)?;
+
+    let pattern = match (args.regex, args.prefix) {
+        (Some(regex), _) => KeyPattern::Regex(regex),
+        (None, Some(prefix)) => KeyPattern::SurtPrefix(surt_prefix(&storage, prefix)),
+        (None, None) => unreachable!("clap requires a pattern"),
+    };
+
+    let matched = storage.del_by_pattern(&pattern, true)?;
+    let mut stdout = io::stdout().lock();
+    for entry in &matched {
+        writeln!(stdout, "{}\t{}", entry.key, entry.url)?;
+    }
+
+    if matched.is_empty() || args.dry_run {
+        info!("{} records match", matched.len());
+        return Ok(());
+    }
+
+    if !args.yes && !confirm(matched.len())? {
+        info!("nothing deleted");
+        return Ok(());
+    }
+
+    let deleted = storage.del_by_pattern(&pattern, false)?;
+    let stats = storage.gc()?;
+
+    info!(
+        "deleted {} records and {} unreferenced bodies, freeing {}",
+        deleted.len(),
+        stats.bodies,
+        stats.bytes.bytes()
+    );
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use std::{
     fmt::Debug,
-    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    io::{self, BufWriter, Read, Seek, Write},
     path::Path,
 };
 
@@ -8,31 +8,49 @@ use flate2::{write::GzEncoder, Compression};
 use neo_mime::MediaType;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
-use time::{format_description::FormatItem, macros::format_description, OffsetDateTime};
+use time::{
+    format_description::FormatItem, macros::format_description, OffsetDateTime, PrimitiveDateTime,
+};
 
-use super::{file_digest, DataPackageEntry};
+use super::{DataPackageEntry, HashingWriter};
 
 // static FORMATTING =!_descr
-static TIME_FMT: &[FormatItem<'_>] =
+pub(crate) static TIME_FMT: &[FormatItem<'_>] =
     format_description!("[year][month][day][hour repr:24][minute][second]");
 
+/// How many lines go in each gzip block of the index. Readers binary search the `.idx` summary, with a line per block,
+/// then decompress only the block a key falls in.
 const CDX_SPLIT_THRESHOLD: usize = 1000;
 
 pub struct CDXWriter<W: Write + Read + Seek> {
     file_name: String,
-    out: BufWriter<W>,
-    aux: BufWriter<W>,
+    out: HashingWriter<BufWriter<W>>,
+    aux: HashingWriter<BufWriter<W>>,
     buffer: Vec<CDXRecord>,
 }
 
 impl<W: Write + Read + Seek> CDXWriter<W> {
-    pub fn new(out: W, aux: W) -> Self {
-        CDXWriter {
+    pub fn new(out: W, aux: W) -> io::Result<Self> {
+        let mut writer = CDXWriter {
             file_name: String::from("index.cdx.gz"),
-            out: BufWriter::new(out),
-            aux: BufWriter::new(aux),
+            out: HashingWriter::new(BufWriter::new(out)),
+            aux: HashingWriter::new(BufWriter::new(aux)),
             buffer: Vec::with_capacity(CDX_SPLIT_THRESHOLD),
-        }
+        };
+
+        // tells readers what the blocks the summary points at hold
+        writeln!(
+            writer.aux,
+            "!meta 0 {}",
+            serde_json::json!({ "format": "cdxj-gzip-1.0", "filename": writer.file_name })
+        )?;
+
+        Ok(writer)
+    }
+
+    /// A `.loc` file for the index, as pywb wants next to a zipnum index to find its blocks' file.
+    pub fn loc(&self) -> String {
+        format!("{0}\t{0}\n", self.file_name)
     }
 }
 
@@ -43,10 +61,9 @@ impl<W: Write + Read + Seek + Debug> CDXWriter<W> {
     ) -> std::io::Result<()> {
         self.buffer.extend(batch);
 
-        if self.buffer.len() >= CDX_SPLIT_THRESHOLD {
-            while !self.buffer.is_empty() {
-                self.flush_lines()?;
-            }
+        // only full blocks are written until the end, so that every block but the last has the same number of lines
+        while self.buffer.len() >= CDX_SPLIT_THRESHOLD {
+            self.flush_lines()?;
         }
 
         Ok(())
@@ -66,7 +83,7 @@ impl<W: Write + Read + Seek + Debug> CDXWriter<W> {
             key: cdxj_lines.peek().unwrap().key.clone(),
             time: cdxj_lines.peek().unwrap().time,
             block: ZipNumBlock {
-                offset: self.out.stream_position()?,
+                offset: self.out.position(),
                 length: 0,
                 digest: [0u8; 32],
                 filename: self.file_name.clone(),
@@ -104,20 +121,12 @@ impl<W: Write + Read + Seek + Debug> CDXWriter<W> {
             self.flush_lines()?;
         }
 
-        self.aux.flush()?;
-        self.out.flush()?;
-
-        let mut out_file = self.out.into_inner().unwrap();
-
-        let out_digest = file_digest(&mut out_file)?;
-        let out_len = out_file.seek(SeekFrom::End(0))?;
-
+        let (out_file, out_digest, out_len) = self.out.into_parts();
+        let mut out_file = out_file.into_inner().map_err(|e| e.into_error())?;
         out_file.rewind()?;
 
-        let mut aux_file = self.aux.into_inner().unwrap();
-        let aux_digest = file_digest(&mut aux_file)?;
-        let aux_len = aux_file.seek(SeekFrom::End(0))?;
-
+        let (aux_file, aux_digest, aux_len) = self.aux.into_parts();
+        let mut aux_file = aux_file.into_inner().map_err(|e| e.into_error())?;
         aux_file.rewind()?;
 
         Ok((
@@ -174,10 +183,13 @@ impl<S: Serialize> CDXStyleRecord<S> {
     }
 }
 
-#[derive(serde::Serialize, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct CDXJBlock {
     pub url: String,
-    #[serde(serialize_with = "super::ser_sha256_as_str")]
+    #[serde(
+        serialize_with = "super::ser_sha256_as_str",
+        deserialize_with = "super::de_sha256_from_str"
+    )]
     pub digest: [u8; 32],
     pub mime: Option<MediaType>,
     pub filename: String,
@@ -186,6 +198,20 @@ pub struct CDXJBlock {
     pub status: u16,
 }
 
+impl CDXRecord {
+    /// Parses a line as [`CDXStyleRecord::to_line`] writes it.
+    pub fn from_line(line: &str) -> Option<CDXRecord> {
+        let mut fields = line.splitn(3, ' ');
+        let key = fields.next()?.to_owned();
+        let time = PrimitiveDateTime::parse(fields.next()?, TIME_FMT)
+            .ok()?
+            .assume_utc();
+        let block = serde_json::from_str(fields.next()?).ok()?;
+
+        Some(CDXRecord { key, time, block })
+    }
+}
+
 #[derive(serde::Serialize, Clone)]
 pub struct ZipNumBlock {
     pub offset: u64,
@@ -194,3 +220,102 @@ pub struct ZipNumBlock {
     pub digest: [u8; 32],
     pub filename: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use sha2::{Digest, Sha256};
+    use tempfile::tempfile;
+    use time::{macros::datetime, Duration};
+
+    use super::{CDXJBlock, CDXRecord, CDXWriter, CDX_SPLIT_THRESHOLD, TIME_FMT};
+    use crate::export::sha256_as_string;
+
+    fn record(i: usize) -> CDXRecord {
+        CDXRecord {
+            key: format!("com,example)/{i:05}"),
+            time: datetime!(2023-07-01 12:00 UTC) + Duration::seconds(i as i64),
+            block: CDXJBlock {
+                url: format!("https://example.com/{i:05}"),
+                digest: [i as u8; 32],
+                mime: None,
+                filename: String::from("00000.warc.gz"),
+                offset: i as u64 * 100,
+                length: 100,
+                status: 200,
+            },
+        }
+    }
+
+    #[test]
+    fn splits_the_index_into_blocks_the_summary_points_at() {
+        let count = CDX_SPLIT_THRESHOLD * 2 + CDX_SPLIT_THRESHOLD / 2;
+        let mut writer = CDXWriter::new(tempfile().unwrap(), tempfile().unwrap()).unwrap();
+        // batches don't line up with blocks
+        for batch in (0..count).collect::<Vec<_>>().chunks(300) {
+            writer
+                .write_batch(batch.iter().map(|&i| record(i)))
+                .unwrap();
+        }
+
+        let ((mut out, out_entry), (mut aux, aux_entry)) = writer.finalize("indexes").unwrap();
+        let (mut blocks, mut summary) = (Vec::new(), String::new());
+        out.read_to_end(&mut blocks).unwrap();
+        aux.read_to_string(&mut summary).unwrap();
+
+        assert_eq!(out_entry.path, "indexes/index.cdx.gz");
+        assert_eq!(out_entry.bytes, blocks.len() as u64);
+        assert_eq!(out_entry.hash, <[u8; 32]>::from(Sha256::digest(&blocks)));
+        assert_eq!(aux_entry.path, "indexes/index.idx");
+
+        let mut lines = summary.lines();
+        assert_eq!(
+            lines.next(),
+            Some(r#"!meta 0 {"filename":"index.cdx.gz","format":"cdxj-gzip-1.0"}"#)
+        );
+
+        let mut seen = 0;
+        let mut next_offset = 0;
+        for (line, expected) in lines.zip([
+            CDX_SPLIT_THRESHOLD,
+            CDX_SPLIT_THRESHOLD,
+            CDX_SPLIT_THRESHOLD / 2,
+        ]) {
+            let (key, rest) = line.split_once(' ').unwrap();
+            let (time, block) = rest.split_once(' ').unwrap();
+            let block: serde_json::Value = serde_json::from_str(block).unwrap();
+            let (offset, length) = (
+                block["offset"].as_u64().unwrap() as usize,
+                block["length"].as_u64().unwrap() as usize,
+            );
+            assert_eq!(offset, next_offset);
+            next_offset = offset + length;
+
+            let gzipped = &blocks[offset..offset + length];
+            assert_eq!(
+                block["digest"],
+                sha256_as_string(&Sha256::digest(gzipped).into())
+            );
+            assert_eq!(block["filename"], "index.cdx.gz");
+
+            let mut text = String::new();
+            GzDecoder::new(gzipped).read_to_string(&mut text).unwrap();
+            let records = text
+                .lines()
+                .map(|line| CDXRecord::from_line(line).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(records.len(), expected);
+
+            // each summary line names the first line of its block
+            assert_eq!(key, records[0].key);
+            assert_eq!(key, record(seen).key);
+            assert_eq!(time, record(seen).time.format(TIME_FMT).unwrap());
+            seen += records.len();
+        }
+
+        assert_eq!(seen, count);
+        assert_eq!(next_offset, blocks.len());
+    }
+}
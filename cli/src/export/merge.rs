@@ -0,0 +1,194 @@
+//! Merging the exports of several crawls, like parallel crawls of one site, into one WACZ package.
+//!
+//! Deduplication only goes as far as the index: every input's WARC files are copied whole, so a capture that's in
+//! several of them is still in each one's records, but only the first is indexed and replayed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::PathBuf,
+};
+
+use tempfile::{tempfile, NamedTempFile};
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+
+use super::{
+    cdxj::{CDXRecord, CDXWriter},
+    metadata::MetadataArgs,
+    package::PackageReader,
+    pages::PagesWriter,
+    run::{create_package, finish_package, write_export, ExportArgs},
+};
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct MergeArgs {
+    #[arg(short, long, help = "output .wacz file")]
+    output: PathBuf,
+    #[arg(
+        long,
+        help = "Also write indexes/index.loc, for serving the index with pywb as a zipnum collection"
+    )]
+    cdx_loc: bool,
+    #[arg(
+        required = true,
+        help = "Archive folders (as written by `evergarden archive`) or WACZ files to merge. Where several have the same capture, only the first one's is indexed, though every input's WARC files are copied whole."
+    )]
+    inputs: Vec<PathBuf>,
+    #[command(flatten)]
+    metadata: MetadataArgs,
+}
+
+pub(crate) fn merge(args: MergeArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let metadata = args.metadata.load()?;
+
+    let output = args.output.canonicalize().ok();
+    if args
+        .inputs
+        .iter()
+        .any(|input| output.is_some() && input.canonicalize().ok() == output)
+    {
+        return Err("the package being written can't also be merged into it".into());
+    }
+
+    // archive folders are exported first, to be merged like any other package
+    let mut exports = Vec::new();
+    let mut packages = Vec::with_capacity(args.inputs.len());
+    for input in &args.inputs {
+        if input.is_dir() {
+            info!("exporting {}", input.display());
+
+            let export = NamedTempFile::new()?;
+            write_export(ExportArgs::new(input.clone(), export.path().to_path_buf()))?;
+            packages.push(PackageReader::open(export.path())?);
+            exports.push(export);
+        } else {
+            packages.push(PackageReader::open(input)?);
+        }
+    }
+
+    let mut package = create_package(&args.output)?;
+    let mut all_entries = Vec::new();
+    let mut index = Vec::new();
+
+    for (input, reader) in args.inputs.iter().zip(&mut packages) {
+        info!("copying WARC files from {}", input.display());
+
+        // WARC files are numbered on from the ones before them, so that their names don't collide
+        let mut counter = all_entries.len();
+        let renamed = reader.copy_warcs_renamed(&mut package, |name| {
+            let extension = name.split_once('.').map_or("warc.gz", |(_, ext)| ext);
+            counter += 1;
+            format!("{:05}.{extension}", counter - 1)
+        })?;
+
+        let mut names = HashMap::with_capacity(renamed.len());
+        for (name, entry) in renamed {
+            names.insert(name, entry.name.clone());
+            all_entries.push(entry);
+        }
+
+        for mut record in reader.take_index() {
+            match names.get(&record.block.filename) {
+                Some(name) => record.block.filename = name.clone(),
+                None => {
+                    warn!(
+                        key = record.key,
+                        file = record.block.filename,
+                        "skipping an index line for a WARC file the package doesn't have"
+                    );
+                    continue;
+                }
+            }
+            index.push(record);
+        }
+    }
+
+    let count = index.len();
+    dedup(&mut index);
+
+    info!(
+        "merged {} index records, leaving {} duplicates out of the index",
+        index.len(),
+        count - index.len()
+    );
+
+    let mut cdx_writer = CDXWriter::new(tempfile()?, tempfile()?)?;
+    cdx_writer.write_batch(index)?;
+
+    // pages are told apart by their url and timestamp
+    let mut pages_writer = PagesWriter::new(tempfile()?, tempfile()?)?;
+    let mut seen = HashSet::new();
+    for (path, is_main) in [
+        ("pages/pages.jsonl", true),
+        ("pages/extraPages.jsonl", false),
+    ] {
+        for reader in &mut packages {
+            for entry in reader.pages(path)? {
+                let page = serde_json::from_str::<serde_json::Value>(&entry)?;
+                if seen.insert((page["url"].to_string(), page["ts"].to_string())) {
+                    pages_writer.add_serialized(&entry, is_main)?;
+                }
+            }
+        }
+    }
+
+    finish_package(
+        package,
+        cdx_writer,
+        pages_writer,
+        all_entries,
+        args.cdx_loc,
+        &metadata,
+    )?;
+
+    info!("wrote merged package to {}", args.output.display());
+
+    Ok(())
+}
+
+/// Sorts the index, keeping the same capture only once, from the first input it's in.
+fn dedup(index: &mut Vec<CDXRecord>) {
+    // duplicates are only next to each other once they're sorted by everything that tells them apart, and the sort is
+    // stable, so the first input's is the one that comes first among them
+    index.sort_by(|a, b| (&a.key, a.time, a.block.digest).cmp(&(&b.key, b.time, b.block.digest)));
+    index.dedup_by(|a, b| a.key == b.key && a.time == b.time && a.block.digest == b.block.digest);
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+    use crate::export::cdxj::CDXJBlock;
+
+    fn record(digest: u8, filename: &str) -> CDXRecord {
+        CDXRecord {
+            key: String::from("com,example)/"),
+            time: datetime!(2023-07-01 12:00 UTC),
+            block: CDXJBlock {
+                url: String::from("https://example.com/"),
+                digest: [digest; 32],
+                mime: None,
+                filename: filename.to_owned(),
+                offset: 0,
+                length: 100,
+                status: 200,
+            },
+        }
+    }
+
+    #[test]
+    fn keeps_each_capture_once_from_the_first_input() {
+        let mut index = vec![record(1, "a"), record(2, "b"), record(1, "c")];
+        dedup(&mut index);
+
+        let kept = index
+            .iter()
+            .map(|r| (r.block.digest[0], r.block.filename.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [(1, "a"), (2, "b")]);
+    }
+}
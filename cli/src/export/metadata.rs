@@ -0,0 +1,83 @@
+//! Descriptive metadata for packages, which replayweb.page shows along with them.
+
+use std::{collections::BTreeMap, error::Error, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// fields of datapackage.json that evergarden fills in itself
+const RESERVED: &[&str] = &[
+    "profile",
+    "wacz_version",
+    "software",
+    "created",
+    "resources",
+];
+
+/// A package's title, description, and anything else to catalogue it with, as it goes in `datapackage.json`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PackageMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Arguments describing the packages a command writes.
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct MetadataArgs {
+    #[arg(long, help = "Title of the package")]
+    title: Option<String>,
+    #[arg(long, help = "Description of the package")]
+    description: Option<String>,
+    #[arg(
+        long = "metadata",
+        value_name = "KEY=VALUE",
+        help = "Other metadata to add to datapackage.json. Can be given more than once.",
+        value_parser = parse_key_value
+    )]
+    metadata: Vec<(String, String)>,
+    #[arg(
+        long,
+        help = "JSON file of metadata for datapackage.json, like {\"title\": \"...\", \"curator\": \"...\"}. Flags override what it says."
+    )]
+    metadata_file: Option<PathBuf>,
+}
+
+impl MetadataArgs {
+    pub(crate) fn load(self) -> Result<PackageMetadata, Box<dyn Error>> {
+        let mut metadata = match &self.metadata_file {
+            Some(path) => serde_json::from_slice::<PackageMetadata>(&std::fs::read(path)?)
+                .map_err(|e| format!("couldn't read {}: {e}", path.display()))?,
+            None => PackageMetadata::default(),
+        };
+
+        metadata.title = self.title.or(metadata.title);
+        metadata.description = self.description.or(metadata.description);
+        metadata.extra.extend(
+            self.metadata
+                .into_iter()
+                .map(|(key, value)| (key, serde_json::Value::String(value))),
+        );
+
+        if let Some(key) = metadata
+            .extra
+            .keys()
+            .find(|key| RESERVED.contains(&key.as_str()))
+        {
+            return Err(
+                format!("{key} is written by evergarden, and can't be set as metadata").into(),
+            );
+        }
+
+        Ok(metadata)
+    }
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(String::from("expected KEY=VALUE")),
+    }
+}
@@ -1,11 +1,17 @@
 pub(crate) mod cdxj;
+pub(crate) mod merge;
+pub(crate) mod metadata;
+pub(crate) mod package;
 pub(crate) mod pages;
 pub(crate) mod run;
+pub(crate) mod validate;
 pub(crate) mod warc;
+pub(crate) mod workers;
 
-use std::io::{self, BufReader, Read, Seek, Write};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 
-use serde::{Serialize, Serializer};
+use metadata::PackageMetadata;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
 
 #[derive(Serialize)]
@@ -14,14 +20,19 @@ pub struct DataPackage {
     pub wacz_version: &'static str,
     pub software: &'static str,
     pub created: String,
+    #[serde(flatten)]
+    pub metadata: PackageMetadata,
     pub resources: Vec<DataPackageEntry>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DataPackageEntry {
     pub name: String,
     pub path: String,
-    #[serde(serialize_with = "ser_sha256_as_str")]
+    #[serde(
+        serialize_with = "ser_sha256_as_str",
+        deserialize_with = "de_sha256_from_str"
+    )]
     pub hash: [u8; 32],
     pub bytes: u64,
 }
@@ -41,21 +52,138 @@ where
     ser.serialize_str(&sha256_as_string(hash))
 }
 
-pub fn file_digest<R: Read + Seek>(file: &mut R) -> io::Result<[u8; 32]> {
-    file.rewind().unwrap();
-    let mut out = vec![];
-    file.read_to_end(&mut out).unwrap();
-    file.rewind().unwrap();
+pub fn de_sha256_from_str<'de, D>(de: D) -> Result<[u8; 32], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(de)?;
+
+    sha256_from_str(&value)
+        .ok_or_else(|| serde::de::Error::custom(format!("not a sha256 digest: {value}")))
+}
+
+/// Reads a digest written by [`sha256_as_string`], as `sha256:<hex>`.
+pub fn sha256_from_str(value: &str) -> Option<[u8; 32]> {
+    let mut hash = [0u8; 32];
+
+    value
+        .strip_prefix("sha256:")
+        .filter(|hex| hex.len() == 64)
+        .and_then(|hex| faster_hex::hex_decode(hex.as_bytes(), &mut hash).ok())?;
+
+    Some(hash)
+}
 
-    let mut reader = BufReader::new(file);
+/// The digest of a file that's already been written, like a finished package. The file is left rewound.
+pub fn file_digest<R: Read + Seek>(file: &mut R) -> io::Result<[u8; 32]> {
+    file.rewind()?;
 
     let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(&mut *file), &mut hasher)?;
+
+    file.rewind()?;
+
+    Ok(hasher.finalize().into())
+}
+
+/// Keeps the digest and length of what's written through it, so that files don't have to be read back to describe
+/// them in the package.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> HashingWriter<W> {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+            len: 0,
+        }
+    }
 
-    std::io::copy(&mut reader, &mut hasher).unwrap();
+    /// The writer underneath, for what shouldn't be counted, like a package's own headers around the files in it.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
 
-    hasher.flush()?;
+    /// How many bytes have been written since the last [`HashingWriter::take_digest`], or since it was made.
+    pub fn position(&self) -> u64 {
+        self.len
+    }
+
+    /// The digest and length of what's been written, starting over for what's written next.
+    pub fn take_digest(&mut self) -> ([u8; 32], u64) {
+        (
+            std::mem::take(&mut self.hasher).finalize().into(),
+            std::mem::take(&mut self.len),
+        )
+    }
+
+    /// The writer, along with the digest and length of what was written to it.
+    pub fn into_parts(mut self) -> (W, [u8; 32], u64) {
+        let (digest, len) = self.take_digest();
+        (self.inner, digest, len)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A file that's only measured: what's written to it is counted and thrown away, so that a package can be laid out
+/// without writing it.
+#[derive(Default)]
+pub struct MeasuredFile {
+    position: u64,
+    size: u64,
+}
+
+impl MeasuredFile {
+    /// How big the file would be.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Write for MeasuredFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.size = self.size.max(self.position);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-    let digest = hasher.finalize();
+impl Seek for MeasuredFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
 
-    Ok(digest.into())
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't seek before the start of a file",
+            )
+        })?;
+        Ok(self.position)
+    }
 }
@@ -0,0 +1,124 @@
+//! Reading back WACZ packages written by an export, to update them or merge them with others.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufRead, BufReader, Seek, Write},
+    path::Path,
+};
+
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+use time::OffsetDateTime;
+use zip::{ZipArchive, ZipWriter};
+
+use super::{cdxj::CDXRecord, DataPackageEntry};
+
+#[derive(Deserialize)]
+struct DataPackageResources {
+    resources: Vec<DataPackageEntry>,
+}
+
+/// A WACZ package written by an earlier export.
+pub(crate) struct PackageReader {
+    archive: ZipArchive<BufReader<File>>,
+    resources: Vec<DataPackageEntry>,
+    index: Vec<CDXRecord>,
+    // index times only go down to the second, so captures are told apart by their unix timestamp
+    captures: HashSet<(String, i64)>,
+}
+
+impl PackageReader {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<PackageReader> {
+        let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+        let package: DataPackageResources =
+            serde_json::from_reader(archive.by_name("datapackage.json")?)?;
+
+        let mut index = Vec::new();
+        let lines = BufReader::new(MultiGzDecoder::new(
+            archive.by_name("indexes/index.cdx.gz")?,
+        ))
+        .lines();
+        for line in lines {
+            let line = line?;
+            let record = CDXRecord::from_line(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unreadable index line: {line}"),
+                )
+            })?;
+            index.push(record);
+        }
+
+        let captures = index
+            .iter()
+            .map(|record| (record.key.clone(), record.time.unix_timestamp()))
+            .collect();
+
+        Ok(PackageReader {
+            archive,
+            resources: package.resources,
+            index,
+            captures,
+        })
+    }
+
+    /// Whether the package already has the capture of `key` from `fetched_at`.
+    pub(crate) fn contains(&self, key: &str, fetched_at: OffsetDateTime) -> bool {
+        self.captures
+            .contains(&(key.to_owned(), fetched_at.unix_timestamp()))
+    }
+
+    fn warcs(&self) -> impl Iterator<Item = &DataPackageEntry> {
+        self.resources
+            .iter()
+            .filter(|resource| resource.path.starts_with("archive/"))
+    }
+
+    /// Copies the package's WARC files into `package` as they are, returning their entries.
+    pub(crate) fn copy_warcs<W: Write + Seek>(
+        &mut self,
+        package: &mut ZipWriter<W>,
+    ) -> io::Result<Vec<DataPackageEntry>> {
+        let warcs = self.warcs().cloned().collect::<Vec<_>>();
+        for warc in &warcs {
+            package.raw_copy_file(self.archive.by_name(&warc.path)?)?;
+        }
+
+        Ok(warcs)
+    }
+
+    /// Copies the package's WARC files into `package` under the names `rename` gives them, returning their new entries
+    /// along with the names they had.
+    pub(crate) fn copy_warcs_renamed<W: Write + Seek>(
+        &mut self,
+        package: &mut ZipWriter<W>,
+        mut rename: impl FnMut(&str) -> String,
+    ) -> io::Result<Vec<(String, DataPackageEntry)>> {
+        let warcs = self.warcs().cloned().collect::<Vec<_>>();
+        let mut renamed = Vec::with_capacity(warcs.len());
+        for warc in warcs {
+            let name = rename(&warc.name);
+            let path = format!("archive/{name}");
+            package.raw_copy_file_rename(self.archive.by_name(&warc.path)?, &path)?;
+
+            renamed.push((warc.name.clone(), DataPackageEntry { name, path, ..warc }));
+        }
+
+        Ok(renamed)
+    }
+
+    /// Takes the package's index, sorted, to merge new records into.
+    pub(crate) fn take_index(&mut self) -> Vec<CDXRecord> {
+        std::mem::take(&mut self.index)
+    }
+
+    /// The entries of one of the package's pages files, without its header.
+    pub(crate) fn pages(&mut self, path: &str) -> io::Result<Vec<String>> {
+        BufReader::new(self.archive.by_name(path)?)
+            .lines()
+            .skip(1)
+            .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+            .collect()
+    }
+}
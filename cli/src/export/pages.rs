@@ -1,6 +1,6 @@
 use core::fmt;
 use std::{
-    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    io::{BufWriter, Read, Seek, Write},
     path::Path,
 };
 
@@ -9,7 +9,7 @@ use serde::Serialize;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-use super::{file_digest, DataPackageEntry};
+use super::{DataPackageEntry, HashingWriter};
 
 #[derive(Serialize)]
 struct PageHeader<'a> {
@@ -24,17 +24,21 @@ struct PageEntry<'a> {
     url: &'a str,
     #[serde(with = "time::serde::rfc3339")]
     ts: OffsetDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
 }
 
 pub struct PagesWriter<W: Write + Read + Seek> {
-    main: BufWriter<W>,
-    extra: BufWriter<W>,
+    main: HashingWriter<BufWriter<W>>,
+    extra: HashingWriter<BufWriter<W>>,
 }
 
 impl<W: Write + Read + Seek + fmt::Debug> PagesWriter<W> {
     pub fn new(main: W, extra: W) -> EvergardenResult<Self> {
-        let mut main = BufWriter::new(main);
-        let mut extra = BufWriter::new(extra);
+        let mut main = HashingWriter::new(BufWriter::new(main));
+        let mut extra = HashingWriter::new(BufWriter::new(extra));
 
         main.start_pages("entrypoint-pages", "main pages!")?;
         extra.start_pages("extra-pages", "crawled pages")?;
@@ -42,32 +46,43 @@ impl<W: Write + Read + Seek + fmt::Debug> PagesWriter<W> {
         Ok(PagesWriter { main, extra })
     }
 
-    pub fn add_entry(&mut self, record: &ResponseMetadata, is_main: bool) -> EvergardenResult<()> {
+    /// Adds a page, with `text` extracted from its body if the crawl didn't annotate it with any.
+    pub fn add_entry(
+        &mut self,
+        record: &ResponseMetadata,
+        is_main: bool,
+        text: Option<&str>,
+    ) -> EvergardenResult<()> {
         if is_main {
-            self.main.pages_entry(record)
+            self.main.pages_entry(record, text)
         } else {
-            self.extra.pages_entry(record)
+            self.extra.pages_entry(record, text)
         }
     }
 
+    /// Adds a page entry that's already serialized, like one from an earlier export.
+    pub fn add_serialized(&mut self, entry: &str, is_main: bool) -> EvergardenResult<()> {
+        let out = if is_main {
+            &mut self.main
+        } else {
+            &mut self.extra
+        };
+
+        out.write_all(entry.as_bytes())?;
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+
     pub fn finalize(
-        mut self,
+        self,
         path: impl AsRef<Path>,
     ) -> EvergardenResult<((W, DataPackageEntry), (W, DataPackageEntry))> {
-        self.main.flush()?;
-        self.extra.flush()?;
-
-        let mut main_file = self.main.into_inner().unwrap();
-
-        let main_digest = file_digest(&mut main_file)?;
-        let main_len = main_file.seek(SeekFrom::End(0))?;
-
+        let (main_file, main_digest, main_len) = self.main.into_parts();
+        let mut main_file = main_file.into_inner().map_err(|e| e.into_error())?;
         main_file.rewind()?;
 
-        let mut extra_file = self.extra.into_inner().unwrap();
-        let extra_digest = file_digest(&mut extra_file)?;
-        let extra_len = extra_file.seek(SeekFrom::End(0))?;
-
+        let (extra_file, extra_digest, extra_len) = self.extra.into_parts();
+        let mut extra_file = extra_file.into_inner().map_err(|e| e.into_error())?;
         extra_file.rewind()?;
 
         Ok((
@@ -115,11 +130,21 @@ pub trait PageEntryWriter: Write {
         Ok(())
     }
 
-    fn pages_entry(&mut self, record: &ResponseMetadata) -> EvergardenResult<()> {
+    fn pages_entry(
+        &mut self,
+        record: &ResponseMetadata,
+        text: Option<&str>,
+    ) -> EvergardenResult<()> {
         self.write_all(&serde_json::to_vec(&PageEntry {
             id: record.id,
             url: record.url.url.as_str(),
             ts: record.fetched_at,
+            title: record.annotations.as_ref().and_then(|a| a.title.as_deref()),
+            text: record
+                .annotations
+                .as_ref()
+                .and_then(|a| a.text.as_deref())
+                .or(text),
         })?)?;
 
         self.write_all(b"\n")?;
@@ -1,21 +1,47 @@
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
     fs::{create_dir_all, File, OpenOptions},
     io::{self, BufReader, BufWriter, Read, Seek, Write},
+    iter::Peekable,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use super::{
-    cdxj::CDXWriter,
+    cdxj::{CDXRecord, CDXWriter},
+    file_digest,
+    metadata::{MetadataArgs, PackageMetadata},
+    package::PackageReader,
     pages::PagesWriter,
-    warc::{RotatingWarcRecorder, WarcRecorder},
-    DataPackage, DataPackageEntry,
+    validate::validate,
+    warc::{
+        RotatingWarcRecorder, WarcCompression, WarcDir, WarcFormat, WarcInfo, WarcOutput,
+        WarcVersion,
+    },
+    workers::{write_group, EncodedGroup, PendingBody, PendingRecord, WarcWorkers},
+    DataPackage, DataPackageEntry, MeasuredFile,
+};
+use crate::filter::FilterArgs;
+use bytes::Bytes;
+use clap::builder::TypedValueParser;
+use evergarden_client::{
+    config::{GlobalConfig, HttpConfig},
+    extract, robots,
+};
+use evergarden_common::{
+    encoding::{decode_body, transcode_body},
+    EvergardenResult, Filter, HttpResponse, ResponseMetadata, RevisitInfo, Storage, StorageBackend,
 };
-use evergarden_common::{CrawlInfo, EvergardenResult, ResponseMetadata, Storage};
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use ssri::Integrity;
+use tempfile::tempfile;
 use tracing_subscriber::filter::LevelFilter;
+use uuid::Uuid;
 
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tracing::{debug, info};
@@ -26,18 +52,195 @@ use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 pub(crate) struct ExportArgs {
     #[arg(short, long, help = "export folder for `evergarden archive`")]
     input: PathBuf,
-    #[arg(short, long, help = "output .wacz folder")]
+    #[arg(short, long, help = "output .wacz file, or directory with --directory")]
     output: PathBuf,
+    #[arg(
+        long,
+        help = "Write the WARC files, a CDXJ index and pages lists into the <output> directory, instead of a WACZ package",
+        conflicts_with = "cdx_loc"
+    )]
+    directory: bool,
+    #[arg(long, help = "write TLS session details as WARC metadata records")]
+    tls_metadata: bool,
+    #[arg(
+        long,
+        help = "WARC format version to write; 1.0 is for tools that can't read 1.1 yet",
+        default_value = "1.1",
+        value_parser = clap::builder::PossibleValuesParser::new(["1.0", "1.1"])
+            .map(|s| s.parse::<WarcVersion>().unwrap()),
+    )]
+    warc_version: WarcVersion,
+    #[arg(
+        long,
+        help = "Compress WARC records with zstd, as .warc.zst files. Compresses better, but fewer tools can read them."
+    )]
+    zstd: bool,
+    #[arg(
+        long,
+        help = "zstd compression level",
+        default_value_t = 19,
+        requires = "zstd"
+    )]
+    zstd_level: i32,
+    #[arg(
+        long,
+        help = "zstd dictionary to compress records with (as made by `zstd --train`), embedded in every WARC file",
+        requires = "zstd"
+    )]
+    zstd_dictionary: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Also write indexes/index.loc, for serving the index with pywb as a zipnum collection"
+    )]
+    cdx_loc: bool,
+    #[arg(
+        long,
+        help = "Add the readable text of html pages to pages.jsonl, for full-text search in replayweb.page"
+    )]
+    page_text: bool,
+    #[arg(
+        long,
+        help = "Write what scripts annotated each page with as a JSON `resource` record, at urn:annotations:<url>"
+    )]
+    annotation_records: bool,
+    #[arg(
+        long,
+        help = "WACZ from an earlier export of the same crawl. Only records it doesn't have are exported, into a package along with everything it has."
+    )]
+    previous: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Write only the records that aren't in --previous, as a package of their own",
+        requires = "previous"
+    )]
+    delta: bool,
+    #[arg(
+        long,
+        help = "Split the export into packages of about this much WARC data, like `2GB`, named <output>-00000.wacz and so on, along with a multi-WACZ <output>.json that lists them",
+        value_parser = |size: &str| size.parse::<ByteUnit>().map_err(|e| e.to_string()),
+        conflicts_with_all = ["directory", "previous"]
+    )]
+    max_wacz_size: Option<ByteUnit>,
+    #[arg(
+        long,
+        help = "Threads to read and compress records on. They're written out in order, so the export's the same as with one.",
+        default_value = "1",
+        conflicts_with = "max_wacz_size"
+    )]
+    jobs: NonZeroUsize,
+    #[arg(
+        long,
+        help = "Check every record's metadata and body, and list the files the package would have and their sizes, without writing it",
+        conflicts_with_all = ["directory", "max_wacz_size", "jobs"]
+    )]
+    dry_run: bool,
+    #[command(flatten)]
+    filter: FilterArgs,
+    #[command(flatten)]
+    metadata: MetadataArgs,
 }
 
-fn open(path: impl AsRef<Path>) -> io::Result<File> {
+impl ExportArgs {
+    /// The arguments for a plain export of the archive folder `input` to the WACZ package `output`.
+    pub(crate) fn new(input: PathBuf, output: PathBuf) -> ExportArgs {
+        ExportArgs {
+            input,
+            output,
+            directory: false,
+            tls_metadata: false,
+            warc_version: WarcVersion::V1_1,
+            zstd: false,
+            zstd_level: 19,
+            zstd_dictionary: None,
+            cdx_loc: false,
+            page_text: false,
+            annotation_records: false,
+            previous: None,
+            delta: false,
+            max_wacz_size: None,
+            jobs: NonZeroUsize::MIN,
+            dry_run: false,
+            filter: FilterArgs::default(),
+            metadata: MetadataArgs::default(),
+        }
+    }
+}
+
+fn create(path: impl AsRef<Path>) -> io::Result<File> {
     OpenOptions::new()
         .create(true)
+        .truncate(true)
         .read(true)
         .write(true)
         .open(path.as_ref())
 }
 
+/// How records are written into an export's output: one at a time, or read and compressed by threads of their own
+/// and written in the order they were handed over.
+struct RecordWriter {
+    recorder: RotatingWarcRecorder<ExportOutput>,
+    workers: Option<WarcWorkers>,
+}
+
+/// Where an export's files go.
+enum ExportOutput {
+    /// A WACZ package.
+    Package(ZipWriter<BufWriter<File>>),
+    /// A directory laid out like a WACZ package's contents.
+    Directory(WarcDir),
+    /// A WACZ package that's only measured, for a dry run.
+    Measured(ZipWriter<MeasuredFile>),
+}
+
+impl Write for ExportOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ExportOutput::Package(package) => package.write(buf),
+            ExportOutput::Directory(dir) => dir.write(buf),
+            ExportOutput::Measured(package) => package.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ExportOutput::Package(package) => package.flush(),
+            ExportOutput::Directory(dir) => dir.flush(),
+            ExportOutput::Measured(package) => package.flush(),
+        }
+    }
+}
+
+impl WarcOutput for ExportOutput {
+    fn start_file(&mut self, path: &str) -> io::Result<()> {
+        match self {
+            ExportOutput::Package(package) => WarcOutput::start_file(package, path),
+            ExportOutput::Directory(dir) => dir.start_file(path),
+            ExportOutput::Measured(package) => WarcOutput::start_file(package, path),
+        }
+    }
+}
+
+/// A zipnum index for packages, or plain CDXJ lines for directories, since that's what tools reading those take.
+enum IndexWriter {
+    Zipnum(Box<CDXWriter<File>>),
+    Lines(BufWriter<File>),
+}
+
+impl IndexWriter {
+    fn write_batch(&mut self, batch: impl IntoIterator<Item = CDXRecord>) -> io::Result<()> {
+        match self {
+            IndexWriter::Zipnum(writer) => writer.write_batch(batch),
+            IndexWriter::Lines(out) => {
+                for record in batch {
+                    out.write_all(&record.to_line())?;
+                    out.write_all(b"\n")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 trait ZipWriterExt {
     fn add_file(
         &mut self,
@@ -69,84 +272,446 @@ impl<W: Write + Seek> ZipWriterExt for ZipWriter<W> {
     }
 }
 
+/// The decoded, utf-8 body of an html response. Bodies that can't be decoded are left out rather than failing the export.
+fn read_html(
+    storage: &Storage,
+    meta: &ResponseMetadata,
+    hash: &Integrity,
+) -> EvergardenResult<Option<Vec<u8>>> {
+    let mut body = Vec::new();
+    if let Some(mut reader) = storage.read_body_sync(hash.clone(), meta.compression)? {
+        reader.read_to_end(&mut body)?;
+    }
+
+    Ok(decode_html(meta, &body))
+}
+
+/// An html response's body as received, decoded and transcoded to utf-8, if it can be.
+pub(crate) fn decode_html(meta: &ResponseMetadata, body: &[u8]) -> Option<Vec<u8>> {
+    decode_body(&meta.headers, body)
+        .ok()
+        .map(|body| transcode_body(&meta.headers, &body).0.into_owned())
+}
+
+/// Resource records of what scripts annotated each capture with, sorted to go in among the stored records.
+fn annotation_records(storage: &Storage, filter: Filter) -> EvergardenResult<Vec<PendingRecord>> {
+    let mut records = Vec::new();
+
+    for record in storage.query(filter) {
+        let (_, _, meta) = record?;
+        let Some(annotations) = meta
+            .annotations
+            .as_ref()
+            .filter(|_| meta.resource.is_none())
+        else {
+            continue;
+        };
+
+        let body = serde_json::to_vec(annotations)?;
+        let mut resource = Arc::unwrap_or_clone(
+            HttpResponse::resource("annotations", &meta, "application/json", Bytes::new()).meta,
+        );
+        // dated with the capture, like what's made while it's rendered
+        resource.fetched_at = meta.fetched_at;
+
+        records.push(PendingRecord {
+            key: resource.url.url.to_string(),
+            body: PendingBody::Derived(body),
+            meta: resource,
+            revisit: None,
+        });
+    }
+
+    records.sort_by(|a, b| (&a.key, a.meta.fetched_at).cmp(&(&b.key, b.meta.fetched_at)));
+    Ok(records)
+}
+
+// responses are only marked noindex if the crawl honored robots directives, and the meta tags need a look at the body
+pub(crate) fn is_noindex(meta: &ResponseMetadata, html: Option<&[u8]>, user_agent: &str) -> bool {
+    let Some(directives) = meta.robots else {
+        return false;
+    };
+
+    directives.noindex || html.is_some_and(|body| robots::meta_directives(body, user_agent).noindex)
+}
+
+/// The captures an export writes in full, which its revisits can refer to.
+struct Captures {
+    /// The earliest capture of every payload, which later captures of it are exported as revisits of. Storage turns
+    /// most duplicates into revisits as they come in; this catches the rest, like those stored before it compared
+    /// payloads.
+    earliest: HashMap<String, RevisitInfo>,
+    ids: HashSet<Uuid>,
+}
+
+impl Captures {
+    /// What `meta` is exported as a revisit of, if anything. A stored revisit whose capture isn't exported, because a
+    /// filter left it out or it was deleted or refetched since, only stays one if another capture has its payload.
+    fn revisit_of<'a>(&'a self, meta: &'a ResponseMetadata) -> Option<&'a RevisitInfo> {
+        let duplicate = meta
+            .payload_digest
+            .as_ref()
+            .and_then(|digest| self.earliest.get(digest))
+            .filter(|first| first.id != meta.id);
+
+        meta.revisit
+            .as_ref()
+            .filter(|revisit| self.ids.contains(&revisit.id))
+            .or(duplicate)
+    }
+}
+
+fn full_captures(storage: &Storage, filter: Filter) -> EvergardenResult<Captures> {
+    let mut earliest: HashMap<String, RevisitInfo> = HashMap::new();
+    let mut ids = HashSet::new();
+
+    for record in storage.query(filter) {
+        let (_, _, meta) = record?;
+        // empty bodies all share a digest, and there's nothing to save by deduplicating them
+        if meta.revisit.is_some() || meta.resource.is_some() {
+            continue;
+        }
+        ids.insert(meta.id);
+
+        let Some(digest) = meta
+            .payload_digest
+            .as_ref()
+            .filter(|_| meta.size != Some(0))
+        else {
+            continue;
+        };
+
+        let capture = RevisitInfo {
+            id: meta.id,
+            url: meta.url.url.clone(),
+            fetched_at: meta.fetched_at,
+        };
+        match earliest.get(digest) {
+            Some(first) if first.fetched_at <= capture.fetched_at => {}
+            _ => {
+                earliest.insert(digest.clone(), capture);
+            }
+        }
+    }
+
+    Ok(Captures { earliest, ids })
+}
+
 pub(crate) fn export(args: ExportArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt().with_max_level(log_level).init();
 
+    write_export(args)
+}
+
+pub(crate) fn write_export(args: ExportArgs) -> Result<(), Box<dyn Error>> {
     debug!("opening storage");
 
-    let storage = Storage::new(&args.input, false)?;
+    let (storage, storage_config) = Storage::open_crawl(&args.input)?;
 
-    let output_dir = tempfile::tempdir_in("./")?;
-    let output_path = PathBuf::from(output_dir.path());
+    let info = storage.read_info_sync()?;
+    let mut entry_points = info.entry_points.clone();
+    entry_points.sort();
 
-    let _ = create_dir_all(output_path.join("archive"));
-    let _ = create_dir_all(output_path.join("indexes"));
-    let _ = create_dir_all(output_path.join("pages"));
+    // folders that were imported into have no crawl config, and export with the defaults
+    #[derive(Deserialize)]
+    struct Saved {
+        general: Option<GlobalConfig>,
+        http: Option<HttpConfig>,
+    }
+    let saved: Saved = serde_json::from_str(&info.config)?;
+    let robots = saved.http.map(|http| http.robots).unwrap_or_default();
+    let operator = saved.general.and_then(|general| general.operator);
+
+    if matches!(storage_config.backend, StorageBackend::Warc) {
+        return Err(format!(
+            "this crawl was written straight to WARC files, in {}",
+            args.input.join("warc").display()
+        )
+        .into());
+    }
+    let filter = Filter::from(args.filter);
+
+    // a dry run reads everything first, since what it's for is turning up what would stop the export partway
+    if args.dry_run {
+        info!("checking records");
+
+        let problems = validate(&storage, filter.clone())?;
+        if problems > 0 {
+            return Err(format!("found {problems} damaged records").into());
+        }
+    }
 
     // set up our writers
 
     debug!("opening output files");
 
-    let mut warc_writer = RotatingWarcRecorder::new(
-        output_path.join("archive"),
-        "archive/",
-        ByteUnit::Gigabyte(1).as_u64(),
-    )?;
+    let metadata = args.metadata.load()?;
 
-    let mut cdx_writer = CDXWriter::new(
-        open(output_path.join("indexes/index.cdx.gz"))?,
-        open(output_path.join("indexes/index.idx"))?,
-    );
+    let mut previous = match &args.previous {
+        Some(path) if path.canonicalize().ok() == args.output.canonicalize().ok() => {
+            return Err("--previous can't be the package being written".into());
+        }
+        Some(path) => Some(PackageReader::open(path)?),
+        None => None,
+    };
 
-    let mut pages_writer = PagesWriter::new(
-        open(output_path.join("pages/pages.jsonl"))?,
-        open(output_path.join("pages/extraPages.jsonl"))?,
-    )?;
+    if args.directory && args.previous.is_some() && !args.delta {
+        return Err("a directory can only be exported with --previous along with --delta".into());
+    }
 
-    // get a list of records from our storage
+    let mut output = if args.directory {
+        for dir in ["archive", "indexes", "pages"] {
+            create_dir_all(args.output.join(dir))?;
+        }
 
-    let mut records = storage
-        .list()?
-        .collect::<EvergardenResult<Vec<(String, Integrity, ResponseMetadata)>>>()
-        .unwrap();
+        ExportOutput::Directory(WarcDir::new(&args.output))
+    } else if args.dry_run {
+        ExportOutput::Measured(start_package(MeasuredFile::default())?)
+    } else if args.max_wacz_size.is_some() {
+        ExportOutput::Package(create_package(part_path(&args.output, 0))?)
+    } else {
+        ExportOutput::Package(create_package(&args.output)?)
+    };
 
-    info!("found {} WARC records!", records.len());
+    let compression = if args.zstd {
+        WarcCompression::Zstd {
+            level: args.zstd_level,
+            dictionary: match &args.zstd_dictionary {
+                Some(path) => Some(std::fs::read(path)?.into()),
+                None => None,
+            },
+        }
+    } else {
+        WarcCompression::Gzip
+    };
+
+    // an updated package keeps the earlier one's WARC files as they are, and adds new ones after them
+    let mut all_entries = Vec::new();
+    if let Some(previous) = previous.as_mut().filter(|_| !args.delta) {
+        info!(
+            "copying WARC files from {}",
+            args.previous.as_ref().unwrap().display()
+        );
+        match &mut output {
+            ExportOutput::Package(package) => all_entries.extend(previous.copy_warcs(package)?),
+            ExportOutput::Measured(package) => all_entries.extend(previous.copy_warcs(package)?),
+            ExportOutput::Directory(_) => unreachable!("directories are only exported as deltas"),
+        }
+    }
+
+    // WARC files go straight into the output. a package is written one file at a time, so its indexes and pages, which
+    // are much smaller, wait in temporary files until the WARCs are done.
+    let warc_info = WarcInfo::new(&info, operator);
+    let warc_format = WarcFormat {
+        version: args.warc_version,
+        compression,
+    };
+    let mut warc_writer = RecordWriter {
+        recorder: RotatingWarcRecorder::starting_at(
+            output,
+            "archive/",
+            ByteUnit::Gigabyte(1).as_u64(),
+            warc_info.clone(),
+            warc_format.clone(),
+            all_entries.len(),
+        )?,
+        workers: (args.jobs.get() > 1).then(|| {
+            WarcWorkers::start(args.jobs.get(), &storage, &warc_format, args.tls_metadata)
+        }),
+    };
+    let mut parts = 1;
+    let mut part_records = 0;
+
+    let (mut cdx_writer, mut pages_writer) = if args.directory {
+        (
+            IndexWriter::Lines(BufWriter::new(File::create(
+                args.output.join("indexes/index.cdxj"),
+            )?)),
+            PagesWriter::new(
+                create(args.output.join("pages/pages.jsonl"))?,
+                create(args.output.join("pages/extraPages.jsonl"))?,
+            )?,
+        )
+    } else {
+        (
+            IndexWriter::Zipnum(Box::new(CDXWriter::new(tempfile()?, tempfile()?)?)),
+            PagesWriter::new(tempfile()?, tempfile()?)?,
+        )
+    };
+
+    let mut previous_index = Vec::new();
+    if let Some(previous) = previous.as_mut().filter(|_| !args.delta) {
+        for entry in previous.pages("pages/pages.jsonl")? {
+            pages_writer.add_serialized(&entry, true)?;
+        }
+        for entry in previous.pages("pages/extraPages.jsonl")? {
+            pages_writer.add_serialized(&entry, false)?;
+        }
+
+        previous_index = previous.take_index();
+    }
+    let mut previous_index = previous_index.into_iter().peekable();
+
+    // records are read from storage as they're written. the index keeps them sorted by key, which keeps the resulting CDXJ sorted.
+    let count = storage.count(&filter)?;
 
-    let bar = ProgressBar::new(records.len() as u64).with_style(
+    info!("found {count} WARC records!");
+
+    let captures = full_captures(&storage, filter.clone())?;
+
+    let bar = ProgressBar::new(count as u64).with_style(
         ProgressStyle::with_template("{bar:40.cyan/blue} {pos:>7}/{len:7} records written")
             .unwrap()
             .progress_chars("##-"),
     );
-    // sort our records by time, key
 
-    records.sort_unstable_by(|(lkey, _, lmeta), (rkey, _, rmeta)| {
-        (lkey, lmeta.fetched_at.to_hms()).cmp(&(rkey, rmeta.fetched_at.to_hms()))
-    });
+    // writes records, batch by batch. ensures resulting CDXJ will be sorted
+    let mut annotations = if args.annotation_records {
+        annotation_records(&storage, filter.clone())?
+    } else {
+        Vec::new()
+    }
+    .into_iter()
+    .filter(|record| {
+        !previous
+            .as_ref()
+            .is_some_and(|previous| previous.contains(&record.key, record.meta.fetched_at))
+    })
+    .peekable();
 
-    let CrawlInfo {
-        mut entry_points, ..
-    } = storage.read_info_sync()?;
-    entry_points.sort();
+    for (group_key, group) in &storage.query(filter).group_by(|record| {
+        record
+            .as_ref()
+            .ok()
+            .map(|(lkey, _, lmeta)| (lkey.clone(), lmeta.fetched_at.to_hms()))
+    }) {
+        // a package that's grown past --max-wacz-size is finished, and the records left go in the next one
+        // --max-wacz-size can't be used along with --jobs, so everything written so far is in the recorder's output
+        if let Some(max) = args.max_wacz_size {
+            let recorder = &mut warc_writer.recorder;
+            if part_records > 0 && recorder.bytes_written() >= max.as_u64() {
+                let path = part_path(&args.output, parts);
+                debug!("starting {}", path.display());
 
-    // writes records, batch by batch. ensures resulting CDXJ will be sorted
-    for (_, group) in &records
-        .into_iter()
-        .group_by(|(lkey, _, lmeta)| (lkey.clone(), lmeta.fetched_at.to_hms()))
-    {
-        let mut records = Vec::with_capacity(8);
+                let next = RotatingWarcRecorder::starting_at(
+                    ExportOutput::Package(create_package(&path)?),
+                    "archive/",
+                    ByteUnit::Gigabyte(1).as_u64(),
+                    warc_info.clone(),
+                    warc_format.clone(),
+                    0,
+                )?;
+                let (entries, output) = std::mem::replace(recorder, next).finalize()?;
+                finish_output(
+                    output,
+                    std::mem::replace(
+                        &mut cdx_writer,
+                        IndexWriter::Zipnum(Box::new(CDXWriter::new(tempfile()?, tempfile()?)?)),
+                    ),
+                    std::mem::replace(
+                        &mut pages_writer,
+                        PagesWriter::new(tempfile()?, tempfile()?)?,
+                    ),
+                    entries,
+                    args.cdx_loc,
+                    &metadata,
+                )?;
+                parts += 1;
+                part_records = 0;
+            }
+        }
+
+        // annotation records go in ahead of the stored records they sort before
+        if let Some((key, _)) = &group_key {
+            let before = std::iter::from_fn(|| annotations.next_if(|record| record.key < *key))
+                .collect::<Vec<_>>();
+            if !before.is_empty() {
+                part_records += write_pending(
+                    &mut warc_writer,
+                    &storage,
+                    &mut cdx_writer,
+                    &mut previous_index,
+                    before,
+                    args.tls_metadata,
+                )?;
+            }
+        }
 
-        for (key, hash, meta) in group {
+        let mut pending = Vec::with_capacity(8);
+
+        for record in group {
+            let (key, hash, meta) = record?;
             bar.inc(1);
+
+            if previous
+                .as_ref()
+                .is_some_and(|previous| previous.contains(&key, meta.fetched_at))
+            {
+                continue;
+            }
+
             debug!(key, "writing record");
 
-            pages_writer.add_entry(&meta, entry_points.binary_search(&key).is_ok())?;
+            if meta.resource.is_some() {
+                pending.push(PendingRecord {
+                    key,
+                    body: PendingBody::Stored(hash),
+                    meta,
+                    revisit: None,
+                });
+                continue;
+            }
+
+            let html = if extract::is_html(&meta) && (args.page_text || meta.robots.is_some()) {
+                read_html(&storage, &meta, &hash)?
+            } else {
+                None
+            };
+
+            if !is_noindex(&meta, html.as_deref(), &robots.user_agent) {
+                let text = html
+                    .as_deref()
+                    .filter(|_| args.page_text)
+                    .map(extract::text::page_text);
+                pages_writer.add_entry(
+                    &meta,
+                    entry_points.binary_search(&key).is_ok(),
+                    text.as_deref(),
+                )?;
+            }
+
+            pending.push(PendingRecord {
+                revisit: captures.revisit_of(&meta).cloned(),
+                key,
+                body: PendingBody::Stored(hash),
+                meta,
+            });
+        }
 
-            let cdx =
-                warc_writer.write_warc(&key, &meta, &mut storage.read_body_sync(hash)?.unwrap())?;
-            records.push(cdx.clone());
+        if pending.is_empty() {
+            continue;
         }
 
-        cdx_writer.write_batch(records)?;
+        part_records += write_pending(
+            &mut warc_writer,
+            &storage,
+            &mut cdx_writer,
+            &mut previous_index,
+            pending,
+            args.tls_metadata,
+        )?;
+    }
+
+    let annotations = annotations.collect::<Vec<_>>();
+    if !annotations.is_empty() {
+        write_pending(
+            &mut warc_writer,
+            &storage,
+            &mut cdx_writer,
+            &mut previous_index,
+            annotations,
+            args.tls_metadata,
+        )?;
     }
 
     bar.finish();
@@ -155,32 +720,194 @@ pub(crate) fn export(args: ExportArgs, log_level: LevelFilter) -> Result<(), Box
 
     info!("finishing up WARC/CDX export");
 
-    let warc_entries = warc_writer.finalize()?;
+    let RecordWriter {
+        mut recorder,
+        workers,
+    } = warc_writer;
+    if let Some(mut workers) = workers {
+        while let Some(group) = workers.take(0)? {
+            write_encoded(&mut recorder, &mut cdx_writer, &mut previous_index, group)?;
+        }
+        workers.finish()?;
+    }
+    let (warc_entries, output) = recorder.finalize()?;
+    all_entries.extend(warc_entries);
 
-    let mut all_entries = Vec::new();
-    all_entries.extend_from_slice(&warc_entries);
+    cdx_writer.write_batch(previous_index)?;
 
-    // TODO: compressed cdx files. this seems to use something called zipnum index?https://github.com/harvard-lil/js-wacz/blob/0ccad603752d91545519109851937620a593251a/index.js#L458C2-L458C2
-    let ((cdx_file, cdx_entry), (idx_file, idx_entry)) = cdx_writer.finalize("indexes/")?;
-    all_entries.push(cdx_entry);
-    all_entries.push(idx_entry);
+    finish_output(
+        output,
+        cdx_writer,
+        pages_writer,
+        all_entries,
+        args.cdx_loc,
+        &metadata,
+    )?;
 
-    let ((pages_file, pages_entry), (extrapages_file, extrapages_entry)) =
-        pages_writer.finalize("pages/")?;
-    all_entries.push(pages_entry);
-    all_entries.push(extrapages_entry);
+    if args.max_wacz_size.is_some() {
+        write_multi_wacz(&args.output, parts, metadata)?;
+        info!(
+            "wrote {parts} packages, listed in {}",
+            args.output.with_extension("json").display()
+        );
+    } else if args.directory {
+        info!("wrote export to {}", args.output.display());
+    }
 
-    let package_metadata = DataPackage {
-        profile: "data-package",
+    Ok(())
+}
+
+/// Writes a group of records, or hands it to a thread to write along with any groups it's done with, returning how many
+/// records were written.
+fn write_pending(
+    warc_writer: &mut RecordWriter,
+    storage: &Storage,
+    cdx_writer: &mut IndexWriter,
+    previous_index: &mut Peekable<std::vec::IntoIter<CDXRecord>>,
+    pending: Vec<PendingRecord>,
+    tls_metadata: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let recorder = &mut warc_writer.recorder;
+    let Some(workers) = &mut warc_writer.workers else {
+        let records = write_group(recorder, storage, pending, tls_metadata)?;
+        let written = records.len();
+        write_index(cdx_writer, previous_index, records)?;
+        return Ok(written);
+    };
+
+    workers.send(pending)?;
+    let mut written = 0;
+    while let Some(group) = workers.take(workers.backlog())? {
+        written += write_encoded(recorder, cdx_writer, previous_index, group)?;
+    }
+
+    Ok(written)
+}
+
+/// Writes a group a thread has written the records of, returning how many records it had.
+fn write_encoded(
+    recorder: &mut RotatingWarcRecorder<ExportOutput>,
+    cdx_writer: &mut IndexWriter,
+    previous_index: &mut Peekable<std::vec::IntoIter<CDXRecord>>,
+    mut group: EncodedGroup,
+) -> io::Result<usize> {
+    let records = recorder.write_encoded(&mut group.records, group.index)?;
+    let written = records.len();
+    write_index(cdx_writer, previous_index, records)?;
+    Ok(written)
+}
+
+/// Adds a group's index lines, after any of the earlier package's that sort before them.
+fn write_index(
+    cdx_writer: &mut IndexWriter,
+    previous_index: &mut Peekable<std::vec::IntoIter<CDXRecord>>,
+    records: Vec<CDXRecord>,
+) -> io::Result<()> {
+    if let Some(first) = records.first() {
+        let earlier = std::iter::from_fn(|| {
+            previous_index.next_if(|old| (&old.key, old.time) < (&first.key, first.time))
+        });
+        cdx_writer.write_batch(earlier.collect::<Vec<_>>())?;
+    }
+
+    cdx_writer.write_batch(records)
+}
+
+/// Adds the index and pages to a package or directory its WARC files have been written to, and finishes it.
+fn finish_output(
+    output: ExportOutput,
+    cdx_writer: IndexWriter,
+    pages_writer: PagesWriter<File>,
+    all_entries: Vec<DataPackageEntry>,
+    cdx_loc: bool,
+    metadata: &PackageMetadata,
+) -> Result<(), Box<dyn Error>> {
+    match (output, cdx_writer) {
+        (ExportOutput::Package(package), IndexWriter::Zipnum(cdx_writer)) => {
+            finish_package(
+                package,
+                *cdx_writer,
+                pages_writer,
+                all_entries,
+                cdx_loc,
+                metadata,
+            )?;
+            Ok(())
+        }
+        (ExportOutput::Measured(package), IndexWriter::Zipnum(cdx_writer)) => {
+            let (file, entries) = finish_package(
+                package,
+                *cdx_writer,
+                pages_writer,
+                all_entries,
+                cdx_loc,
+                metadata,
+            )?;
+
+            for entry in &entries {
+                println!("{:>14}  {}", entry.bytes, entry.path);
+            }
+            println!("{:>14}  in all, as a WACZ package", file.size());
+            Ok(())
+        }
+        (ExportOutput::Directory(_), IndexWriter::Lines(mut index)) => {
+            index.flush()?;
+            pages_writer.finalize("pages/")?;
+            Ok(())
+        }
+        _ => unreachable!("the index is set up for the output it goes in"),
+    }
+}
+
+/// Where the package numbered `part` of a split export goes: `crawl.wacz` is split into `crawl-00000.wacz` and on.
+fn part_path(output: &Path, part: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{stem}-{part:05}.wacz"))
+}
+
+/// Lists the packages of a split export in a multi-WACZ file, which replayweb.page loads as one archive.
+fn write_multi_wacz(
+    output: &Path,
+    parts: usize,
+    metadata: PackageMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let mut resources = Vec::with_capacity(parts);
+    for part in 0..parts {
+        let path = part_path(output, part);
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        resources.push(DataPackageEntry {
+            hash: file_digest(&mut File::open(&path)?)?,
+            bytes: path.metadata()?.len(),
+            path: name.clone(),
+            name,
+        });
+    }
+
+    let multi_wacz = DataPackage {
+        profile: "multi-wacz-package",
         wacz_version: "1.1.1",
         software: "Evergarden (https://github.com/kore-signet/evergarden)",
         created: OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
-        resources: all_entries,
+        metadata,
+        resources,
     };
 
-    info!("building WACZ package");
+    std::fs::write(
+        output.with_extension("json"),
+        serde_json::to_vec_pretty(&multi_wacz)?,
+    )?;
 
-    let mut package = ZipWriter::new(BufWriter::new(File::create(args.output)?));
+    Ok(())
+}
+
+/// Starts a WACZ package at `path`, with the directories its files go in.
+pub(crate) fn create_package(path: impl AsRef<Path>) -> io::Result<ZipWriter<BufWriter<File>>> {
+    start_package(BufWriter::new(File::create(path)?))
+}
+
+fn start_package<W: Write + Seek>(out: W) -> io::Result<ZipWriter<W>> {
+    let mut package = ZipWriter::new(out);
 
     package.add_directory(
         "archive",
@@ -195,6 +922,47 @@ pub(crate) fn export(args: ExportArgs, log_level: LevelFilter) -> Result<(), Box
         FileOptions::default().compression_method(CompressionMethod::Deflated),
     )?;
 
+    Ok(package)
+}
+
+/// Adds the index, pages and package metadata to a WACZ package its WARC files have been written to, and finishes it.
+/// Returns what it was written to, and the files in it.
+pub(crate) fn finish_package<W: Write + Seek>(
+    mut package: ZipWriter<W>,
+    cdx_writer: CDXWriter<File>,
+    pages_writer: PagesWriter<File>,
+    mut all_entries: Vec<DataPackageEntry>,
+    cdx_loc: bool,
+    metadata: &PackageMetadata,
+) -> Result<(W, Vec<DataPackageEntry>), Box<dyn Error>> {
+    // the index is a zipnum index: gzip blocks of sorted lines, summarized in index.idx
+    let loc = cdx_loc.then(|| cdx_writer.loc());
+    let ((cdx_file, cdx_entry), (idx_file, idx_entry)) = cdx_writer.finalize("indexes/")?;
+    all_entries.push(cdx_entry);
+    all_entries.push(idx_entry);
+    if let Some(loc) = &loc {
+        all_entries.push(DataPackageEntry {
+            name: "index.loc".to_owned(),
+            path: "indexes/index.loc".to_owned(),
+            hash: Sha256::digest(loc).into(),
+            bytes: loc.len() as u64,
+        });
+    }
+
+    let ((pages_file, pages_entry), (extrapages_file, extrapages_entry)) =
+        pages_writer.finalize("pages/")?;
+    all_entries.push(pages_entry);
+    all_entries.push(extrapages_entry);
+
+    let package_metadata = DataPackage {
+        profile: "data-package",
+        wacz_version: "1.1.1",
+        software: "Evergarden (https://github.com/kore-signet/evergarden)",
+        created: OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
+        metadata: metadata.clone(),
+        resources: all_entries,
+    };
+
     package.add_file(
         "datapackage.json",
         &serde_json::to_vec_pretty(&package_metadata)?[..],
@@ -205,21 +973,167 @@ pub(crate) fn export(args: ExportArgs, log_level: LevelFilter) -> Result<(), Box
 
     package.add_file("indexes/index.cdx.gz", cdx_file, None)?;
     package.add_file("indexes/index.idx", idx_file, Some(9))?;
+    if let Some(loc) = &loc {
+        package.add_file("indexes/index.loc", loc.as_bytes(), Some(9))?;
+    }
 
     package.add_file("pages/pages.jsonl", pages_file, Some(9))?;
     package.add_file("pages/extraPages.jsonl", extrapages_file, Some(9))?;
 
-    info!("copying WARC files");
+    info!("finishing WACZ package!");
+
+    let out = package.finish()?;
+
+    Ok((out, package_metadata.resources))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, num::NonZeroUsize, path::Path};
+
+    use bytes::Bytes;
+    use evergarden_common::{
+        CrawlInfo, HttpResponse, ResponseMetadata, Storage, StorageConfig, UrlInfo,
+    };
+    use http::{HeaderMap, StatusCode, Version};
+    use time::{macros::datetime, Duration};
+    use tokio::runtime::Runtime;
+    use uuid::Uuid;
+
+    use super::{write_export, ExportArgs};
+    use crate::export::{package::PackageReader, sha256_as_string};
 
-    for DataPackageEntry { path, .. } in warc_entries {
-        debug!(?path, "copying WARC");
-        let file = File::open(output_path.join(&path))?;
-        package.add_file(&path, file, None)?;
+    fn store(dir: &Path, pages: impl IntoIterator<Item = usize>) {
+        let rt = Runtime::new().unwrap();
+        let storage = Storage::new(dir, false).unwrap();
+        rt.block_on(async {
+            storage
+                .write_info(&CrawlInfo {
+                    id: Uuid::new_v4(),
+                    config: serde_json::json!({ "storage": StorageConfig::default() }).to_string(),
+                    entry_points: Vec::new(),
+                    seeds: Vec::new(),
+                })
+                .await
+                .unwrap();
+
+            for page in pages {
+                let meta = ResponseMetadata {
+                    url: UrlInfo::start(&format!("https://example.com/{page}")).unwrap(),
+                    status: StatusCode::OK,
+                    version: Version::HTTP_11,
+                    headers: HeaderMap::new(),
+                    remote_addr: None,
+                    fetched_at: datetime!(2023-07-01 12:00:00 UTC) + Duration::seconds(page as i64),
+                    id: Uuid::new_v4(),
+                    truncated: None,
+                    tls: None,
+                    request: None,
+                    payload_digest: None,
+                    canonical: None,
+                    revisit: None,
+                    robots: None,
+                    resource: None,
+                    annotations: None,
+                    compression: None,
+                    size: None,
+                };
+                let body = Bytes::from(format!("page {page}"));
+                storage
+                    .write_res(HttpResponse::from_bytes(meta, body))
+                    .await
+                    .unwrap();
+            }
+        });
     }
 
-    info!("finishing WACZ package!");
+    // what each index entry says about its capture, leaving out where in the WARC files it ended up
+    fn index(package: &Path) -> Vec<(String, String, String, u16)> {
+        PackageReader::open(package)
+            .unwrap()
+            .take_index()
+            .into_iter()
+            .map(|record| {
+                (
+                    record.key,
+                    record.block.url,
+                    sha256_as_string(&record.block.digest),
+                    record.block.status,
+                )
+            })
+            .collect()
+    }
 
-    package.finish()?;
+    fn urls(package: &Path) -> Vec<String> {
+        index(package).into_iter().map(|(_, url, ..)| url).collect()
+    }
 
-    Ok(())
+    #[test]
+    fn jobs_write_the_same_index_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("archive");
+        store(&archive, 0..50);
+
+        let single = dir.path().join("single.wacz");
+        write_export(ExportArgs::new(archive.clone(), single.clone())).unwrap();
+
+        let parallel = dir.path().join("parallel.wacz");
+        let mut args = ExportArgs::new(archive, parallel.clone());
+        args.jobs = NonZeroUsize::new(4).unwrap();
+        write_export(args).unwrap();
+
+        let single = index(&single);
+        assert_eq!(single.len(), 50);
+        assert!(single.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(index(&parallel), single);
+    }
+
+    #[test]
+    fn previous_packages_are_merged_or_left_out_of_deltas() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("archive");
+        store(&archive, 0..2);
+
+        let first = dir.path().join("first.wacz");
+        write_export(ExportArgs::new(archive.clone(), first.clone())).unwrap();
+
+        // the crawl carries on, and captures a page the first package doesn't have
+        store(&archive, 2..3);
+
+        let updated = dir.path().join("updated.wacz");
+        let mut args = ExportArgs::new(archive.clone(), updated.clone());
+        args.previous = Some(first.clone());
+        write_export(args).unwrap();
+
+        let delta = dir.path().join("delta.wacz");
+        let mut args = ExportArgs::new(archive, delta.clone());
+        args.previous = Some(first.clone());
+        args.delta = true;
+        write_export(args).unwrap();
+
+        assert_eq!(
+            urls(&first),
+            ["https://example.com/0", "https://example.com/1"]
+        );
+        assert_eq!(
+            urls(&updated),
+            [
+                "https://example.com/0",
+                "https://example.com/1",
+                "https://example.com/2"
+            ]
+        );
+        assert_eq!(urls(&delta), ["https://example.com/2"]);
+
+        // the updated package keeps the first one's WARC files, and adds its own after them
+        let warcs = |package: &Path| {
+            PackageReader::open(package)
+                .unwrap()
+                .take_index()
+                .into_iter()
+                .map(|record| record.block.filename)
+                .collect::<BTreeSet<_>>()
+        };
+        assert!(warcs(&first).is_subset(&warcs(&updated)));
+    }
 }
@@ -0,0 +1,29 @@
+//! Checking the records an export would write, so that a damaged archive folder turns up before hours of exporting it
+//! do.
+
+use evergarden_common::{EvergardenResult, Filter, Storage};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::warn;
+
+/// Checks every record `filter` picks out with [`Storage::verify`]. Problems are logged as they're found, and counted.
+pub(super) fn validate(storage: &Storage, filter: Filter) -> EvergardenResult<usize> {
+    let bar = ProgressBar::new(storage.count(&filter)? as u64).with_style(
+        ProgressStyle::with_template("{bar:40.cyan/blue} {pos:>7}/{len:7} records checked")
+            .unwrap()
+            .progress_chars("##-"),
+    );
+
+    let mut problems = 0;
+    for record in storage.verify(filter) {
+        bar.inc(1);
+
+        if let (entry, Some(damage)) = record? {
+            warn!(key = entry.key, "{damage}");
+            problems += 1;
+        }
+    }
+
+    bar.finish();
+
+    Ok(problems)
+}
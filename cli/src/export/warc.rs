@@ -1,22 +1,273 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    fs::File,
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write},
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
 };
 
-use evergarden_common::ResponseMetadata;
+use evergarden_common::{
+    CrawlInfo, RequestMetadata, ResourceInfo, ResponseMetadata, RevisitInfo, TlsInfo,
+};
 use flate2::{write::GzEncoder, Compression};
 use http::header::CONTENT_TYPE;
 use neo_mime::MediaType;
+use sha2::{Digest, Sha256};
 
 use tempfile::tempfile;
-use time::format_description::well_known::Rfc3339;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
 use super::{
     cdxj::{self, CDXRecord},
-    file_digest, sha256_as_string, DataPackageEntry,
+    sha256_as_string, sha256_from_str, DataPackageEntry, HashingWriter,
 };
 
+/// The version of the WARC format records are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WarcVersion {
+    /// For tools that don't read 1.1 yet: dates to the second, and none of the headers 1.1 added.
+    V1_0,
+    #[default]
+    V1_1,
+}
+
+impl FromStr for WarcVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.0" => Ok(WarcVersion::V1_0),
+            "1.1" => Ok(WarcVersion::V1_1),
+            other => Err(format!("unknown WARC version {other}, expected 1.0 or 1.1")),
+        }
+    }
+}
+
+impl WarcVersion {
+    fn line(self) -> &'static str {
+        match self {
+            WarcVersion::V1_0 => "WARC/1.0",
+            WarcVersion::V1_1 => "WARC/1.1",
+        }
+    }
+
+    fn date(self, date: OffsetDateTime) -> String {
+        match self {
+            WarcVersion::V1_0 => date.replace_nanosecond(0).unwrap().format(&Rfc3339),
+            WarcVersion::V1_1 => date.format(&Rfc3339),
+        }
+        .unwrap()
+    }
+
+    fn identical_payload_profile(self) -> &'static str {
+        match self {
+            WarcVersion::V1_0 => "http://netpreserve.org/warc/1.0/revisit/identical-payload-digest",
+            WarcVersion::V1_1 => "http://netpreserve.org/warc/1.1/revisit/identical-payload-digest",
+        }
+    }
+}
+
+/// How each record of a WARC file is compressed.
+#[derive(Clone, Debug, Default)]
+pub enum WarcCompression {
+    /// A gzip member per record, as `.warc.gz`, which everything reads.
+    #[default]
+    Gzip,
+    /// A zstd frame per record, as `.warc.zst`. Compresses much better, particularly with a dictionary, but fewer
+    /// tools read it. The dictionary is put at the start of every file, in a skippable frame, for readers to find.
+    Zstd {
+        level: i32,
+        dictionary: Option<Arc<[u8]>>,
+    },
+}
+
+impl WarcCompression {
+    fn extension(&self) -> &'static str {
+        match self {
+            WarcCompression::Gzip => "warc.gz",
+            WarcCompression::Zstd { .. } => "warc.zst",
+        }
+    }
+}
+
+/// Which version of the format WARC files are written in, and how.
+#[derive(Clone, Debug, Default)]
+pub struct WarcFormat {
+    pub version: WarcVersion,
+    pub compression: WarcCompression,
+}
+
+/// One record's worth of compressed output.
+enum RecordEncoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> RecordEncoder<W> {
+    fn finish(self) -> io::Result<W> {
+        match self {
+            RecordEncoder::Gzip(encoder) => encoder.finish(),
+            RecordEncoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for RecordEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RecordEncoder::Gzip(encoder) => encoder.write(buf),
+            RecordEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordEncoder::Gzip(encoder) => encoder.flush(),
+            RecordEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Somewhere WARC files are written to, one at a time.
+pub trait WarcOutput: Write {
+    /// Starts the file at `path`, which everything written goes to until the next one is started.
+    fn start_file(&mut self, path: &str) -> io::Result<()>;
+}
+
+/// WARC files in a directory on disk.
+pub struct WarcDir {
+    dir: PathBuf,
+    current: Option<BufWriter<File>>,
+}
+
+impl WarcDir {
+    pub fn new(dir: impl AsRef<Path>) -> WarcDir {
+        WarcDir {
+            dir: dir.as_ref().to_path_buf(),
+            current: None,
+        }
+    }
+}
+
+impl WarcOutput for WarcDir {
+    fn start_file(&mut self, path: &str) -> io::Result<()> {
+        if let Some(mut previous) = self.current.take() {
+            previous.flush()?;
+        }
+
+        self.current = Some(BufWriter::new(File::create(self.dir.join(path))?));
+        Ok(())
+    }
+}
+
+impl Write for WarcDir {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.current {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("no WARC file was started")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// WARC files stored, uncompressed, in a zip package as they're written.
+impl<W: Write + Seek> WarcOutput for ZipWriter<W> {
+    fn start_file(&mut self, path: &str) -> io::Result<()> {
+        Ok(ZipWriter::start_file(
+            self,
+            path,
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?)
+    }
+}
+
+/// A WARC file being written, in a given format. Its length and digest are counted as it's written, since its output
+/// can't always be read back.
+pub struct WarcFile<O: Write> {
+    out: HashingWriter<O>,
+    version: WarcVersion,
+    compression: WarcCompression,
+}
+
+impl<O: Write> WarcFile<O> {
+    pub fn new(out: O, format: WarcFormat) -> WarcFile<O> {
+        WarcFile {
+            out: HashingWriter::new(out),
+            version: format.version,
+            compression: format.compression,
+        }
+    }
+
+    /// Starts a record, which is compressed on its own so that it can be read without the rest of the file.
+    fn record(&mut self) -> io::Result<RecordEncoder<&mut WarcFile<O>>> {
+        Ok(match self.compression.clone() {
+            WarcCompression::Gzip => RecordEncoder::Gzip(GzEncoder::new(self, Compression::new(5))),
+            WarcCompression::Zstd {
+                level,
+                dictionary: Some(dictionary),
+            } => RecordEncoder::Zstd(zstd::Encoder::with_dictionary(self, level, &dictionary)?),
+            WarcCompression::Zstd {
+                level,
+                dictionary: None,
+            } => RecordEncoder::Zstd(zstd::Encoder::new(self, level)?),
+        })
+    }
+
+    /// Writes the zstd dictionary records are compressed with, if any, in a skippable frame.
+    fn write_dictionary(&mut self) -> io::Result<()> {
+        const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5D;
+
+        if let WarcCompression::Zstd {
+            dictionary: Some(dictionary),
+            ..
+        } = &self.compression
+        {
+            let dictionary = Arc::clone(dictionary);
+            self.write_all(&SKIPPABLE_FRAME_MAGIC.to_le_bytes())?;
+            self.write_all(&(dictionary.len() as u32).to_le_bytes())?;
+            self.write_all(&dictionary)?;
+        }
+
+        Ok(())
+    }
+
+    /// How many bytes have been written to the file so far.
+    pub fn position(&self) -> u64 {
+        self.out.position()
+    }
+
+    /// What the file was written to.
+    pub fn into_inner(self) -> O {
+        self.out.into_parts().0
+    }
+
+    /// Ends the file, returning its digest and length, and starts counting again for the next one written to the
+    /// same output.
+    fn end_file(&mut self) -> io::Result<([u8; 32], u64)> {
+        self.flush()?;
+
+        Ok(self.out.take_digest())
+    }
+}
+
+impl<O: Write> Write for WarcFile<O> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
 pub trait RecordWriter: Write {
     fn line_end(&mut self) -> io::Result<()> {
         self.write_all(b"\r\n")
@@ -35,12 +286,9 @@ pub trait RecordWriter: Write {
     }
 }
 
-pub trait HttpResponseWriter: Write + Seek + RecordWriter {
-    fn write_http_response(
-        &mut self,
-        meta: &ResponseMetadata,
-        body: &mut impl Read,
-    ) -> std::io::Result<u64> {
+pub trait HttpResponseWriter: RecordWriter {
+    /// Writes the status line and headers of a response, up to where its body starts.
+    fn write_http_head(&mut self, meta: &ResponseMetadata) -> std::io::Result<()> {
         self.line(format!(
             "{:?} {} {}",
             meta.version,
@@ -54,20 +302,12 @@ pub trait HttpResponseWriter: Write + Seek + RecordWriter {
             self.header(name.as_str(), value.as_bytes())?;
         }
 
-        self.line("")?;
-
-        std::io::copy(body, self)?;
-
-        self.flush()?;
-
-        let end = self.stream_position()?;
-
-        Ok(end)
+        self.line("")
     }
 }
 
 impl<T> RecordWriter for T where T: Write {}
-impl<T> HttpResponseWriter for T where T: Write + Seek {}
+impl<T> HttpResponseWriter for T where T: Write {}
 
 pub trait WarcRecorder {
     fn write_warc(
@@ -82,46 +322,164 @@ pub trait WarcRecorder {
         meta: &ResponseMetadata,
         http_block: &mut impl Read,
         digest: &[u8; 32],
+        payload_digest: &[u8; 32],
         content_len: u64,
     ) -> std::io::Result<()>;
+
+    /// Writes a `metadata` record (as `application/warc-fields`) concurrent to the response described by `meta`.
+    fn write_metadata(
+        &mut self,
+        meta: &ResponseMetadata,
+        fields: &[(&str, String)],
+    ) -> std::io::Result<()>;
+
+    /// Writes a `resource` record for something evergarden made itself, like a screenshot.
+    fn write_resource(
+        &mut self,
+        key: &str,
+        meta: &ResponseMetadata,
+        resource: &ResourceInfo,
+        body: &mut impl Read,
+    ) -> std::io::Result<CDXRecord>;
+
+    /// Writes a `revisit` record, holding only the response's headers, for a response whose payload is that of the
+    /// earlier capture described by `revisit`.
+    fn write_revisit(
+        &mut self,
+        surt: &str,
+        meta: &ResponseMetadata,
+        revisit: &RevisitInfo,
+    ) -> std::io::Result<CDXRecord>;
+}
+
+pub fn tls_fields(tls: &TlsInfo) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("tls-version", tls.version.clone()),
+        ("tls-cipher", tls.cipher.clone()),
+    ];
+
+    for cert in &tls.certificates {
+        fields.push(("tls-certificate-sha256", cert.sha256.clone()));
+        fields.push(("tls-certificate-subject", cert.subject.clone()));
+        fields.push(("tls-certificate-issuer", cert.issuer.clone()));
+
+        if let Some(not_before) = cert.not_before {
+            fields.push((
+                "tls-certificate-not-before",
+                not_before.format(&Rfc3339).unwrap(),
+            ));
+        }
+
+        if let Some(not_after) = cert.not_after {
+            fields.push((
+                "tls-certificate-not-after",
+                not_after.format(&Rfc3339).unwrap(),
+            ));
+        }
+    }
+
+    fields
+}
+
+/// Writes a `request` record for the request that got the response described by `meta`, linked to it with
+/// `WARC-Concurrent-To`.
+fn write_request(
+    out: &mut WarcFile<impl Write>,
+    meta: &ResponseMetadata,
+    request: &RequestMetadata,
+) -> std::io::Result<()> {
+    let mut block = Vec::with_capacity(512);
+    block.line(format!(
+        "{} {} {:?}",
+        request.method, request.target, request.version
+    ))?;
+    for (name, value) in request.headers.iter() {
+        block.header(name.as_str(), value.as_bytes())?;
+    }
+    block.line("")?;
+    if let Some(body) = &request.body {
+        block.write_all(body)?;
+    }
+
+    let digest: [u8; 32] = Sha256::digest(&block).into();
+
+    let version = out.version;
+    let mut out = out.record()?;
+
+    out.line(version.line())?;
+
+    out.header("WARC-Type", "request")?;
+    out.header("WARC-Target-URI", meta.url.url.as_str())?;
+    out.header("WARC-Date", version.date(meta.fetched_at))?;
+    out.header(
+        "WARC-Record-ID",
+        format!("<urn:uuid:{}>", Uuid::new_v4().hyphenated()),
+    )?;
+    out.header(
+        "WARC-Concurrent-To",
+        format!("<urn:uuid:{}>", meta.id.hyphenated()),
+    )?;
+    out.header("Content-Type", "application/http;msgtype=request")?;
+    out.header("WARC-Block-Digest", sha256_as_string(&digest))?;
+    out.header("Content-Length", block.len().to_string())?;
+
+    out.line("")?;
+
+    out.write_all(&block)?;
+    out.line("")?;
+    out.line("")?;
+
+    out.flush()?;
+    out.finish()?;
+
+    Ok(())
 }
 
-impl WarcRecorder for BufWriter<File> {
+impl<O: Write> WarcRecorder for WarcFile<O> {
     fn write_warc(
         &mut self,
         surt: &str,
         meta: &ResponseMetadata,
         body: &mut impl Read,
     ) -> std::io::Result<CDXRecord> {
-        let mut http_block_out = BufWriter::new(tempfile()?);
-        let content_len = http_block_out.write_http_response(meta, body)?;
-        http_block_out.flush()?;
-
-        let mut http_block_out = http_block_out.into_inner().unwrap();
-        http_block_out.sync_data()?;
+        // the block is hashed as it's written out, so that its digest is known before the record's headers. the
+        // payload, the body alone, is hashed on its own on the way.
+        let mut http_block_out = HashingWriter::new(BufWriter::new(tempfile()?));
+        http_block_out.write_http_head(meta)?;
 
-        let block_digest = file_digest(&mut http_block_out)?;
+        let mut payload_out = HashingWriter::new(&mut http_block_out);
+        std::io::copy(body, &mut payload_out)?;
+        let (_, payload_digest, _) = payload_out.into_parts();
 
+        let (http_block_out, block_digest, content_len) = http_block_out.into_parts();
+        let mut http_block_out = http_block_out.into_inner().map_err(|e| e.into_error())?;
         http_block_out.rewind()?;
 
-        let start_position = self.stream_position()?;
+        let start_position = self.position();
 
         self.write_raw_warc(
             meta,
             &mut BufReader::new(http_block_out),
             &block_digest,
+            &payload_digest,
             content_len,
         )?;
         self.flush()?;
 
-        let end_position = self.stream_position()?;
+        let end_position = self.position();
+
+        // the request follows its response, so that the index only has to point at the response
+        if let Some(request) = &meta.request {
+            write_request(self, meta, request)?;
+        }
 
         Ok(CDXRecord {
             key: surt.to_owned(),
             time: meta.fetched_at,
             block: cdxj::CDXJBlock {
                 url: meta.url.url.to_string(),
-                digest: block_digest,
+                // indexes point at payloads, which is what revisits of this capture share with it
+                digest: payload_digest,
                 mime: meta
                     .headers
                     .get(CONTENT_TYPE)
@@ -141,18 +499,20 @@ impl WarcRecorder for BufWriter<File> {
         meta: &ResponseMetadata,
         http_block: &mut impl Read,
         digest: &[u8; 32],
+        payload_digest: &[u8; 32],
         content_len: u64,
     ) -> std::io::Result<()> {
         use http::Version;
 
-        let mut out = GzEncoder::new(self, Compression::new(5));
+        let version = self.version;
+        let mut out = self.record()?;
 
-        out.line("WARC/1.1")?;
+        out.line(version.line())?;
 
         out.header("WARC-Target-URI", meta.url.url.as_str())?;
         out.header("Content-Type", "application/http;msgtype=response")?;
         out.header("WARC-Type", "response")?;
-        out.header("WARC-Date", meta.fetched_at.format(&Rfc3339).unwrap())?;
+        out.header("WARC-Date", version.date(meta.fetched_at))?;
         out.header(
             "WARC-Record-ID",
             format!("<urn:uuid:{}>", meta.id.hyphenated()),
@@ -162,19 +522,26 @@ impl WarcRecorder for BufWriter<File> {
             out.header("WARC-IP-Address", ip.to_string())?;
         }
 
-        out.header(
-            "WARC-Protocol",
-            match meta.version {
-                Version::HTTP_09 => "http/0.9",
-                Version::HTTP_10 => "http/1.0",
-                Version::HTTP_11 => "http/1.1",
-                Version::HTTP_2 => "h2",
-                Version::HTTP_3 => "h3",
-                _ => unreachable!(),
-            },
-        )?;
+        if version != WarcVersion::V1_0 {
+            out.header(
+                "WARC-Protocol",
+                match meta.version {
+                    Version::HTTP_09 => "http/0.9",
+                    Version::HTTP_10 => "http/1.0",
+                    Version::HTTP_11 => "http/1.1",
+                    Version::HTTP_2 => "h2",
+                    Version::HTTP_3 => "h3",
+                    _ => unreachable!(),
+                },
+            )?;
+        }
+
+        if let Some(reason) = meta.truncated {
+            out.header("WARC-Truncated", reason.as_str())?;
+        }
 
         out.header("WARC-Block-Digest", sha256_as_string(digest))?;
+        out.header("WARC-Payload-Digest", sha256_as_string(payload_digest))?;
         out.header("Content-Length", content_len.to_string())?;
 
         out.line("")?;
@@ -186,109 +553,408 @@ impl WarcRecorder for BufWriter<File> {
 
         Ok(())
     }
+
+    fn write_metadata(
+        &mut self,
+        meta: &ResponseMetadata,
+        fields: &[(&str, String)],
+    ) -> std::io::Result<()> {
+        let mut block = Vec::with_capacity(512);
+        for (name, value) in fields {
+            block.header(name, value)?;
+        }
+
+        let digest: [u8; 32] = Sha256::digest(&block).into();
+
+        let version = self.version;
+        let mut out = self.record()?;
+
+        out.line(version.line())?;
+
+        out.header("WARC-Type", "metadata")?;
+        out.header("WARC-Target-URI", meta.url.url.as_str())?;
+        out.header("WARC-Date", version.date(meta.fetched_at))?;
+        out.header(
+            "WARC-Record-ID",
+            format!("<urn:uuid:{}>", Uuid::new_v4().hyphenated()),
+        )?;
+        out.header(
+            "WARC-Concurrent-To",
+            format!("<urn:uuid:{}>", meta.id.hyphenated()),
+        )?;
+        out.header("Content-Type", "application/warc-fields")?;
+        out.header("WARC-Block-Digest", sha256_as_string(&digest))?;
+        out.header("Content-Length", block.len().to_string())?;
+
+        out.line("")?;
+
+        out.write_all(&block)?;
+        out.line("")?;
+        out.line("")?;
+
+        out.flush()?;
+        out.finish()?;
+
+        Ok(())
+    }
+
+    fn write_resource(
+        &mut self,
+        key: &str,
+        meta: &ResponseMetadata,
+        resource: &ResourceInfo,
+        body: &mut impl Read,
+    ) -> std::io::Result<CDXRecord> {
+        let mut block = Vec::new();
+        body.read_to_end(&mut block)?;
+
+        let digest: [u8; 32] = Sha256::digest(&block).into();
+
+        let start_position = self.position();
+
+        let version = self.version;
+        let mut out = self.record()?;
+
+        out.line(version.line())?;
+
+        out.header("WARC-Type", "resource")?;
+        out.header("WARC-Target-URI", meta.url.url.as_str())?;
+        out.header("WARC-Date", version.date(meta.fetched_at))?;
+        out.header(
+            "WARC-Record-ID",
+            format!("<urn:uuid:{}>", meta.id.hyphenated()),
+        )?;
+        out.header(
+            "WARC-Concurrent-To",
+            format!("<urn:uuid:{}>", resource.concurrent_to.hyphenated()),
+        )?;
+        out.header("Content-Type", &resource.content_type)?;
+        out.header("WARC-Block-Digest", sha256_as_string(&digest))?;
+        out.header("Content-Length", block.len().to_string())?;
+
+        out.line("")?;
+
+        out.write_all(&block)?;
+        out.line("")?;
+        out.line("")?;
+
+        out.flush()?;
+        out.finish()?;
+
+        self.flush()?;
+        let end_position = self.position();
+
+        Ok(CDXRecord {
+            key: key.to_owned(),
+            time: meta.fetched_at,
+            block: cdxj::CDXJBlock {
+                url: meta.url.url.to_string(),
+                digest,
+                mime: MediaType::parse(&resource.content_type)
+                    .ok()
+                    .map(|v| v.without_params()),
+                filename: String::new(),
+                offset: start_position,
+                length: end_position - start_position,
+                status: meta.status.as_u16(),
+            },
+        })
+    }
+
+    fn write_revisit(
+        &mut self,
+        surt: &str,
+        meta: &ResponseMetadata,
+        revisit: &RevisitInfo,
+    ) -> std::io::Result<CDXRecord> {
+        let mut block = Cursor::new(Vec::with_capacity(1024));
+        block.write_http_head(meta)?;
+        let block = block.into_inner();
+
+        let digest: [u8; 32] = Sha256::digest(&block).into();
+
+        let start_position = self.position();
+
+        let version = self.version;
+        let mut out = self.record()?;
+
+        out.line(version.line())?;
+
+        out.header("WARC-Type", "revisit")?;
+        out.header("WARC-Target-URI", meta.url.url.as_str())?;
+        out.header("WARC-Date", version.date(meta.fetched_at))?;
+        out.header(
+            "WARC-Record-ID",
+            format!("<urn:uuid:{}>", meta.id.hyphenated()),
+        )?;
+        out.header("WARC-Profile", version.identical_payload_profile())?;
+        out.header(
+            "WARC-Refers-To",
+            format!("<urn:uuid:{}>", revisit.id.hyphenated()),
+        )?;
+        if version != WarcVersion::V1_0 {
+            out.header("WARC-Refers-To-Target-URI", revisit.url.as_str())?;
+            out.header("WARC-Refers-To-Date", version.date(revisit.fetched_at))?;
+        }
+
+        if let Some(ip) = meta.remote_addr {
+            out.header("WARC-IP-Address", ip.to_string())?;
+        }
+
+        if let Some(payload_digest) = &meta.payload_digest {
+            out.header("WARC-Payload-Digest", payload_digest)?;
+        }
+
+        out.header("Content-Type", "application/http;msgtype=response")?;
+        out.header("WARC-Block-Digest", sha256_as_string(&digest))?;
+        out.header("Content-Length", block.len().to_string())?;
+
+        out.line("")?;
+
+        out.write_all(&block)?;
+        out.line("")?;
+        out.line("")?;
+
+        out.flush()?;
+        out.finish()?;
+
+        self.flush()?;
+        let end_position = self.position();
+
+        Ok(CDXRecord {
+            key: surt.to_owned(),
+            time: meta.fetched_at,
+            block: cdxj::CDXJBlock {
+                url: meta.url.url.to_string(),
+                digest: meta
+                    .payload_digest
+                    .as_deref()
+                    .and_then(sha256_from_str)
+                    .unwrap_or(digest),
+                mime: MediaType::parse("warc/revisit").ok(),
+                filename: String::new(),
+                offset: start_position,
+                length: end_position - start_position,
+                status: meta.status.as_u16(),
+            },
+        })
+    }
 }
 
-pub struct RotatingWarcRecorder {
+/// Describes the crawl a WARC file came from, in the `warcinfo` record it starts with.
+#[derive(Clone, Debug)]
+pub struct WarcInfo {
+    /// Recorded as `isPartOf`, which every file of the crawl shares.
+    pub crawl_id: Uuid,
+    /// `sha256:<hex>` of the crawl's configuration, as saved along with it.
+    pub config_digest: String,
+    pub operator: Option<String>,
+}
+
+impl WarcInfo {
+    pub fn new(info: &CrawlInfo, operator: Option<String>) -> WarcInfo {
+        WarcInfo {
+            crawl_id: info.id,
+            config_digest: sha256_as_string(&Sha256::digest(info.config.as_bytes()).into()),
+            operator,
+        }
+    }
+
+    fn fields(&self, version: WarcVersion) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            (
+                "software",
+                format!(
+                    "Evergarden/{} (https://github.com/kore-signet/evergarden)",
+                    env!("CARGO_PKG_VERSION")
+                ),
+            ),
+            (
+                "format",
+                match version {
+                    WarcVersion::V1_0 => "WARC File Format 1.0",
+                    WarcVersion::V1_1 => "WARC File Format 1.1",
+                }
+                .to_owned(),
+            ),
+            (
+                "conformsTo",
+                match version {
+                    WarcVersion::V1_0 => "http://bibnum.bnf.fr/WARC/WARC_ISO_28500_version1_latestdraft.pdf",
+                    WarcVersion::V1_1 => "http://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/",
+                }
+                .to_owned(),
+            ),
+            ("isPartOf", self.crawl_id.hyphenated().to_string()),
+            ("evergarden-config-digest", self.config_digest.clone()),
+        ];
+
+        if let Some(operator) = &self.operator {
+            fields.push(("operator", operator.clone()));
+        }
+
+        fields
+    }
+}
+
+/// Writes the `warcinfo` record a WARC file named `filename` starts with.
+fn write_warcinfo(
+    out: &mut WarcFile<impl Write>,
+    filename: &str,
+    info: &WarcInfo,
+) -> std::io::Result<()> {
+    let mut block = Vec::with_capacity(512);
+    for (name, value) in info.fields(out.version) {
+        block.header(name, value)?;
+    }
+
+    let digest: [u8; 32] = Sha256::digest(&block).into();
+
+    let version = out.version;
+    let mut out = out.record()?;
+
+    out.line(version.line())?;
+
+    out.header("WARC-Type", "warcinfo")?;
+    out.header("WARC-Date", version.date(OffsetDateTime::now_utc()))?;
+    out.header("WARC-Filename", filename)?;
+    out.header(
+        "WARC-Record-ID",
+        format!("<urn:uuid:{}>", Uuid::new_v4().hyphenated()),
+    )?;
+    out.header("Content-Type", "application/warc-fields")?;
+    out.header("WARC-Block-Digest", sha256_as_string(&digest))?;
+    out.header("Content-Length", block.len().to_string())?;
+
+    out.line("")?;
+
+    out.write_all(&block)?;
+    out.line("")?;
+    out.line("")?;
+
+    out.flush()?;
+    out.finish()?;
+
+    Ok(())
+}
+
+/// Writes WARC files to an output, starting a new one whenever the last grows past a size.
+pub struct RotatingWarcRecorder<O: WarcOutput> {
     threshold: u64,
     counter: usize,
+    info: WarcInfo,
+    format: WarcFormat,
     packaged_path: PathBuf,
-    dir: PathBuf,
-    current_file: BufWriter<File>,
-    digests: Vec<(usize, [u8; 32], u64)>,
+    current_file: WarcFile<O>,
+    entries: Vec<DataPackageEntry>,
 }
 
-impl RotatingWarcRecorder {
-    pub fn new(
-        dir: impl AsRef<Path>,
+impl<O: WarcOutput> RotatingWarcRecorder<O> {
+    /// Starts writing to `output`, numbering files from `counter` so that they go after any already there.
+    pub fn starting_at(
+        output: O,
         packaged_path: impl AsRef<Path>,
         threshold: u64,
-    ) -> std::io::Result<RotatingWarcRecorder> {
-        let first_file_name = dir.as_ref().join(format!("{:05}.warc.gz", 0));
-
-        let first_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(first_file_name)?;
-
-        Ok(RotatingWarcRecorder {
+        info: WarcInfo,
+        format: WarcFormat,
+        counter: usize,
+    ) -> std::io::Result<RotatingWarcRecorder<O>> {
+        let mut recorder = RotatingWarcRecorder {
             threshold,
-            counter: 0,
+            counter,
+            current_file: WarcFile::new(output, format.clone()),
+            info,
+            format,
             packaged_path: packaged_path.as_ref().to_path_buf(),
-            dir: dir.as_ref().to_path_buf(),
-            current_file: BufWriter::new(first_file),
-            digests: Vec::new(),
-        })
-    }
+            entries: Vec::new(),
+        };
+        recorder.start()?;
 
-    pub fn rotate(&mut self) -> std::io::Result<()> {
-        self.counter += 1;
+        Ok(recorder)
+    }
 
-        self.current_file.flush()?;
+    fn file_name(format: &WarcFormat, index: usize) -> String {
+        format!("{:05}.{}", index, format.compression.extension())
+    }
 
-        let next_file_name = self.dir.join(format!("{:05}.warc.gz", self.counter));
-        let next_file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(next_file_name)?;
-        let old_file = std::mem::replace(&mut self.current_file, BufWriter::new(next_file));
+    fn packaged_file_path(&self, index: usize) -> String {
+        self.packaged_path
+            .join(Self::file_name(&self.format, index))
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
 
-        self.add_digest(
-            self.counter.saturating_sub(1),
-            &mut old_file.into_inner().unwrap(),
-        )?;
+    /// Starts the file numbered `counter`, and writes what it starts with.
+    fn start(&mut self) -> std::io::Result<()> {
+        let path = self.packaged_file_path(self.counter);
+        self.current_file.out.get_mut().start_file(&path)?;
 
-        Ok(())
+        self.current_file.write_dictionary()?;
+        write_warcinfo(
+            &mut self.current_file,
+            &Self::file_name(&self.format, self.counter),
+            &self.info,
+        )
     }
 
-    pub fn add_digest(&mut self, idx: usize, file: &mut File) -> io::Result<()> {
-        let len = file.metadata()?.len();
+    /// Ends the current file, adding it to the entries [`RotatingWarcRecorder::finalize`] returns.
+    fn end(&mut self) -> std::io::Result<()> {
+        let (hash, bytes) = self.current_file.end_file()?;
 
-        let digest = file_digest(file)?;
-
-        self.digests.push((idx, digest, len));
+        self.entries.push(DataPackageEntry {
+            name: Self::file_name(&self.format, self.counter),
+            path: self.packaged_file_path(self.counter),
+            hash,
+            bytes,
+        });
 
         Ok(())
     }
 
-    pub fn finalize(self) -> std::io::Result<Vec<DataPackageEntry>> {
-        let Self {
-            counter,
+    /// How many bytes of WARC files have been written, across all of them.
+    pub fn bytes_written(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.bytes).sum::<u64>() + self.current_file.position()
+    }
 
-            mut current_file,
-            mut digests,
-            ..
-        } = self;
+    pub fn rotate(&mut self) -> std::io::Result<()> {
+        self.end()?;
+        self.counter += 1;
+        self.start()
+    }
 
-        current_file.flush()?;
+    /// Writes records that were written and compressed elsewhere, by a [`WarcFile`] of their own, returning their
+    /// `index` pointed at where they went.
+    pub fn write_encoded(
+        &mut self,
+        records: &mut impl Read,
+        mut index: Vec<CDXRecord>,
+    ) -> std::io::Result<Vec<CDXRecord>> {
+        let start_position = self.current_file.position();
+        std::io::copy(records, &mut self.current_file)?;
 
-        let mut current_file = current_file.into_inner().unwrap();
+        let filename = Self::file_name(&self.format, self.counter);
+        for record in &mut index {
+            record.block.offset += start_position;
+            record.block.filename = filename.clone();
+        }
 
-        digests.push((
-            counter,
-            file_digest(&mut current_file)?,
-            current_file.metadata()?.len(),
-        ));
-
-        Ok(digests
-            .into_iter()
-            .map(|(index, digest, len)| DataPackageEntry {
-                name: format!("{:05}.warc.gz", index),
-                path: self
-                    .packaged_path
-                    .join(format!("{:05}.warc.gz", index))
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-                hash: digest,
-                bytes: len,
-            })
-            .collect())
+        if self.current_file.position() > self.threshold {
+            self.rotate()?;
+        }
+
+        Ok(index)
+    }
+
+    /// Ends the last file, returning every file written along with the output they went to.
+    pub fn finalize(mut self) -> std::io::Result<(Vec<DataPackageEntry>, O)> {
+        self.end()?;
+
+        Ok((self.entries, self.current_file.out.into_parts().0))
     }
 }
 
-impl WarcRecorder for RotatingWarcRecorder {
+impl<O: WarcOutput> WarcRecorder for RotatingWarcRecorder<O> {
     fn write_warc(
         &mut self,
         surt: &str,
@@ -296,7 +962,7 @@ impl WarcRecorder for RotatingWarcRecorder {
         body: &mut impl Read,
     ) -> std::io::Result<CDXRecord> {
         let mut cdx = self.current_file.write_warc(surt, meta, body)?;
-        cdx.block.filename = format!("{:05}.warc.gz", self.counter);
+        cdx.block.filename = Self::file_name(&self.format, self.counter);
 
         if cdx.block.offset + cdx.block.length > self.threshold {
             self.rotate()?;
@@ -310,9 +976,315 @@ impl WarcRecorder for RotatingWarcRecorder {
         meta: &ResponseMetadata,
         http_block: &mut impl Read,
         digest: &[u8; 32],
+        payload_digest: &[u8; 32],
         content_len: u64,
     ) -> std::io::Result<()> {
         self.current_file
-            .write_raw_warc(meta, http_block, digest, content_len)
+            .write_raw_warc(meta, http_block, digest, payload_digest, content_len)
+    }
+
+    fn write_metadata(
+        &mut self,
+        meta: &ResponseMetadata,
+        fields: &[(&str, String)],
+    ) -> std::io::Result<()> {
+        self.current_file.write_metadata(meta, fields)
+    }
+
+    fn write_resource(
+        &mut self,
+        key: &str,
+        meta: &ResponseMetadata,
+        resource: &ResourceInfo,
+        body: &mut impl Read,
+    ) -> std::io::Result<CDXRecord> {
+        let mut cdx = self
+            .current_file
+            .write_resource(key, meta, resource, body)?;
+        cdx.block.filename = Self::file_name(&self.format, self.counter);
+
+        if cdx.block.offset + cdx.block.length > self.threshold {
+            self.rotate()?;
+        }
+
+        Ok(cdx)
+    }
+
+    fn write_revisit(
+        &mut self,
+        surt: &str,
+        meta: &ResponseMetadata,
+        revisit: &RevisitInfo,
+    ) -> std::io::Result<CDXRecord> {
+        let mut cdx = self.current_file.write_revisit(surt, meta, revisit)?;
+        cdx.block.filename = Self::file_name(&self.format, self.counter);
+
+        if cdx.block.offset + cdx.block.length > self.threshold {
+            self.rotate()?;
+        }
+
+        Ok(cdx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::Arc};
+
+    use evergarden_common::{RequestMetadata, ResponseMetadata, RevisitInfo, UrlInfo};
+    use flate2::read::MultiGzDecoder;
+    use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Method, StatusCode, Version};
+    use sha2::{Digest, Sha256};
+    use time::macros::datetime;
+    use uuid::Uuid;
+
+    use super::{
+        RotatingWarcRecorder, WarcCompression, WarcDir, WarcFile, WarcFormat, WarcInfo,
+        WarcRecorder, WarcVersion,
+    };
+    use crate::{
+        export::sha256_as_string,
+        import::reader::{open_warc, WarcRecord},
+    };
+
+    fn meta(url: &str) -> ResponseMetadata {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+
+        ResponseMetadata {
+            url: UrlInfo::start(url).unwrap(),
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            remote_addr: Some("93.184.216.34:443".parse().unwrap()),
+            fetched_at: datetime!(2023-07-01 12:00:00.5 UTC),
+            id: Uuid::new_v4(),
+            truncated: None,
+            tls: None,
+            request: None,
+            payload_digest: None,
+            canonical: None,
+            revisit: None,
+            robots: None,
+            resource: None,
+            annotations: None,
+            compression: None,
+            size: None,
+        }
+    }
+
+    fn read(name: &str, warc: &[u8]) -> Vec<WarcRecord> {
+        open_warc(name, warc)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    fn digest(bytes: &[u8]) -> String {
+        sha256_as_string(&Sha256::digest(bytes).into())
+    }
+
+    #[test]
+    fn responses_round_trip_with_their_requests() {
+        let mut meta = meta("https://example.com/page");
+        meta.request = Some(RequestMetadata {
+            method: Method::GET,
+            target: String::from("/page"),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            body: None,
+        });
+        let body = b"<p>hello</p>";
+
+        let mut file = WarcFile::new(Vec::new(), WarcFormat::default());
+        let cdx = file
+            .write_warc("com,example)/page", &meta, &mut &body[..])
+            .unwrap();
+        let warc = file.into_inner();
+
+        let records = read("test.warc.gz", &warc);
+        assert_eq!(records.len(), 2);
+
+        let (response, request) = (&records[0], &records[1]);
+        let id = format!("urn:uuid:{}", meta.id.hyphenated());
+        assert_eq!(response.header("WARC-Type"), Some("response"));
+        assert_eq!(response.uri_header("WARC-Record-ID"), Some(id.as_str()));
+        assert_eq!(
+            response.header("WARC-Target-URI"),
+            Some("https://example.com/page")
+        );
+        assert_eq!(response.header("WARC-Date"), Some("2023-07-01T12:00:00.5Z"));
+        assert_eq!(
+            response.header("WARC-IP-Address"),
+            Some("93.184.216.34:443")
+        );
+        assert_eq!(response.header("WARC-Protocol"), Some("http/1.1"));
+        assert!(response.block.ends_with(body));
+        assert_eq!(
+            response.header("WARC-Block-Digest"),
+            Some(digest(&response.block).as_str())
+        );
+        assert_eq!(
+            response.header("WARC-Payload-Digest"),
+            Some(digest(body).as_str())
+        );
+
+        assert_eq!(request.header("WARC-Type"), Some("request"));
+        assert_eq!(request.uri_header("WARC-Concurrent-To"), Some(id.as_str()));
+        assert!(request.block.starts_with(b"GET /page HTTP/1.1\r\n"));
+
+        // the index points at the response alone, with the payload's digest
+        assert_eq!(cdx.key, "com,example)/page");
+        assert_eq!(cdx.block.digest, <[u8; 32]>::from(Sha256::digest(body)));
+        assert_eq!(cdx.block.mime.unwrap().as_ref(), "text/html");
+        let start = cdx.block.offset as usize;
+        let indexed = read(
+            "test.warc.gz",
+            &warc[start..start + cdx.block.length as usize],
+        );
+        assert_eq!(indexed.len(), 1);
+        assert_eq!(indexed[0].header("WARC-Type"), Some("response"));
+    }
+
+    #[test]
+    fn revisits_refer_to_their_original() {
+        let original = meta("https://example.com/a");
+        let mut revisit = meta("https://example.com/b");
+        let payload = <[u8; 32]>::from(Sha256::digest(b"same"));
+        revisit.payload_digest = Some(sha256_as_string(&payload));
+        let info = RevisitInfo {
+            id: original.id,
+            url: original.url.url.clone(),
+            fetched_at: original.fetched_at,
+        };
+
+        let mut file = WarcFile::new(Vec::new(), WarcFormat::default());
+        let cdx = file
+            .write_revisit("com,example)/b", &revisit, &info)
+            .unwrap();
+        let records = read("test.warc.gz", &file.into_inner());
+
+        let record = &records[0];
+        assert_eq!(record.header("WARC-Type"), Some("revisit"));
+        assert_eq!(
+            record.header("WARC-Profile"),
+            Some("http://netpreserve.org/warc/1.1/revisit/identical-payload-digest")
+        );
+        assert_eq!(
+            record.uri_header("WARC-Refers-To"),
+            Some(format!("urn:uuid:{}", original.id.hyphenated()).as_str())
+        );
+        assert_eq!(
+            record.header("WARC-Refers-To-Target-URI"),
+            Some("https://example.com/a")
+        );
+        assert_eq!(
+            record.header("WARC-Payload-Digest"),
+            revisit.payload_digest.as_deref()
+        );
+        assert!(record.block.ends_with(b"\r\n\r\n"));
+
+        assert_eq!(cdx.block.digest, payload);
+        assert_eq!(cdx.block.mime.unwrap().as_ref(), "warc/revisit");
+    }
+
+    #[test]
+    fn files_start_with_a_warcinfo_and_the_dictionary() {
+        let dir = tempfile::tempdir().unwrap();
+        let crawl_id = Uuid::new_v4();
+        let dictionary: Arc<[u8]> = b"<html><head><title>".repeat(64).into();
+        let format = WarcFormat {
+            version: WarcVersion::V1_0,
+            compression: WarcCompression::Zstd {
+                level: 3,
+                dictionary: Some(Arc::clone(&dictionary)),
+            },
+        };
+
+        let mut recorder = RotatingWarcRecorder::starting_at(
+            WarcDir::new(dir.path()),
+            "",
+            u64::MAX,
+            WarcInfo {
+                crawl_id,
+                config_digest: digest(b"{}"),
+                operator: None,
+            },
+            format,
+            0,
+        )
+        .unwrap();
+        let cdx = recorder
+            .write_warc(
+                "com,example)/",
+                &meta("https://example.com/"),
+                &mut &b"<html><head><title>hi"[..],
+            )
+            .unwrap();
+        let (entries, _) = recorder.finalize().unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "00000.warc.zst");
+        assert_eq!(cdx.block.filename, "00000.warc.zst");
+        let warc = std::fs::read(dir.path().join("00000.warc.zst")).unwrap();
+        assert_eq!(entries[0].bytes, warc.len() as u64);
+        assert_eq!(entries[0].hash, <[u8; 32]>::from(Sha256::digest(&warc)));
+
+        // the dictionary's in a skippable frame ahead of the records
+        assert_eq!(warc[..4], 0x184D2A5Du32.to_le_bytes());
+        assert_eq!(&warc[8..8 + dictionary.len()], &dictionary[..]);
+
+        let records = read("00000.warc.zst", &warc);
+        assert_eq!(records.len(), 2);
+
+        let warcinfo = &records[0];
+        assert_eq!(warcinfo.header("WARC-Type"), Some("warcinfo"));
+        assert_eq!(warcinfo.header("WARC-Filename"), Some("00000.warc.zst"));
+        let fields = String::from_utf8_lossy(&warcinfo.block);
+        assert!(fields.contains("format: WARC File Format 1.0\r\n"));
+        assert!(fields.contains(&format!("isPartOf: {}\r\n", crawl_id.hyphenated())));
+
+        // 1.0 has no fractional seconds, nor the headers 1.1 added
+        let response = &records[1];
+        assert_eq!(response.header("WARC-Date"), Some("2023-07-01T12:00:00Z"));
+        assert_eq!(response.header("WARC-Protocol"), None);
+
+        let mut decompressed = Vec::new();
+        zstd::Decoder::with_dictionary(&warc[cdx.block.offset as usize..], &dictionary)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.starts_with(b"WARC/1.0\r\n"));
+    }
+
+    #[test]
+    fn gzip_records_are_members_of_their_own() {
+        let mut file = WarcFile::new(Vec::new(), WarcFormat::default());
+        let first = file
+            .write_warc(
+                "com,example)/a",
+                &meta("https://example.com/a"),
+                &mut &b"a"[..],
+            )
+            .unwrap();
+        let second = file
+            .write_warc(
+                "com,example)/b",
+                &meta("https://example.com/b"),
+                &mut &b"b"[..],
+            )
+            .unwrap();
+        let warc = file.into_inner();
+
+        assert_eq!(first.block.offset + first.block.length, second.block.offset);
+        let mut member = String::new();
+        MultiGzDecoder::new(&warc[second.block.offset as usize..])
+            .read_to_string(&mut member)
+            .unwrap();
+        assert!(member.starts_with("WARC/1.1\r\n"));
+        assert!(member.contains("WARC-Target-URI: https://example.com/b\r\n"));
     }
 }
@@ -0,0 +1,219 @@
+//! Writing the records an export has picked out into WARC records, on its own thread or spread across several.
+
+use std::{
+    error::Error,
+    io::{self, Cursor, Read, Seek},
+    sync::mpsc::{channel, sync_channel, Receiver, SyncSender},
+    thread::JoinHandle,
+};
+
+use evergarden_common::{EvergardenResult, ResponseMetadata, RevisitInfo, Storage};
+use ssri::Integrity;
+use tempfile::{spooled_tempfile, SpooledTempFile};
+
+use super::{
+    cdxj::CDXRecord,
+    warc::{tls_fields, WarcFile, WarcFormat, WarcRecorder},
+};
+
+/// How much of a group's records is kept in memory before the rest goes to a temporary file.
+const SPOOLED_GROUP_SIZE: usize = 8 * 1024 * 1024;
+
+/// How many groups each thread can have been handed before the oldest have to be written out.
+const GROUPS_IN_FLIGHT: usize = 4;
+
+/// A record to write, and whether it's written as a revisit.
+pub(super) struct PendingRecord {
+    pub(super) key: String,
+    pub(super) body: PendingBody,
+    pub(super) meta: ResponseMetadata,
+    pub(super) revisit: Option<RevisitInfo>,
+}
+
+/// Where a record's body comes from.
+pub(super) enum PendingBody {
+    /// Storage, by the body's hash.
+    Stored(Integrity),
+    /// The export itself, like the JSON of a page's annotations.
+    Derived(Vec<u8>),
+}
+
+impl PendingBody {
+    fn reader<'a>(
+        self,
+        storage: &'a Storage,
+        key: &str,
+        meta: &ResponseMetadata,
+    ) -> EvergardenResult<Box<dyn Read + 'a>> {
+        Ok(match self {
+            PendingBody::Stored(hash) => match storage.read_body_sync(hash, meta.compression)? {
+                Some(reader) => Box::new(reader),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("the body of {key} is missing"),
+                    )
+                    .into())
+                }
+            },
+            PendingBody::Derived(body) => Box::new(Cursor::new(body)),
+        })
+    }
+}
+
+/// Writes a group of records, returning their index lines in the order they were written.
+pub(super) fn write_group(
+    recorder: &mut impl WarcRecorder,
+    storage: &Storage,
+    group: Vec<PendingRecord>,
+    tls_metadata: bool,
+) -> EvergardenResult<Vec<CDXRecord>> {
+    let mut records = Vec::with_capacity(group.len());
+
+    for PendingRecord {
+        key,
+        body,
+        meta,
+        revisit,
+    } in group
+    {
+        if let Some(resource) = &meta.resource {
+            records.push(recorder.write_resource(
+                &key,
+                &meta,
+                resource,
+                &mut body.reader(storage, &key, &meta)?,
+            )?);
+            continue;
+        }
+
+        records.push(match &revisit {
+            Some(revisit) => recorder.write_revisit(&key, &meta, revisit)?,
+            None => recorder.write_warc(&key, &meta, &mut body.reader(storage, &key, &meta)?)?,
+        });
+
+        if let Some(tls) = meta.tls.as_ref().filter(|_| tls_metadata) {
+            recorder.write_metadata(&meta, &tls_fields(tls))?;
+        }
+    }
+
+    Ok(records)
+}
+
+/// A group's records, compressed and ready to go in a WARC file as they are. Their index lines point into `records`,
+/// from its start.
+pub(super) struct EncodedGroup {
+    pub(super) records: SpooledTempFile,
+    pub(super) index: Vec<CDXRecord>,
+}
+
+fn encode_group(
+    storage: &Storage,
+    format: &WarcFormat,
+    group: Vec<PendingRecord>,
+    tls_metadata: bool,
+) -> EvergardenResult<EncodedGroup> {
+    let mut file = WarcFile::new(spooled_tempfile(SPOOLED_GROUP_SIZE), format.clone());
+    let index = write_group(&mut file, storage, group, tls_metadata)?;
+
+    let mut records = file.into_inner();
+    records.rewind()?;
+    Ok(EncodedGroup { records, index })
+}
+
+/// Threads reading and compressing groups of records, which are handed out in turn and taken back in the same order,
+/// so that they're written into the export just as they would be on one thread.
+pub(super) struct WarcWorkers {
+    senders: Vec<SyncSender<Vec<PendingRecord>>>,
+    results: Vec<Receiver<EvergardenResult<EncodedGroup>>>,
+    handles: Vec<JoinHandle<()>>,
+    sent: usize,
+    taken: usize,
+}
+
+impl WarcWorkers {
+    /// Starts `jobs` threads writing records in `format`.
+    pub(super) fn start(
+        jobs: usize,
+        storage: &Storage,
+        format: &WarcFormat,
+        tls_metadata: bool,
+    ) -> WarcWorkers {
+        let mut senders = Vec::with_capacity(jobs);
+        let mut results = Vec::with_capacity(jobs);
+        let mut handles = Vec::with_capacity(jobs);
+        for _ in 0..jobs {
+            let storage = storage.clone();
+            let format = format.clone();
+
+            let (sender, receiver) = sync_channel::<Vec<PendingRecord>>(GROUPS_IN_FLIGHT);
+            let (result_sender, result_receiver) = channel();
+            handles.push(std::thread::spawn(move || {
+                for records in receiver {
+                    let result = encode_group(&storage, &format, records, tls_metadata);
+                    let failed = result.is_err();
+                    if result_sender.send(result).is_err() || failed {
+                        break;
+                    }
+                }
+            }));
+            senders.push(sender);
+            results.push(result_receiver);
+        }
+
+        WarcWorkers {
+            senders,
+            results,
+            handles,
+            sent: 0,
+            taken: 0,
+        }
+    }
+
+    /// Hands a group of records to the next thread in turn.
+    pub(super) fn send(&mut self, group: Vec<PendingRecord>) -> Result<(), Box<dyn Error>> {
+        let worker = self.sent % self.senders.len();
+        if self.senders[worker].send(group).is_err() {
+            // threads only stop taking records once they've failed, and the failure is the last thing they sent back
+            return Err(match self.results[worker].iter().find_map(Result::err) {
+                Some(e) => e.into(),
+                None => "a WARC writer thread stopped early".into(),
+            });
+        }
+
+        self.sent += 1;
+        Ok(())
+    }
+
+    /// The oldest group not taken yet, once more than `backlog` groups are waiting to be taken. Waits for it to be
+    /// written if it isn't yet.
+    pub(super) fn take(&mut self, backlog: usize) -> Result<Option<EncodedGroup>, Box<dyn Error>> {
+        if self.sent - self.taken <= backlog {
+            return Ok(None);
+        }
+
+        let worker = self.taken % self.results.len();
+        let group = self.results[worker]
+            .recv()
+            .map_err(|_| "a WARC writer thread panicked")??;
+        self.taken += 1;
+        Ok(Some(group))
+    }
+
+    /// How many groups can wait to be taken before they hold up the threads.
+    pub(super) fn backlog(&self) -> usize {
+        self.senders.len() * GROUPS_IN_FLIGHT
+    }
+
+    /// Stops the threads, once every group has been taken.
+    pub(super) fn finish(self) -> Result<(), Box<dyn Error>> {
+        drop(self.senders);
+        for handle in self.handles {
+            if handle.join().is_err() {
+                return Err("a WARC writer thread panicked".into());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::ops::RangeInclusive;
+
+use evergarden_client::config::StatusRange;
+use evergarden_common::Filter;
+use regex::Regex;
+use time::{
+    format_description::well_known::Rfc3339, macros::format_description, Date, OffsetDateTime,
+};
+
+/// Arguments narrowing a command down to some of the stored responses.
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct FilterArgs {
+    #[arg(
+        long = "include-host",
+        value_name = "HOST",
+        help = "Only responses from this host. Can be given more than once."
+    )]
+    hosts: Vec<String>,
+    #[arg(
+        long = "mime",
+        value_name = "MIME",
+        help = "Only responses of this content type, like `text/html`, or `image/*` for any image. Can be given more than once."
+    )]
+    mimes: Vec<String>,
+    #[arg(
+        long = "status",
+        value_name = "STATUS",
+        help = "Only responses with this status, like `200`, `2xx` or `300-399`. Can be given more than once.",
+        value_parser = parse_status_range
+    )]
+    statuses: Vec<RangeInclusive<u16>>,
+    #[arg(
+        long,
+        help = "Only responses fetched at or after this time, as an RFC 3339 timestamp or a date like `2023-08-01`",
+        value_parser = parse_time
+    )]
+    since: Option<OffsetDateTime>,
+    #[arg(
+        long,
+        help = "Only responses fetched before this time, as an RFC 3339 timestamp or a date like `2023-08-01`",
+        value_parser = parse_time
+    )]
+    until: Option<OffsetDateTime>,
+    #[arg(
+        long,
+        value_name = "REGEX",
+        help = "Only responses whose url matches this regex, like `\\.pdf$`"
+    )]
+    url_regex: Option<Regex>,
+}
+
+impl From<FilterArgs> for Filter {
+    fn from(args: FilterArgs) -> Filter {
+        Filter {
+            hosts: args.hosts,
+            statuses: args.statuses,
+            mimes: args.mimes,
+            since: args.since,
+            until: args.until,
+            url_regex: args.url_regex,
+            ..Filter::default()
+        }
+    }
+}
+
+fn parse_status_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    value.parse::<StatusRange>().map(RangeInclusive::from)
+}
+
+fn parse_time(value: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .or_else(|_| {
+            Date::parse(value, format_description!("[year]-[month]-[day]"))
+                .map(|date| date.midnight().assume_utc())
+        })
+        .map_err(|_| format!("not an RFC 3339 timestamp or date: {value}"))
+}
@@ -0,0 +1,144 @@
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use evergarden_common::{Storage, StorageBackend};
+use regex::bytes::{Regex, RegexBuilder};
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::{export::run::decode_html, filter::FilterArgs};
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct GrepArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(help = "The regex to look for in bodies")]
+    pattern: String,
+    #[arg(short = 'I', long, help = "Match letters regardless of case")]
+    ignore_case: bool,
+    #[arg(
+        short = 'l',
+        long,
+        help = "Only print the urls of matching records, without context"
+    )]
+    urls_only: bool,
+    #[arg(
+        short = 'C',
+        long,
+        default_value_t = 40,
+        help = "How many characters around a match to print with it"
+    )]
+    context: usize,
+    #[arg(
+        short = 'm',
+        long,
+        default_value_t = 5,
+        help = "How many matches to print for each record"
+    )]
+    max_count: usize,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+pub(crate) fn grep(args: GrepArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    // matches go to stdout
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    let pattern = RegexBuilder::new(&args.pattern)
+        .case_insensitive(args.ignore_case)
+        .build()?;
+
+    let (storage, storage_config) = Storage::open_crawl(&args.input)?;
+    if matches!(storage_config.backend, StorageBackend::Warc) {
+        return Err(
+            "this crawl's responses were written to WARC files, which hold their bodies instead"
+                .into(),
+        );
+    }
+
+    let mut out = io::stdout().lock();
+    let (mut searched, mut matched) = (0, 0);
+    let mut body = Vec::new();
+
+    for record in storage.query(args.filter.into()) {
+        let (key, hash, meta) = record?;
+        // revisits share the body of the capture they revisit, which is searched on its own
+        if meta.revisit.is_some() {
+            continue;
+        }
+
+        body.clear();
+        match storage.read_body_sync(hash, meta.compression)? {
+            Some(mut reader) => reader.read_to_end(&mut body)?,
+            None => {
+                warn!(key, "the body is missing");
+                continue;
+            }
+        };
+        searched += 1;
+
+        // searched as it reads, without its Content-Encoding and in utf-8 if it names a charset
+        let text = decode_html(&meta, &body);
+        let text = text.as_deref().unwrap_or(&body);
+
+        let url = meta.url.url.as_str();
+        if args.urls_only {
+            if pattern.is_match(text) {
+                matched += 1;
+                writeln!(out, "{url}")?;
+            }
+            continue;
+        }
+
+        let mut found = false;
+        for snippet in snippets(&pattern, text, args.context).take(args.max_count) {
+            found = true;
+            writeln!(out, "{url}\t{snippet}")?;
+        }
+        matched += usize::from(found);
+    }
+
+    out.flush()?;
+    info!("{matched} of {searched} bodies matched");
+
+    Ok(())
+}
+
+/// Each match with up to `context` characters on either side, on one line.
+fn snippets<'a>(
+    pattern: &'a Regex,
+    text: &'a [u8],
+    context: usize,
+) -> impl Iterator<Item = String> + 'a {
+    pattern.find_iter(text).map(move |found| {
+        // characters are at most four bytes, so that's as far as context can reach
+        let reach = context.saturating_mul(4);
+        let before =
+            String::from_utf8_lossy(&text[found.start().saturating_sub(reach)..found.start()]);
+        let after = String::from_utf8_lossy(
+            &text[found.end()..found.end().saturating_add(reach).min(text.len())],
+        );
+
+        let skipped = before.chars().count().saturating_sub(context);
+        let before = before
+            .char_indices()
+            .nth(skipped)
+            .map_or("", |(at, _)| &before[at..]);
+        let after = after
+            .char_indices()
+            .nth(context)
+            .map_or(&*after, |(at, _)| &after[..at]);
+
+        let snippet = format!(
+            "{before}{}{after}",
+            String::from_utf8_lossy(found.as_bytes())
+        );
+        snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+    })
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use evergarden_common::{
+    Compression, CrawlInfo, EvergardenError, HttpResponse, ResourceInfo, ResponseMetadata, Storage,
+    StorageBackend, StorageConfig, TruncatedReason, UrlInfo,
+};
+use http::{header::TRANSFER_ENCODING, HeaderMap, HeaderName, HeaderValue, StatusCode, Version};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::runtime::Runtime;
+use tracing::{debug, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use url::Url;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+pub(crate) mod reader;
+
+use reader::{is_warc, open_warc, WarcReader, WarcRecord};
+use ssri::Integrity;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ImportArgs {
+    #[arg(
+        short,
+        long,
+        help = "archive folder to import into, like one written by `evergarden archive`"
+    )]
+    output: PathBuf,
+    #[arg(
+        required = true,
+        help = "WARC (.warc, .warc.gz or .warc.zst) or WACZ files to import. Later captures of a url replace earlier ones."
+    )]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Default)]
+struct ImportStats {
+    imported: usize,
+    skipped: usize,
+}
+
+/// Where an imported record's payload is stored, and how.
+#[derive(Clone)]
+struct Payload {
+    hash: Integrity,
+    compression: Option<Compression>,
+}
+
+/// Imports records into storage, remembering where each one's payload went, by record id and by payload digest, so
+/// that revisits of them can find it. A key would lead to whatever was captured at the url last instead.
+struct Importer {
+    rt: Runtime,
+    storage: Storage,
+    by_id: HashMap<String, Payload>,
+    by_digest: HashMap<String, Payload>,
+    stats: ImportStats,
+}
+
+pub(crate) fn import(args: ImportArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let mut importer = Importer::open(&args.output)?;
+
+    for path in &args.files {
+        let name = path.to_string_lossy();
+        info!("importing {name}");
+
+        if name.ends_with(".wacz") {
+            let mut package = ZipArchive::new(BufReader::new(File::open(path)?))?;
+            for idx in 0..package.len() {
+                let file = package.by_index(idx)?;
+                let name = file.name().to_owned();
+                if name.starts_with("archive/") && is_warc(&name) {
+                    debug!("importing {name}");
+                    importer.import_warc(open_warc(&name, file)?)?;
+                }
+            }
+        } else {
+            importer.import_warc(open_warc(&name, File::open(path)?)?)?;
+        }
+    }
+
+    info!(
+        "imported {} records, skipping {} that weren't responses, revisits or resources",
+        importer.stats.imported, importer.stats.skipped
+    );
+
+    Ok(())
+}
+
+impl Importer {
+    fn open(output: &Path) -> Result<Importer, Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        let storage = Storage::new(output, false)?;
+
+        // a new archive folder gets a crawl of its own, so that it can be exported like any other
+        let storage_config = match storage.read_info_sync() {
+            Ok(info) => info.storage_config()?,
+            Err(EvergardenError::Cache(_)) => {
+                rt.block_on(storage.write_info(&CrawlInfo {
+                    id: Uuid::new_v4(),
+                    config: serde_json::json!({ "storage": StorageConfig::default() }).to_string(),
+                    entry_points: Vec::new(),
+                    seeds: Vec::new(),
+                }))?;
+                StorageConfig::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if matches!(storage_config.backend, StorageBackend::Warc) {
+            return Err(
+                "this crawl writes its responses to WARC files, and can't store more".into(),
+            );
+        }
+
+        Ok(Importer {
+            rt,
+            storage: storage.with_config(storage_config)?,
+            by_id: HashMap::new(),
+            by_digest: HashMap::new(),
+            stats: ImportStats::default(),
+        })
+    }
+
+    fn import_warc(&mut self, reader: WarcReader<impl BufRead>) -> Result<(), Box<dyn Error>> {
+        for record in reader {
+            let record = record?;
+
+            let response = match record.header("WARC-Type") {
+                Some("response") => response(&record),
+                Some("revisit") => self.revisit(&record)?,
+                Some("resource") => resource(&record),
+                _ => None,
+            };
+
+            let Some(response) = response else {
+                self.stats.skipped += 1;
+                continue;
+            };
+
+            let key = match response.meta.resource {
+                Some(_) => response.meta.url.url.to_string(),
+                None => self.storage.surt(response.meta.url.key_url()),
+            };
+
+            self.rt.block_on(self.storage.write_res(response))?;
+            self.stats.imported += 1;
+
+            let Some((hash, meta)) = self.storage.read_entry_sync(&key)? else {
+                continue;
+            };
+            let payload = Payload {
+                hash,
+                compression: meta.compression,
+            };
+            if let Some(id) = record.uri_header("WARC-Record-ID") {
+                self.by_id.insert(id.to_owned(), payload.clone());
+            }
+            if let Some(digest) = record.header("WARC-Payload-Digest") {
+                self.by_digest.insert(digest.to_owned(), payload);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A revisit's payload is that of the capture it refers to, by payload digest or record id, if that was imported.
+    fn revisit(&self, record: &WarcRecord) -> Result<Option<HttpResponse>, Box<dyn Error>> {
+        let Some((meta, _)) = http_response(record) else {
+            return Ok(None);
+        };
+
+        let original = record
+            .header("WARC-Payload-Digest")
+            .and_then(|digest| self.by_digest.get(digest))
+            .or_else(|| self.by_id.get(record.uri_header("WARC-Refers-To")?));
+        let Some(Payload { hash, compression }) = original.cloned() else {
+            warn!(url = %meta.url.url, "skipping a revisit of a capture that wasn't imported");
+            return Ok(None);
+        };
+
+        let Some(mut reader) = self.storage.read_body_sync(hash, compression)? else {
+            warn!(url = %meta.url.url, "skipping a revisit of a capture whose body is gone");
+            return Ok(None);
+        };
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+
+        Ok(Some(HttpResponse::from_bytes(meta, body.into())))
+    }
+}
+
+/// The metadata a record's headers say about the capture it's part of, with the response parts left for the caller.
+fn record_meta(record: &WarcRecord) -> Option<ResponseMetadata> {
+    let url = Url::parse(record.uri_header("WARC-Target-URI")?).ok()?;
+    let fetched_at = OffsetDateTime::parse(record.header("WARC-Date")?, &Rfc3339).ok()?;
+    let id = record
+        .uri_header("WARC-Record-ID")
+        .and_then(|id| id.strip_prefix("urn:uuid:"))
+        .and_then(|id| Uuid::parse_str(id).ok())
+        .unwrap_or_else(Uuid::new_v4);
+    // evergarden writes the port along with the address
+    let remote_addr = record.header("WARC-IP-Address").and_then(|addr| {
+        addr.parse::<SocketAddr>().ok().or_else(|| {
+            let ip = addr.parse::<IpAddr>().ok()?;
+            Some(SocketAddr::new(
+                ip,
+                url.port_or_known_default().unwrap_or(0),
+            ))
+        })
+    });
+    let truncated = record.header("WARC-Truncated").map(|reason| match reason {
+        "length" => TruncatedReason::Length,
+        "time" => TruncatedReason::Time,
+        "disconnect" => TruncatedReason::Disconnect,
+        _ => TruncatedReason::Unspecified,
+    });
+
+    Some(ResponseMetadata {
+        url: UrlInfo::seed(url),
+        status: StatusCode::OK,
+        version: Version::HTTP_11,
+        headers: HeaderMap::new(),
+        remote_addr,
+        fetched_at,
+        id,
+        truncated,
+        tls: None,
+        request: None,
+        payload_digest: None,
+        canonical: None,
+        revisit: None,
+        robots: None,
+        resource: None,
+        annotations: None,
+        compression: None,
+        size: None,
+    })
+}
+
+/// The http response in a `response` or `revisit` record, and where its body starts in the block.
+fn http_response(record: &WarcRecord) -> Option<(ResponseMetadata, usize)> {
+    let mut meta = record_meta(record)?;
+    let block = &record.block;
+
+    let status_line_end = block.iter().position(|&b| b == b'\n')? + 1;
+    let status_line = String::from_utf8_lossy(&block[..status_line_end]);
+    let mut status_line = status_line.split_ascii_whitespace();
+
+    meta.version = match status_line.next()? {
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/2" | "HTTP/2.0" => Version::HTTP_2,
+        "HTTP/3" | "HTTP/3.0" => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    };
+    meta.status = StatusCode::from_bytes(status_line.next()?.as_bytes()).ok()?;
+
+    let mut headers = [httparse::EMPTY_HEADER; 256];
+    let httparse::Status::Complete((headers_len, headers)) =
+        httparse::parse_headers(&block[status_line_end..], &mut headers).ok()?
+    else {
+        return None;
+    };
+
+    for header in headers {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(header.name.as_bytes()),
+            HeaderValue::from_bytes(header.value),
+        ) else {
+            continue;
+        };
+        meta.headers.append(name, value);
+    }
+
+    Some((meta, status_line_end + headers_len))
+}
+
+fn response(record: &WarcRecord) -> Option<HttpResponse> {
+    let (meta, body_start) = http_response(record)?;
+    let body = &record.block[body_start..];
+
+    // bodies are stored as they were received after undoing the transfer encoding, which WARC files keep
+    let chunked = meta
+        .headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    let body = match chunked.then(|| dechunk(body)).flatten() {
+        Some(body) => Bytes::from(body),
+        None => Bytes::copy_from_slice(body),
+    };
+
+    Some(HttpResponse::from_bytes(meta, body))
+}
+
+fn resource(record: &WarcRecord) -> Option<HttpResponse> {
+    let mut meta = record_meta(record)?;
+    let content_type = record
+        .header("Content-Type")
+        .unwrap_or("application/octet-stream");
+
+    if let Ok(value) = HeaderValue::from_str(content_type) {
+        meta.headers.insert(http::header::CONTENT_TYPE, value);
+    }
+    meta.resource = Some(ResourceInfo {
+        content_type: content_type.to_owned(),
+        concurrent_to: record
+            .uri_header("WARC-Concurrent-To")
+            .and_then(|id| id.strip_prefix("urn:uuid:"))
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .unwrap_or_else(Uuid::nil),
+    });
+
+    Some(HttpResponse::from_bytes(
+        meta,
+        Bytes::copy_from_slice(&record.block),
+    ))
+}
+
+/// Undoes chunked transfer encoding, or `None` if `body` isn't validly chunked, as with bodies some tools (evergarden
+/// included) write already dechunked.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len());
+
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+
+        if size == 0 {
+            return Some(out);
+        }
+
+        out.extend_from_slice(body.get(..size)?);
+        body = body.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header::CONTENT_TYPE, StatusCode, Version};
+    use url::Url;
+
+    use super::{dechunk, http_response, reader::WarcReader, Importer};
+
+    fn warc_record(headers: &[(&str, &str)], block: &[u8]) -> Vec<u8> {
+        let mut record = b"WARC/1.1\r\n".to_vec();
+        for (name, value) in headers {
+            record.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+        }
+        record.extend_from_slice(format!("Content-Length: {}\r\n\r\n", block.len()).as_bytes());
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+        record
+    }
+
+    fn response_record(id: &str, url: &str, date: &str, digest: &str, body: &str) -> Vec<u8> {
+        warc_record(
+            &[
+                ("WARC-Type", "response"),
+                ("WARC-Record-ID", &format!("<urn:uuid:{id}>")),
+                ("WARC-Target-URI", url),
+                ("WARC-Date", date),
+                ("WARC-Payload-Digest", digest),
+            ],
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+    }
+
+    #[test]
+    fn http_response_parses_the_status_and_headers() {
+        let warc = response_record(
+            "c4c5b7cc-6f2b-4ee4-9b9f-6b2c0b3c3f01",
+            "https://example.com/",
+            "2023-07-01T12:00:00Z",
+            "sha1:AAAA",
+            "hello",
+        );
+        let record = WarcReader::new(&warc[..]).next().unwrap().unwrap();
+
+        let (meta, body_start) = http_response(&record).unwrap();
+        assert_eq!(meta.status, StatusCode::OK);
+        assert_eq!(meta.version, Version::HTTP_11);
+        assert_eq!(meta.headers[CONTENT_TYPE], "text/plain");
+        assert_eq!(meta.url.url.as_str(), "https://example.com/");
+        assert_eq!(meta.id.to_string(), "c4c5b7cc-6f2b-4ee4-9b9f-6b2c0b3c3f01");
+        assert_eq!(&record.block[body_start..], b"hello");
+    }
+
+    #[test]
+    fn dechunks_only_chunked_bodies() {
+        assert_eq!(
+            dechunk(b"5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n").as_deref(),
+            Some(&b"hello, world"[..])
+        );
+        assert_eq!(dechunk(b"hello, world"), None);
+        assert_eq!(dechunk(b"ff\r\nshort\r\n0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn revisits_get_the_payload_they_refer_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut importer = Importer::open(dir.path()).unwrap();
+
+        let first = "3f5a1f0e-1b1c-4c8e-8f5e-0d6a1b2c3d01";
+        let mut warc = response_record(
+            first,
+            "https://example.com/page",
+            "2023-07-01T12:00:00Z",
+            "sha1:FIRST",
+            "first",
+        );
+        // the url is captured again, with a different body, before the revisit of the first capture
+        warc.extend(response_record(
+            "3f5a1f0e-1b1c-4c8e-8f5e-0d6a1b2c3d02",
+            "https://example.com/page",
+            "2023-07-02T12:00:00Z",
+            "sha1:SECOND",
+            "second",
+        ));
+        warc.extend(warc_record(
+            &[
+                ("WARC-Type", "revisit"),
+                (
+                    "WARC-Record-ID",
+                    "<urn:uuid:3f5a1f0e-1b1c-4c8e-8f5e-0d6a1b2c3d03>",
+                ),
+                ("WARC-Target-URI", "https://example.com/copy"),
+                ("WARC-Date", "2023-07-03T12:00:00Z"),
+                ("WARC-Payload-Digest", "sha1:FIRST"),
+                ("WARC-Refers-To", &format!("<urn:uuid:{first}>")),
+                ("WARC-Refers-To-Target-URI", "https://example.com/page"),
+            ],
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n",
+        ));
+
+        importer.import_warc(WarcReader::new(&warc[..])).unwrap();
+        assert_eq!(importer.stats.imported, 3);
+
+        let body = |url: &str| {
+            importer.rt.block_on(async {
+                let res = importer
+                    .storage
+                    .retrieve_by_url(Url::parse(url).unwrap())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                res.decoded_body().await.unwrap()
+            })
+        };
+        assert_eq!(body("https://example.com/copy"), "first");
+        assert_eq!(body("https://example.com/page"), "second");
+    }
+}
@@ -0,0 +1,160 @@
+//! Reading records out of WARC files, as written by evergarden or other tools.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use flate2::read::MultiGzDecoder;
+
+/// A WARC record's headers and block.
+pub(crate) struct WarcRecord {
+    headers: Vec<(String, String)>,
+    pub(crate) block: Vec<u8>,
+}
+
+impl WarcRecord {
+    /// The value of the header `name`, which is case-insensitive.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A header holding a uri, which can be in angle brackets.
+    pub(crate) fn uri_header(&self, name: &str) -> Option<&str> {
+        self.header(name)
+            .map(|value| value.trim_start_matches('<').trim_end_matches('>'))
+    }
+}
+
+/// Reads the records of a decompressed WARC file, one at a time.
+pub(crate) struct WarcReader<R: BufRead> {
+    inner: R,
+}
+
+impl<R: BufRead> WarcReader<R> {
+    pub(crate) fn new(inner: R) -> WarcReader<R> {
+        WarcReader { inner }
+    }
+
+    pub(crate) fn next_record(&mut self) -> io::Result<Option<WarcRecord>> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        // records are separated by blank lines
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.inner.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim_ascii().is_empty() {
+                break;
+            }
+        }
+
+        if !line.starts_with(b"WARC/") {
+            return Err(invalid("expected a WARC record"));
+        }
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        loop {
+            line.clear();
+            if self.inner.read_until(b'\n', &mut line)? == 0 {
+                return Err(invalid("WARC record headers end early"));
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            if text.trim().is_empty() {
+                break;
+            }
+
+            // a line starting with whitespace continues the header before it
+            match (text.starts_with([' ', '\t']), headers.last_mut()) {
+                (true, Some((_, value))) => {
+                    value.push(' ');
+                    value.push_str(text.trim());
+                }
+                _ => {
+                    let (name, value) = text
+                        .split_once(':')
+                        .ok_or_else(|| invalid("malformed WARC header"))?;
+                    headers.push((name.trim().to_owned(), value.trim().to_owned()));
+                }
+            }
+        }
+
+        let mut record = WarcRecord {
+            headers,
+            block: Vec::new(),
+        };
+
+        let length = record
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .ok_or_else(|| invalid("WARC record without a Content-Length"))?;
+        (&mut self.inner)
+            .take(length)
+            .read_to_end(&mut record.block)?;
+        if record.block.len() as u64 != length {
+            return Err(invalid("WARC record block ends early"));
+        }
+
+        Ok(Some(record))
+    }
+}
+
+impl<R: BufRead> Iterator for WarcReader<R> {
+    type Item = io::Result<WarcRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Whether `name` is that of a WARC file [`open_warc`] can read.
+pub(crate) fn is_warc(name: &str) -> bool {
+    [".warc", ".warc.gz", ".warc.zst"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+/// Reads the WARC file `name` from `reader`, decompressing it as its extension says.
+pub(crate) fn open_warc<'a>(
+    name: &str,
+    reader: impl Read + 'a,
+) -> io::Result<WarcReader<Box<dyn BufRead + 'a>>> {
+    let reader: Box<dyn BufRead + 'a> = if name.ends_with(".warc.gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else if name.ends_with(".warc.zst") {
+        Box::new(BufReader::new(zstd_decoder(BufReader::new(reader))?))
+    } else if name.ends_with(".warc") {
+        Box::new(BufReader::new(reader))
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} isn't a .warc, .warc.gz or .warc.zst file"),
+        ));
+    };
+
+    Ok(WarcReader::new(reader))
+}
+
+// zstd WARC files can start with the dictionary their records are compressed with, in a skippable frame
+fn zstd_decoder<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5D;
+
+    let start = reader.fill_buf()?;
+    if start.len() >= 8 && start[..4] == SKIPPABLE_FRAME_MAGIC.to_le_bytes() {
+        let length = u32::from_le_bytes(start[4..8].try_into().unwrap()) as usize;
+        reader.consume(8);
+
+        let mut dictionary = vec![0; length];
+        reader.read_exact(&mut dictionary)?;
+
+        return Ok(Box::new(zstd::Decoder::with_dictionary(
+            reader,
+            &dictionary,
+        )?));
+    }
+
+    Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+}
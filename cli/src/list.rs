@@ -0,0 +1,63 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use clap::builder::TypedValueParser;
+use evergarden_common::{ListingFormat, ListingSort, Storage};
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::filter::FilterArgs;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ListArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(short, long, help = "file to write the listing to, instead of stdout")]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value = "jsonl",
+        value_parser = clap::builder::PossibleValuesParser::new(["jsonl", "csv"])
+            .map(|s| s.parse::<ListingFormat>().unwrap()),
+    )]
+    format: ListingFormat,
+    #[arg(
+        long,
+        default_value = "key",
+        help = "What to sort records by. Sizes sort largest first, and times earliest first",
+        value_parser = clap::builder::PossibleValuesParser::new(["key", "url", "status", "size", "time"])
+            .map(|s| s.parse::<ListingSort>().unwrap()),
+    )]
+    sort: ListingSort,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+pub(crate) fn list(args: ListArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    // the listing itself may go to stdout
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    let storage = Storage::new(&args.input, false)?;
+
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let count = storage.write_listing(
+        args.filter.into(),
+        args.format,
+        args.sort,
+        BufWriter::new(out),
+    )?;
+    info!("listed {count} records");
+
+    Ok(())
+}
@@ -1,11 +1,23 @@
-use std::error::Error;
+use std::{error::Error, process::ExitCode};
 
 use clap::builder::TypedValueParser;
 use clap::{Parser, Subcommand};
 use tracing::metadata::LevelFilter;
 
 mod archiver;
+mod cat;
+mod compact;
+mod config;
+mod delete;
 mod export;
+mod filter;
+mod grep;
+mod import;
+mod list;
+mod prune;
+mod serve;
+mod stats;
+mod verify;
 
 #[derive(clap::Parser, Debug)]
 #[command(author = "Kore Signet-Yang <kore@cat-girl.gay>")]
@@ -24,11 +36,44 @@ struct Args {
 
 #[derive(Subcommand, Debug)]
 enum EvergardenSubcommand {
+    /// Exports an archive folder as a WACZ package, or as WARC files and indexes in a directory.
     Export(export::run::ExportArgs),
     Archive(archiver::ArchiverArgs),
+    /// Removes records from an archive folder, and the bodies no longer referred to.
+    Prune(prune::PruneArgs),
+    /// Lists the records in an archive folder, as JSON Lines or CSV.
+    List(list::ListArgs),
+    /// Deletes records from an archive folder by key, like captures of private or off-scope pages.
+    Delete(delete::DeleteArgs),
+    /// Rewrites an archive folder without the content nothing refers to anymore, optionally recompressing it.
+    Compact(compact::CompactArgs),
+    /// Imports the responses in WARC or WACZ files into an archive folder, to dedupe, merge or re-export them.
+    Import(import::ImportArgs),
+    /// Merges archive folders and WACZ files, like those of parallel crawls of one site, into one WACZ package. Captures
+    /// in more than one of them are indexed once.
+    Merge(export::merge::MergeArgs),
+    /// Answers CDX queries about an archive folder's captures over HTTP, like pywb's CDX server, for wayback machines to
+    /// use it as an index.
+    Serve(serve::ServeArgs),
+    /// Checks that every record in an archive folder can be read, and that its body is there and matches its size and
+    /// digest. Damaged records are listed on stdout, and can be deleted or moved out to a quarantine directory.
+    ///
+    /// Exits with 0 if every record is intact, 1 on errors, 2 if damaged records were found and left in place, and 3 if
+    /// they were deleted or quarantined.
+    Verify(verify::VerifyArgs),
+    /// Summarizes what an archive folder holds: totals, the hosts and content types it spent the most on, statuses, the
+    /// largest records, and when it was fetched.
+    Stats(stats::StatsArgs),
+    /// Writes a stored record's body to stdout, optionally with its headers, to look at a capture without exporting it.
+    Cat(cat::CatArgs),
+    /// Checks crawl configs, or writes a starter one.
+    Config(config::ConfigArgs),
+    /// Searches the bodies in an archive folder for a regex, printing the url of each record it's found in with the
+    /// text around it.
+    Grep(grep::GrepArgs),
 }
 
-pub fn main() -> Result<(), Box<dyn Error>> {
+pub fn main() -> Result<ExitCode, Box<dyn Error>> {
     let args = Args::parse();
 
     match args.subcommand {
@@ -40,5 +85,23 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 
             rt.block_on(archiver::run_archiver(archiver_args, args.log_level))
         }
-    }
+        EvergardenSubcommand::Prune(prune_args) => prune::prune(prune_args, args.log_level),
+        EvergardenSubcommand::List(list_args) => list::list(list_args, args.log_level),
+        EvergardenSubcommand::Delete(delete_args) => delete::delete(delete_args, args.log_level),
+        EvergardenSubcommand::Compact(compact_args) => {
+            compact::compact(compact_args, args.log_level)
+        }
+        EvergardenSubcommand::Import(import_args) => import::import(import_args, args.log_level),
+        EvergardenSubcommand::Merge(merge_args) => export::merge::merge(merge_args, args.log_level),
+        EvergardenSubcommand::Serve(serve_args) => serve::serve(serve_args, args.log_level),
+        EvergardenSubcommand::Config(config_args) => config::config(config_args, args.log_level),
+        EvergardenSubcommand::Cat(cat_args) => cat::cat(cat_args, args.log_level),
+        EvergardenSubcommand::Stats(stats_args) => stats::stats(stats_args, args.log_level),
+        EvergardenSubcommand::Grep(grep_args) => grep::grep(grep_args, args.log_level),
+        EvergardenSubcommand::Verify(verify_args) => {
+            return verify::verify(verify_args, args.log_level)
+        }
+    }?;
+
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,55 @@
+use std::{error::Error, path::PathBuf, time::Duration};
+
+use evergarden_common::{PruneFilter, Storage};
+use regex::Regex;
+use time::OffsetDateTime;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use ubyte::ToByteUnit;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct PruneArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(
+        long,
+        help = "Remove records fetched longer ago than this, like `30days`",
+        value_parser = humantime::parse_duration
+    )]
+    older_than: Option<Duration>,
+    #[arg(long, help = "Remove records whose host matches this regex")]
+    host: Option<Regex>,
+    #[arg(long, help = "Remove records with a 4xx or 5xx status")]
+    failed: bool,
+    #[arg(long, help = "Remove records whose SURT key matches this regex")]
+    key: Option<Regex>,
+}
+
+pub(crate) fn prune(args: PruneArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let (storage, _) = Storage::open_crawl(&args.input)?;
+
+    let filter = PruneFilter {
+        fetched_before: args.older_than.map(|age| OffsetDateTime::now_utc() - age),
+        host: args.host,
+        failed: args.failed,
+        key: args.key,
+    };
+
+    // without any predicates, only the bodies nothing refers to anymore are removed
+    let stats = if filter.is_empty() {
+        storage.gc()?
+    } else {
+        storage.prune(&filter)?
+    };
+
+    info!(
+        "removed {} records and {} unreferenced bodies, freeing {}",
+        stats.entries,
+        stats.bodies,
+        stats.bytes.bytes()
+    );
+
+    Ok(())
+}
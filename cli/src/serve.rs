@@ -0,0 +1,326 @@
+//! A CDX query API over an archive folder's index, like pywb's and OutbackCDX's, for wayback machines to look captures
+//! up in.
+
+use std::{
+    convert::Infallible, error::Error, net::SocketAddr, ops::Range, path::PathBuf, str::FromStr,
+};
+
+use evergarden_common::{EvergardenResult, Filter, Storage};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use time::{Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use url::Url;
+
+use crate::export::cdxj::{CDXStyleRecord, TIME_FMT};
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ServeArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(long, default_value = "127.0.0.1:8080", help = "Address to listen on")]
+    bind: SocketAddr,
+}
+
+pub(crate) fn serve(args: ServeArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+
+    let (storage, _) = Storage::open_crawl(&args.input)?;
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async move {
+        let make_service = make_service_fn(move |_| {
+            let storage = storage.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| answer(storage.clone(), req))) }
+        });
+
+        let server = Server::try_bind(&args.bind)?.serve(make_service);
+        info!("answering CDX queries at http://{}/cdx", args.bind);
+
+        server
+            .with_graceful_shutdown(async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await
+    })?;
+
+    Ok(())
+}
+
+async fn answer(storage: Storage, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET || req.uri().path() != "/cdx" {
+        return Ok(plain(
+            StatusCode::NOT_FOUND,
+            String::from("queries go to /cdx?url=<url>"),
+        ));
+    }
+
+    let query = match CdxQuery::parse(&storage, req.uri().query().unwrap_or_default()) {
+        Ok(query) => query,
+        Err(e) => return Ok(plain(StatusCode::BAD_REQUEST, e)),
+    };
+
+    let output = query.output;
+    match tokio::task::spawn_blocking(move || query.run(&storage)).await {
+        Ok(Ok(lines)) => Ok(Response::builder()
+            .header(CONTENT_TYPE, output.content_type())
+            .body(Body::from(lines))
+            .unwrap()),
+        Ok(Err(e)) => {
+            warn!("failed to answer a CDX query: {e}");
+            Ok(plain(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+        Err(e) => Ok(plain(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+fn plain(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Body::from(message))
+        .unwrap()
+}
+
+/// Which captures a query's url picks out.
+#[derive(Clone, Copy, Debug)]
+enum MatchType {
+    Exact,
+    /// Everything whose url starts with it.
+    Prefix,
+    /// Everything on its host.
+    Host,
+    /// Everything on its host and the hosts under it.
+    Domain,
+}
+
+impl FromStr for MatchType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(MatchType::Exact),
+            "prefix" => Ok(MatchType::Prefix),
+            "host" => Ok(MatchType::Host),
+            "domain" => Ok(MatchType::Domain),
+            other => Err(format!(
+                "unknown matchType {other}, expected exact, prefix, host or domain"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Output {
+    /// `<key> <timestamp> <json>` lines, like a CDXJ index.
+    Cdxj,
+    /// A JSON object per line, with the key and timestamp as `urlkey` and `timestamp`.
+    Json,
+}
+
+impl Output {
+    fn content_type(self) -> &'static str {
+        match self {
+            Output::Cdxj => "text/x-cdxj",
+            Output::Json => "application/x-ndjson",
+        }
+    }
+}
+
+/// What's known of a capture without its WARC record, since stored responses aren't in WARC files.
+#[derive(Serialize)]
+struct StoredBlock {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    urlkey: &'a str,
+    timestamp: String,
+    #[serde(flatten)]
+    block: &'a StoredBlock,
+}
+
+#[derive(Debug)]
+struct CdxQuery {
+    filter: Filter,
+    limit: Option<usize>,
+    output: Output,
+}
+
+impl CdxQuery {
+    /// Parses a query string the way pywb's CDX server takes it: `url`, with `matchType`, `from`, `to`, `limit` and
+    /// `output`. Like there, `example.com/path*` is a prefix query and `*.example.com` a domain one.
+    /// Urls are looked up under the keys `storage` stores them under.
+    fn parse(storage: &Storage, query: &str) -> Result<CdxQuery, String> {
+        let mut url = None;
+        let mut match_type = None;
+        let mut filter = Filter::default();
+        let mut limit = None;
+        let mut output = Output::Cdxj;
+
+        for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            match &*name {
+                "url" => url = Some(value.into_owned()),
+                "matchType" => match_type = Some(value.parse::<MatchType>()?),
+                "from" => {
+                    filter.since = Some(
+                        timestamp_span(&value)
+                            .ok_or_else(|| format!("not a timestamp: {value}"))?
+                            .start,
+                    );
+                }
+                "to" => {
+                    filter.until = Some(
+                        timestamp_span(&value)
+                            .ok_or_else(|| format!("not a timestamp: {value}"))?
+                            .end,
+                    );
+                }
+                "limit" => {
+                    limit = Some(
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| format!("not a limit: {value}"))?,
+                    );
+                }
+                "output" => {
+                    output = match &*value {
+                        "json" => Output::Json,
+                        "cdxj" => Output::Cdxj,
+                        other => {
+                            return Err(format!("unknown output {other}, expected cdxj or json"))
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        let url = url.ok_or("a url is needed")?;
+        let (url, match_type) = match (url.strip_prefix("*."), url.strip_suffix('*')) {
+            (Some(domain), _) => (domain, match_type.unwrap_or(MatchType::Domain)),
+            (None, Some(prefix)) => (prefix, match_type.unwrap_or(MatchType::Prefix)),
+            (None, None) => (url.as_str(), match_type.unwrap_or(MatchType::Exact)),
+        };
+
+        // urls are looked up without a scheme as often as with one
+        let url = if url.contains("://") {
+            Url::parse(url)
+        } else {
+            Url::parse(&format!("http://{url}"))
+        }
+        .map_err(|e| format!("not a url: {url} ({e})"))?;
+
+        let key = storage.surt(url);
+        let host = key.split_once(')').map_or(key.as_str(), |(host, _)| host);
+        match match_type {
+            MatchType::Exact => filter.keys = vec![key],
+            MatchType::Prefix => filter.key_prefixes = vec![key],
+            MatchType::Host => filter.key_prefixes = vec![format!("{host})")],
+            MatchType::Domain => {
+                // subdomains come after a comma in SURTs, and ports after a colon
+                let domain = host.split_once(':').map_or(host, |(domain, _)| domain);
+                filter.key_prefixes = vec![
+                    format!("{domain})"),
+                    format!("{domain},"),
+                    format!("{domain}:"),
+                ];
+            }
+        }
+
+        Ok(CdxQuery {
+            filter,
+            limit,
+            output,
+        })
+    }
+
+    /// The lines answering the query, sorted by key.
+    fn run(self, storage: &Storage) -> EvergardenResult<Vec<u8>> {
+        let mut out = Vec::new();
+
+        for record in storage
+            .query(self.filter)
+            .take(self.limit.unwrap_or(usize::MAX))
+        {
+            let (key, _, meta) = record?;
+            let record = CDXStyleRecord {
+                block: StoredBlock {
+                    url: meta.url.url.to_string(),
+                    // like in exported indexes, where revisits are warc/revisit records
+                    mime: match &meta.revisit {
+                        Some(_) => Some(String::from("warc/revisit")),
+                        None => meta.mime(),
+                    },
+                    status: meta.status.as_u16(),
+                    digest: meta.payload_digest,
+                },
+                key,
+                time: meta.fetched_at,
+            };
+
+            match self.output {
+                Output::Cdxj => out.extend_from_slice(&record.to_line()),
+                Output::Json => serde_json::to_writer(
+                    &mut out,
+                    &JsonLine {
+                        urlkey: &record.key,
+                        timestamp: record.time.format(TIME_FMT).unwrap(),
+                        block: &record.block,
+                    },
+                )?,
+            }
+            out.push(b'\n');
+        }
+
+        Ok(out)
+    }
+}
+
+/// The stretch of time a CDX timestamp like `2023`, `202308` or `20230801120000` covers: from its start to the start of
+/// the next year, month, or whatever else it stops at.
+fn timestamp_span(value: &str) -> Option<Range<OffsetDateTime>> {
+    if !value.bytes().all(|b| b.is_ascii_digit())
+        || !matches!(value.len(), 4 | 6 | 8 | 10 | 12 | 14)
+    {
+        return None;
+    }
+
+    let field = |range: Range<usize>, default: u8| {
+        value
+            .get(range)
+            .map_or(Some(default), |field| field.parse().ok())
+    };
+    let year = value[..4].parse::<i32>().ok()?;
+    let month = Month::try_from(field(4..6, 1)?).ok()?;
+    let date = Date::from_calendar_date(year, month, field(6..8, 1)?).ok()?;
+    let time = Time::from_hms(field(8..10, 0)?, field(10..12, 0)?, field(12..14, 0)?).ok()?;
+
+    let start = PrimitiveDateTime::new(date, time).assume_utc();
+    let end = match value.len() {
+        4 => start.replace_year(year + 1).ok()?,
+        6 if month == Month::December => start
+            .replace_year(year + 1)
+            .ok()?
+            .replace_month(Month::January)
+            .ok()?,
+        6 => start.replace_month(month.next()).ok()?,
+        8 => start + Duration::DAY,
+        10 => start + Duration::HOUR,
+        12 => start + Duration::MINUTE,
+        _ => start + Duration::SECOND,
+    };
+
+    Some(start..end)
+}
@@ -0,0 +1,100 @@
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use evergarden_common::{Breakdown, CrawlStats, Storage};
+use time::format_description::well_known::Rfc3339;
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::filter::FilterArgs;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct StatsArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(long, help = "Print the statistics as JSON, instead of as tables")]
+    json: bool,
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "How many hosts, content types and largest records to list"
+    )]
+    top: usize,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+pub(crate) fn stats(args: StatsArgs, log_level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    let storage = Storage::new(&args.input, false)?;
+    let mut stats = storage.stats(args.filter.into(), args.top)?;
+    stats.hosts.truncate(args.top);
+    stats.mimes.truncate(args.top);
+
+    let mut out = io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &stats)?;
+        writeln!(out)?;
+    } else {
+        write_tables(&mut out, &stats)?;
+    }
+
+    Ok(())
+}
+
+fn write_tables(out: &mut impl Write, stats: &CrawlStats) -> io::Result<()> {
+    let time = |time: Option<time::OffsetDateTime>| {
+        time.and_then(|time| time.format(&Rfc3339).ok())
+            .unwrap_or_else(|| String::from("-"))
+    };
+
+    writeln!(out, "{:>14}  records", stats.records)?;
+    writeln!(out, "{:>14}  bytes", stats.bytes)?;
+    writeln!(out, "{:>14}  revisits", stats.revisits)?;
+    writeln!(out, "{:>14}  resources", stats.resources)?;
+    writeln!(out, "{:>14}  truncated", stats.truncated)?;
+    writeln!(out, "first fetched   {}", time(stats.first_fetched))?;
+    writeln!(out, "last fetched    {}", time(stats.last_fetched))?;
+
+    write_breakdown(out, "host", &stats.hosts)?;
+    write_breakdown(out, "content type", &stats.mimes)?;
+
+    writeln!(out, "\n{:>14}  status", "records")?;
+    for (status, records) in &stats.statuses {
+        writeln!(out, "{records:>14}  {status}")?;
+    }
+
+    writeln!(out, "\n{:>14}  {:>14}  largest", "bytes", "status")?;
+    for record in &stats.largest {
+        writeln!(
+            out,
+            "{:>14}  {:>14}  {}",
+            record.size.unwrap_or_default(),
+            record.status,
+            record.url
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_breakdown(out: &mut impl Write, heading: &str, breakdown: &[Breakdown]) -> io::Result<()> {
+    writeln!(out, "\n{:>14}  {:>14}  {heading}", "records", "bytes")?;
+    for row in breakdown {
+        writeln!(
+            out,
+            "{:>14}  {:>14}  {}",
+            row.records,
+            row.bytes,
+            row.name.as_deref().unwrap_or("-")
+        )?;
+    }
+
+    Ok(())
+}
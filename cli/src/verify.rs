@@ -0,0 +1,164 @@
+//! Checking an archive folder's records against their bodies, for disks and buckets that lose or mangle data.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use evergarden_common::{Damage, IndexEntry, KeyPattern, Storage, StorageBackend};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use ubyte::ToByteUnit;
+
+use crate::filter::FilterArgs;
+
+/// Every record is intact.
+const INTACT: u8 = 0;
+/// Damaged records were found, and left where they are.
+const DAMAGED: u8 = 2;
+/// Damaged records were found, and deleted or quarantined.
+const REMOVED: u8 = 3;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct VerifyArgs {
+    #[arg(short, long, help = "export folder for `evergarden archive`")]
+    input: PathBuf,
+    #[arg(
+        long,
+        conflicts_with = "quarantine",
+        help = "Delete damaged records, and the bodies no longer referred to"
+    )]
+    delete: bool,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Move damaged records out to this directory: what's known of them to `damaged.jsonl`, and their bodies, as stored, to `bodies/`"
+    )]
+    quarantine: Option<PathBuf>,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+/// A line of a quarantine's `damaged.jsonl`.
+#[derive(Serialize)]
+struct QuarantinedRecord<'a> {
+    key: &'a str,
+    url: &'a str,
+    integrity: String,
+    problem: String,
+}
+
+pub(crate) fn verify(args: VerifyArgs, log_level: LevelFilter) -> Result<ExitCode, Box<dyn Error>> {
+    // the damaged records are listed on stdout
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(io::stderr)
+        .init();
+
+    let (storage, storage_config) = Storage::open_crawl(&args.input)?;
+    if matches!(storage_config.backend, StorageBackend::Warc) {
+        return Err(
+            "this crawl's responses were written to WARC files, and there are no bodies to verify"
+                .into(),
+        );
+    }
+
+    let filter = args.filter.into();
+    let bar = ProgressBar::new(storage.count(&filter)? as u64).with_style(
+        ProgressStyle::with_template("{bar:40.cyan/blue} {pos:>7}/{len:7} records verified")
+            .unwrap()
+            .progress_chars("##-"),
+    );
+
+    let mut damaged = Vec::new();
+    let mut stdout = io::stdout().lock();
+    for record in storage.verify(filter) {
+        bar.inc(1);
+
+        if let (entry, Some(damage)) = record? {
+            bar.suspend(|| writeln!(stdout, "{}\t{damage}", entry.key))?;
+            damaged.push((entry, damage));
+        }
+    }
+    bar.finish();
+
+    if damaged.is_empty() {
+        info!("every record is intact");
+        return Ok(ExitCode::from(INTACT));
+    }
+
+    if let Some(dir) = &args.quarantine {
+        quarantine(&storage, dir, &damaged)?;
+    } else if !args.delete {
+        info!("found {} damaged records", damaged.len());
+        return Ok(ExitCode::from(DAMAGED));
+    }
+
+    let keys = damaged
+        .iter()
+        .map(|(entry, _)| entry.key.clone())
+        .collect::<HashSet<_>>();
+    let removed = storage.del_by_pattern(&KeyPattern::Keys(keys), false)?;
+    let stats = storage.gc()?;
+
+    info!(
+        "removed {} damaged records and {} unreferenced bodies, freeing {}",
+        removed.len(),
+        stats.bodies,
+        stats.bytes.bytes()
+    );
+
+    Ok(ExitCode::from(REMOVED))
+}
+
+/// Writes what's known of the damaged records to `dir`, along with whatever's left of their bodies, so that they can
+/// be looked into once they're gone from the archive folder.
+fn quarantine(
+    storage: &Storage,
+    dir: &Path,
+    damaged: &[(IndexEntry, Damage)],
+) -> Result<(), Box<dyn Error>> {
+    let bodies = dir.join("bodies");
+    fs::create_dir_all(&bodies)?;
+
+    let mut listing = BufWriter::new(
+        File::options()
+            .create(true)
+            .append(true)
+            .open(dir.join("damaged.jsonl"))?,
+    );
+
+    for (entry, damage) in damaged {
+        let (_, hex) = entry.integrity.to_hex();
+        serde_json::to_writer(
+            &mut listing,
+            &QuarantinedRecord {
+                key: &entry.key,
+                url: entry.url.as_str(),
+                integrity: entry.integrity.to_string(),
+                problem: damage.to_string(),
+            },
+        )?;
+        listing.write_all(b"\n")?;
+
+        // a missing body has nothing to keep, and an unreadable one may only be partly kept
+        if let Ok(Some(mut body)) = storage.read_stored_body_sync(entry.integrity.clone()) {
+            let _ = io::copy(&mut body, &mut File::create(bodies.join(hex))?);
+        }
+    }
+
+    listing.flush()?;
+    info!(
+        "quarantined {} damaged records in {}",
+        damaged.len(),
+        dir.display()
+    );
+
+    Ok(())
+}
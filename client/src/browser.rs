@@ -0,0 +1,564 @@
+use std::{
+    collections::HashMap, future::Future, net::SocketAddr, str::FromStr, sync::Arc, time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use chromiumoxide::{
+    cdp::browser_protocol::{
+        fetch::{
+            self, ContinueRequestParams, EventRequestPaused, FailRequestParams, RequestPattern,
+        },
+        network::{
+            ErrorReason, EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent,
+            EventResponseReceived, GetResponseBodyParams, Headers, RequestId, Response,
+            SetBypassServiceWorkerParams, SetExtraHttpHeadersParams,
+        },
+        page::{EventLoadEventFired, NavigateParams},
+    },
+    page::ScreenshotParams,
+    Browser, Page,
+};
+use evergarden_common::{
+    BodyReadError, EvergardenError, EvergardenResult, HttpResponse, RequestMetadata,
+    ResponseMetadata, UrlInfo,
+};
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use hyper::{
+    header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING},
+    HeaderMap, Method, StatusCode, Version,
+};
+use time::OffsetDateTime;
+use tokio::sync::{Mutex, OnceCell};
+use tracing::{debug, info};
+use url::{Position, Url};
+use uuid::Uuid;
+
+use crate::{config::BrowserConfig, dns::OverrideResolver, proxy::ResolvingProxy};
+
+fn browser_error(e: impl std::fmt::Display) -> EvergardenError {
+    EvergardenError::Browser(e.to_string())
+}
+
+/// Renders pages in a headless Chromium, launched the first time it's needed. Every request a page makes is held
+/// until it's known to go somewhere the address policy allows, and carries the configured headers.
+pub struct BrowserBackend {
+    config: BrowserConfig,
+    resolver: OverrideResolver,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    browser: OnceCell<Mutex<Browser>>,
+    proxy: OnceCell<ResolvingProxy>,
+}
+
+impl std::fmt::Debug for BrowserBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BrowserBackend")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// A response the browser received while loading a page.
+pub struct Capture {
+    pub url: Url,
+    pub status: StatusCode,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub remote_addr: Option<SocketAddr>,
+    pub request: RequestMetadata,
+    pub fetched_at: OffsetDateTime,
+    pub body: Bytes,
+}
+
+impl Capture {
+    pub fn into_response(self, url: UrlInfo) -> HttpResponse {
+        HttpResponse::from_bytes(
+            ResponseMetadata {
+                url,
+                id: Uuid::new_v4(),
+                status: self.status,
+                version: self.version,
+                headers: self.headers,
+                remote_addr: self.remote_addr,
+                tls: None,
+                request: Some(self.request),
+                fetched_at: self.fetched_at,
+                truncated: None,
+                canonical: None,
+                payload_digest: None,
+                revisit: None,
+                robots: None,
+                resource: None,
+                annotations: None,
+                compression: None,
+                size: None,
+            },
+            self.body,
+        )
+    }
+}
+
+/// The page's own document (after any redirects), and everything else it loaded.
+pub struct RenderedPage {
+    pub document: Capture,
+    pub resources: Vec<Capture>,
+    /// A png of the whole page, if enabled.
+    pub screenshot: Option<Bytes>,
+    /// The page's html as it stood once loaded, if enabled.
+    pub dom: Option<Bytes>,
+}
+
+// a request the page made that hasn't finished loading yet
+struct InFlight {
+    request: RequestMetadata,
+    fetched_at: OffsetDateTime,
+    response: Option<Response>,
+}
+
+impl BrowserBackend {
+    pub fn new(
+        config: BrowserConfig,
+        resolver: OverrideResolver,
+        headers: Vec<(HeaderName, HeaderValue)>,
+    ) -> BrowserBackend {
+        BrowserBackend {
+            config,
+            resolver,
+            headers,
+            browser: OnceCell::new(),
+            proxy: OnceCell::new(),
+        }
+    }
+
+    /// Whether `url` should be loaded in the browser.
+    pub fn renders(&self, url: &Url) -> bool {
+        self.config
+            .patterns
+            .iter()
+            .any(|pattern| pattern.is_match(url.as_str()))
+    }
+
+    async fn browser(&self) -> EvergardenResult<&Mutex<Browser>> {
+        self.browser
+            .get_or_try_init(|| async {
+                let proxy = self
+                    .proxy
+                    .get_or_try_init(|| ResolvingProxy::start(self.resolver.clone()))
+                    .await?;
+
+                // cross-site frames get processes of their own otherwise, whose requests the page can't hold
+                let mut config = chromiumoxide::BrowserConfig::builder()
+                    .arg("--disable-features=TranslateUI,IsolateOrigins,site-per-process")
+                    .arg("--disable-site-isolation-trials")
+                    // every connection goes through the proxy, loopback ones included, so that hosts are only ever
+                    // looked up by the crawler's own resolver
+                    .arg(format!("--proxy-server=http://{}", proxy.addr()))
+                    .arg("--proxy-bypass-list=<-loopback>");
+                if let Some(rules) = self.resolver.host_resolver_rules() {
+                    config = config.arg(format!("--host-resolver-rules={rules}"));
+                }
+                config = config.args(self.config.args.iter());
+                if let Some(executable) = &self.config.executable {
+                    config = config.chrome_executable(executable);
+                }
+
+                let (browser, mut handler) =
+                    Browser::launch(config.build().map_err(EvergardenError::Browser)?)
+                        .await
+                        .map_err(browser_error)?;
+
+                tokio::task::spawn(async move {
+                    while let Some(event) = handler.next().await {
+                        if let Err(e) = event {
+                            debug!("browser connection: {e}");
+                        }
+                    }
+                });
+
+                info!("launched browser");
+                Ok(Mutex::new(browser))
+            })
+            .await
+    }
+
+    /// Closes the browser, if it was ever launched.
+    pub async fn shutdown(&self) {
+        if let Some(browser) = self.browser.get() {
+            let mut browser = browser.lock().await;
+            if let Err(e) = browser.close().await {
+                debug!("closing browser: {e}");
+            }
+            let _ = browser.wait().await;
+        }
+    }
+
+    /// Loads `url` in a new tab, waiting until it's loaded and its network has been idle for a while, or until `timeout`
+    /// runs out. Requests `allows` turns down, like those robots.txt disallows, are never sent.
+    pub async fn render<F, Fut>(
+        &self,
+        url: &Url,
+        timeout: Duration,
+        allows: F,
+    ) -> EvergardenResult<RenderedPage>
+    where
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        if !self.allows_host(url).await {
+            return Err(EvergardenError::Skipped(format!(
+                "{} resolves to addresses disallowed by the address policy",
+                url.host_str().unwrap_or_default()
+            )));
+        }
+
+        let page = self
+            .browser()
+            .await?
+            .lock()
+            .await
+            .new_page("about:blank")
+            .await
+            .map_err(browser_error)?;
+
+        let mut res = self.capture(&page, url, timeout, allows).await;
+        if let (true, Ok(rendered)) = (self.config.screenshots, &mut res) {
+            rendered.screenshot = match page
+                .screenshot(ScreenshotParams::builder().full_page(true).build())
+                .await
+            {
+                Ok(png) => Some(Bytes::from(png)),
+                Err(e) => {
+                    debug!(%url, "couldn't take screenshot: {e}");
+                    None
+                }
+            };
+        }
+
+        if let (true, Ok(rendered)) = (self.config.dom_snapshots, &mut res) {
+            rendered.dom = match page.content().await {
+                Ok(html) => Some(Bytes::from(html)),
+                Err(e) => {
+                    debug!(%url, "couldn't serialize dom: {e}");
+                    None
+                }
+            };
+        }
+
+        let _ = page.close().await;
+
+        res
+    }
+
+    async fn allows_host(&self, url: &Url) -> bool {
+        match url.host() {
+            Some(host) => self.resolver.allows_host(host).await,
+            None => true,
+        }
+    }
+
+    // the browser only sees the proxy's address, so the capture gets the one the proxy connected to instead. one that
+    // never went through the proxy, like a response from the browser's cache, has no address to vouch for it
+    fn pinned(&self, mut capture: Capture) -> Option<Capture> {
+        capture.remote_addr = self
+            .proxy
+            .get()
+            .and_then(|proxy| proxy.connected_to(&capture.url));
+
+        let permitted = capture.remote_addr.is_some_and(|addr| {
+            self.resolver
+                .permits_addr(capture.url.host_str(), addr.ip())
+        });
+
+        if !permitted {
+            debug!(url = %capture.url, "dropping browser response from a disallowed address");
+            return None;
+        }
+
+        Some(capture)
+    }
+
+    // lets a held request go on, or fails it if it isn't allowed
+    async fn resolve_paused(
+        &self,
+        page: &Page,
+        event: &EventRequestPaused,
+        allowed: impl Future<Output = bool>,
+    ) {
+        let url = Url::parse(&event.request.url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"));
+
+        let permitted = match url {
+            Some(url) => self.allows_host(&url).await && allowed.await,
+            None => true,
+        };
+
+        let res = if permitted {
+            page.execute(ContinueRequestParams::new(event.request_id.clone()))
+                .await
+                .map(drop)
+        } else {
+            debug!(url = event.request.url, "blocking browser request");
+            page.execute(FailRequestParams::new(
+                event.request_id.clone(),
+                ErrorReason::BlockedByClient,
+            ))
+            .await
+            .map(drop)
+        };
+
+        if let Err(e) = res {
+            debug!(
+                url = event.request.url,
+                "couldn't resume browser request: {e}"
+            );
+        }
+    }
+
+    async fn capture<F, Fut>(
+        &self,
+        page: &Page,
+        url: &Url,
+        timeout: Duration,
+        allows: F,
+    ) -> EvergardenResult<RenderedPage>
+    where
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        if !self.headers.is_empty() {
+            let headers = self
+                .headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_owned().into()))
+                })
+                .collect::<serde_json::Map<_, _>>();
+
+            page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers)))
+                .await
+                .map_err(browser_error)?;
+        }
+
+        // a service worker would answer the page's requests out of sight of the network events
+        page.execute(SetBypassServiceWorkerParams::new(true))
+            .await
+            .map_err(browser_error)?;
+
+        let mut paused = listen::<EventRequestPaused>(page).await?;
+        page.execute(
+            fetch::EnableParams::builder()
+                .pattern(RequestPattern::builder().url_pattern("*").build())
+                .build(),
+        )
+        .await
+        .map_err(browser_error)?;
+
+        let mut requests = listen::<EventRequestWillBeSent>(page).await?;
+        let mut responses = listen::<EventResponseReceived>(page).await?;
+        let mut finished = listen::<EventLoadingFinished>(page).await?;
+        let mut failed = listen::<EventLoadingFailed>(page).await?;
+        let mut loaded = listen::<EventLoadEventFired>(page).await?;
+
+        let mut held = FuturesUnordered::new();
+        let hold = |event: Arc<EventRequestPaused>| {
+            let allowed = Url::parse(&event.request.url).ok().map(&allows);
+            async move {
+                let allowed = async {
+                    match allowed {
+                        Some(allowed) => allowed.await,
+                        None => true,
+                    }
+                };
+                self.resolve_paused(page, &event, allowed).await
+            }
+        };
+
+        // navigating waits for the document's response, so its request has to be let through in the meantime
+        let navigate = page.execute(NavigateParams::new(url.as_str()));
+        tokio::pin!(navigate);
+        let navigation = loop {
+            tokio::select! {
+                navigation = &mut navigate => break navigation.map_err(browser_error)?.result,
+                Some(event) = paused.next() => held.push(hold(event)),
+                Some(()) = held.next() => {},
+            }
+        };
+
+        if let Some(err) = navigation.error_text {
+            return Err(EvergardenError::Browser(err));
+        }
+
+        // the document's request shares its id with the navigation's loader
+        let document_id = navigation
+            .loader_id
+            .map(|id| RequestId::new(id.inner().clone()));
+
+        let mut in_flight: HashMap<RequestId, InFlight> = HashMap::new();
+        let mut document = None;
+        let mut resources = Vec::new();
+        let mut is_loaded = false;
+
+        loop {
+            let settled = is_loaded && in_flight.is_empty();
+
+            tokio::select! {
+                Some(event) = paused.next() => held.push(hold(event)),
+                Some(()) = held.next() => {},
+                Some(event) = requests.next() => {
+                    // a redirect reuses the request id, and its response never gets a body
+                    if let (Some(redirect), Some(previous)) = (&event.redirect_response, in_flight.remove(&event.request_id)) {
+                        resources.extend(capture_of(previous, redirect, Bytes::new()).and_then(|c| self.pinned(c)));
+                    }
+
+                    in_flight.insert(event.request_id.clone(), InFlight {
+                        request: request_of(&event.request),
+                        fetched_at: OffsetDateTime::now_utc(),
+                        response: None,
+                    });
+                },
+                Some(event) = responses.next() => {
+                    if let Some(request) = in_flight.get_mut(&event.request_id) {
+                        request.response = Some(event.response.clone());
+                    }
+                },
+                Some(event) = finished.next() => {
+                    let Some(InFlight { response: Some(response), request, fetched_at }) = in_flight.remove(&event.request_id) else {
+                        continue;
+                    };
+
+                    let body = match page.execute(GetResponseBodyParams::new(event.request_id.clone())).await {
+                        Ok(body) if body.result.base64_encoded => STANDARD.decode(&body.result.body).map(Bytes::from).unwrap_or_default(),
+                        Ok(body) => Bytes::from(body.result.body.clone()),
+                        Err(e) => {
+                            debug!(url = response.url, "no body from browser: {e}");
+                            continue;
+                        }
+                    };
+
+                    let capture = capture_of(InFlight { request, fetched_at, response: None }, &response, body);
+                    if Some(&event.request_id) == document_id.as_ref() {
+                        match capture.map(|c| self.pinned(c)) {
+                            Some(None) => {
+                                return Err(EvergardenError::Skipped(String::from("the browser connected to an address disallowed by the address policy")));
+                            }
+                            capture => document = capture.flatten(),
+                        }
+                    } else {
+                        resources.extend(capture.and_then(|c| self.pinned(c)));
+                    }
+                },
+                Some(event) = failed.next() => {
+                    debug!(error = event.error_text, "browser request failed");
+                    in_flight.remove(&event.request_id);
+                    if Some(&event.request_id) == document_id.as_ref() {
+                        return Err(EvergardenError::Browser(event.error_text.clone()));
+                    }
+                },
+                Some(_) = loaded.next() => is_loaded = true,
+                _ = tokio::time::sleep(self.config.settle), if settled => break,
+                _ = tokio::time::sleep_until(deadline) => break,
+            }
+        }
+
+        match document {
+            Some(document) => Ok(RenderedPage {
+                document,
+                resources,
+                screenshot: None,
+                dom: None,
+            }),
+            None => Err(BodyReadError::TimedOut.into()),
+        }
+    }
+}
+
+async fn listen<T: chromiumoxide::cdp::IntoEventKind + Unpin>(
+    page: &Page,
+) -> EvergardenResult<chromiumoxide::listeners::EventStream<T>> {
+    page.event_listener::<T>().await.map_err(browser_error)
+}
+
+fn request_of(request: &chromiumoxide::cdp::browser_protocol::network::Request) -> RequestMetadata {
+    let target = Url::parse(&request.url)
+        .map(|url| url[Position::BeforePath..Position::AfterQuery].to_owned())
+        .unwrap_or_else(|_| request.url.clone());
+
+    RequestMetadata {
+        method: Method::from_str(&request.method).unwrap_or(Method::GET),
+        target,
+        version: Version::HTTP_11,
+        headers: header_map(&request.headers),
+        // chromium leaves out bodies too large to send over the protocol
+        body: request.post_data.clone().map(Bytes::from),
+    }
+}
+
+// only http(s) responses are worth keeping, not data: urls and the like
+fn capture_of(request: InFlight, response: &Response, body: Bytes) -> Option<Capture> {
+    let url = Url::parse(&response.url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))?;
+
+    let version = match response.protocol.as_deref() {
+        Some("http/1.0") => Version::HTTP_10,
+        Some("h2") => Version::HTTP_2,
+        Some("h3" | "h3-29") => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    };
+
+    // the browser hands over decoded bodies, so the headers shouldn't claim otherwise
+    let mut headers = header_map(&response.headers);
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(TRANSFER_ENCODING);
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+
+    let remote_addr = response
+        .remote_ip_address
+        .as_deref()
+        .and_then(|ip| ip.trim_matches(['[', ']']).parse().ok())
+        .zip(
+            response
+                .remote_port
+                .and_then(|port| u16::try_from(port).ok()),
+        )
+        .map(|(ip, port)| SocketAddr::new(ip, port));
+
+    let mut request_meta = request.request;
+    request_meta.version = version;
+
+    Some(Capture {
+        url,
+        status: StatusCode::from_u16(response.status as u16).ok()?,
+        version,
+        headers,
+        remote_addr,
+        request: request_meta,
+        fetched_at: request.fetched_at,
+        body,
+    })
+}
+
+// devtools joins repeated headers with newlines
+fn header_map(headers: &Headers) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    let Some(headers) = headers.inner().as_object() else {
+        return map;
+    };
+
+    for (name, value) in headers {
+        let (Ok(name), Some(value)) = (HeaderName::from_str(name), value.as_str()) else {
+            continue;
+        };
+
+        for value in value.split('\n') {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                map.append(&name, value);
+            }
+        }
+    }
+
+    map
+}
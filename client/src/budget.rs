@@ -0,0 +1,145 @@
+//! Keeping track of how much of its [`BudgetConfig`] a crawl has spent.
+
+use std::{
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use crate::config::BudgetConfig;
+
+/// The limit a crawl ran into.
+#[derive(Clone, Copy, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetLimit {
+    Requests,
+    Bytes,
+    Duration,
+}
+
+impl Display for BudgetLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BudgetLimit::Requests => "request",
+            BudgetLimit::Bytes => "byte",
+            BudgetLimit::Duration => "time",
+        })
+    }
+}
+
+/// What the crawl has spent so far, shared by the client spending it and whatever stops the crawl once it's gone.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    inner: Arc<BudgetInner>,
+}
+
+#[derive(Debug)]
+struct BudgetInner {
+    config: BudgetConfig,
+    started: Instant,
+    requests: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget::new(BudgetConfig::default())
+    }
+}
+
+impl Budget {
+    /// Starts spending `config`, with the clock starting now.
+    pub fn new(config: BudgetConfig) -> Budget {
+        Budget {
+            inner: Arc::new(BudgetInner {
+                config,
+                started: Instant::now(),
+                requests: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Spends a request, unless that would go over the budget.
+    pub fn take_request(&self) -> bool {
+        let max = self.inner.config.max_requests.unwrap_or(u64::MAX);
+        self.inner
+            .requests
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |requests| {
+                (requests < max).then_some(requests + 1)
+            })
+            .is_ok()
+    }
+
+    pub fn spend_bytes(&self, bytes: u64) {
+        self.inner.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// How many requests were sent and how many body bytes were read.
+    pub fn spent(&self) -> (u64, u64) {
+        (
+            self.inner.requests.load(Ordering::Relaxed),
+            self.inner.bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// The first limit the crawl has reached, if it has.
+    pub fn exhausted(&self) -> Option<BudgetLimit> {
+        let BudgetConfig {
+            max_requests,
+            max_bytes,
+            max_duration,
+        } = &self.inner.config;
+        let (requests, bytes) = self.spent();
+
+        if max_requests.is_some_and(|max| requests >= max) {
+            Some(BudgetLimit::Requests)
+        } else if max_bytes.is_some_and(|max| bytes >= max) {
+            Some(BudgetLimit::Bytes)
+        } else if max_duration.is_some_and(|max| self.inner.started.elapsed() >= max) {
+            Some(BudgetLimit::Duration)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_out_at_the_first_limit_reached() {
+        let budget = Budget::new(BudgetConfig {
+            max_requests: Some(2),
+            max_bytes: Some(100),
+            max_duration: None,
+        });
+
+        assert!(budget.take_request());
+        budget.spend_bytes(60);
+        assert_eq!(budget.exhausted(), None);
+
+        budget.spend_bytes(40);
+        assert_eq!(budget.exhausted(), Some(BudgetLimit::Bytes));
+
+        assert!(budget.take_request());
+        assert_eq!(budget.exhausted(), Some(BudgetLimit::Requests));
+        assert!(!budget.take_request());
+        assert_eq!(budget.spent(), (2, 100));
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let budget = Budget::default();
+        assert!(budget.take_request());
+        budget.spend_bytes(u64::MAX / 2);
+
+        assert_eq!(budget.exhausted(), None);
+    }
+}
@@ -1,6 +1,11 @@
-use std::{str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
 
-use actors::{Actor, Mailbox, Message, ProgramState};
+use actors::{Actor, Blocking, Mailbox, Message, ProgramState};
 
 use bytes::Bytes;
 use evergarden_common::Storage;
@@ -8,32 +13,49 @@ use futures_util::{Future, TryStreamExt};
 use governor::{Jitter, RateLimiter};
 use hyper::{
     client::{connect::HttpInfo, HttpConnector},
+    header::{ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HOST, LINK, LOCATION},
     http::{HeaderName, HeaderValue},
-    Body, Client, Request,
+    Body, Client, HeaderMap, Method, Request, StatusCode,
 };
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_trust_dns::TrustDnsResolver;
 
 use time::OffsetDateTime;
 use tokio::{
-    sync::{watch, OwnedSemaphorePermit, Semaphore, SemaphorePermit},
-    time::timeout,
+    sync::{watch, Notify, OwnedSemaphorePermit, Semaphore, SemaphorePermit},
+    task::JoinSet,
+    time::{timeout, Instant},
 };
-use tracing::{debug, error};
+use tracing::{debug, error, info};
+use url::{Position, Url};
 use uuid::Uuid;
 
 use crate::{
-    config::{HeaderPair, HttpConfig, RateLimitingConfig},
+    browser::BrowserBackend,
+    budget::Budget,
+    config::{
+        AdaptiveConcurrencyConfig, CanonicalConfig, HeaderPair, HttpConfig, PreflightConfig,
+        RateLimitingConfig, RewriteConfig, SkipConfig, SkipMode,
+    },
+    dns::{AddressGuard, BlockedAddress, OverrideResolver},
+    extract::Discovery,
+    frontier::{Blocklist, Frontier, QueuedUrl},
+    robots::{self, RobotsCache, RobotsTxt},
     scripting::script::ScriptManager,
+    tls::TlsInfoConnector,
 };
 
 use evergarden_common::*;
 
-type HttpsConn = HttpsConnector<HttpConnector<TrustDnsResolver>>;
+// rfc 9309 asks that at least five consecutive redirects be followed
+const MAX_ROBOTS_REDIRECTS: usize = 5;
+
+type HttpsConn = TlsInfoConnector<HttpsConnector<AddressGuard<HttpConnector<OverrideResolver>>>>;
 
 #[derive(Clone, Debug)]
 pub struct HttpRateLimiter {
     total_permits: usize,
+    adaptive: Option<Arc<AdaptiveLimiter>>,
     permits: Arc<Semaphore>,
     limiter: Arc<
         RateLimiter<
@@ -50,6 +72,10 @@ impl HttpRateLimiter {
     pub fn new(config: RateLimitingConfig) -> HttpRateLimiter {
         HttpRateLimiter {
             total_permits: config.max_tasks_per_worker.into(),
+            adaptive: config
+                .adaptive
+                .clone()
+                .map(|cfg| Arc::new(AdaptiveLimiter::new(cfg))),
             permits: Arc::new(Semaphore::new(config.max_tasks_per_worker.into())),
             limiter: Arc::new(RateLimiter::direct(config.as_quota())),
             jitter: config.jitter,
@@ -65,72 +91,378 @@ impl HttpRateLimiter {
         permit.unwrap()
     }
 
+    /// A slot among the worker's concurrent fetches, leaving the rate limit to [`HttpRateLimiter::until_ready`], so that
+    /// urls taken off the frontier only to be put back don't use it up.
     pub async fn acquire_owned(&self) -> OwnedSemaphorePermit {
-        let (permit, _) = tokio::join! {
-            self.permits.clone().acquire_owned(),
-            self.limiter.until_ready_with_jitter(Jitter::up_to(self.jitter))
-        };
+        self.permits.clone().acquire_owned().await.unwrap()
+    }
 
-        permit.unwrap()
+    /// Waits until the rate limit allows another fetch.
+    pub async fn until_ready(&self) {
+        self.limiter
+            .until_ready_with_jitter(Jitter::up_to(self.jitter))
+            .await
     }
 
     pub fn is_idle(&self) -> bool {
         self.total_permits == self.permits.available_permits()
     }
+
+    /// Takes a slot under the host's adaptive concurrency limit, if adaptive concurrency is enabled, or fails with
+    /// [`HostBusy`] if the host has none left. Nothing waits for one while holding a slot among the worker's fetches,
+    /// which would hold up fetches to every other host too.
+    pub fn try_acquire_host(&self, host: &str) -> Result<Option<HostPermit>, HostBusy> {
+        match self.adaptive.as_ref() {
+            Some(adaptive) => adaptive.try_acquire(host).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Waits for hosts that turned urls away to have room again, returning each with how many of those it can take now.
+    /// Never returns if adaptive concurrency isn't enabled.
+    pub async fn released_hosts(&self) -> Vec<(String, usize)> {
+        match self.adaptive.as_ref() {
+            Some(adaptive) => adaptive.released().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Returned when a host has as many requests underway as its concurrency window allows.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HostBusy;
+
+#[derive(Debug)]
+struct HostWindow {
+    limit: f64,
+    in_flight: usize,
+    error_rate: f64,
+    // urls turned away with `HostBusy`, which are waiting for room
+    waiting: usize,
+}
+
+impl HostWindow {
+    fn room(&self) -> usize {
+        (self.limit.floor() as usize)
+            .max(1)
+            .saturating_sub(self.in_flight)
+    }
+}
+
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    config: AdaptiveConcurrencyConfig,
+    hosts: Mutex<HashMap<String, HostWindow>>,
+    // hosts with urls waiting that had a request finish since the last call to `released`
+    released: Mutex<HashSet<String>>,
+    notify: Notify,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(config: AdaptiveConcurrencyConfig) -> AdaptiveLimiter {
+        AdaptiveLimiter {
+            config,
+            hosts: Mutex::new(HashMap::new()),
+            released: Mutex::new(HashSet::new()),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn try_acquire(self: &Arc<Self>, host: &str) -> Result<HostPermit, HostBusy> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let window = hosts.entry(host.to_owned()).or_insert_with(|| HostWindow {
+            limit: self.config.min_tasks_per_host.get() as f64,
+            in_flight: 0,
+            error_rate: 0.0,
+            waiting: 0,
+        });
+
+        if window.room() == 0 {
+            window.waiting += 1;
+            return Err(HostBusy);
+        }
+
+        window.in_flight += 1;
+        Ok(HostPermit {
+            limiter: Arc::clone(self),
+            host: host.to_owned(),
+        })
+    }
+
+    async fn released(&self) -> Vec<(String, usize)> {
+        loop {
+            let released = self.take_released();
+            if !released.is_empty() {
+                return released;
+            }
+
+            // a release while this wasn't waiting leaves a permit behind, so none are missed
+            self.notify.notified().await;
+        }
+    }
+
+    // each host is handed out with room for no more than the urls waiting for it, so that the ones still waiting after
+    // those are tried get released again
+    fn take_released(&self) -> Vec<(String, usize)> {
+        let released = std::mem::take(&mut *self.released.lock().unwrap());
+
+        let mut hosts = self.hosts.lock().unwrap();
+        let mut released = released
+            .into_iter()
+            .filter_map(|host| {
+                let window = hosts.get_mut(&host)?;
+                let room = window.room().min(window.waiting);
+                window.waiting -= room;
+                (room > 0).then_some((host, room))
+            })
+            .collect::<Vec<_>>();
+        released.sort();
+
+        released
+    }
+
+    fn record(&self, host: &str, latency: Duration, success: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(window) = hosts.get_mut(host) else {
+            return;
+        };
+
+        let min = self.config.min_tasks_per_host.get() as f64;
+        let max = self.config.max_tasks_per_host.get() as f64;
+
+        window.error_rate = window.error_rate * 0.9 + if success { 0.0 } else { 0.1 };
+
+        if !success
+            || window.error_rate > self.config.max_error_rate
+            || latency > self.config.target_latency
+        {
+            window.limit = (window.limit * self.config.backoff).max(min);
+            debug!(host, limit = window.limit, "decreasing concurrency");
+        } else {
+            window.limit = (window.limit + 1.0 / window.limit).min(max);
+        }
+    }
+
+    fn release(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(window) = hosts.get_mut(host) else {
+            return;
+        };
+
+        window.in_flight = window.in_flight.saturating_sub(1);
+        if window.waiting > 0 {
+            self.released.lock().unwrap().insert(host.to_owned());
+            self.notify.notify_one();
+        } else if window.in_flight == 0
+            && window.limit <= self.config.min_tasks_per_host.get() as f64
+        {
+            // an idle host back where it started has nothing worth remembering
+            hosts.remove(host);
+        }
+    }
+}
+
+/// A slot in a host's concurrency window, released on drop.
+pub struct HostPermit {
+    limiter: Arc<AdaptiveLimiter>,
+    host: String,
+}
+
+impl HostPermit {
+    /// Feeds the outcome of the request back into the host's concurrency limit.
+    pub fn finish(self, latency: Duration, success: bool) {
+        self.limiter.record(&self.host, latency, success);
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        self.limiter.release(&self.host);
+    }
+}
+
+/// Spaces out requests to the same host by a per-host delay (e.g. a robots.txt `Crawl-delay`).
+#[derive(Clone, Debug, Default)]
+pub struct HostLimiter {
+    next_slot: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl HostLimiter {
+    /// Reserves the host's next slot, returning when it starts if that's yet to come.
+    pub fn reserve(&self, host: &str, delay: Duration) -> Option<Instant> {
+        if delay.is_zero() {
+            return None;
+        }
+
+        let mut slots = self.next_slot.lock().unwrap();
+        let now = Instant::now();
+        // hosts whose slot has passed are no different from those never seen
+        slots.retain(|_, slot| *slot > now);
+
+        let slot = slots.get(host).copied().unwrap_or(now);
+        slots.insert(host.to_owned(), slot + delay);
+        (slot > now).then_some(slot)
+    }
+
+    /// When the host's next slot starts, if that's yet to come.
+    pub fn busy_until(&self, host: &str) -> Option<Instant> {
+        self.next_slot
+            .lock()
+            .unwrap()
+            .get(host)
+            .copied()
+            .filter(|slot| *slot > Instant::now())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct HttpClient {
     headers: Vec<(HeaderName, HeaderValue)>,
     limiter: HttpRateLimiter,
+    host_limiter: HostLimiter,
+    robots: Option<Arc<RobotsCache>>,
+    browser: Option<Arc<BrowserBackend>>,
+    preflight: Arc<PreflightConfig>,
+    skip: Arc<SkipConfig>,
+    rewrite: Arc<RewriteConfig>,
+    canonical: CanonicalConfig,
+    canonical_aliases: CanonicalAliases,
     client: Client<HttpsConn>,
     max_body_length: Option<usize>,
+    truncate_bodies: bool,
+    refresh_since: Option<OffsetDateTime>,
     timeout: Duration,
-    storage: Mailbox<Storage>,
+    storage: Mailbox<Blocking<Storage>>,
     scrapers: Mailbox<ScriptManager>,
+    // our own mailbox, for queueing urls we discover ourselves (like sitemaps from robots.txt)
+    queue: Option<Mailbox<HttpClient>>,
+    blocklist: Blocklist,
+    budget: Budget,
+    // set for dry runs, which only read the bodies something would look for urls in
+    dry_run: Option<Arc<Discovery>>,
 }
 
 impl HttpClient {
     pub fn new(
         http_config: &HttpConfig,
         rate: HttpRateLimiter,
-        storage: Mailbox<Storage>,
+        skip: Arc<SkipConfig>,
+        rewrite: Arc<RewriteConfig>,
+        storage: Mailbox<Blocking<Storage>>,
         scripts: Mailbox<ScriptManager>,
     ) -> EvergardenResult<HttpClient> {
         let (dns_config, dns_options) =
             trust_dns_resolver::system_conf::read_system_conf().unwrap_or_default();
-        let mut resolver = TrustDnsResolver::with_config_and_options(dns_config, dns_options)
-            .into_http_connector();
+        let policy = Arc::new(http_config.address_policy.clone());
+        let resolver = OverrideResolver::new(
+            TrustDnsResolver::with_config_and_options(dns_config, dns_options),
+            &http_config.dns,
+            Arc::clone(&policy),
+        )?;
+        let browser_resolver = resolver.clone();
+        let mut resolver = HttpConnector::new_with_resolver(resolver);
         resolver.enforce_http(false);
+        let resolver = AddressGuard::new(resolver, policy);
 
         let connector = HttpsConnectorBuilder::new()
             .with_native_roots()
             .https_or_http()
             .enable_http1()
             .wrap_connector(resolver);
+        let connector = TlsInfoConnector::new(connector);
 
         let hyper_client = Client::builder().build::<_, hyper::Body>(connector);
 
+        let mut headers = http_config
+            .headers
+            .iter()
+            .map(|HeaderPair { name, value }| {
+                (
+                    HeaderName::from_str(name).unwrap(),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // the browser asks for the encodings it can decode by itself
+        let browser = (!http_config.browser.patterns.is_empty()).then(|| {
+            Arc::new(BrowserBackend::new(
+                http_config.browser.clone(),
+                browser_resolver,
+                headers.clone(),
+            ))
+        });
+
+        if http_config.accept_encoding && !headers.iter().any(|(name, _)| name == ACCEPT_ENCODING) {
+            headers.push((
+                ACCEPT_ENCODING,
+                HeaderValue::from_static("gzip, deflate, br, zstd"),
+            ));
+        }
+
         Ok(HttpClient {
             storage,
-            headers: http_config
-                .headers
-                .iter()
-                .map(|HeaderPair { name, value }| {
-                    (
-                        HeaderName::from_str(name).unwrap(),
-                        HeaderValue::from_str(value).unwrap(),
-                    )
-                })
-                .collect::<Vec<_>>(),
+            headers,
             limiter: rate,
+            host_limiter: HostLimiter::default(),
+            robots: (http_config.robots.uses_robots_txt() || http_config.robots.directives)
+                .then(|| Arc::new(RobotsCache::new(http_config.robots.clone()))),
+            browser,
+            preflight: Arc::new(http_config.preflight.clone()),
+            skip,
+            rewrite,
+            canonical: http_config.canonical,
+            canonical_aliases: CanonicalAliases::default(),
             client: hyper_client,
             max_body_length: http_config.max_body_length,
+            truncate_bodies: http_config.truncate_bodies,
+            refresh_since: None,
             timeout: http_config.timeout,
             scrapers: scripts,
+            queue: None,
+            blocklist: Blocklist::default(),
+            budget: Budget::default(),
+            dry_run: None,
         })
     }
 
+    /// Lets the client queue urls it discovers by itself into `mailbox`, which should be the one it's spawned on.
+    pub fn with_queue(mut self, mailbox: Mailbox<HttpClient>) -> HttpClient {
+        self.queue = Some(mailbox);
+        self
+    }
+
+    /// Shares the canonical urls the client finds in headers with whatever finds them in bodies, like the scripts.
+    pub fn with_canonical_aliases(mut self, aliases: CanonicalAliases) -> HttpClient {
+        self.canonical_aliases = aliases;
+        self
+    }
+
+    /// Refuses urls in `blocklist`, including queued ones blocked before they're fetched.
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> HttpClient {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Spends `budget` on every request, and stops fetching once it's exhausted.
+    pub fn with_budget(mut self, budget: Budget) -> HttpClient {
+        self.budget = budget;
+        self
+    }
+
+    /// Only read the bodies of responses that `discovery` would look at for urls, dropping the rest once their headers
+    /// are in, for dry runs.
+    pub fn dry_run(mut self, discovery: Discovery) -> HttpClient {
+        self.dry_run = Some(Arc::new(discovery));
+        self
+    }
+
+    /// Re-fetch stored responses that were captured before `since`, instead of answering with them.
+    pub fn refresh_since(mut self, since: OffsetDateTime) -> HttpClient {
+        self.refresh_since = Some(since);
+        self
+    }
+
     // pub (crate) fn write_body(&self, key: &str, mut body: hyper::Body) -> HttpResult<()> {
 
     // // }
@@ -149,22 +481,298 @@ impl HttpClient {
     //     Ok(IVec::from(out))
     // }
 
-    #[tracing::instrument(ret(Display), err, skip(self), target = "evergarden::http", fields(url = %url))]
-    pub async fn get(&self, url: UrlInfo) -> EvergardenResult<HttpResponse> {
-        let mut request = Request::get(url.url.as_str());
+    /// Fetches a url, honoring robots.txt rules, per-host crawl delays and the HEAD preflight filters if enabled. The
+    /// url's host's concurrency window is fed back to with how the fetch went, if a slot in it was taken. The worker slot
+    /// `permit` is given up while waiting out a crawl delay.
+    pub async fn fetch(
+        &self,
+        url: UrlInfo,
+        permit: OwnedSemaphorePermit,
+        host_permit: Option<HostPermit>,
+    ) -> EvergardenResult<HttpResponse> {
+        if self.skip.skips_fetching(&url.url) {
+            return Err(EvergardenError::Skipped(String::from(
+                "url extension matches skip list",
+            )));
+        }
+
+        self.limiter.until_ready().await;
+
+        let origin = url.url.origin().ascii_serialization();
+        let mut delay = Duration::ZERO;
+        let mut permit = Some(permit);
+
+        if let Some(robots_cache) = self.robots.as_ref().filter(|c| c.config.uses_robots_txt()) {
+            let robots = self.robots_for(robots_cache, &url).await;
+            if robots_cache.config.enabled && !robots.is_allowed(&url.url) {
+                debug!(
+                    url = url.url.as_str(),
+                    "skipping url disallowed by robots.txt"
+                );
+                return Err(EvergardenError::RobotsDisallowed);
+            }
+
+            if robots_cache.config.enabled {
+                delay = robots_cache.delay_for(&robots);
+            }
+        }
+
+        // only bare GETs are worth probing with a HEAD first
+        if self.preflight.enabled && url.request.is_none() {
+            self.wait_for_host(&origin, delay, &mut permit).await;
+            self.preflight(&url.url).await?;
+        }
+
+        // fetches that were already underway when the budget ran out don't go over it
+        if !self.budget.take_request() {
+            return Err(EvergardenError::BudgetExhausted);
+        }
+
+        self.wait_for_host(&origin, delay, &mut permit).await;
+
+        let started = Instant::now();
+        let browser = self
+            .browser
+            .as_ref()
+            .filter(|b| url.request.is_none() && b.renders(&url.url));
+        let res = match browser {
+            Some(browser) => self.render(browser, url).await,
+            None => self.get(url).await,
+        };
+
+        if let Some(permit) = host_permit {
+            let success = match &res {
+                Ok(res) => {
+                    !(res.meta.status.is_server_error()
+                        || res.meta.status == StatusCode::TOO_MANY_REQUESTS)
+                }
+                Err(_) => false,
+            };
+
+            permit.finish(started.elapsed(), success);
+        }
+
+        res
+    }
+
+    // sleeps out the host's crawl delay without a worker slot, which would hold up fetches to every other host, taking
+    // one back once it's done
+    async fn wait_for_host(
+        &self,
+        origin: &str,
+        delay: Duration,
+        permit: &mut Option<OwnedSemaphorePermit>,
+    ) {
+        let Some(slot) = self.host_limiter.reserve(origin, delay) else {
+            return;
+        };
+
+        permit.take();
+        tokio::time::sleep_until(slot).await;
+        *permit = Some(self.limiter.acquire_owned().await);
+    }
+
+    /// Issues a HEAD request and checks the advertised `Content-Type` and `Content-Length` against the preflight filters.
+    /// Servers that don't answer HEAD properly are given the benefit of the doubt.
+    async fn preflight(&self, url: &Url) -> EvergardenResult<()> {
+        let request = self
+            .request_builder(Method::HEAD, url.as_str())
+            .body(Body::empty())
+            .unwrap();
+
+        let res = match timeout(self.timeout, self.client.request(request)).await {
+            Ok(Ok(res)) if res.status().is_success() => res,
+            _ => return Ok(()),
+        };
+
+        let headers = res.headers();
+
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+
+        if let (Some(length), Some(max)) = (
+            content_length,
+            self.preflight
+                .max_content_length
+                .or(self.max_body_length.map(|v| v as u64)),
+        ) {
+            if length > max {
+                debug!(url = url.as_str(), length, "preflight: body too large");
+                return Err(EvergardenError::PreflightRejected(format!(
+                    "content-length {length} exceeds limit of {max}"
+                )));
+            }
+        }
+
+        if !self.preflight.matches_type(headers) {
+            debug!(url = url.as_str(), "preflight: content-type filtered out");
+            return Err(EvergardenError::PreflightRejected(format!(
+                "content-type {:?} not allowed",
+                headers.get(CONTENT_TYPE)
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn request_builder(&self, method: Method, url: &str) -> hyper::http::request::Builder {
+        let mut request = Request::builder().method(method).uri(url);
         request
             .headers_mut()
             .unwrap()
             .extend(self.headers.iter().cloned());
 
+        request
+    }
+
+    async fn robots_for(&self, cache: &RobotsCache, url: &UrlInfo) -> Arc<RobotsTxt> {
+        let origin = url.url.origin().ascii_serialization();
+
+        cache
+            .entry(&origin)
+            .get_or_init(|| async {
+                let robots = match url.url.join("/robots.txt") {
+                    Ok(robots_url) => {
+                        self.fetch_robots(&robots_url, &cache.config.user_agent)
+                            .await
+                    }
+                    Err(_) => RobotsTxt::allow_all(),
+                };
+
+                if let Some(delay) = robots.crawl_delay.filter(|_| cache.config.enabled) {
+                    info!(origin, ?delay, "using crawl delay from robots.txt");
+                }
+
+                if cache.config.sitemaps {
+                    self.queue_sitemaps(url, &robots.sitemaps).await;
+                }
+
+                Arc::new(robots)
+            })
+            .await
+            .clone()
+    }
+
+    // sitemaps are queued as if linked from the page that led us to the origin, and only if they don't leave its scope
+    async fn queue_sitemaps(&self, from: &UrlInfo, sitemaps: &[String]) {
+        let Some(queue) = self.queue.as_ref() else {
+            return;
+        };
+
+        for sitemap in sitemaps {
+            let Some(url) = from.clone().hop(sitemap) else {
+                continue;
+            };
+
+            if url.hops > from.hops || self.skip.skips_fetching(&url.url) {
+                debug!(
+                    url = url.url.as_str(),
+                    "not queueing sitemap from robots.txt"
+                );
+                continue;
+            }
+
+            info!(url = url.url.as_str(), "queueing sitemap from robots.txt");
+            tokio::task::spawn(queue.deferred_request(url).await);
+        }
+    }
+
+    // as rfc 9309 has it, a robots.txt the server fails to serve disallows everything, and one that's missing (or
+    // redirected too many times) allows everything. any other failure to retrieve it is treated as permission too
+    async fn fetch_robots(&self, robots_url: &Url, user_agent: &str) -> RobotsTxt {
+        let mut url = robots_url.clone();
+
+        for _ in 0..=MAX_ROBOTS_REDIRECTS {
+            let request = self.request_builder(Method::GET, url.as_str());
+
+            let res = match timeout(
+                self.timeout,
+                self.client.request(request.body(Body::empty()).unwrap()),
+            )
+            .await
+            {
+                Ok(Ok(res)) => res,
+                _ => return RobotsTxt::allow_all(),
+            };
+
+            let status = res.status();
+            if status.is_redirection() {
+                match res
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok())
+                {
+                    Some(next) => {
+                        url = next;
+                        continue;
+                    }
+                    None => return RobotsTxt::allow_all(),
+                }
+            }
+
+            if status.is_server_error() {
+                info!(url = url.as_str(), %status, "robots.txt failed, so nothing is allowed");
+                return RobotsTxt::disallow_all();
+            }
+
+            if !status.is_success() {
+                return RobotsTxt::allow_all();
+            }
+
+            return match timeout(self.timeout, hyper::body::to_bytes(res.into_body())).await {
+                Ok(Ok(body)) => RobotsTxt::parse(&String::from_utf8_lossy(&body), user_agent),
+                _ => RobotsTxt::allow_all(),
+            };
+        }
+
+        debug!(
+            url = robots_url.as_str(),
+            "robots.txt redirected too many times"
+        );
+        RobotsTxt::allow_all()
+    }
+
+    #[tracing::instrument(ret(Display), err, skip(self), target = "evergarden::http", fields(url = %url))]
+    pub async fn get(&self, url: UrlInfo) -> EvergardenResult<HttpResponse> {
+        let request = match &url.request {
+            Some(spec) => {
+                let mut request = self.request_builder(spec.method.clone(), url.url.as_str());
+                request.headers_mut().unwrap().extend(spec.headers.clone());
+                request.body(spec.body.clone().map_or_else(Body::empty, Body::from))
+            }
+            None => self
+                .request_builder(Method::GET, url.url.as_str())
+                .body(Body::empty()),
+        }
+        .unwrap();
+
+        // hyper fills in the host header itself, so add it to what we record
+        let mut sent_headers = request.headers().clone();
+        if let (false, Some(host)) = (sent_headers.contains_key(HOST), url.url.host_str()) {
+            let host = match url.url.port() {
+                Some(port) => format!("{host}:{port}"),
+                None => host.to_owned(),
+            };
+
+            if let Ok(host) = HeaderValue::from_str(&host) {
+                sent_headers.insert(HOST, host);
+            }
+        }
+
+        let sent_method = request.method().clone();
+        let sent_body = url
+            .request
+            .as_ref()
+            .and_then(|spec| spec.body.clone())
+            .map(Bytes::from);
+        let sent_target = url.url[Position::BeforePath..Position::AfterQuery].to_owned();
+
         let fetched_at = OffsetDateTime::now_utc();
 
-        let (header, body) = match timeout(
-            self.timeout,
-            self.client.request(request.body(Body::empty()).unwrap()),
-        )
-        .await
-        {
+        let (header, mut body) = match timeout(self.timeout, self.client.request(request)).await {
             Ok(Ok(res)) => res.into_parts(),
             Ok(Err(e)) => return Err(BodyReadError::Client(e).into()),
             Err(_) => {
@@ -173,10 +781,40 @@ impl HttpClient {
             }
         };
 
+        let skipped = self.skip.skips_type(&header.headers);
+        if skipped && self.skip.mode == SkipMode::Fetch {
+            debug!("aborting body: content-type matches skip list");
+            return Err(EvergardenError::Skipped(String::from(
+                "content-type matches skip list",
+            )));
+        }
+
+        if self.dry_run.as_ref().is_some_and(|discovery| {
+            !discovery.wants_body(&url.url, header.status, &header.headers)
+        }) {
+            debug!("dry run: dropping body, since nothing would look at it");
+            body = Body::empty();
+        }
+
         debug!("reading body");
 
         let (body_tx, body_rx) = async_broadcast::broadcast(1024);
-        let body_task = tokio::task::spawn(broadcast_body(self.max_body_length, body, body_tx));
+        let truncated = Arc::new(OnceLock::new());
+        let body_task = tokio::task::spawn(broadcast_body(
+            self.max_body_length,
+            self.truncate_bodies.then(|| Arc::clone(&truncated)),
+            body,
+            body_tx,
+            self.budget.clone(),
+        ));
+
+        let canonical = canonical_link(&header.headers, &url.url).filter(|c| c != &url.url);
+        if let (true, Some(canonical)) = (self.canonical.dedupe, &canonical) {
+            self.canonical_aliases
+                .record(url.key_url(), canonical.clone());
+        }
+
+        let robots = self.header_directives(&header.headers);
 
         let res = HttpResponse {
             meta: Arc::new(ResponseMetadata {
@@ -186,19 +824,46 @@ impl HttpClient {
                 version: header.version,
                 headers: header.headers,
                 remote_addr: header.extensions.get::<HttpInfo>().map(|v| v.remote_addr()),
+                tls: header.extensions.get::<TlsInfo>().cloned(),
+                request: Some(RequestMetadata {
+                    method: sent_method,
+                    target: sent_target,
+                    version: header.version,
+                    headers: sent_headers,
+                    body: sent_body,
+                }),
                 fetched_at,
+                truncated: None,
+                canonical,
+                payload_digest: None,
+                revisit: None,
+                robots,
+                resource: None,
+                annotations: None,
+                compression: None,
+                size: None,
             }),
             body: body_rx,
+            truncated,
         };
 
         let scrapers_handle = self.scrapers.clone();
         let scraper_res = res.clone();
         tokio::task::spawn(async move { scrapers_handle.request(scraper_res).await });
 
-        let (body, storage) = tokio::join!(
-            body_task,
-            self.storage.request(StorageMessage::Store(res.clone())),
-        );
+        let skipped = skipped || self.skip.skips_url(&res.meta.url.url);
+        let store = async {
+            if skipped {
+                debug!("not storing response: matches skip list");
+                return Ok(StorageResponse::Stored);
+            }
+
+            self.storage
+                .request(StorageMessage::Store(res.clone()))
+                .await
+        };
+
+        let (body, storage) = tokio::join!(body_task, store);
 
         body.unwrap()?;
         storage?;
@@ -210,6 +875,253 @@ impl HttpClient {
     }
 }
 
+impl HttpClient {
+    /// Loads a page in the browser, storing everything it requested along the way. Only the page itself is handed to
+    /// the scripts.
+    #[tracing::instrument(ret(Display), err, skip(self, browser), target = "evergarden::http", fields(url = %url))]
+    async fn render(
+        &self,
+        browser: &BrowserBackend,
+        url: UrlInfo,
+    ) -> EvergardenResult<HttpResponse> {
+        let page = browser
+            .render(&url.url, self.timeout, |request| {
+                self.robots_allow(&url, request)
+            })
+            .await?;
+
+        let read = page
+            .resources
+            .iter()
+            .chain([&page.document])
+            .map(|c| c.body.len() as u64);
+        self.budget.spend_bytes(read.sum());
+
+        for resource in page.resources {
+            let Some(resource_url) = url.clone().hop(resource.url.as_str()) else {
+                continue;
+            };
+
+            if self.skip.skips_url(&resource_url.url) {
+                continue;
+            }
+
+            if let Err(e) = self
+                .storage
+                .request(StorageMessage::Store(resource.into_response(resource_url)))
+                .await
+            {
+                error!("failed to store browser request: {e}");
+            }
+        }
+
+        let document_url = match url.clone().hop(page.document.url.as_str()) {
+            Some(redirected) if page.document.url != url.url => redirected,
+            _ => url,
+        };
+
+        let mut res = page.document.into_response(document_url);
+        let meta = Arc::get_mut(&mut res.meta).unwrap();
+        meta.canonical =
+            canonical_link(&meta.headers, &meta.url.url).filter(|c| c != &meta.url.url);
+        meta.robots = self.header_directives(&meta.headers);
+
+        let scrapers_handle = self.scrapers.clone();
+        let scraper_res = res.clone();
+        tokio::task::spawn(async move { scrapers_handle.request(scraper_res).await });
+
+        if self.skip.skips_type(&res.meta.headers) || self.skip.skips_url(&res.meta.url.url) {
+            return Ok(res);
+        }
+
+        self.storage
+            .request(StorageMessage::Store(res.clone()))
+            .await?;
+
+        let resources = [
+            page.screenshot
+                .map(|png| HttpResponse::resource("screenshot", &res.meta, "image/png", png)),
+            page.dom.map(|html| {
+                HttpResponse::resource("dom", &res.meta, "text/html; charset=utf-8", html)
+            }),
+        ];
+
+        for resource in resources.into_iter().flatten() {
+            let url = resource.meta.url.url.clone();
+            if let Err(e) = self.storage.request(StorageMessage::Store(resource)).await {
+                error!(%url, "failed to store rendered resource: {e}");
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Whether robots.txt lets the page at `url` request `request`.
+    async fn robots_allow(&self, url: &UrlInfo, request: Url) -> bool {
+        let Some(robots_cache) = self.robots.as_ref().filter(|c| c.config.enabled) else {
+            return true;
+        };
+
+        match url.clone().hop(request.as_str()) {
+            Some(request) => self
+                .robots_for(robots_cache, &request)
+                .await
+                .is_allowed(&request.url),
+            None => true,
+        }
+    }
+
+    fn header_directives(&self, headers: &HeaderMap) -> Option<RobotsDirectives> {
+        self.robots
+            .as_ref()
+            .filter(|c| c.config.directives)
+            .map(|c| robots::header_directives(headers, &c.config.user_agent))
+    }
+
+    /// The stored capture of the canonical url `url` is known to be an alias of, if any.
+    async fn stored_canonical(&self, url: &UrlInfo) -> Option<HttpResponse> {
+        let canonical = self.canonical_aliases.get(&url.key_url())?;
+        match self
+            .storage
+            .request(StorageMessage::Retrieve(canonical))
+            .await
+        {
+            Ok(StorageResponse::Retrieve(Some(res))) => Some(res),
+            _ => None,
+        }
+    }
+
+    async fn record_failure(&self, url: UrlInfo, res: &EvergardenResult<HttpResponse>) {
+        let (class, status, message) = match res {
+            Ok(res) if res.meta.status.is_client_error() => (
+                FailureClass::ClientError,
+                Some(res.meta.status),
+                res.meta.status.to_string(),
+            ),
+            Ok(res) if res.meta.status.is_server_error() => (
+                FailureClass::ServerError,
+                Some(res.meta.status),
+                res.meta.status.to_string(),
+            ),
+            Ok(_) => return,
+            Err(e) => match failure_class(e) {
+                Some(class) => (class, None, e.to_string()),
+                None => return,
+            },
+        };
+
+        let failure = FailedFetch {
+            url,
+            class,
+            status: status.map(|s| s.as_u16()),
+            message,
+            attempts: 1,
+            last_attempt: OffsetDateTime::now_utc(),
+        };
+
+        if let Err(e) = self
+            .storage
+            .request(StorageMessage::RecordFailure(failure))
+            .await
+        {
+            error!("failed to record failed fetch: {e}");
+        }
+    }
+}
+
+/// Urls whose responses advertised a different canonical url, when deduplicating by canonical. Shared between the
+/// client, which finds them in headers, and the scripts, which find them in html.
+#[derive(Clone, Debug, Default)]
+pub struct CanonicalAliases {
+    inner: Arc<Mutex<HashMap<Url, Url>>>,
+}
+
+impl CanonicalAliases {
+    pub fn record(&self, url: Url, canonical: Url) {
+        self.inner.lock().unwrap().insert(url, canonical);
+    }
+
+    pub fn get(&self, url: &Url) -> Option<Url> {
+        self.inner.lock().unwrap().get(url).cloned()
+    }
+}
+
+/// Finds the target of a `Link: <...>; rel="canonical"` header, resolved against the response url.
+fn canonical_link(headers: &HeaderMap, base: &Url) -> Option<Url> {
+    for value in headers.get_all(LINK) {
+        let Ok(mut rest) = value.to_str() else {
+            continue;
+        };
+
+        // each link is `<target>` followed by `;`-separated params, and links are separated by commas
+        while let Some(start) = rest.find('<') {
+            let end = start + rest[start..].find('>')?;
+            let target = &rest[start + 1..end];
+            let params = &rest[end + 1..];
+            let params = &params[..params.find('<').unwrap_or(params.len())];
+            rest = &rest[end + 1..];
+
+            let is_canonical = params.split(';').any(|param| {
+                let Some((name, value)) = param.split_once('=') else {
+                    return false;
+                };
+
+                name.trim().eq_ignore_ascii_case("rel")
+                    && value
+                        .trim()
+                        .trim_matches('"')
+                        .split_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+            });
+
+            if is_canonical {
+                return base.join(target).ok();
+            }
+        }
+    }
+
+    None
+}
+
+// urls we deliberately didn't fetch (or weren't allowed to connect to) aren't failures
+fn failure_class(err: &EvergardenError) -> Option<FailureClass> {
+    let err = match err {
+        EvergardenError::RobotsDisallowed
+        | EvergardenError::PreflightRejected(_)
+        | EvergardenError::Skipped(_)
+        | EvergardenError::BudgetExhausted => return None,
+        EvergardenError::BodyRead(err) => err,
+        _ => return Some(FailureClass::Other),
+    };
+
+    Some(match err.as_ref() {
+        BodyReadError::TimedOut => FailureClass::Timeout,
+        BodyReadError::BodyTooLarge | BodyReadError::IOError(_) => FailureClass::Body,
+        BodyReadError::Client(e) => {
+            let mut source = std::error::Error::source(e);
+            while let Some(err) = source {
+                if err.is::<BlockedAddress>() {
+                    return None;
+                }
+                if err.is::<trust_dns_resolver::error::ResolveError>() {
+                    return Some(FailureClass::Dns);
+                }
+                source = err.source();
+            }
+
+            if e.is_timeout() {
+                FailureClass::Timeout
+            } else if e.is_connect() {
+                FailureClass::Connect
+            } else if e.is_incomplete_message() || e.is_body_write_aborted() {
+                FailureClass::Body
+            } else {
+                FailureClass::Other
+            }
+        }
+    })
+}
+
 impl Actor for HttpClient {
     type Input = UrlInfo;
 
@@ -222,59 +1134,176 @@ impl Actor for HttpClient {
         futures_util::future::ready(unreachable!())
     }
 
-    fn run_async_loop(
+    async fn run_async_loop(
         self,
-        rx: flume::Receiver<actors::Message<Self::Input, Self::Output>>,
+        inbox: actors::Inbox<Self::Input, Self::Output>,
         mut program_state: watch::Receiver<ProgramState>,
-    ) -> impl Future<Output = ()> + Send {
-        async move {
-            loop {
-                tokio::select! {
-                    Ok(Message { value, output }) = rx.recv_async() => {
-                        if let Ok(StorageResponse::Retrieve(Some(res))) = self.storage.request(StorageMessage::Retrieve(value.url.clone())).await {
-                            output.send(Ok(res)).unwrap();
+    ) {
+        let mut frontier = Frontier::new(self.blocklist.clone());
+        // fetches that are underway, which are waited for before closing so that what they fetch is stored
+        let mut fetches = JoinSet::new();
+
+        loop {
+            frontier.promote_due();
+            let next_due = frontier.next_due();
+
+            tokio::select! {
+                Some(Message { mut value, output }) = inbox.recv() => {
+                    let received = Instant::now();
+                    value.url = self.rewrite.apply(value.url);
+
+                    if self.blocklist.blocks(&value.url) {
+                        debug!(url = value.url.as_str(), "skipping url blocked by a script");
+                        let _ = output.send(Err(EvergardenError::Skipped(String::from("url was blocked by a script"))));
+                        inbox.metrics().handled(received.elapsed());
+                        continue;
+                    }
+
+                    // scheduled urls are explicit refetches, so whatever is stored doesn't answer them
+                    if value.not_before.is_none() {
+                        if let Some(res) = self.stored_canonical(&value).await {
+                            let _ = output.send(Ok(res));
+                            inbox.metrics().handled(received.elapsed());
                             continue;
                         }
 
-                        let cli = self.clone();
+                        if let Ok(StorageResponse::Retrieve(Some(res))) = self.storage.request(StorageMessage::Retrieve(value.key_url())).await {
+                            if self.refresh_since.map(|since| res.meta.fetched_at >= since).unwrap_or(true) {
+                                let _ = output.send(Ok(res));
+                                inbox.metrics().handled(received.elapsed());
+                                continue;
+                            }
+                        }
+                    }
+
+                    if let Err(e) = self.storage.request(StorageMessage::Queue(value.clone())).await {
+                        error!("failed to persist queued url: {e}");
+                    }
+
+                    frontier.push(value, output);
+                },
+                // whatever's still queued once the budget's spent is left for a resume
+                permit = self.limiter.acquire_owned(), if !frontier.is_empty() && self.budget.exhausted().is_none() => {
+                    let popped = frontier.pop();
 
-                        let permit = cli.limiter.acquire_owned().await;
+                    // a blocked url would otherwise be queued again when the crawl's resumed
+                    let skipped = frontier.take_skipped();
+                    if !skipped.is_empty() {
+                        let storage = self.storage.clone();
                         tokio::task::spawn(async move {
-                            let res = cli.get(value).await;
-                            output.send(res).unwrap();
-                            drop(permit);
+                            for url in skipped {
+                                let _ = storage.request(StorageMessage::Unqueue(url.key_url())).await;
+                            }
                         });
-                    },
-                    _ = program_state.changed() => {
-                        break
-                    },
-                    else => break
-                }
-            }
+                    }
 
-            self.close().await;
+                    let Some(queued) = popped else {
+                        continue;
+                    };
+                    // a host with no room for another request, or that's still waiting out its crawl delay, keeps its
+                    // urls in the frontier until it has, instead of them waiting it out while holding up fetches to
+                    // every other host
+                    let origin = queued.url.url.origin().ascii_serialization();
+                    if let Some(at) = self.host_limiter.busy_until(&origin) {
+                        frontier.hold(queued, at);
+                        continue;
+                    }
+                    let host_permit = match self.limiter.try_acquire_host(&origin) {
+                        Ok(host_permit) => host_permit,
+                        Err(HostBusy) => {
+                            frontier.park(origin, queued);
+                            continue;
+                        }
+                    };
+
+                    let QueuedUrl { url, output, .. } = queued;
+                    // whoever asked for it may have stopped waiting, like a script that timed out
+                    let Some(url) = inbox.unless_abandoned(url, &output) else {
+                        continue;
+                    };
+                    let cli = self.clone();
+                    let inbox = inbox.clone();
+
+                    // fetches are answered once they're done, however long the url waited in the frontier
+                    fetches.spawn(async move {
+                        let started = Instant::now();
+                        // its canonical url may have been found while it waited
+                        let stored = match url.not_before {
+                            None => cli.stored_canonical(&url).await,
+                            Some(_) => None,
+                        };
+                        let res = match stored {
+                            Some(res) => Ok(res),
+                            None => cli.fetch(url.clone(), permit, host_permit).await,
+                        };
+                        // it wasn't fetched, so it stays queued for a resume
+                        if !matches!(res, Err(EvergardenError::BudgetExhausted)) {
+                            let _ = cli.storage.request(StorageMessage::Unqueue(url.key_url())).await;
+                            cli.record_failure(url, &res).await;
+                        }
+                        // whoever asked may have stopped waiting, like a script that timed out
+                        let _ = output.send(res);
+                        inbox.metrics().handled(started.elapsed());
+                    });
+                },
+                Some(_) = fetches.join_next() => {},
+                hosts = self.limiter.released_hosts(), if frontier.has_parked() => {
+                    for (host, room) in hosts {
+                        frontier.unpark(&host, room);
+                    }
+                },
+                // wakes the loop up to promote delayed urls
+                _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {},
+                _ = program_state.changed() => {
+                    break
+                },
+                else => break
+            }
         }
+
+        while fetches.join_next().await.is_some() {}
+        self.close().await;
     }
 
-    type CloseFuture<'a> = futures_util::future::Ready<()> where Self: 'a;
+    type CloseFuture<'a> = impl Future<Output = ()> + Send + 'a where Self: 'a;
 
     fn close<'a>(self) -> Self::CloseFuture<'a> {
-        futures_util::future::ready(())
+        async move {
+            if let Some(browser) = self.browser {
+                browser.shutdown().await;
+            }
+        }
     }
 }
 
+/// Streams a response body to all receivers, spending what's read from `budget`. If `truncate` is set, oversized bodies
+/// are cut off at `max_length` and marked as truncated instead of erroring.
 pub async fn broadcast_body(
     max_length: Option<usize>,
+    truncate: Option<Arc<OnceLock<TruncatedReason>>>,
     mut body: hyper::Body,
     into: async_broadcast::Sender<BodyResult<Bytes>>,
+    budget: Budget,
 ) -> EvergardenResult<()> {
     let mut received = 0;
     loop {
         match body.try_next().await {
             Ok(Some(chunk)) => {
                 received += chunk.len();
+                budget.spend_bytes(chunk.len() as u64);
                 if let Some(max_length) = max_length {
                     if received > max_length {
+                        if let Some(truncated) = truncate {
+                            let keep = chunk.len() - (received - max_length);
+                            if keep > 0 {
+                                let _ = into.broadcast(Ok(chunk.slice(..keep))).await;
+                            }
+
+                            let _ = truncated.set(TruncatedReason::Length);
+                            into.close();
+                            return Ok(());
+                        }
+
                         let _ = into
                             .broadcast(Err(Arc::new(BodyReadError::BodyTooLarge)))
                             .await;
@@ -298,3 +1327,155 @@ pub async fn broadcast_body(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+
+    use super::{AdaptiveLimiter, HostBusy, HostLimiter};
+    use crate::config::AdaptiveConcurrencyConfig;
+
+    fn limiter() -> Arc<AdaptiveLimiter> {
+        Arc::new(AdaptiveLimiter::new(AdaptiveConcurrencyConfig {
+            min_tasks_per_host: NonZeroUsize::new(2).unwrap(),
+            max_tasks_per_host: NonZeroUsize::new(3).unwrap(),
+            target_latency: Duration::from_secs(1),
+            max_error_rate: 0.5,
+            backoff: 0.5,
+        }))
+    }
+
+    fn limit(limiter: &AdaptiveLimiter) -> f64 {
+        limiter.hosts.lock().unwrap()["example.com"].limit
+    }
+
+    const FAST: Duration = Duration::from_millis(100);
+    const SLOW: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn record_grows_the_limit_while_the_host_keeps_up() {
+        let limiter = limiter();
+        let _permit = limiter.try_acquire("example.com").unwrap();
+
+        limiter.record("example.com", FAST, true);
+        assert_eq!(limit(&limiter), 2.5);
+        limiter.record("example.com", FAST, true);
+        assert!((limit(&limiter) - 2.9).abs() < 1e-9);
+
+        // up to the maximum, and no further
+        for _ in 0..10 {
+            limiter.record("example.com", FAST, true);
+        }
+        assert_eq!(limit(&limiter), 3.0);
+    }
+
+    #[test]
+    fn record_backs_off_on_slow_or_failed_requests() {
+        let limiter = limiter();
+        let _permit = limiter.try_acquire("example.com").unwrap();
+        for _ in 0..10 {
+            limiter.record("example.com", FAST, true);
+        }
+
+        limiter.record("example.com", SLOW, true);
+        assert_eq!(limit(&limiter), 2.0);
+
+        // down to the minimum, and no further
+        limiter.record("example.com", FAST, false);
+        assert_eq!(limit(&limiter), 2.0);
+    }
+
+    #[test]
+    fn record_backs_off_once_errors_are_too_frequent() {
+        let limiter = limiter();
+        let _permit = limiter.try_acquire("example.com").unwrap();
+        for _ in 0..10 {
+            limiter.record("example.com", FAST, true);
+        }
+
+        for _ in 0..10 {
+            limiter.record("example.com", FAST, false);
+        }
+        assert_eq!(limit(&limiter), 2.0);
+
+        // fast successes only grow the limit again once the error rate has come back down
+        limiter.record("example.com", FAST, true);
+        limiter.record("example.com", FAST, true);
+        assert_eq!(limit(&limiter), 2.0);
+        limiter.record("example.com", FAST, true);
+        assert_eq!(limit(&limiter), 2.5);
+    }
+
+    #[tokio::test]
+    async fn busy_hosts_are_released() {
+        let limiter = limiter();
+        let first = limiter.try_acquire("example.com").unwrap();
+        let _second = limiter.try_acquire("example.com").unwrap();
+        assert_eq!(limiter.try_acquire("example.com").err(), Some(HostBusy));
+
+        drop(first);
+        assert_eq!(limiter.released().await, [(String::from("example.com"), 1)]);
+        let _third = limiter.try_acquire("example.com").unwrap();
+    }
+
+    #[test]
+    fn hosts_are_released_once_per_waiting_url() {
+        let limiter = limiter();
+        let first = limiter.try_acquire("example.com").unwrap();
+        let second = limiter.try_acquire("example.com").unwrap();
+        for _ in 0..2 {
+            assert_eq!(limiter.try_acquire("example.com").err(), Some(HostBusy));
+        }
+
+        drop(first);
+        drop(second);
+        assert_eq!(limiter.take_released(), [(String::from("example.com"), 2)]);
+
+        // whatever finishes after that has nobody waiting for it
+        let third = limiter.try_acquire("example.com").unwrap();
+        drop(third);
+        assert_eq!(limiter.take_released(), []);
+    }
+
+    #[test]
+    fn nothing_is_kept_for_hosts_nobody_waits_for() {
+        let limiter = limiter();
+        for i in 0..1000 {
+            let host = format!("{i}.example.com");
+            let permit = limiter.try_acquire(&host).unwrap();
+            permit.finish(SLOW, true);
+        }
+
+        assert!(limiter.released.lock().unwrap().is_empty());
+        assert_eq!(limiter.take_released(), []);
+        assert!(limiter.hosts.lock().unwrap().is_empty());
+
+        // a host whose limit grew is remembered until it's back where it started
+        let permit = limiter.try_acquire("example.com").unwrap();
+        permit.finish(FAST, true);
+        assert_eq!(limit(&limiter), 2.5);
+        let permit = limiter.try_acquire("example.com").unwrap();
+        permit.finish(SLOW, true);
+        assert!(limiter.hosts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn host_limiter_spaces_out_slots() {
+        let limiter = HostLimiter::default();
+        let delay = Duration::from_secs(3600);
+
+        assert_eq!(limiter.busy_until("https://example.com"), None);
+        assert_eq!(limiter.reserve("https://example.com", delay), None);
+
+        let next = limiter.busy_until("https://example.com").unwrap();
+        assert_eq!(limiter.reserve("https://example.com", delay), Some(next));
+        assert_eq!(
+            limiter.busy_until("https://example.com"),
+            Some(next + delay)
+        );
+
+        // other hosts, and hosts without a delay, don't wait on it
+        assert_eq!(limiter.reserve("https://example.org", delay), None);
+        assert_eq!(limiter.reserve("https://example.com", Duration::ZERO), None);
+    }
+}
@@ -1,29 +1,206 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
     num::{NonZeroU32, NonZeroUsize},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
     time::Duration,
 };
 
-use actors::Mailbox;
-use evergarden_common::{HttpResponse, ResponseMetadata};
+use actors::{Blocking, Mailbox};
+use evergarden_common::{HttpResponse, Storage, StorageConfig};
 use governor::Quota;
-use hyper::header::CONTENT_TYPE;
+use hyper::{
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    HeaderMap, StatusCode,
+};
+use ipnet::IpNet;
 use neo_mime::{MediaRange, MediaType};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use url::Url;
 
-use crate::client::HttpClient;
+use crate::{
+    client::{CanonicalAliases, HttpClient},
+    frontier::Blocklist,
+};
 
 #[derive(Clone)]
 pub struct GlobalState {
     pub config: GlobalConfig,
+    pub skip: Arc<SkipConfig>,
+    pub canonical: CanonicalConfig,
+    pub extract: Arc<ExtractConfig>,
+    pub robots: Arc<RobotsConfig>,
     pub client: Mailbox<HttpClient>,
+    pub storage: Mailbox<Blocking<Storage>>,
+    pub blocklist: Blocklist,
+    pub canonical_aliases: CanonicalAliases,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GlobalConfig {
     pub max_hops: usize,
+    /// Who's running the crawl, like a name and an email address, recorded in the warcinfo record of its WARC files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+}
+
+/// Limits on how much one run of the crawl does. Once it reaches any of them, it stops fetching and ends, leaving
+/// whatever's still queued for a resume.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BudgetConfig {
+    /// How many requests to send, not counting robots.txt and preflights.
+    #[serde(default)]
+    pub max_requests: Option<u64>,
+    /// How many bytes of response bodies to read.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// How long to crawl for.
+    #[serde(with = "humantime_serde", default)]
+    pub max_duration: Option<Duration>,
+}
+
+/// Built-in link extractors, which run on every response alongside any matching scripts.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ExtractConfig {
+    /// Queue the article links of RSS and Atom feeds.
+    #[serde(default)]
+    pub feeds: bool,
+    /// Queue in-scope urls found in string literals of javascript and json responses. This is a heuristic, so
+    /// expect some urls that don't exist.
+    #[serde(default)]
+    pub script_urls: bool,
+    /// Queue the pages and nested sitemaps listed in xml sitemaps (including gzipped `.xml.gz` ones).
+    #[serde(default)]
+    pub sitemaps: bool,
+}
+
+/// Rewrites applied to every url before it is queued (and so before its storage key is computed), so that
+/// tracking and session parameters don't produce duplicate captures of the same page.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RewriteConfig {
+    /// Query parameters to remove, case-insensitively. A trailing `*` matches any parameter with that prefix.
+    #[serde(default)]
+    pub strip_params: Vec<String>,
+    /// Regex replacements over the whole url, applied in order after `strip_params`.
+    #[serde(default)]
+    pub rules: Vec<RewriteRule>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RewriteRule {
+    #[serde(with = "serde_regex")]
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+impl RewriteConfig {
+    pub fn apply(&self, mut url: Url) -> Url {
+        if !self.strip_params.is_empty() && url.query().is_some() {
+            let pairs = url
+                .query_pairs()
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect::<Vec<_>>();
+            let kept = pairs
+                .iter()
+                .filter(|(k, _)| !self.strips_param(k))
+                .collect::<Vec<_>>();
+
+            // only re-serialize when something was removed, so untouched queries keep their exact encoding
+            if kept.len() != pairs.len() {
+                if kept.is_empty() {
+                    url.set_query(None);
+                } else {
+                    url.query_pairs_mut().clear().extend_pairs(kept);
+                }
+            }
+        }
+
+        for rule in &self.rules {
+            let rewritten = rule.pattern.replace_all(url.as_str(), &rule.replacement);
+            if let Ok(rewritten) = Url::parse(&rewritten) {
+                url = rewritten;
+            }
+        }
+
+        url
+    }
+
+    fn strips_param(&self, name: &str) -> bool {
+        self.strip_params
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name
+                    .get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix)),
+                None => name.eq_ignore_ascii_case(pattern),
+            })
+    }
+}
+
+/// Denylists for urls and responses that shouldn't be archived.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SkipConfig {
+    /// File extensions (with or without the leading dot), checked before a url is enqueued.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Content types, checked as soon as response headers arrive.
+    #[serde(default)]
+    pub mime_types: Vec<MediaRange>,
+    #[serde(default)]
+    pub mode: SkipMode,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipMode {
+    /// Never fetch matching urls, and abort the body of matching responses.
+    #[default]
+    Fetch,
+    /// Fetch matching responses (so scripts still see them), but don't store them.
+    Store,
+}
+
+impl SkipConfig {
+    /// Whether a url should never even be requested.
+    pub fn skips_fetching(&self, url: &Url) -> bool {
+        self.mode == SkipMode::Fetch && self.skips_url(url)
+    }
+
+    pub fn skips_url(&self, url: &Url) -> bool {
+        if self.extensions.is_empty() {
+            return false;
+        }
+
+        let Some(extension) = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|file| file.rsplit_once('.'))
+            .map(|(_, ext)| ext)
+        else {
+            return false;
+        };
+
+        self.extensions.iter().any(|skipped| {
+            skipped
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(extension)
+        })
+    }
+
+    pub fn skips_type(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| MediaType::parse(header).ok())
+            .map(|header| self.mime_types.iter().any(|range| range.matches(&header)))
+            .unwrap_or(false)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -32,8 +209,210 @@ pub struct HttpConfig {
     pub timeout: Duration,
     #[serde(default)]
     pub max_body_length: Option<usize>,
+    /// Keep the first `max_body_length` bytes of oversized bodies instead of failing the fetch.
+    #[serde(default)]
+    pub truncate_bodies: bool,
     #[serde(default)]
     pub headers: Vec<HeaderPair>,
+    /// Advertise every content coding we can decode (gzip, deflate, br and zstd), unless `headers` sets
+    /// `Accept-Encoding` itself. Bodies are always stored as received.
+    #[serde(default)]
+    pub accept_encoding: bool,
+    #[serde(default)]
+    pub robots: RobotsConfig,
+    #[serde(default)]
+    pub preflight: PreflightConfig,
+    #[serde(default)]
+    pub dns: DnsConfig,
+    #[serde(default)]
+    pub address_policy: AddressPolicy,
+    #[serde(default)]
+    pub canonical: CanonicalConfig,
+    #[serde(default)]
+    pub browser: BrowserConfig,
+}
+
+/// What to do with the canonical url a response advertises, in a `Link: <...>; rel="canonical"` header or an html
+/// `<link rel="canonical">`. One from the header is always recorded in the response metadata.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default)]
+pub struct CanonicalConfig {
+    /// Queue the canonical url as if it had been discovered on the page.
+    #[serde(default)]
+    pub enqueue: bool,
+    /// Answer later requests for a url with the stored capture of its canonical url, once it is known.
+    #[serde(default)]
+    pub dedupe: bool,
+}
+
+/// Which addresses the crawler may connect to. Private, shared (carrier-grade nat), loopback and link-local addresses are
+/// refused by default, since scripts can submit arbitrary urls. Addresses from static `dns` overrides are always
+/// allowed.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AddressPolicy {
+    /// Allow connecting to any address.
+    #[serde(default)]
+    pub allow_private: bool,
+    /// Ranges that are allowed even though they are private.
+    #[serde(default)]
+    pub allowed_ranges: Vec<IpNet>,
+}
+
+impl AddressPolicy {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+
+        self.allow_private
+            || !is_internal(ip)
+            || self.allowed_ranges.iter().any(|range| range.contains(&ip))
+    }
+}
+
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [first, second, ..] = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                // carrier-grade nat, 100.64.0.0/10
+                || (first == 100 && (second & 0xc0) == 64)
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                // unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // link-local, fe80::/10
+                || (first & 0xffc0) == 0xfe80
+                // the deprecated site-local, fec0::/10
+                || (first & 0xffc0) == 0xfec0
+        }
+    }
+}
+
+/// Static name resolution, consulted before any dns lookups are made.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DnsConfig {
+    /// Maps hostnames to the addresses they should be fetched from.
+    #[serde(default)]
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+    /// An `/etc/hosts`-style file to read additional overrides from. Entries in `hosts` take precedence.
+    #[serde(default)]
+    pub hosts_file: Option<PathBuf>,
+}
+
+/// Pages to load in a headless Chromium (over the DevTools protocol) instead of fetching them directly.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BrowserConfig {
+    /// Urls matching any of these are rendered in the browser, with every request the page makes recorded. Everything
+    /// else is fetched by the plain client.
+    #[serde(with = "serde_regex", default)]
+    pub patterns: Vec<Regex>,
+    /// The Chromium binary to launch. Looked up in the usual places if unset.
+    #[serde(default)]
+    pub executable: Option<PathBuf>,
+    /// Extra command line arguments for Chromium.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// How long the page's network has to be idle after it loads before it's considered done.
+    #[serde(with = "humantime_serde", default = "default_settle")]
+    pub settle: Duration,
+    /// Store a full-page png screenshot of every rendered page as `urn:screenshot:<url>`.
+    #[serde(default)]
+    pub screenshots: bool,
+    /// Store the serialized DOM of every rendered page, after its scripts ran, as `urn:dom:<url>`.
+    #[serde(default)]
+    pub dom_snapshots: bool,
+}
+
+fn default_settle() -> Duration {
+    Duration::from_millis(500)
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            executable: None,
+            args: Vec::new(),
+            settle: default_settle(),
+            screenshots: false,
+            dom_snapshots: false,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RobotsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_robots_agent")]
+    pub user_agent: String,
+    #[serde(with = "humantime_serde", default)]
+    pub max_crawl_delay: Option<Duration>,
+    /// Queue the sitemaps listed in each origin's robots.txt, if they're in scope. This fetches robots.txt even if
+    /// `enabled` is off; set `extract.sitemaps` to also follow what they list.
+    #[serde(default)]
+    pub sitemaps: bool,
+    /// Honor `noindex`/`nofollow` from `X-Robots-Tag` headers and robots meta tags, and `rel="nofollow"` links: nothing is
+    /// queued from nofollow pages, and noindex pages aren't listed as pages when exporting.
+    #[serde(default)]
+    pub directives: bool,
+}
+
+impl RobotsConfig {
+    /// Whether robots.txt needs to be fetched at all.
+    pub fn uses_robots_txt(&self) -> bool {
+        self.enabled || self.sitemaps
+    }
+}
+
+fn default_robots_agent() -> String {
+    String::from("evergarden")
+}
+
+impl Default for RobotsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user_agent: default_robots_agent(),
+            max_crawl_delay: None,
+            sitemaps: false,
+            directives: false,
+        }
+    }
+}
+
+/// Filters checked against the headers of a HEAD request before committing to a GET.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PreflightConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to `max_body_length` when unset.
+    #[serde(default)]
+    pub max_content_length: Option<u64>,
+    #[serde(default)]
+    pub mime_types: Vec<MediaRange>,
+}
+
+impl PreflightConfig {
+    pub fn matches_type(&self, headers: &HeaderMap) -> bool {
+        if self.mime_types.is_empty() {
+            return true;
+        }
+
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| MediaType::parse(header).ok())
+            .map(|header| self.mime_types.iter().any(|range| range.matches(&header)))
+            .unwrap_or(true)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -42,12 +421,69 @@ pub struct HeaderPair {
     pub value: String,
 }
 
+/// How a script is run.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptEngine {
+    /// `command` is spawned once per worker and spoken to over the stdio protocol.
+    #[default]
+    Process,
+    /// `command` is the path to a WebAssembly module (binary or text), instantiated once per worker. See
+    /// [`crate::scripting::wasm`] for the interface it has to implement.
+    Wasm,
+    /// `command` is the path to a Rhai script, evaluated in-process for each response. See
+    /// [`crate::scripting::rhai`] for what it can access.
+    Rhai,
+    /// `command` is the path of a unix socket a long-running service listens on. Each worker opens its own
+    /// connection and speaks the stdio protocol over it.
+    Unix,
+    /// `command` is the `host:port` address of a long-running service, connected to like with `unix`.
+    Tcp,
+}
+
+/// Whether responses wait for a script.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptMode {
+    /// Each response is done being processed once the script is done with it, so a slow script holds up the
+    /// responses after it.
+    #[default]
+    Blocking,
+    /// Responses are queued for the script and left to it. The crawl still waits for it to finish before ending.
+    Detached,
+}
+
+fn default_script_queue() -> usize {
+    256
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ScriptConfig {
     pub filter: ScriptFilter,
+    #[serde(default)]
+    pub engine: ScriptEngine,
     pub command: String,
+    #[serde(default)]
     pub args: Vec<String>,
+    /// How many instances of the script process responses in parallel.
     pub workers: usize,
+    /// How many responses can wait for a free worker before whoever is handing them over has to wait too.
+    #[serde(default = "default_script_queue")]
+    pub queue_size: usize,
+    #[serde(default)]
+    pub mode: ScriptMode,
+    /// Hand scripts bodies with their content encoding undone and converted to utf-8, instead of as received. Their
+    /// headers are adjusted to match, and the charset they were in is passed along as `original_charset`.
+    #[serde(default)]
+    pub decode_body: bool,
+    /// How long a worker may spend on a single response before it's killed and restarted.
+    #[serde(with = "humantime_serde", default)]
+    pub timeout: Option<Duration>,
+    /// Arbitrary settings handed to every worker at startup, as json. Processes get them in the
+    /// `EVERGARDEN_SCRIPT_CONFIG` environment variable, rhai scripts as the `config` constant, and wasm modules
+    /// through their `configure` export. Services connected to over a socket are configured on their own end.
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -56,11 +492,31 @@ pub struct ScriptFilter {
     pub(crate) url_pattern: Option<Regex>,
     #[serde(default)]
     pub(crate) mime_types: Vec<MediaRange>,
+    /// Status codes to run on, like `200`, `"2xx"` or `"400-403"`. Without any, every status but 4xx and 5xx errors
+    /// matches.
+    #[serde(default)]
+    pub(crate) status: Vec<StatusRange>,
+    /// Also run on 4xx and 5xx responses when no `status` is given.
+    #[serde(default)]
+    pub(crate) include_errors: bool,
 }
 
 impl ScriptFilter {
     pub fn matches(&self, data: &HttpResponse) -> bool {
-        self.matches_url(data.meta.url.url.as_str()) && self.matches_types(&data.meta)
+        self.matches_head(&data.meta.url.url, data.meta.status, &data.meta.headers)
+    }
+
+    /// Whether a response from `url` with this status and these headers matches, before its body's been read.
+    pub fn matches_head(&self, url: &Url, status: StatusCode, headers: &HeaderMap) -> bool {
+        self.matches_url(url.as_str()) && self.matches_types(headers) && self.matches_status(status)
+    }
+
+    fn matches_status(&self, status: StatusCode) -> bool {
+        if self.status.is_empty() {
+            return self.include_errors || !(status.is_client_error() || status.is_server_error());
+        }
+
+        self.status.iter().any(|range| range.contains(status))
     }
 
     fn matches_url(&self, url: &str) -> bool {
@@ -70,8 +526,8 @@ impl ScriptFilter {
             .unwrap_or(true)
     }
 
-    fn matches_types(&self, data: &ResponseMetadata) -> bool {
-        data.headers
+    fn matches_types(&self, headers: &HeaderMap) -> bool {
+        headers
             .get(CONTENT_TYPE)
             .and_then(|header| header.to_str().ok())
             .and_then(|header| MediaType::parse(header).ok())
@@ -80,6 +536,78 @@ impl ScriptFilter {
     }
 }
 
+/// An inclusive range of status codes, written as a single code (`404`), a class (`"4xx"`) or a range (`"400-403"`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "StatusRangeRepr", into = "String")]
+pub struct StatusRange {
+    start: u16,
+    end: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatusRangeRepr {
+    Code(u16),
+    Range(String),
+}
+
+impl StatusRange {
+    pub fn contains(&self, status: StatusCode) -> bool {
+        (self.start..=self.end).contains(&status.as_u16())
+    }
+}
+
+impl FromStr for StatusRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid status range {s:?}, expected e.g. 404, 4xx or 400-403");
+        let code = |s: &str| s.trim().parse::<u16>().map_err(|_| invalid());
+
+        let (start, end) = if let Some(class) = s.strip_suffix("xx") {
+            let class = code(class)?;
+            (class * 100, class * 100 + 99)
+        } else if let Some((start, end)) = s.split_once('-') {
+            (code(start)?, code(end)?)
+        } else {
+            (code(s)?, code(s)?)
+        };
+
+        if !(100..=999).contains(&start) || end > 999 || start > end {
+            return Err(invalid());
+        }
+
+        Ok(StatusRange { start, end })
+    }
+}
+
+impl TryFrom<StatusRangeRepr> for StatusRange {
+    type Error = String;
+
+    fn try_from(repr: StatusRangeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            StatusRangeRepr::Code(code) => code.to_string().parse(),
+            StatusRangeRepr::Range(range) => range.parse(),
+        }
+    }
+}
+
+impl From<StatusRange> for RangeInclusive<u16> {
+    fn from(range: StatusRange) -> RangeInclusive<u16> {
+        range.start..=range.end
+    }
+}
+
+impl From<StatusRange> for String {
+    fn from(range: StatusRange) -> String {
+        if range.start == range.end {
+            range.start.to_string()
+        } else {
+            format!("{}-{}", range.start, range.end)
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RateLimitingDuration {
@@ -105,6 +633,46 @@ pub struct RateLimitingConfig {
     pub per: RateLimitingDuration,
     #[serde(with = "humantime_serde")]
     pub jitter: Duration,
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveConcurrencyConfig>,
+}
+
+/// AIMD tuning of the number of concurrent requests per host: the limit grows while a host answers quickly and
+/// successfully, and is cut back when responses get slow or start failing.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AdaptiveConcurrencyConfig {
+    #[serde(default = "default_min_tasks_per_host")]
+    pub min_tasks_per_host: NonZeroUsize,
+    #[serde(default = "default_max_tasks_per_host")]
+    pub max_tasks_per_host: NonZeroUsize,
+    #[serde(with = "humantime_serde", default = "default_target_latency")]
+    pub target_latency: Duration,
+    /// Error ratio (as a moving average) above which the limit is decreased.
+    #[serde(default = "default_max_error_rate")]
+    pub max_error_rate: f64,
+    /// Multiplier applied to the limit on each slow or failed response.
+    #[serde(default = "default_backoff")]
+    pub backoff: f64,
+}
+
+fn default_min_tasks_per_host() -> NonZeroUsize {
+    NonZeroUsize::new(1).unwrap()
+}
+
+fn default_max_tasks_per_host() -> NonZeroUsize {
+    NonZeroUsize::new(16).unwrap()
+}
+
+fn default_target_latency() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_max_error_rate() -> f64 {
+    0.1
+}
+
+fn default_backoff() -> f64 {
+    0.5
 }
 
 impl Default for RateLimitingConfig {
@@ -114,6 +682,7 @@ impl Default for RateLimitingConfig {
             n: NonZeroU32::new(200).unwrap(),
             per: RateLimitingDuration::Second,
             jitter: Duration::from_millis(50),
+            adaptive: None,
         }
     }
 }
@@ -127,10 +696,278 @@ impl RateLimitingConfig {
     }
 }
 
+/// Something that happens to a crawl, which [`HooksConfig`] can report.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrawlEvent {
+    Started,
+    Finished,
+    /// The crawl stopped on an error.
+    Failed,
+    /// The crawl reached a limit of its [`BudgetConfig`], and is stopping.
+    BudgetExhausted,
+}
+
+fn default_hook_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Where to report a crawl starting, finishing, failing or running out of budget, for crawls nobody's watching. Every
+/// event comes with a json payload of what the crawl's done so far, which has a `text` summary for chat webhooks like
+/// Slack's.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HooksConfig {
+    /// Urls to POST the payload to.
+    #[serde(default)]
+    pub webhooks: Vec<Url>,
+    /// A command to run for each event, with the payload on stdin and the event in `EVERGARDEN_EVENT`.
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Which events to report. Every one, without any.
+    #[serde(default)]
+    pub events: Vec<CrawlEvent>,
+    /// How long a webhook or the command may take before it's given up on.
+    #[serde(with = "humantime_serde", default = "default_hook_timeout")]
+    pub timeout: Duration,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            command: None,
+            args: Vec::new(),
+            events: Vec::new(),
+            timeout: default_hook_timeout(),
+        }
+    }
+}
+
+impl HooksConfig {
+    pub fn reports(&self, event: CrawlEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FullConfig {
     pub general: GlobalConfig,
     pub ratelimiter: RateLimitingConfig,
     pub http: HttpConfig,
+    #[serde(default)]
+    pub skip: SkipConfig,
+    #[serde(default)]
+    pub rewrite: RewriteConfig,
+    #[serde(default)]
+    pub extract: ExtractConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
     pub scripts: BTreeMap<Arc<str>, ScriptConfig>,
 }
+
+impl FullConfig {
+    /// What's wrong with the config that parsing it doesn't catch, like headers that can't be sent or script commands
+    /// that don't exist, so that it's found before a crawl starts rather than partway through.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for HeaderPair { name, value } in &self.http.headers {
+            if HeaderName::from_str(name).is_err() {
+                problems.push(format!("http.headers: {name:?} isn't a valid header name"));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!(
+                    "http.headers: the value of {name} isn't a valid header value"
+                ));
+            }
+        }
+
+        if let Some(hosts_file) = &self.http.dns.hosts_file {
+            if !hosts_file.is_file() {
+                problems.push(format!(
+                    "http.dns.hosts_file: {} doesn't exist",
+                    hosts_file.display()
+                ));
+            }
+        }
+
+        if let Some(executable) = &self.http.browser.executable {
+            if !is_executable(executable) {
+                problems.push(format!(
+                    "http.browser.executable: {} isn't an executable file",
+                    executable.display()
+                ));
+            }
+        }
+
+        if let Some(adaptive) = &self.ratelimiter.adaptive {
+            if adaptive.min_tasks_per_host > adaptive.max_tasks_per_host {
+                problems.push(String::from(
+                    "ratelimiter.adaptive: min_tasks_per_host is more than max_tasks_per_host",
+                ));
+            }
+        }
+
+        if let Some(command) = &self.hooks.command {
+            if find_executable(command).is_none() {
+                problems.push(format!(
+                    "hooks.command: {command} isn't an executable file, or on PATH"
+                ));
+            }
+        }
+
+        for (name, script) in &self.scripts {
+            if script.workers == 0 {
+                problems.push(format!("scripts.{name}: workers has to be at least 1"));
+            }
+
+            match script.engine {
+                ScriptEngine::Process if find_executable(&script.command).is_none() => {
+                    problems.push(format!(
+                        "scripts.{name}: {} isn't an executable file, or on PATH",
+                        script.command
+                    ));
+                }
+                ScriptEngine::Wasm | ScriptEngine::Rhai
+                    if !Path::new(&script.command).is_file() =>
+                {
+                    problems.push(format!("scripts.{name}: {} doesn't exist", script.command));
+                }
+                // services are started on their own, and may not be listening yet
+                ScriptEngine::Tcp
+                    if !script
+                        .command
+                        .rsplit_once(':')
+                        .is_some_and(|(_, port)| port.parse::<u16>().is_ok()) =>
+                {
+                    problems.push(format!(
+                        "scripts.{name}: {} isn't a host:port address",
+                        script.command
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        problems
+    }
+}
+
+/// Looks `command` up the way spawning it would: as a path if it has a slash in it, and on `PATH` otherwise.
+fn find_executable(command: &str) -> Option<PathBuf> {
+    let path = Path::new(command);
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_path_buf());
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::StatusCode;
+
+    use super::{AddressPolicy, FullConfig, StatusRange};
+
+    #[test]
+    fn status_ranges() {
+        let range = |s: &str| s.parse::<StatusRange>();
+        let status = |code| StatusCode::from_u16(code).unwrap();
+
+        assert!(range("404").unwrap().contains(status(404)));
+        assert!(!range("404").unwrap().contains(status(403)));
+        assert!(range("2xx").unwrap().contains(status(204)));
+        assert!(!range("2xx").unwrap().contains(status(301)));
+        assert!(range("400-403").unwrap().contains(status(401)));
+        assert!(!range("400-403").unwrap().contains(status(404)));
+
+        assert!(range("403-400").is_err());
+        assert!(range("0xx").is_err());
+        assert!(range("abc").is_err());
+        assert!(range("1000").is_err());
+
+        let parsed: Vec<StatusRange> = serde_json::from_str(r#"[200, "5xx"]"#).unwrap();
+        assert_eq!(
+            parsed,
+            vec![range("200").unwrap(), range("500-599").unwrap()]
+        );
+    }
+
+    #[test]
+    fn refuses_internal_addresses() {
+        let policy = AddressPolicy::default();
+        let permits = |ip: &str| policy.permits(ip.parse().unwrap());
+
+        for internal in [
+            "10.1.2.3",
+            "127.0.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "100.127.255.254",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "fec0::1",
+            "::ffff:192.168.0.1",
+        ] {
+            assert!(!permits(internal), "{internal} should be refused");
+        }
+        for public in [
+            "93.184.216.34",
+            "100.63.255.255",
+            "100.128.0.1",
+            "2606:4700::1",
+        ] {
+            assert!(permits(public), "{public} should be allowed");
+        }
+    }
+
+    #[test]
+    fn finds_config_problems() {
+        let config = serde_json::from_str::<FullConfig>(
+            r#"{
+                "general": { "max_hops": 1 },
+                "http": {
+                    "timeout": "10s",
+                    "headers": [{ "name": "User Agent", "value": "evergarden" }]
+                },
+                "ratelimiter": { "max_tasks_per_worker": 1, "n": 1, "per": "second", "jitter": "0s" },
+                "scripts": {
+                    "missing": { "filter": {}, "command": "/nonexistent/evergarden-script", "workers": 0 }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.problems(),
+            [
+                r#"http.headers: "User Agent" isn't a valid header name"#,
+                "scripts.missing: workers has to be at least 1",
+                "scripts.missing: /nonexistent/evergarden-script isn't an executable file, or on PATH",
+            ]
+        );
+    }
+}
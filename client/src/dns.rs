@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use evergarden_common::EvergardenResult;
+use hyper::{client::connect::dns::Name, service::Service, Uri};
+use hyper_trust_dns::TrustDnsResolver;
+use url::Host;
+
+use crate::config::{AddressPolicy, DnsConfig};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Returned when every address a host resolves to is refused by the [`AddressPolicy`].
+#[derive(Debug, thiserror::Error)]
+#[error("{0} only resolves to addresses disallowed by the address policy")]
+pub struct BlockedAddress(pub String);
+
+/// Resolver that answers from a static table of host overrides, falling back to trust-dns for everything else.
+/// Looked up addresses are filtered through the [`AddressPolicy`]; overrides are trusted as-is.
+#[derive(Clone)]
+pub struct OverrideResolver {
+    inner: TrustDnsResolver,
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    policy: Arc<AddressPolicy>,
+}
+
+impl OverrideResolver {
+    pub fn new(
+        inner: TrustDnsResolver,
+        config: &DnsConfig,
+        policy: Arc<AddressPolicy>,
+    ) -> EvergardenResult<OverrideResolver> {
+        let mut overrides = match &config.hosts_file {
+            Some(path) => parse_hosts(&std::fs::read_to_string(path)?),
+            None => HashMap::new(),
+        };
+
+        for (host, addrs) in &config.hosts {
+            overrides.insert(host.to_ascii_lowercase(), addrs.clone());
+        }
+
+        Ok(OverrideResolver {
+            inner,
+            overrides: Arc::new(overrides),
+            policy,
+        })
+    }
+
+    /// Whether every address `host` resolves to is allowed, for whatever connects to it without going through this
+    /// resolver, like the browser. Hosts that don't resolve at all aren't.
+    pub async fn allows_host(&self, host: Host<&str>) -> bool {
+        let name = match host {
+            Host::Ipv4(ip) => return self.policy.permits(ip.into()),
+            Host::Ipv6(ip) => return self.policy.permits(ip.into()),
+            Host::Domain(name) if self.overrides.contains_key(&name.to_ascii_lowercase()) => {
+                return true
+            }
+            Host::Domain(name) => name,
+        };
+
+        let Ok(name) = Name::from_str(name) else {
+            return false;
+        };
+
+        match self.inner.clone().call(name).await {
+            Ok(addrs) => {
+                let addrs = addrs.collect::<Vec<_>>();
+                !addrs.is_empty() && addrs.iter().all(|addr| self.policy.permits(addr.ip()))
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Whether a connection to `host` was allowed to end up at `ip`.
+    pub fn permits_addr(&self, host: Option<&str>, ip: IpAddr) -> bool {
+        self.policy.permits(ip)
+            || host
+                .and_then(|host| self.overrides.get(&host.to_ascii_lowercase()))
+                .is_some_and(|addrs| addrs.contains(&ip))
+    }
+
+    /// The overrides as Chromium's `--host-resolver-rules`, so that the browser looks hosts up the same way.
+    pub fn host_resolver_rules(&self) -> Option<String> {
+        let mut rules = self
+            .overrides
+            .iter()
+            .filter_map(|(host, addrs)| {
+                Some(match addrs.first()? {
+                    IpAddr::V4(ip) => format!("MAP {host} {ip}"),
+                    IpAddr::V6(ip) => format!("MAP {host} [{ip}]"),
+                })
+            })
+            .collect::<Vec<_>>();
+        rules.sort();
+
+        (!rules.is_empty()).then(|| rules.join(","))
+    }
+}
+
+impl Service<Name> for OverrideResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        if let Some(addrs) = self.overrides.get(&name.as_str().to_ascii_lowercase()) {
+            let addrs = addrs
+                .iter()
+                .map(|ip| SocketAddr::new(*ip, 0))
+                .collect::<Vec<_>>();
+            return Box::pin(async move { Ok(addrs.into_iter()) });
+        }
+
+        let policy = Arc::clone(&self.policy);
+        let lookup = self.inner.call(name.clone());
+
+        Box::pin(async move {
+            let addrs = lookup
+                .await?
+                .filter(|addr| policy.permits(addr.ip()))
+                .collect::<Vec<_>>();
+
+            if addrs.is_empty() {
+                return Err(BlockedAddress(name.as_str().to_owned()).into());
+            }
+
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Connector wrapper that applies the [`AddressPolicy`] to urls with ip literal hosts, which never reach the resolver.
+#[derive(Clone)]
+pub struct AddressGuard<C> {
+    inner: C,
+    policy: Arc<AddressPolicy>,
+}
+
+impl<C> AddressGuard<C> {
+    pub fn new(inner: C, policy: Arc<AddressPolicy>) -> AddressGuard<C> {
+        AddressGuard { inner, policy }
+    }
+}
+
+impl<C> Service<Uri> for AddressGuard<C>
+where
+    C: Service<Uri>,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let literal = dst
+            .host()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+            .and_then(|host| host.parse::<IpAddr>().ok());
+
+        if let Some(ip) = literal.filter(|ip| !self.policy.permits(*ip)) {
+            return Box::pin(async move { Err(BlockedAddress(ip.to_string()).into()) });
+        }
+
+        let fut = self.inner.call(dst);
+        Box::pin(async move { fut.await.map_err(Into::into) })
+    }
+}
+
+// parses lines of `<address> <hostname> [aliases...]`, ignoring comments and malformed lines
+fn parse_hosts(file: &str) -> HashMap<String, Vec<IpAddr>> {
+    let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+
+    for line in file.lines() {
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        let Some(Ok(addr)) = fields.next().map(str::parse::<IpAddr>) else {
+            continue;
+        };
+
+        for host in fields {
+            hosts
+                .entry(host.to_ascii_lowercase())
+                .or_default()
+                .push(addr);
+        }
+    }
+
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        future::{ready, Ready},
+        io::Write,
+        net::IpAddr,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use hyper::{service::Service, Uri};
+    use hyper_trust_dns::TrustDnsResolver;
+
+    use super::{parse_hosts, AddressGuard, BlockedAddress, OverrideResolver};
+    use crate::config::{AddressPolicy, DnsConfig};
+
+    // a connector that always connects
+    struct Connects;
+
+    impl Service<Uri> for Connects {
+        type Response = ();
+        type Error = BlockedAddress;
+        type Future = Ready<Result<(), BlockedAddress>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), BlockedAddress>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            ready(Ok(()))
+        }
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_hosts_files() {
+        let hosts = parse_hosts(
+            "# a comment\n\
+             10.0.0.1\tIntranet intranet.local # trailing comment\n\
+             10.0.0.2 intranet\n\
+             ::1 localhost6\n\
+             not-an-address example.com\n\
+             10.0.0.3\n",
+        );
+
+        let mut expected = HashMap::new();
+        expected.insert(
+            String::from("intranet"),
+            vec![ip("10.0.0.1"), ip("10.0.0.2")],
+        );
+        expected.insert(String::from("intranet.local"), vec![ip("10.0.0.1")]);
+        expected.insert(String::from("localhost6"), vec![ip("::1")]);
+        assert_eq!(hosts, expected);
+    }
+
+    #[test]
+    fn host_resolver_rules_map_each_host_to_its_first_address() {
+        let mut hosts_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            hosts_file,
+            "10.0.0.9 example.com\n10.0.0.1 mirror.example.com"
+        )
+        .unwrap();
+
+        let mut config = DnsConfig {
+            hosts_file: Some(hosts_file.path().to_path_buf()),
+            ..Default::default()
+        };
+        config.hosts.insert(
+            String::from("Example.com"),
+            vec![ip("2001:db8::1"), ip("10.0.0.2")],
+        );
+        let resolver = OverrideResolver::new(
+            TrustDnsResolver::new(),
+            &config,
+            Arc::new(AddressPolicy::default()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolver.host_resolver_rules().as_deref(),
+            Some("MAP example.com [2001:db8::1],MAP mirror.example.com 10.0.0.1")
+        );
+        // overrides are trusted, even though the policy disallows private addresses
+        assert!(resolver.permits_addr(Some("mirror.example.com"), ip("10.0.0.1")));
+        assert!(!resolver.permits_addr(Some("other.example.com"), ip("10.0.0.1")));
+    }
+
+    #[tokio::test]
+    async fn address_guard_refuses_disallowed_literals() {
+        let mut guard = AddressGuard::new(Connects, Arc::new(AddressPolicy::default()));
+
+        for uri in [
+            "http://127.0.0.1/",
+            "http://[::1]:8080/",
+            "http://[::ffff:10.0.0.1]/",
+        ] {
+            let err = guard.call(Uri::from_static(uri)).await.unwrap_err();
+            assert!(err.is::<BlockedAddress>(), "{uri}");
+        }
+        assert!(guard
+            .call(Uri::from_static("http://93.184.216.34/"))
+            .await
+            .is_ok());
+        // names are left to the resolver
+        assert!(guard
+            .call(Uri::from_static("http://localhost/"))
+            .await
+            .is_ok());
+    }
+}
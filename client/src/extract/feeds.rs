@@ -0,0 +1,109 @@
+use neo_mime::MediaType;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+/// Feed types, plus generic xml since plenty of feeds are served as that.
+pub fn is_feed_type(media_type: &MediaType) -> bool {
+    matches!(
+        (media_type.type_(), media_type.subtype()),
+        ("application", "rss+xml" | "atom+xml" | "rdf+xml" | "xml") | ("text", "xml")
+    )
+}
+
+/// Article links of every item in an RSS 2.0/1.0 or Atom feed.
+pub fn entry_links(body: &[u8]) -> Vec<String> {
+    let mut reader = Reader::from_reader(body);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut links = Vec::new();
+    let mut in_entry = false;
+    let mut in_link = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => match e.local_name().as_ref() {
+                b"item" | b"entry" => in_entry = true,
+                b"link" if in_entry => match atom_link(&e) {
+                    Some(href) => links.extend(href),
+                    // rss puts the url in the element's text instead
+                    None => in_link = true,
+                },
+                _ => {}
+            },
+            Ok(Event::Empty(e)) if in_entry && e.local_name().as_ref() == b"link" => {
+                links.extend(atom_link(&e).flatten());
+            }
+            Ok(Event::Text(text)) if in_link => {
+                if let Ok(text) = text.unescape() {
+                    links.push(text.trim().to_owned());
+                }
+            }
+            Ok(Event::CData(text)) if in_link => {
+                links.push(String::from_utf8_lossy(&text).trim().to_owned());
+            }
+            Ok(Event::End(e)) => match e.local_name().as_ref() {
+                b"item" | b"entry" => in_entry = false,
+                b"link" => in_link = false,
+                _ => {}
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    links.retain(|link| !link.is_empty());
+    links
+}
+
+// None when the element has no href (so it's an rss link), and Some(None) for atom links that aren't the article itself
+fn atom_link(e: &BytesStart) -> Option<Option<String>> {
+    let href = e.try_get_attribute("href").ok()??;
+    let href = href.unescape_value().ok()?.into_owned();
+
+    let rel = e
+        .try_get_attribute("rel")
+        .ok()
+        .flatten()
+        .and_then(|rel| rel.unescape_value().ok().map(|v| v.into_owned()));
+
+    match rel.as_deref() {
+        None | Some("alternate") => Some(Some(href)),
+        Some(_) => Some(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::entry_links;
+
+    #[test]
+    fn rss_and_atom() {
+        let rss = br#"<?xml version="1.0"?>
+<rss version="2.0"><channel>
+  <link>https://example.com/</link>
+  <item><title>one</title><link>https://example.com/posts/1</link></item>
+  <item><link><![CDATA[/posts/2?a=1&b=2]]></link></item>
+</channel></rss>"#;
+
+        assert_eq!(
+            entry_links(rss),
+            vec!["https://example.com/posts/1", "/posts/2?a=1&b=2"]
+        );
+
+        let atom = br#"<feed xmlns="http://www.w3.org/2005/Atom">
+  <link href="https://example.com/" rel="self"/>
+  <entry>
+    <link href="https://example.com/a" rel="alternate"/>
+    <link href="https://example.com/a.mp3" rel="enclosure"/>
+  </entry>
+  <entry><link href="/b"/></entry>
+</feed>"#;
+
+        assert_eq!(entry_links(atom), vec!["https://example.com/a", "/b"]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+
+use evergarden_common::UrlInfo;
+use lazy_regex::regex;
+use neo_mime::MediaType;
+
+pub fn is_script_type(media_type: &MediaType) -> bool {
+    match (media_type.type_(), media_type.subtype()) {
+        ("application" | "text", "javascript" | "x-javascript" | "ecmascript") => true,
+        ("application", "json") => true,
+        _ => media_type.suffix() == Some("json"),
+    }
+}
+
+/// Absolute and root-relative urls in string literals, kept only when they stay in the scope of `source`
+/// (or on its host, for unscoped crawls).
+pub fn string_urls(body: &[u8], source: &UrlInfo) -> Vec<String> {
+    // json escapes slashes
+    let body = String::from_utf8_lossy(body).replace("\\/", "/");
+
+    let mut seen = HashSet::new();
+    regex!(r#"["'`]((?:https?:)?//[^\s"'`<>\\]+|/[A-Za-z0-9_\-.~%][^\s"'`<>\\]*)["'`]"#)
+        .captures_iter(&body)
+        .map(|captures| captures[1].to_owned())
+        .filter(|literal| {
+            let Ok(url) = source.url.join(literal) else {
+                return false;
+            };
+
+            let in_scope = match &source.scope {
+                Some(scope) => scope.contains(&url),
+                None => url.host() == source.url.host(),
+            };
+
+            in_scope && seen.insert(url)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use evergarden_common::UrlInfo;
+
+    use super::string_urls;
+
+    #[test]
+    fn script_literals() {
+        let source = UrlInfo::start("https://example.com/static/app.js").unwrap();
+        let body = br#"
+            fetch("/api/v1/items?page=2");
+            const cdn = 'https://cdn.other.com/lib.js';
+            const img = `https://example.com/img/a.png`;
+            const re = "/\d+/";
+            const json = {"next": "https:\/\/example.com\/page\/3", "again": "/api/v1/items?page=2"};
+            // "/ not a url"
+        "#;
+
+        assert_eq!(
+            string_urls(body, &source),
+            vec![
+                "/api/v1/items?page=2",
+                "https://example.com/img/a.png",
+                "https://example.com/page/3"
+            ]
+        );
+    }
+}
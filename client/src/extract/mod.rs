@@ -0,0 +1,152 @@
+pub mod feeds;
+pub mod literals;
+pub mod sitemaps;
+pub mod text;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use evergarden_common::ResponseMetadata;
+use hyper::{header::CONTENT_TYPE, HeaderMap, StatusCode};
+use lazy_regex::regex;
+use neo_mime::MediaType;
+use url::Url;
+
+use crate::config::{ExtractConfig, ScriptConfig, ScriptFilter};
+
+fn media_type(headers: &HeaderMap) -> Option<MediaType> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| MediaType::parse(header).ok())
+}
+
+pub fn is_html(meta: &ResponseMetadata) -> bool {
+    media_type(&meta.headers).is_some_and(|media_type| {
+        matches!(
+            (media_type.type_(), media_type.subtype()),
+            ("text", "html") | ("application", "xhtml+xml")
+        )
+    })
+}
+
+/// Finds the target of an html `<link rel="canonical" href="...">`, resolved against the response url `base`.
+pub fn canonical_link(html: &[u8], base: &Url) -> Option<Url> {
+    let html = String::from_utf8_lossy(html);
+
+    for tag in regex!(r"(?i)<link\s[^>]*>").find_iter(&html) {
+        let (mut rel, mut href) = (None, None);
+        for attr in regex!(r#"(?i)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+            .captures_iter(tag.as_str())
+        {
+            let value = attr
+                .get(2)
+                .or(attr.get(3))
+                .or(attr.get(4))
+                .map(|v| v.as_str());
+            match attr[1].to_ascii_lowercase().as_str() {
+                "rel" => rel = value,
+                "href" => href = value,
+                _ => {}
+            }
+        }
+
+        let is_canonical = rel.is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+        });
+        if let (true, Some(href)) = (is_canonical, href) {
+            return base.join(&href.trim().replace("&amp;", "&")).ok();
+        }
+    }
+
+    None
+}
+
+/// Whether any enabled built-in extractor wants to look at the body of a response from `url` with these headers.
+pub fn wants_body(config: &ExtractConfig, url: &Url, headers: &HeaderMap) -> bool {
+    let Some(media_type) = media_type(headers) else {
+        return false;
+    };
+
+    (config.feeds && feeds::is_feed_type(&media_type))
+        || (config.script_urls && literals::is_script_type(&media_type))
+        || (config.sitemaps && sitemaps::is_sitemap_type(&media_type, url))
+}
+
+/// Everything that looks at response bodies for urls to crawl: the built-in extractors, and scripts. Dry runs only
+/// read the bodies these would look at.
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    extract: ExtractConfig,
+    filters: Vec<ScriptFilter>,
+}
+
+impl Discovery {
+    pub fn new(extract: &ExtractConfig, scripts: &BTreeMap<Arc<str>, ScriptConfig>) -> Discovery {
+        Discovery {
+            extract: extract.clone(),
+            filters: scripts
+                .values()
+                .map(|script| script.filter.clone())
+                .collect(),
+        }
+    }
+
+    /// Whether anything would look at the body of a response from `url` with this status and these headers.
+    pub fn wants_body(&self, url: &Url, status: StatusCode, headers: &HeaderMap) -> bool {
+        wants_body(&self.extract, url, headers)
+            || self
+                .filters
+                .iter()
+                .any(|filter| filter.matches_head(url, status, headers))
+    }
+}
+
+/// Runs the enabled built-in extractors over a decoded body, returning the (possibly relative) urls found.
+pub fn extract_urls(config: &ExtractConfig, meta: &ResponseMetadata, body: &[u8]) -> Vec<String> {
+    let Some(media_type) = media_type(&meta.headers) else {
+        return Vec::new();
+    };
+
+    let mut urls = Vec::new();
+
+    if config.feeds && feeds::is_feed_type(&media_type) {
+        urls.extend(feeds::entry_links(body));
+    }
+
+    if config.script_urls && literals::is_script_type(&media_type) {
+        urls.extend(literals::string_urls(body, &meta.url));
+    }
+
+    if config.sitemaps && sitemaps::is_sitemap_type(&media_type, &meta.url.url) {
+        urls.extend(sitemaps::locations(body));
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::canonical_link;
+
+    #[test]
+    fn finds_canonical_links_in_html() {
+        let base = Url::parse("https://example.com/a/page?session=1").unwrap();
+        let canonical = |html: &str| canonical_link(html.as_bytes(), &base).map(String::from);
+
+        assert_eq!(
+            canonical(
+                r#"<head><link rel="stylesheet" href="/s.css"><LINK REL="Canonical" HREF="/a/page"></head>"#
+            ),
+            Some("https://example.com/a/page".to_owned())
+        );
+        assert_eq!(
+            canonical("<link href='page?id=1&amp;lang=en' rel='alternate canonical'>"),
+            Some("https://example.com/a/page?id=1&lang=en".to_owned())
+        );
+        assert_eq!(canonical(r#"<link rel="alternate" href="/b">"#), None);
+        assert_eq!(canonical(r#"<link rel="canonical">"#), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{borrow::Cow, io::Read};
+
+use flate2::read::GzDecoder;
+use neo_mime::MediaType;
+use quick_xml::{events::Event, Reader};
+use url::Url;
+
+// sitemaps are mostly served as xml, but gzipped ones usually come as a plain gzip file
+pub fn is_sitemap_type(media_type: &MediaType, url: &Url) -> bool {
+    match (media_type.type_(), media_type.subtype()) {
+        ("application", "xml") | ("text", "xml") => true,
+        ("application", "gzip" | "x-gzip" | "octet-stream") => url.path().ends_with(".xml.gz"),
+        _ => false,
+    }
+}
+
+/// Every `<loc>` of a sitemap's `<url>` and `<sitemap>` entries, so sitemap indexes are followed as well.
+/// Anything that isn't a urlset or sitemap index yields nothing.
+pub fn locations(body: &[u8]) -> Vec<String> {
+    let body = gunzip(body);
+    let mut reader = Reader::from_reader(body.as_ref());
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut locs = Vec::new();
+    let mut is_sitemap = false;
+    let mut in_entry = false;
+    let mut in_loc = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => match e.local_name().as_ref() {
+                b"urlset" | b"sitemapindex" => is_sitemap = true,
+                b"url" | b"sitemap" if is_sitemap => in_entry = true,
+                b"loc" if in_entry => in_loc = true,
+                _ => {}
+            },
+            Ok(Event::Text(text)) if in_loc => {
+                if let Ok(text) = text.unescape() {
+                    locs.push(text.trim().to_owned());
+                }
+            }
+            Ok(Event::CData(text)) if in_loc => {
+                locs.push(String::from_utf8_lossy(&text).trim().to_owned());
+            }
+            Ok(Event::End(e)) => match e.local_name().as_ref() {
+                b"url" | b"sitemap" => in_entry = false,
+                b"loc" => in_loc = false,
+                _ => {}
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    locs.retain(|loc| !loc.is_empty());
+    locs
+}
+
+fn gunzip(body: &[u8]) -> Cow<'_, [u8]> {
+    if !body.starts_with(&[0x1f, 0x8b]) {
+        return Cow::Borrowed(body);
+    }
+
+    let mut out = Vec::new();
+    match GzDecoder::new(body).read_to_end(&mut out) {
+        Ok(_) => Cow::Owned(out),
+        Err(_) => Cow::Borrowed(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::locations;
+
+    #[test]
+    fn urlsets_and_indexes() {
+        let urlset = br#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://example.com/</loc><lastmod>2023-01-01</lastmod></url>
+  <url><loc> https://example.com/a?x=1&amp;y=2 </loc></url>
+</urlset>"#;
+
+        assert_eq!(
+            locations(urlset),
+            vec!["https://example.com/", "https://example.com/a?x=1&y=2"]
+        );
+
+        let index = br#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://example.com/posts.xml.gz</loc></sitemap>
+</sitemapindex>"#;
+
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(index).unwrap();
+
+        assert_eq!(
+            locations(&gzipped.finish().unwrap()),
+            vec!["https://example.com/posts.xml.gz"]
+        );
+
+        assert!(
+            locations(br#"<rss><channel><item><loc>/nope</loc></item></channel></rss>"#).is_empty()
+        );
+    }
+}
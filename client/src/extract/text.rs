@@ -0,0 +1,113 @@
+use lazy_regex::regex;
+
+// elements whose contents are never part of the page's readable text, or are boilerplate repeated on every page
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "head", "nav", "header", "footer", "aside",
+    "form", "iframe",
+];
+
+const INLINE: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "dfn", "em", "i", "kbd", "mark", "q",
+    "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+];
+
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// The readable text of an html page, with markup, scripts and navigation removed and whitespace collapsed.
+pub fn page_text(html: &[u8]) -> String {
+    let html = String::from_utf8_lossy(html);
+
+    let mut text = String::with_capacity(html.len() / 4);
+    let mut skipping: Vec<String> = Vec::new();
+    let mut last = 0;
+
+    for tag in regex!(r"(?s)<!--.*?-->|<[!?][^>]*>|<(/?)([a-zA-Z][a-zA-Z0-9-]*)[^>]*>")
+        .captures_iter(&html)
+    {
+        let whole = tag.get(0).unwrap();
+        if skipping.is_empty() {
+            text.push_str(&decode_entities(&html[last..whole.start()]));
+        }
+        last = whole.end();
+
+        let Some(name) = tag.get(2) else {
+            continue;
+        };
+        let name = name.as_str().to_ascii_lowercase();
+        let closing = !tag[1].is_empty();
+        let self_closing = whole.as_str().ends_with("/>") || VOID.contains(&name.as_str());
+
+        // tags separate words, except for the inline ones
+        if !INLINE.contains(&name.as_str()) {
+            text.push(' ');
+        }
+
+        if closing {
+            // unclosed elements inside a skipped one are closed along with it
+            if let Some(open) = skipping.iter().rposition(|open| *open == name) {
+                skipping.truncate(open);
+            }
+        } else if !self_closing && SKIPPED.contains(&name.as_str()) {
+            skipping.push(name);
+        }
+    }
+
+    if skipping.is_empty() {
+        text.push_str(&decode_entities(&html[last..]));
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn decode_entities(text: &str) -> std::borrow::Cow<'_, str> {
+    regex!(r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]+);").replace_all(
+        text,
+        |entity: &lazy_regex::Captures| {
+            let name = &entity[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => name
+                        .strip_prefix('#')
+                        .and_then(|dec| dec.parse().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+
+            match decoded {
+                Some(c) => c.to_string(),
+                None => entity[0].to_owned(),
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::page_text;
+
+    #[test]
+    fn readable_text() {
+        let html = br#"<!doctype html>
+            <html><head><title>Home</title><style>p { color: red }</style></head>
+            <body>
+                <nav><ul><li><a href="/">Home</a></li><li>About</li></ul></nav>
+                <h1>Hello&nbsp;<em>there</em></h1>
+                <!-- <p>commented out</p> -->
+                <p>Fish &amp; chips<br>cost &#163;5 &lt;3</p>
+                <script>document.write("<p>not text</p>")</script>
+                <footer>&copy; someone</footer>
+            </body></html>"#;
+
+        assert_eq!(page_text(html), "Hello there Fish & chips cost £5 <3");
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use evergarden_common::{EvergardenError, EvergardenResult, HttpResponse, UrlInfo};
+use regex::Regex;
+use time::OffsetDateTime;
+use tokio::{sync::oneshot, time::Instant};
+use url::Url;
+
+pub type FetchResponder = oneshot::Sender<EvergardenResult<HttpResponse>>;
+
+pub struct QueuedUrl {
+    pub url: UrlInfo,
+    pub output: FetchResponder,
+    seq: u64,
+}
+
+impl QueuedUrl {
+    fn key(&self) -> (i32, Reverse<u64>) {
+        (self.url.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for QueuedUrl {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for QueuedUrl {}
+
+impl PartialOrd for QueuedUrl {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedUrl {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+// a url waiting for its `not_before`, ordered so the heap pops the earliest one first
+struct DelayedUrl {
+    at: Instant,
+    queued: QueuedUrl,
+}
+
+impl DelayedUrl {
+    fn key(&self) -> Reverse<(Instant, u64)> {
+        Reverse((self.at, self.queued.seq))
+    }
+}
+
+impl PartialEq for DelayedUrl {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for DelayedUrl {}
+
+impl PartialOrd for DelayedUrl {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DelayedUrl {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Urls that must not be crawled, added to by scripts as the crawl goes. Shared between the client and the scripts.
+#[derive(Clone, Debug, Default)]
+pub struct Blocklist {
+    inner: Arc<RwLock<BlocklistInner>>,
+}
+
+#[derive(Debug, Default)]
+struct BlocklistInner {
+    urls: HashSet<Url>,
+    patterns: Vec<Regex>,
+}
+
+impl Blocklist {
+    pub fn block_url(&self, url: Url) {
+        self.inner.write().unwrap().urls.insert(url);
+    }
+
+    /// Blocks every url the pattern matches. Patterns already in the list are ignored, since scripts tend to
+    /// repeat them for every page.
+    pub fn block_pattern(&self, pattern: Regex) {
+        let mut inner = self.inner.write().unwrap();
+        if !inner
+            .patterns
+            .iter()
+            .any(|p| p.as_str() == pattern.as_str())
+        {
+            inner.patterns.push(pattern);
+        }
+    }
+
+    pub fn blocks(&self, url: &Url) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner.urls.contains(url) || inner.patterns.iter().any(|p| p.is_match(url.as_str()))
+    }
+}
+
+/// Queue of urls waiting to be fetched, ordered by priority and then by insertion order. Urls with a `not_before` in the
+/// future, or [held](Frontier::hold) for a host's crawl delay, are held back until it passes, and urls
+/// [parked](Frontier::park) for a busy host until it has room for them.
+#[derive(Default)]
+pub struct Frontier {
+    queue: BinaryHeap<QueuedUrl>,
+    delayed: BinaryHeap<DelayedUrl>,
+    parked: HashMap<String, VecDeque<QueuedUrl>>,
+    counter: u64,
+    blocklist: Blocklist,
+    skipped: Vec<UrlInfo>,
+}
+
+impl Frontier {
+    pub fn new(blocklist: Blocklist) -> Frontier {
+        Frontier {
+            blocklist,
+            ..Frontier::default()
+        }
+    }
+
+    pub fn push(&mut self, url: UrlInfo, output: FetchResponder) {
+        self.counter += 1;
+        let wait = url
+            .not_before
+            .and_then(|at| (at - OffsetDateTime::now_utc()).try_into().ok());
+        let queued = QueuedUrl {
+            url,
+            output,
+            seq: self.counter,
+        };
+
+        match wait {
+            Some(wait) => self.delayed.push(DelayedUrl {
+                at: Instant::now() + wait,
+                queued,
+            }),
+            None => self.queue.push(queued),
+        }
+    }
+
+    /// Moves delayed urls whose time has come into the queue.
+    pub fn promote_due(&mut self) {
+        let now = Instant::now();
+        while self.delayed.peek().is_some_and(|d| d.at <= now) {
+            let DelayedUrl { queued, .. } = self.delayed.pop().unwrap();
+            self.queue.push(queued);
+        }
+    }
+
+    /// When the next delayed url is due, if any are waiting.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.delayed.peek().map(|d| d.at)
+    }
+
+    /// Pops the next url to fetch. Urls that were blocked while they waited are answered as skipped, and kept for
+    /// [`Frontier::take_skipped`].
+    pub fn pop(&mut self) -> Option<QueuedUrl> {
+        while let Some(queued) = self.queue.pop() {
+            if !self.blocklist.blocks(&queued.url.url) {
+                return Some(queued);
+            }
+
+            let _ = queued
+                .output
+                .send(Err(EvergardenError::Skipped(String::from(
+                    "url was blocked by a script",
+                ))));
+            self.skipped.push(queued.url);
+        }
+
+        None
+    }
+
+    /// Holds a popped url back until `at`, like one whose host is waiting out its crawl delay.
+    pub fn hold(&mut self, queued: QueuedUrl, at: Instant) {
+        self.delayed.push(DelayedUrl { at, queued });
+    }
+
+    /// Sets aside a popped url until its host has room for another request.
+    pub fn park(&mut self, host: String, queued: QueuedUrl) {
+        self.parked.entry(host).or_default().push_back(queued);
+    }
+
+    /// Puts up to `room` of the urls parked for `host` back in the queue, in the order they were parked.
+    pub fn unpark(&mut self, host: &str, room: usize) {
+        let Some(parked) = self.parked.get_mut(host) else {
+            return;
+        };
+
+        self.queue.extend(parked.drain(..room.min(parked.len())));
+        if parked.is_empty() {
+            self.parked.remove(host);
+        }
+    }
+
+    /// Whether any urls are parked for a busy host.
+    pub fn has_parked(&self) -> bool {
+        !self.parked.is_empty()
+    }
+
+    /// The urls skipped since this was last called, which are still persisted as queued.
+    pub fn take_skipped(&mut self) -> Vec<UrlInfo> {
+        std::mem::take(&mut self.skipped)
+    }
+
+    /// How many urls are ready to be fetched, not counting delayed ones.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether no url is ready to be fetched, though delayed ones may still be waiting.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evergarden_common::UrlInfo;
+    use tokio::sync::oneshot;
+
+    use super::{Blocklist, Frontier};
+
+    #[test]
+    fn hands_back_urls_blocked_while_queued() {
+        let blocklist = Blocklist::default();
+        let mut frontier = Frontier::new(blocklist.clone());
+
+        let (output, mut skipped_answer) = oneshot::channel();
+        frontier.push(UrlInfo::start("https://example.com/a").unwrap(), output);
+        let (output, _) = oneshot::channel();
+        frontier.push(UrlInfo::start("https://example.com/b").unwrap(), output);
+
+        blocklist.block_url("https://example.com/a".parse().unwrap());
+
+        let popped = frontier.pop().unwrap();
+        assert_eq!(popped.url.url.as_str(), "https://example.com/b");
+        assert!(skipped_answer.try_recv().unwrap().is_err());
+
+        let skipped = frontier.take_skipped();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].url.as_str(), "https://example.com/a");
+        assert!(frontier.take_skipped().is_empty());
+    }
+
+    #[test]
+    fn parks_urls_until_their_host_has_room() {
+        let mut frontier = Frontier::default();
+        for url in ["https://example.com/a", "https://example.com/b"] {
+            let (output, _) = oneshot::channel();
+            frontier.push(UrlInfo::start(url).unwrap(), output);
+        }
+
+        let a = frontier.pop().unwrap();
+        frontier.park(String::from("https://example.com"), a);
+        let b = frontier.pop().unwrap();
+        frontier.park(String::from("https://example.com"), b);
+        assert!(frontier.is_empty() && frontier.has_parked());
+
+        frontier.unpark("https://example.com", 1);
+        assert_eq!(
+            frontier.pop().unwrap().url.url.as_str(),
+            "https://example.com/a"
+        );
+        assert!(frontier.pop().is_none());
+
+        frontier.unpark("https://example.com", 2);
+        assert_eq!(
+            frontier.pop().unwrap().url.url.as_str(),
+            "https://example.com/b"
+        );
+        assert!(!frontier.has_parked());
+    }
+}
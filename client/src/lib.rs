@@ -1,7 +1,15 @@
 #![feature(impl_trait_in_assoc_type)]
 #![feature(return_position_impl_trait_in_trait)]
 
+pub mod browser;
+pub mod budget;
 pub mod client;
 // pub mod recorder;
 pub mod config;
+pub mod dns;
+pub mod extract;
+pub mod frontier;
+pub mod proxy;
+pub mod robots;
 pub mod scripting;
+pub mod tls;
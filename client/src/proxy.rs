@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{client::connect::dns::Name, service::Service};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::debug;
+use url::{Host, Position, Url};
+
+use crate::dns::OverrideResolver;
+
+// chromium's request heads are nowhere near this
+const MAX_HEAD: usize = 64 * 1024;
+
+type Connected = Arc<Mutex<HashMap<String, SocketAddr>>>;
+
+/// Local http proxy the browser connects through, so that hosts are looked up by the [`OverrideResolver`] and connected
+/// to at the addresses the [`AddressPolicy`](crate::config::AddressPolicy) allowed, instead of the browser looking them
+/// up again on its own, where they could resolve somewhere else.
+pub struct ResolvingProxy {
+    addr: SocketAddr,
+    connected: Connected,
+    task: JoinHandle<()>,
+}
+
+impl ResolvingProxy {
+    pub async fn start(resolver: OverrideResolver) -> io::Result<ResolvingProxy> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let connected = Connected::default();
+
+        let task = tokio::spawn({
+            let connected = Arc::clone(&connected);
+            async move {
+                loop {
+                    let stream = match listener.accept().await {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            debug!("browser proxy: {e}");
+                            continue;
+                        }
+                    };
+
+                    let (resolver, connected) = (resolver.clone(), Arc::clone(&connected));
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, resolver, connected).await {
+                            debug!("browser proxy: {e}");
+                        }
+                    });
+                }
+            }
+        });
+
+        Ok(ResolvingProxy {
+            addr,
+            connected,
+            task,
+        })
+    }
+
+    /// Where the proxy listens, for the browser's `--proxy-server`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The address the proxy last connected to for `url`'s host and port, if it ever did.
+    pub fn connected_to(&self, url: &Url) -> Option<SocketAddr> {
+        let authority = format!("{}:{}", url.host_str()?, url.port_or_known_default()?);
+        self.connected
+            .lock()
+            .unwrap()
+            .get(&authority.to_ascii_lowercase())
+            .copied()
+    }
+}
+
+impl Drop for ResolvingProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    resolver: OverrideResolver,
+    connected: Connected,
+) -> io::Result<()> {
+    let mut client = BufReader::new(stream);
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if client.read_until(b'\n', &mut head).await? == 0 {
+            return Ok(());
+        }
+
+        if head.len() > MAX_HEAD {
+            return reply(client.get_mut(), "431 Request Header Fields Too Large").await;
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target), Some(version)) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return reply(client.get_mut(), "400 Bad Request").await;
+    };
+
+    // tunnels are passed through untouched; plain http requests are sent on in origin form, on a connection of their
+    // own, since the browser would otherwise reuse it for whatever host it asks for next
+    let (authority, forwarded) = if method.eq_ignore_ascii_case("CONNECT") {
+        (target.to_ascii_lowercase(), None)
+    } else {
+        let Some((url, host, port)) = Url::parse(target).ok().and_then(|url| {
+            let host = url.host_str()?.to_owned();
+            let port = url.port_or_known_default()?;
+            Some((url, host, port))
+        }) else {
+            return reply(client.get_mut(), "400 Bad Request").await;
+        };
+
+        let mut forwarded = format!(
+            "{method} {} {version}\r\n",
+            &url[Position::BeforePath..Position::AfterQuery]
+        );
+        for line in lines.filter(|line| !line.is_empty()) {
+            let name = line.split(':').next().unwrap_or_default().trim();
+            if !["connection", "proxy-connection", "keep-alive"]
+                .iter()
+                .any(|hop| name.eq_ignore_ascii_case(hop))
+            {
+                forwarded.push_str(line);
+                forwarded.push_str("\r\n");
+            }
+        }
+        forwarded.push_str("Connection: close\r\n\r\n");
+
+        (
+            format!("{host}:{port}").to_ascii_lowercase(),
+            Some(forwarded),
+        )
+    };
+
+    let Some((host, port)) = authority
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+    else {
+        return reply(client.get_mut(), "400 Bad Request").await;
+    };
+
+    let addrs = resolve(host, &resolver).await;
+    if addrs.is_empty() {
+        debug!(
+            host,
+            "browser proxy refused a host disallowed by the address policy"
+        );
+        return reply(client.get_mut(), "403 Forbidden").await;
+    }
+
+    let mut upstream = None;
+    for ip in addrs {
+        match TcpStream::connect((ip, port)).await {
+            Ok(stream) => {
+                upstream = Some(stream);
+                break;
+            }
+            Err(e) => debug!(host, %ip, "browser proxy couldn't connect: {e}"),
+        }
+    }
+    let Some(mut upstream) = upstream else {
+        return reply(client.get_mut(), "502 Bad Gateway").await;
+    };
+
+    connected
+        .lock()
+        .unwrap()
+        .insert(authority, upstream.peer_addr()?);
+
+    match forwarded {
+        Some(head) => upstream.write_all(head.as_bytes()).await?,
+        None => {
+            client
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?
+        }
+    }
+
+    upstream.write_all(client.buffer()).await?;
+    let mut client = client.into_inner();
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+
+    Ok(())
+}
+
+// the addresses `host` may be connected at, none if the address policy allows none of them
+async fn resolve(host: &str, resolver: &OverrideResolver) -> Vec<IpAddr> {
+    match Host::parse(host) {
+        Ok(Host::Ipv4(ip)) if resolver.allows_host(Host::Ipv4(ip)).await => vec![ip.into()],
+        Ok(Host::Ipv6(ip)) if resolver.allows_host(Host::Ipv6(ip)).await => vec![ip.into()],
+        Ok(Host::Domain(name)) => match Name::from_str(&name) {
+            Ok(name) => match resolver.clone().call(name).await {
+                Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+                Err(_) => Vec::new(),
+            },
+            Err(_) => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+async fn reply(client: &mut TcpStream, status: &str) -> io::Result<()> {
+    client
+        .write_all(
+            format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use hyper_trust_dns::TrustDnsResolver;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use url::Url;
+
+    use super::ResolvingProxy;
+    use crate::{
+        config::{AddressPolicy, DnsConfig},
+        dns::OverrideResolver,
+    };
+
+    async fn proxy(policy: AddressPolicy) -> ResolvingProxy {
+        let resolver = OverrideResolver::new(
+            TrustDnsResolver::new(),
+            &DnsConfig::default(),
+            Arc::new(policy),
+        )
+        .unwrap();
+        ResolvingProxy::start(resolver).await.unwrap()
+    }
+
+    async fn ask(proxy: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn refuses_disallowed_addresses() {
+        let proxy = proxy(AddressPolicy::default()).await;
+
+        let response = ask(proxy.addr(), "CONNECT 127.0.0.1:443 HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+        assert_eq!(
+            proxy.connected_to(&Url::parse("https://127.0.0.1/").unwrap()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn forwards_requests_in_origin_form() {
+        let proxy = proxy(AddressPolicy {
+            allow_private: true,
+            ..Default::default()
+        })
+        .await;
+
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let serving = tokio::spawn(async move {
+            let (mut stream, _) = server.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let url = format!("http://{server_addr}/page?q=1");
+        let response = ask(
+            proxy.addr(),
+            &format!(
+                "GET {url} HTTP/1.1\r\nHost: {server_addr}\r\nProxy-Connection: keep-alive\r\n\r\n"
+            ),
+        )
+        .await;
+        assert!(response.ends_with("\r\n\r\nhi"), "{response}");
+
+        let request = serving.await.unwrap();
+        assert_eq!(
+            request,
+            format!("GET /page?q=1 HTTP/1.1\r\nHost: {server_addr}\r\nConnection: close\r\n\r\n")
+        );
+        assert_eq!(
+            proxy.connected_to(&Url::parse(&url).unwrap()),
+            Some(server_addr)
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use evergarden_common::RobotsDirectives;
+use hyper::{header::HeaderName, HeaderMap};
+use lazy_regex::regex;
+use tokio::sync::OnceCell;
+use url::Url;
+
+use crate::config::RobotsConfig;
+
+#[derive(Clone, Debug)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/// The subset of a robots.txt file that applies to our user agent.
+#[derive(Clone, Debug, Default)]
+pub struct RobotsTxt {
+    rules: Vec<Rule>,
+    pub crawl_delay: Option<Duration>,
+    /// `Sitemap:` urls, which apply to every user agent.
+    pub sitemaps: Vec<String>,
+}
+
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+impl RobotsTxt {
+    pub fn allow_all() -> RobotsTxt {
+        RobotsTxt::default()
+    }
+
+    pub fn disallow_all() -> RobotsTxt {
+        RobotsTxt {
+            rules: vec![Rule {
+                allow: false,
+                pattern: String::from("/"),
+            }],
+            ..RobotsTxt::default()
+        }
+    }
+
+    pub fn parse(body: &str, user_agent: &str) -> RobotsTxt {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut sitemaps = Vec::new();
+        let mut reading_agents = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let value = value.trim();
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if !reading_agents {
+                        groups.push(Group::default());
+                    }
+
+                    reading_agents = true;
+                    groups
+                        .last_mut()
+                        .unwrap()
+                        .agents
+                        .push(value.to_ascii_lowercase());
+                    continue;
+                }
+                "allow" | "disallow" if !value.is_empty() => {
+                    if let Some(group) = groups.last_mut() {
+                        group.rules.push(Rule {
+                            allow: key.trim().eq_ignore_ascii_case("allow"),
+                            pattern: value.to_owned(),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    if let (Some(group), Ok(secs)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if secs.is_finite() && secs >= 0.0 {
+                            group.crawl_delay = Some(Duration::from_secs_f64(secs));
+                        }
+                    }
+                }
+                "sitemap" if !value.is_empty() => {
+                    // not part of any group
+                    sitemaps.push(value.to_owned());
+                    continue;
+                }
+                "request-rate" => {
+                    if let (Some(group), Some(delay)) =
+                        (groups.last_mut(), parse_request_rate(value))
+                    {
+                        group.crawl_delay = Some(group.crawl_delay.map_or(delay, |d| d.max(delay)));
+                    }
+                }
+                _ => {}
+            }
+
+            reading_agents = false;
+        }
+
+        let user_agent = user_agent.to_ascii_lowercase();
+        let mut selected = groups
+            .iter()
+            .filter(|g| {
+                g.agents
+                    .iter()
+                    .any(|agent| agent != "*" && user_agent.contains(agent.as_str()))
+            })
+            .peekable();
+
+        let selected: Vec<&Group> = if selected.peek().is_some() {
+            selected.collect()
+        } else {
+            groups
+                .iter()
+                .filter(|g| g.agents.iter().any(|agent| agent == "*"))
+                .collect()
+        };
+
+        RobotsTxt {
+            rules: selected
+                .iter()
+                .flat_map(|g| g.rules.iter().cloned())
+                .collect(),
+            crawl_delay: selected.iter().filter_map(|g| g.crawl_delay).max(),
+            sitemaps,
+        }
+    }
+
+    /// Checks a url against the allow/disallow rules, with the longest matching rule winning (and allow winning ties).
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(q) => format!("{}?{}", url.path(), q),
+            None => url.path().to_owned(),
+        };
+
+        self.rules
+            .iter()
+            .filter(|rule| pattern_matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
+}
+
+// parses the non-standard `Request-rate: <n>/<time>` directive into a delay between requests
+fn parse_request_rate(value: &str) -> Option<Duration> {
+    let (n, per) = value.split_whitespace().next()?.split_once('/')?;
+    let n = n.parse::<u32>().ok().filter(|n| *n > 0)?;
+
+    let (amount, unit) = match per.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => per.split_at(idx),
+        None => (per, "s"),
+    };
+
+    let amount = amount.parse::<u64>().ok()?;
+    let secs = match unit {
+        "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(secs) / n)
+}
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = path.strip_prefix(parts.next().unwrap_or("")) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<&str>>();
+    for (idx, part) in parts.iter().enumerate() {
+        if anchored && idx == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+static X_ROBOTS_TAG: HeaderName = HeaderName::from_static("x-robots-tag");
+
+// `X-Robots-Tag` and robots meta tags name agents like robots.txt groups do, e.g. `X-Robots-Tag: evergarden: noindex`
+fn names_us(agent: &str, user_agent: &str) -> bool {
+    let agent = agent.trim().to_ascii_lowercase();
+    agent == "robots" || !agent.is_empty() && user_agent.to_ascii_lowercase().contains(&agent)
+}
+
+/// Directives from the `X-Robots-Tag` headers that apply to everyone or to our user agent.
+pub fn header_directives(headers: &HeaderMap, user_agent: &str) -> RobotsDirectives {
+    let mut directives = RobotsDirectives::default();
+
+    for value in headers.get_all(&X_ROBOTS_TAG) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        // directives with values (like `unavailable_after: <date>`) have a colon too, but never a comma before it
+        match value.split_once(':') {
+            Some((agent, list)) if !agent.contains(',') && !is_valued_directive(agent) => {
+                if names_us(agent, user_agent) {
+                    directives.add(list);
+                }
+            }
+            _ => directives.add(value),
+        }
+    }
+
+    directives
+}
+
+fn is_valued_directive(name: &str) -> bool {
+    matches!(
+        name.trim().to_ascii_lowercase().as_str(),
+        "unavailable_after" | "max-snippet" | "max-image-preview" | "max-video-preview"
+    )
+}
+
+/// Directives from `<meta name="robots">` tags (or ones naming our user agent) in an html document.
+pub fn meta_directives(html: &[u8], user_agent: &str) -> RobotsDirectives {
+    let html = String::from_utf8_lossy(html);
+    let mut directives = RobotsDirectives::default();
+
+    for tag in regex!(r"(?i)<meta\s[^>]*>").find_iter(&html) {
+        let (mut name, mut content) = (None, None);
+        for attr in regex!(r#"(?i)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+            .captures_iter(tag.as_str())
+        {
+            let value = attr
+                .get(2)
+                .or(attr.get(3))
+                .or(attr.get(4))
+                .map(|v| v.as_str());
+            match attr[1].to_ascii_lowercase().as_str() {
+                "name" => name = value,
+                "content" => content = value,
+                _ => {}
+            }
+        }
+
+        if let (Some(name), Some(content)) = (name, content) {
+            if names_us(name, user_agent) {
+                directives.add(content);
+            }
+        }
+    }
+
+    directives
+}
+
+/// Per-origin cache of parsed robots.txt files. Each origin is only fetched once, even when many requests for it are in flight.
+pub struct RobotsCache {
+    pub config: RobotsConfig,
+    entries: Mutex<HashMap<String, Arc<OnceCell<Arc<RobotsTxt>>>>>,
+}
+
+impl RobotsCache {
+    pub fn new(config: RobotsConfig) -> RobotsCache {
+        RobotsCache {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn entry(&self, origin: &str) -> Arc<OnceCell<Arc<RobotsTxt>>> {
+        let mut entries = self.entries.lock().unwrap();
+        Arc::clone(entries.entry(origin.to_owned()).or_default())
+    }
+
+    /// Effective delay between requests to a host, taking the configured cap into account.
+    pub fn delay_for(&self, robots: &RobotsTxt) -> Duration {
+        let delay = robots.crawl_delay.unwrap_or_default();
+        match self.config.max_crawl_delay {
+            Some(max) => delay.min(max),
+            None => delay,
+        }
+    }
+}
+
+impl std::fmt::Debug for RobotsCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RobotsCache")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::{header::HeaderValue, HeaderMap};
+
+    use super::{header_directives, meta_directives, RobotsTxt};
+
+    const ROBOTS: &str = "
+User-agent: *
+Disallow: /private/
+Allow: /private/public$
+Crawl-delay: 2
+
+# evergarden gets its own rules
+User-agent: evergarden
+User-agent: other-bot
+Disallow: /*.json$
+Disallow: /admin
+Request-rate: 1/10s
+
+Sitemap: https://example.com/sitemap.xml
+";
+
+    #[test]
+    fn robots_rules() {
+        macro_rules! allowed {
+            ($robots:expr, $url:literal) => {
+                $robots.is_allowed(&url::Url::parse($url).unwrap())
+            };
+        }
+
+        let wildcard = RobotsTxt::parse(ROBOTS, "some-other-crawler/1.0");
+        assert_eq!(wildcard.crawl_delay, Some(Duration::from_secs(2)));
+        assert!(!allowed!(wildcard, "https://example.com/private/page"));
+        assert!(allowed!(wildcard, "https://example.com/private/public"));
+        assert!(!allowed!(
+            wildcard,
+            "https://example.com/private/public/more"
+        ));
+        assert!(allowed!(wildcard, "https://example.com/admin"));
+
+        let ours = RobotsTxt::parse(ROBOTS, "Evergarden/0.1");
+        assert_eq!(ours.crawl_delay, Some(Duration::from_secs(10)));
+        assert!(allowed!(ours, "https://example.com/private/page"));
+        assert!(!allowed!(ours, "https://example.com/admin/users?id=1"));
+        assert!(!allowed!(ours, "https://example.com/api/data.json"));
+        assert!(allowed!(ours, "https://example.com/api/data.json?page=2"));
+        assert_eq!(ours.sitemaps, vec!["https://example.com/sitemap.xml"]);
+
+        assert!(allowed!(
+            RobotsTxt::allow_all(),
+            "https://example.com/admin"
+        ));
+        assert!(!allowed!(RobotsTxt::disallow_all(), "https://example.com/"));
+        assert!(!allowed!(
+            RobotsTxt::disallow_all(),
+            "https://example.com/page?q=1"
+        ));
+    }
+
+    #[test]
+    fn page_directives() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-robots-tag",
+            HeaderValue::from_static("otherbot: noindex"),
+        );
+        headers.append(
+            "x-robots-tag",
+            HeaderValue::from_static("unavailable_after: 25 Jun 2010 15:00:00 PST"),
+        );
+        assert_eq!(
+            header_directives(&headers, "evergarden/0.1"),
+            Default::default()
+        );
+
+        headers.append(
+            "x-robots-tag",
+            HeaderValue::from_static("Evergarden: nofollow"),
+        );
+        let directives = header_directives(&headers, "evergarden/0.1");
+        assert!(directives.nofollow && !directives.noindex);
+
+        let html = br#"<html><head>
+<meta charset="utf-8">
+<meta name="otherbot" content="none">
+<META NAME='robots' CONTENT='noindex'>
+</head></html>"#;
+        let directives = meta_directives(html, "evergarden/0.1");
+        assert!(directives.noindex && !directives.nofollow);
+    }
+}
@@ -1,2 +1,46 @@
+use std::time::Duration;
+
+use evergarden_common::{Annotations, RequestSpec};
+
 pub mod protocol;
+pub mod rhai;
 pub mod script;
+pub mod wasm;
+
+/// A url an in-process script submitted.
+pub struct Submitted {
+    pub url: String,
+    pub priority: Option<i32>,
+    pub request: Option<RequestSpec>,
+    /// How long to wait before fetching it, which also makes it a refetch if it was already crawled.
+    pub delay: Option<Duration>,
+}
+
+/// Urls a script wants kept out of the crawl from now on.
+#[derive(Debug)]
+pub enum Blocked {
+    /// A single url, resolved against the response's url.
+    Url(String),
+    /// A regex, matched against whole urls.
+    Pattern(String),
+}
+
+/// What an in-process script produced for a response.
+#[derive(Default)]
+pub struct Yielded {
+    pub urls: Vec<Submitted>,
+    pub annotations: Option<Annotations>,
+    pub blocked: Vec<Blocked>,
+}
+
+impl Yielded {
+    /// Merges into the annotations yielded so far.
+    pub fn annotate(&mut self, annotations: Annotations) {
+        self.annotations = Some(
+            self.annotations
+                .take()
+                .unwrap_or_default()
+                .merge(annotations),
+        );
+    }
+}
@@ -1,23 +1,108 @@
 use std::{
     io,
     ops::{Deref, DerefMut},
+    time::Duration,
 };
 
-use evergarden_common::{EvergardenResult, HttpResponse};
+use encoding_rs::Encoding;
+use evergarden_common::{
+    Annotations, EvergardenResult, HttpResponse, RequestSpec, ResponseMetadata,
+};
 use futures_util::TryStreamExt;
+use hyper::{
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+    http::HeaderValue,
+};
+use serde::Serialize;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use super::Blocked;
+
+/// Bumped whenever the framing of an existing opcode changes. Scripts have to answer with the same version.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// What the crawler understands on top of the basic `Submit`/`EndFile` opcodes, announced in the handshake.
+pub const CAPABILITIES: &[&str] = &[
+    "submit_priority",
+    "submit_request",
+    "fetch",
+    "annotate",
+    "decoded_bodies",
+    "block",
+    "text",
+    "submit_after",
+];
+
 #[derive(Debug)]
 pub enum ClientRequest {
     Submit {
-        // OPCODE = 0
+        // OPCODE = 0, or OPCODE = 3 when a priority is given, or OPCODE = 5 with a priority and a json request spec
         url: String,
+        priority: Option<i32>,
+        request: Option<RequestSpec>,
     },
     Fetch {
         // OPCODE = 1
         url: String,
     },
     EndFile, // OPCODE = 2
+    Annotate {
+        // OPCODE = 4, followed by a json object
+        annotations: Annotations,
+    },
+    Hello {
+        // OPCODE = 6, answering the crawler's hello with the script's version and a json list of capabilities
+        version: u16,
+        capabilities: Vec<String>,
+    },
+    Block {
+        // OPCODE = 7, followed by a u8 kind (0 for a url, 1 for a regex) and a string
+        blocked: Blocked,
+    },
+    Text {
+        // OPCODE = 8, followed by a u32 length and utf-8 text
+        text: String,
+    },
+    SubmitAfter {
+        // OPCODE = 9, followed by a u32 delay in milliseconds and the url
+        url: String,
+        delay: Duration,
+    },
+}
+
+/// Metadata for a body that was decoded before being handed to a script, with headers adjusted to match it.
+#[derive(Serialize)]
+pub(crate) struct DecodedMeta {
+    #[serde(flatten)]
+    meta: ResponseMetadata,
+    /// The charset the body was converted from, if it was.
+    original_charset: Option<&'static str>,
+}
+
+impl DecodedMeta {
+    pub(crate) fn new(meta: &ResponseMetadata, charset: Option<&'static Encoding>) -> DecodedMeta {
+        let mut meta = meta.clone();
+        meta.headers.remove(CONTENT_ENCODING);
+        meta.headers.remove(CONTENT_LENGTH);
+
+        if charset.is_some() {
+            let media_type = meta
+                .headers
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(';').next())
+                .map(|v| format!("{}; charset=utf-8", v.trim()));
+
+            if let Some(value) = media_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                meta.headers.insert(CONTENT_TYPE, value);
+            }
+        }
+
+        DecodedMeta {
+            meta,
+            original_charset: charset.map(Encoding::name),
+        }
+    }
 }
 
 #[repr(u8)]
@@ -25,6 +110,7 @@ pub enum ServerRequest {
     Submit = 0,
     AnswerFetch = 1,
     CloseScript = 2,
+    Hello = 3,
 }
 
 #[repr(transparent)]
@@ -66,6 +152,8 @@ impl<R: AsyncRead + Unpin> ClientReader<R> {
                 Ok(ClientRequest::Submit {
                     url: String::from_utf8(buffer)
                         .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                    priority: None,
+                    request: None,
                 })
             }
             1 => {
@@ -79,14 +167,106 @@ impl<R: AsyncRead + Unpin> ClientReader<R> {
                 })
             }
             2 => Ok(ClientRequest::EndFile),
+            3 => {
+                // SUBMIT WITH PRIORITY
+                let priority = self.reader.read_i32_le().await?;
+                let len = self.reader.read_u16_le().await?;
+                let mut buffer = vec![0u8; len as usize];
+                self.read_exact(&mut buffer[..]).await?;
+                Ok(ClientRequest::Submit {
+                    url: String::from_utf8(buffer)
+                        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                    priority: Some(priority),
+                    request: None,
+                })
+            }
+            4 => {
+                // ANNOTATE
+                let len = self.reader.read_u32_le().await?;
+                let mut buffer = vec![0u8; len as usize];
+                self.read_exact(&mut buffer[..]).await?;
+                Ok(ClientRequest::Annotate {
+                    annotations: serde_json::from_slice(&buffer)
+                        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                })
+            }
+            5 => {
+                // SUBMIT REQUEST
+                let priority = self.reader.read_i32_le().await?;
+                let len = self.reader.read_u16_le().await?;
+                let mut url = vec![0u8; len as usize];
+                self.read_exact(&mut url[..]).await?;
+                let len = self.reader.read_u32_le().await?;
+                let mut spec = vec![0u8; len as usize];
+                self.read_exact(&mut spec[..]).await?;
+                Ok(ClientRequest::Submit {
+                    url: String::from_utf8(url)
+                        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                    priority: Some(priority),
+                    request: Some(
+                        serde_json::from_slice(&spec)
+                            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                    ),
+                })
+            }
+            6 => {
+                // HELLO
+                let version = self.reader.read_u16_le().await?;
+                let len = self.reader.read_u32_le().await?;
+                let mut buffer = vec![0u8; len as usize];
+                self.read_exact(&mut buffer[..]).await?;
+                Ok(ClientRequest::Hello {
+                    version,
+                    capabilities: serde_json::from_slice(&buffer)
+                        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                })
+            }
+            7 => {
+                // BLOCK
+                let kind = self.reader.read_u8().await?;
+                let len = self.reader.read_u16_le().await?;
+                let mut buffer = vec![0u8; len as usize];
+                self.read_exact(&mut buffer[..]).await?;
+                let rule = String::from_utf8(buffer)
+                    .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+                Ok(ClientRequest::Block {
+                    blocked: match kind {
+                        0 => Blocked::Url(rule),
+                        1 => Blocked::Pattern(rule),
+                        _ => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+                    },
+                })
+            }
+            8 => {
+                // TEXT
+                let len = self.reader.read_u32_le().await?;
+                let mut buffer = vec![0u8; len as usize];
+                self.read_exact(&mut buffer[..]).await?;
+                Ok(ClientRequest::Text {
+                    text: String::from_utf8(buffer)
+                        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                })
+            }
+            9 => {
+                // SUBMIT AFTER
+                let delay = self.reader.read_u32_le().await?;
+                let len = self.reader.read_u16_le().await?;
+                let mut buffer = vec![0u8; len as usize];
+                self.read_exact(&mut buffer[..]).await?;
+                Ok(ClientRequest::SubmitAfter {
+                    url: String::from_utf8(buffer)
+                        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?,
+                    delay: Duration::from_millis(delay.into()),
+                })
+            }
             _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
         }
     }
 }
 
-#[repr(transparent)]
 pub struct ClientWriter<W: AsyncWrite> {
     writer: W,
+    decode_bodies: bool,
 }
 
 impl<W: AsyncWrite> Deref for ClientWriter<W> {
@@ -105,7 +285,16 @@ impl<W: AsyncWrite> DerefMut for ClientWriter<W> {
 
 impl<W: AsyncWrite + Unpin> ClientWriter<W> {
     pub fn new(writer: W) -> ClientWriter<W> {
-        ClientWriter { writer }
+        ClientWriter {
+            writer,
+            decode_bodies: false,
+        }
+    }
+
+    /// Send bodies decoded and converted to utf-8, as a single chunk, instead of as they were received.
+    pub fn decoding_bodies(mut self, decode: bool) -> ClientWriter<W> {
+        self.decode_bodies = decode;
+        self
     }
 
     pub async fn submit(&mut self, res: &HttpResponse) -> EvergardenResult<()> {
@@ -113,6 +302,19 @@ impl<W: AsyncWrite + Unpin> ClientWriter<W> {
         self.write_res(res).await
     }
 
+    /// Opens the handshake with our version and capabilities.
+    pub async fn hello(&mut self) -> EvergardenResult<()> {
+        let capabilities = serde_json::to_vec(CAPABILITIES)?;
+
+        self.writer.write_u8(ServerRequest::Hello as u8).await?;
+        self.writer.write_u16_le(PROTOCOL_VERSION).await?;
+        self.writer.write_u32_le(capabilities.len() as u32).await?;
+        self.writer.write_all(&capabilities).await?;
+        self.writer.flush().await?;
+
+        Ok(())
+    }
+
     pub async fn close_script(&mut self) -> io::Result<()> {
         self.writer
             .write_u8(ServerRequest::CloseScript as u8)
@@ -142,6 +344,22 @@ impl<W: AsyncWrite + Unpin> ClientWriter<W> {
     }
 
     async fn write_res(&mut self, res: &HttpResponse) -> EvergardenResult<()> {
+        if self.decode_bodies {
+            let (body, charset) = res.transcoded_body().await?;
+            let meta_json = serde_json::to_vec(&DecodedMeta::new(&res.meta, charset))?;
+
+            self.writer.write_u64_le(meta_json.len() as u64).await?;
+            self.writer.write_all(&meta_json).await?;
+            if !body.is_empty() {
+                self.writer.write_u64_le(body.len() as u64).await?;
+                self.writer.write_all(&body).await?;
+            }
+            self.writer.write_u64_le(0).await?;
+            self.writer.flush().await?;
+
+            return Ok(());
+        }
+
         let meta_json = serde_json::to_vec(res.meta.as_ref()).unwrap();
 
         self.writer.write_u64_le(meta_json.len() as u64).await?;
@@ -164,3 +382,73 @@ impl<W: AsyncWrite + Unpin> ClientWriter<W> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ClientReader, ClientRequest, ClientWriter, ServerRequest, PROTOCOL_VERSION};
+    use crate::scripting::Blocked;
+
+    fn op(code: u8, fields: &[&[u8]]) -> Vec<u8> {
+        let mut op = vec![code];
+        for field in fields {
+            op.extend_from_slice(field);
+        }
+        op
+    }
+
+    #[tokio::test]
+    async fn reads_each_opcode_with_its_framing() {
+        let url = b"https://example.com/";
+        let len = (url.len() as u16).to_le_bytes();
+        let mut input = [
+            op(3, &[&(-2i32).to_le_bytes(), &len, url]),
+            op(7, &[&[1], &4u16.to_le_bytes(), b"^/a/"]),
+            op(9, &[&250u32.to_le_bytes(), &len, url]),
+            op(8, &[&5u32.to_le_bytes(), b"hello"]),
+            op(2, &[]),
+        ]
+        .concat();
+        input.push(42);
+        let mut reader = ClientReader::new(&input[..]);
+
+        assert!(matches!(
+            reader.read_op().await.unwrap(),
+            ClientRequest::Submit { url, priority: Some(-2), request: None } if url == "https://example.com/"
+        ));
+        assert!(matches!(
+            reader.read_op().await.unwrap(),
+            ClientRequest::Block { blocked: Blocked::Pattern(pattern) } if pattern == "^/a/"
+        ));
+        assert!(matches!(
+            reader.read_op().await.unwrap(),
+            ClientRequest::SubmitAfter { delay, .. } if delay == Duration::from_millis(250)
+        ));
+        assert!(matches!(
+            reader.read_op().await.unwrap(),
+            ClientRequest::Text { text } if text == "hello"
+        ));
+        assert!(matches!(
+            reader.read_op().await.unwrap(),
+            ClientRequest::EndFile
+        ));
+        assert!(reader.read_op().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn hello_announces_the_version_and_capabilities() {
+        let mut writer = ClientWriter::new(Vec::new());
+        writer.hello().await.unwrap();
+        let out = &writer[..];
+
+        assert_eq!(out[0], ServerRequest::Hello as u8);
+        assert_eq!(u16::from_le_bytes([out[1], out[2]]), PROTOCOL_VERSION);
+        let len = u32::from_le_bytes(out[3..7].try_into().unwrap()) as usize;
+        let capabilities: Vec<String> = serde_json::from_slice(&out[7..]).unwrap();
+        assert_eq!(out.len(), 7 + len);
+        assert!(capabilities
+            .iter()
+            .any(|capability| capability == "submit_after"));
+    }
+}
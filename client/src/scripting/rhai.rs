@@ -0,0 +1,313 @@
+//! Rhai scripts, run in-process for rules too small to be worth a separate program.
+//!
+//! A script is evaluated once per response, with these constants in scope:
+//!
+//! - `url`, the response's url.
+//! - `meta`, its metadata as a map, shaped like the json the stdio protocol sends.
+//! - `body`, its decoded body as a string, converted from its charset, with invalid utf-8 replaced.
+//! - `config`, the script's `config` setting, or `()` without one.
+//!
+//! It queues urls by calling `submit(url)` or `submit(url, priority)`, like the `Submit` opcode, or requests with
+//! `submit(url, priority, #{ method: "POST", headers: #{ .. }, body: ".." })`, or schedules a (re)fetch with
+//! `submit_after(url, delay_ms)`, like the `SubmitAfter` opcode. It attaches page metadata with
+//! `annotate(#{ title: .. })`, like the `Annotate` opcode, and the page's readable text with `text(..)`, like the
+//! `Text` opcode. It keeps urls out of the crawl with `block(url)` or `block_pattern(regex)`, like the `Block` opcode.
+//! With a timeout, the script is stopped once it runs past it.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ::rhai::{
+    serde::{from_dynamic, to_dynamic},
+    Dynamic, Engine, EvalAltResult, Map, Scope, AST, INT,
+};
+use evergarden_common::{
+    Annotations, EvergardenError, EvergardenResult, RequestSpec, ResponseMetadata,
+};
+
+use super::{Blocked, Submitted, Yielded};
+
+fn rhai_error(e: Box<EvalAltResult>) -> EvergardenError {
+    EvergardenError::Script(e.to_string())
+}
+
+fn clamp_priority(priority: INT) -> i32 {
+    priority.clamp(i32::MIN.into(), i32::MAX.into()) as i32
+}
+
+/// A compiled script, shared by all of a script's workers.
+#[derive(Clone)]
+pub struct RhaiPlugin {
+    ast: Arc<AST>,
+    timeout: Option<Duration>,
+    config: Dynamic,
+}
+
+impl RhaiPlugin {
+    pub fn load(
+        path: impl AsRef<Path>,
+        timeout: Option<Duration>,
+        config: Option<&serde_json::Value>,
+    ) -> EvergardenResult<RhaiPlugin> {
+        let ast = Engine::new()
+            .compile_file(path.as_ref().to_path_buf())
+            .map_err(rhai_error)?;
+
+        Ok(RhaiPlugin {
+            ast: Arc::new(ast),
+            timeout,
+            config: match config {
+                Some(config) => to_dynamic(config).map_err(rhai_error)?,
+                None => Dynamic::UNIT,
+            },
+        })
+    }
+
+    pub fn instantiate(&self) -> RhaiExtractor {
+        let yielded = Arc::new(Mutex::new(Yielded::default()));
+        let mut engine = Engine::new();
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn("submit", move |url: &str| {
+            queue.lock().unwrap().urls.push(Submitted {
+                url: url.to_owned(),
+                priority: None,
+                request: None,
+                delay: None,
+            });
+        });
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn("submit", move |url: &str, priority: INT| {
+            queue.lock().unwrap().urls.push(Submitted {
+                url: url.to_owned(),
+                priority: Some(clamp_priority(priority)),
+                request: None,
+                delay: None,
+            });
+        });
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn(
+            "submit",
+            move |url: &str, priority: INT, request: Map| -> Result<(), Box<EvalAltResult>> {
+                let request: RequestSpec = from_dynamic(&request.into())?;
+                queue.lock().unwrap().urls.push(Submitted {
+                    url: url.to_owned(),
+                    priority: Some(clamp_priority(priority)),
+                    request: Some(request),
+                    delay: None,
+                });
+
+                Ok(())
+            },
+        );
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn("submit_after", move |url: &str, delay_ms: INT| {
+            queue.lock().unwrap().urls.push(Submitted {
+                url: url.to_owned(),
+                priority: None,
+                request: None,
+                delay: Some(Duration::from_millis(delay_ms.max(0) as u64)),
+            });
+        });
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn(
+            "annotate",
+            move |map: Map| -> Result<(), Box<EvalAltResult>> {
+                let annotations: Annotations = from_dynamic(&map.into())?;
+                queue.lock().unwrap().annotate(annotations);
+
+                Ok(())
+            },
+        );
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn("block", move |url: &str| {
+            queue
+                .lock()
+                .unwrap()
+                .blocked
+                .push(Blocked::Url(url.to_owned()));
+        });
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn("block_pattern", move |pattern: &str| {
+            queue
+                .lock()
+                .unwrap()
+                .blocked
+                .push(Blocked::Pattern(pattern.to_owned()));
+        });
+
+        let queue = Arc::clone(&yielded);
+        engine.register_fn("text", move |text: &str| {
+            queue.lock().unwrap().annotate(Annotations {
+                text: Some(text.to_owned()),
+                ..Annotations::default()
+            });
+        });
+
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        if self.timeout.is_some() {
+            let deadline = Arc::clone(&deadline);
+            engine.on_progress(move |ops| {
+                // checking the clock on every operation would slow scripts down for little gain
+                if ops % 1024 == 0 && Instant::now() > *deadline.lock().unwrap() {
+                    Some(Dynamic::UNIT)
+                } else {
+                    None
+                }
+            });
+        }
+
+        RhaiExtractor {
+            engine,
+            ast: Arc::clone(&self.ast),
+            yielded,
+            timeout: self.timeout,
+            deadline,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// An engine running a script, owned by a single worker.
+pub struct RhaiExtractor {
+    engine: Engine,
+    ast: Arc<AST>,
+    yielded: Arc<Mutex<Yielded>>,
+    timeout: Option<Duration>,
+    deadline: Arc<Mutex<Instant>>,
+    config: Dynamic,
+}
+
+impl RhaiExtractor {
+    /// Runs the script over a response, returning what it submitted.
+    pub fn extract(&self, meta: &ResponseMetadata, body: &[u8]) -> EvergardenResult<Yielded> {
+        let mut scope = Scope::new();
+        scope.push_constant("url", meta.url.url.to_string());
+        scope.push_constant_dynamic("meta", to_dynamic(meta).map_err(rhai_error)?);
+        scope.push_constant("body", String::from_utf8_lossy(body).into_owned());
+        scope.push_constant_dynamic("config", self.config.clone());
+
+        if let Some(timeout) = self.timeout {
+            *self.deadline.lock().unwrap() = Instant::now() + timeout;
+        }
+
+        let res = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        let yielded = std::mem::take(&mut *self.yielded.lock().unwrap());
+        match (self.timeout, res) {
+            (Some(timeout), Err(e)) if matches!(*e, EvalAltResult::ErrorTerminated(..)) => {
+                return Err(EvergardenError::ScriptTimeout(timeout))
+            }
+            (_, res) => res.map_err(rhai_error)?,
+        }
+
+        Ok(yielded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use evergarden_common::{EvergardenError, ResponseMetadata, UrlInfo};
+    use hyper::{HeaderMap, StatusCode, Version};
+    use tempfile::NamedTempFile;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::RhaiPlugin;
+    use crate::scripting::Blocked;
+
+    fn plugin(source: &str, timeout: Option<Duration>) -> RhaiPlugin {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(source.as_bytes()).unwrap();
+        RhaiPlugin::load(
+            file.path(),
+            timeout,
+            Some(&serde_json::json!({ "section": "news" })),
+        )
+        .unwrap()
+    }
+
+    fn meta() -> ResponseMetadata {
+        ResponseMetadata {
+            url: UrlInfo::start("https://example.com/").unwrap(),
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            remote_addr: None,
+            fetched_at: OffsetDateTime::now_utc(),
+            id: Uuid::new_v4(),
+            truncated: None,
+            tls: None,
+            request: None,
+            payload_digest: None,
+            canonical: None,
+            revisit: None,
+            robots: None,
+            resource: None,
+            annotations: None,
+            compression: None,
+            size: None,
+        }
+    }
+
+    #[test]
+    fn yields_what_the_script_submits() {
+        let plugin = plugin(
+            r#"
+            submit(url + config.section);
+            submit_after(url + "feed", 1500);
+            if body.contains("private") { block_pattern("/private/"); }
+            annotate(#{ title: "status " + meta.status });
+            "#,
+            None,
+        );
+
+        let yielded = plugin
+            .instantiate()
+            .extract(&meta(), b"<a href=/private/>")
+            .unwrap();
+        let urls = yielded
+            .urls
+            .iter()
+            .map(|submitted| (submitted.url.as_str(), submitted.delay))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                ("https://example.com/news", None),
+                (
+                    "https://example.com/feed",
+                    Some(Duration::from_millis(1500))
+                )
+            ]
+        );
+        assert!(
+            matches!(&yielded.blocked[..], [Blocked::Pattern(pattern)] if pattern == "/private/")
+        );
+        assert_eq!(
+            yielded.annotations.unwrap().title.as_deref(),
+            Some("status 200")
+        );
+    }
+
+    #[test]
+    fn busy_scripts_are_stopped_at_the_timeout() {
+        let timeout = Duration::from_millis(50);
+        let plugin = plugin("loop { submit(url); }", Some(timeout));
+
+        assert!(matches!(
+            plugin.instantiate().extract(&meta(), b""),
+            Err(EvergardenError::ScriptTimeout(t)) if t == timeout
+        ));
+    }
+}
@@ -1,23 +1,50 @@
-use std::{fmt::Display, process::Stdio, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
-use actors::{Actor, ActorManager, Mailbox};
+use actors::{Actor, ActorManager, Blocking, Mailbox};
+use bytes::Bytes;
 
-use evergarden_common::{EvergardenResult, HttpResponse};
-use futures_util::{stream::FuturesUnordered, Future, FutureExt, StreamExt};
+use evergarden_common::{
+    Annotations, EvergardenError, EvergardenResult, HttpResponse, ResponseMetadata, Storage,
+    StorageMessage, UrlInfo,
+};
+use futures_util::{stream::FuturesUnordered, Future, FutureExt, StreamExt, TryStreamExt};
+use regex::Regex;
+use time::OffsetDateTime;
 
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::{
-    io::{BufReader, BufWriter},
-    process::{Child, ChildStdin, ChildStdout, Command},
+    io::{AsyncRead, AsyncWrite, BufReader, BufWriter},
+    net::TcpStream,
+    process::{Child, Command},
+    task::JoinSet,
 };
-use tracing::{debug, info, Span};
+use tracing::{debug, info, warn, Span};
 
 use crate::{
     client::HttpClient,
-    config::{GlobalState, ScriptConfig, ScriptFilter},
+    config::{GlobalState, ScriptConfig, ScriptEngine, ScriptFilter, ScriptMode, SkipConfig},
+    extract,
+    frontier::Blocklist,
+    robots,
     scripting::protocol::ClientRequest,
 };
 
-use super::protocol::{ClientReader, ClientWriter};
+use super::{
+    protocol::{ClientReader, ClientWriter, DecodedMeta, PROTOCOL_VERSION},
+    rhai::{RhaiExtractor, RhaiPlugin},
+    wasm::{WasmExtractor, WasmPlugin},
+    Blocked, Submitted, Yielded,
+};
 
 pub struct ScriptId {
     pub name: Arc<str>,
@@ -30,24 +57,49 @@ impl Display for ScriptId {
     }
 }
 
+/// How many responses a script was handed, and how many it failed on, for the crawl's report.
+#[derive(Debug, Default)]
+pub struct ScriptCounters {
+    pub runs: AtomicUsize,
+    pub failures: AtomicUsize,
+}
+
 pub struct ScriptManager {
     scripts: Vec<Script>,
+    global: GlobalState,
+    // responses handed to detached scripts, which the crawl waits on before closing them
+    detached: Mutex<JoinSet<()>>,
 }
 
 impl ScriptManager {
-    pub fn new(
+    pub async fn new(
         scripts: impl IntoIterator<Item = (Arc<str>, ScriptConfig)>,
         global: &GlobalState,
     ) -> EvergardenResult<ScriptManager> {
+        let mut spawned = Vec::new();
+        for (name, cfg) in scripts {
+            spawned.push(Script::spawn(name, cfg, global).await?);
+        }
+
         Ok(ScriptManager {
-            scripts: scripts
-                .into_iter()
-                .map(|(name, cfg)| Script::spawn(name, cfg, global))
-                .collect::<EvergardenResult<Vec<Script>>>()?,
+            scripts: spawned,
+            global: global.clone(),
+            detached: Mutex::new(JoinSet::new()),
         })
     }
 
+    /// Each script's counters, by name, which keep counting after the manager's been handed to its actor.
+    pub fn counters(&self) -> Vec<(Arc<str>, Arc<ScriptCounters>)> {
+        self.scripts
+            .iter()
+            .map(|script| (Arc::clone(&script.name), Arc::clone(&script.counters)))
+            .collect()
+    }
+
     pub async fn close_all(self) {
+        let mut detached = self.detached.into_inner().unwrap();
+        while detached.join_next().await.is_some() {}
+
         let mut stream = self
             .scripts
             .into_iter()
@@ -58,11 +110,46 @@ impl ScriptManager {
     }
 
     pub async fn process(&self, data: HttpResponse) -> EvergardenResult<()> {
+        let data = self.with_meta_directives(data).await;
+        let data = self.with_html_canonical(data).await;
+        let nofollow = data.meta.robots.is_some_and(|r| r.nofollow);
+
+        if self.global.canonical.enqueue && !nofollow {
+            self.enqueue_canonical(&data).await;
+        }
+
+        if !nofollow {
+            self.run_extractors(&data).await;
+        }
+
         let mut stream = self
             .scripts
             .iter()
             .filter(|s| s.filter.matches(&data))
-            .map(|v| v.mailbox.request(data.clone()))
+            .map(|script| async {
+                script.counters.runs.fetch_add(1, Ordering::Relaxed);
+                let pending = script.mailbox.deferred_request(data.clone()).await;
+                let counters = Arc::clone(&script.counters);
+                let counted = async move {
+                    let result = pending
+                        .await
+                        .unwrap_or_else(|e| Err(EvergardenError::Script(e.to_string())));
+                    if result.is_err() {
+                        counters.failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                    result
+                };
+
+                match script.mode {
+                    ScriptMode::Blocking => counted.await,
+                    ScriptMode::Detached => {
+                        let mut detached = self.detached.lock().unwrap();
+                        while detached.try_join_next().is_some() {}
+                        detached.spawn(counted.map(drop));
+                        Ok(())
+                    }
+                }
+            })
             .collect::<FuturesUnordered<_>>();
 
         while let Some(v) = stream.next().await {
@@ -71,6 +158,94 @@ impl ScriptManager {
 
         Ok(())
     }
+
+    // the headers' directives are known when fetching, but the meta tags need the body
+    async fn with_meta_directives(&self, mut data: HttpResponse) -> HttpResponse {
+        let Some(directives) = data.meta.robots.filter(|_| extract::is_html(&data.meta)) else {
+            return data;
+        };
+
+        let found = match data.decoded_body().await {
+            Ok(body) => robots::meta_directives(&body, &self.global.robots.user_agent),
+            Err(_) => return data,
+        };
+
+        if found.merge(directives) != directives {
+            let mut meta = ResponseMetadata::clone(&data.meta);
+            meta.robots = Some(found.merge(directives));
+            data.meta = Arc::new(meta);
+        }
+
+        data
+    }
+
+    // a canonical url in the headers goes first, since it's the one recorded with the response
+    async fn with_html_canonical(&self, mut data: HttpResponse) -> HttpResponse {
+        if data.meta.canonical.is_some()
+            || !(self.global.canonical.enqueue || self.global.canonical.dedupe)
+            || !extract::is_html(&data.meta)
+        {
+            return data;
+        }
+
+        let Ok(body) = data.decoded_body().await else {
+            return data;
+        };
+        let Some(canonical) = extract::canonical_link(&body, &data.meta.url.url)
+            .filter(|canonical| canonical != &data.meta.url.url)
+        else {
+            return data;
+        };
+
+        if self.global.canonical.dedupe {
+            self.global
+                .canonical_aliases
+                .record(data.meta.url.key_url(), canonical.clone());
+        }
+
+        let mut meta = ResponseMetadata::clone(&data.meta);
+        meta.canonical = Some(canonical);
+        data.meta = Arc::new(meta);
+        data
+    }
+
+    async fn enqueue_canonical(&self, data: &HttpResponse) {
+        if let Some(canonical) = &data.meta.canonical {
+            self.enqueue_discovered(&data.meta.url, canonical.as_str())
+                .await;
+        }
+    }
+
+    async fn run_extractors(&self, data: &HttpResponse) {
+        if !extract::wants_body(&self.global.extract, &data.meta.url.url, &data.meta.headers) {
+            return;
+        }
+
+        let body = match data.decoded_body().await {
+            Ok(body) => body,
+            Err(e) => {
+                debug!("not extracting from {}: {e}", data.meta.url);
+                return;
+            }
+        };
+
+        for url in extract::extract_urls(&self.global.extract, &data.meta, &body) {
+            self.enqueue_discovered(&data.meta.url, &url).await;
+        }
+    }
+
+    async fn enqueue_discovered(&self, from: &UrlInfo, url: &str) {
+        let Some(url) = from.clone().hop(url) else {
+            return;
+        };
+
+        if url.hops > self.global.config.max_hops || self.global.skip.skips_fetching(&url.url) {
+            return;
+        }
+
+        debug!(%url, "queueing extracted url");
+        tokio::task::spawn(self.global.client.deferred_request(url).await);
+    }
 }
 
 impl Actor for ScriptManager {
@@ -94,35 +269,62 @@ impl Actor for ScriptManager {
 }
 
 pub struct Script {
+    name: Arc<str>,
+    counters: Arc<ScriptCounters>,
     filter: ScriptFilter,
+    mode: ScriptMode,
     #[allow(dead_code)]
     manager: ActorManager<ScriptInstance>,
     mailbox: Mailbox<ScriptInstance>,
 }
 
 impl Script {
-    pub fn spawn(
+    pub async fn spawn(
         name: Arc<str>,
         cfg: ScriptConfig,
         global: &GlobalState,
     ) -> EvergardenResult<Script> {
-        let (mut manager, mailbox) = ActorManager::<ScriptInstance>::new(256);
+        // modules and scripts are compiled once and instantiated per worker
+        let plugin = match cfg.engine {
+            ScriptEngine::Wasm => Some(Plugin::Wasm(WasmPlugin::load(
+                &cfg.command,
+                cfg.timeout,
+                cfg.config.as_ref(),
+            )?)),
+            ScriptEngine::Rhai => Some(Plugin::Rhai(RhaiPlugin::load(
+                &cfg.command,
+                cfg.timeout,
+                cfg.config.as_ref(),
+            )?)),
+            ScriptEngine::Process | ScriptEngine::Unix | ScriptEngine::Tcp => None,
+        };
+
+        // made up front, since starting them can fail
+        let mut instances = Vec::with_capacity(cfg.workers);
         for idx in 0..cfg.workers {
-            manager.spawn_actor(
+            instances.push(
                 ScriptInstance::spawn(
                     ScriptId {
                         name: Arc::clone(&name),
                         counter: idx,
                     },
                     &cfg,
+                    plugin.as_ref(),
                     global,
-                )?,
-                Span::current(),
+                )
+                .await?,
             );
         }
 
+        let (mut manager, mailbox) = ActorManager::<ScriptInstance>::new(cfg.queue_size);
+        let mut instances = instances.into_iter();
+        manager.spawn_pool(cfg.workers, |_| instances.next().unwrap(), Span::current());
+
         Ok(Script {
+            name,
+            counters: Arc::default(),
             filter: cfg.filter,
+            mode: cfg.mode,
             manager,
             mailbox,
         })
@@ -133,48 +335,188 @@ impl Script {
     }
 }
 
+#[derive(Clone)]
+pub enum Plugin {
+    Wasm(WasmPlugin),
+    Rhai(RhaiPlugin),
+}
+
 pub struct ScriptInstance {
     id: ScriptId,
     client: Mailbox<HttpClient>,
-    #[allow(dead_code)]
-    proc: Child,
-    proc_in: ClientWriter<BufWriter<ChildStdin>>,
-    proc_out: ClientReader<BufReader<ChildStdout>>,
+    storage: Mailbox<Blocking<Storage>>,
+    worker: Worker,
+    // kept to restart the worker when it times out
+    script: ScriptConfig,
+    plugin: Option<Plugin>,
     max_hops: usize,
+    skip: Arc<SkipConfig>,
+    blocklist: Blocklist,
+}
+
+type BoxedWrite = Box<dyn AsyncWrite + Send + Sync + Unpin>;
+type BoxedRead = Box<dyn AsyncRead + Send + Sync + Unpin>;
+type ScriptInput = ClientWriter<BufWriter<BoxedWrite>>;
+type ScriptOutput = ClientReader<BufReader<BoxedRead>>;
+
+enum Worker {
+    Process {
+        // none for connections to a service
+        proc: Option<Child>,
+        proc_in: ScriptInput,
+        proc_out: ScriptOutput,
+    },
+    Wasm(WasmExtractor),
+    Rhai(Box<RhaiExtractor>),
+}
+
+impl Worker {
+    async fn spawn(script: &ScriptConfig, plugin: Option<&Plugin>) -> EvergardenResult<Worker> {
+        Ok(match plugin {
+            Some(Plugin::Wasm(plugin)) => Worker::Wasm(plugin.instantiate()?),
+            Some(Plugin::Rhai(plugin)) => Worker::Rhai(Box::new(plugin.instantiate())),
+            None => {
+                let (proc, input, output) = connect(script).await?;
+
+                let mut proc_in =
+                    ClientWriter::new(BufWriter::new(input)).decoding_bodies(script.decode_body);
+                let mut proc_out = ClientReader::new(BufReader::new(output));
+
+                handshake(
+                    &mut proc_in,
+                    &mut proc_out,
+                    script.timeout.unwrap_or(HANDSHAKE_TIMEOUT),
+                )
+                .await?;
+
+                Worker::Process {
+                    proc,
+                    proc_in,
+                    proc_out,
+                }
+            }
+        })
+    }
+}
+
+// spawns the script's process, or connects to the service it points at
+async fn connect(
+    script: &ScriptConfig,
+) -> EvergardenResult<(Option<Child>, BoxedWrite, BoxedRead)> {
+    let unreachable = |e: std::io::Error| {
+        EvergardenError::Script(format!("can't connect to {}: {e}", script.command))
+    };
+
+    match script.engine {
+        #[cfg(unix)]
+        ScriptEngine::Unix => {
+            let (output, input) = UnixStream::connect(&script.command)
+                .await
+                .map_err(unreachable)?
+                .into_split();
+            Ok((None, Box::new(input), Box::new(output)))
+        }
+        #[cfg(not(unix))]
+        ScriptEngine::Unix => Err(EvergardenError::Script(String::from(
+            "unix sockets aren't supported on this platform",
+        ))),
+        ScriptEngine::Tcp => {
+            let (output, input) = TcpStream::connect(&script.command)
+                .await
+                .map_err(unreachable)?
+                .into_split();
+            Ok((None, Box::new(input), Box::new(output)))
+        }
+        _ => {
+            let mut command = Command::new(&script.command);
+            if let Some(config) = &script.config {
+                command.env(SCRIPT_CONFIG_VAR, serde_json::to_string(config)?);
+            }
+
+            let mut proc = command
+                .args(&script.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+
+            let input = proc.stdin.take().unwrap();
+            let output = proc.stdout.take().unwrap();
+            Ok((Some(proc), Box::new(input), Box::new(output)))
+        }
+    }
+}
+
+const SCRIPT_CONFIG_VAR: &str = "EVERGARDEN_SCRIPT_CONFIG";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// agrees on a protocol version with a freshly spawned script, before any responses are sent to it
+async fn handshake(
+    proc_in: &mut ScriptInput,
+    proc_out: &mut ScriptOutput,
+    timeout: Duration,
+) -> EvergardenResult<()> {
+    let failed = |e: &dyn Display| {
+        EvergardenError::Script(format!(
+            "handshake failed ({e}), the script might predate protocol version {PROTOCOL_VERSION}"
+        ))
+    };
+
+    proc_in.hello().await.map_err(|e| failed(&e))?;
+
+    let answer = tokio::time::timeout(timeout, proc_out.read_op())
+        .await
+        .map_err(|_| failed(&format_args!("no answer within {timeout:?}")))?
+        .map_err(|e| failed(&e))?;
+
+    match answer {
+        ClientRequest::Hello {
+            version,
+            capabilities,
+        } if version == PROTOCOL_VERSION => {
+            debug!(?capabilities, "script handshake done");
+            Ok(())
+        }
+        ClientRequest::Hello { version, .. } => Err(EvergardenError::Script(format!(
+            "script speaks protocol version {version}, but the crawler speaks {PROTOCOL_VERSION}"
+        ))),
+        other => Err(failed(&format_args!("got {other:?} instead of a hello"))),
+    }
 }
 
 impl ScriptInstance {
-    #[tracing::instrument(skip(id, script, global), fields(
+    #[tracing::instrument(skip(id, script, plugin, global), fields(
         id = %id,
         script = ?script
     ))]
-    pub fn spawn(
+    pub async fn spawn(
         id: ScriptId,
         script: &ScriptConfig,
+        plugin: Option<&Plugin>,
         global: &GlobalState,
     ) -> EvergardenResult<ScriptInstance> {
-        let mut proc = Command::new(&script.command)
-            .args(&script.args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-
-        let proc_in = BufWriter::new(proc.stdin.take().unwrap());
-        let proc_out = BufReader::new(proc.stdout.take().unwrap());
-
         Ok(ScriptInstance {
             id,
             client: global.client.clone(),
-            proc,
-            proc_in: ClientWriter::new(proc_in),
-            proc_out: ClientReader::new(proc_out),
+            storage: global.storage.clone(),
+            worker: Worker::spawn(script, plugin).await?,
+            script: script.clone(),
+            plugin: plugin.cloned(),
             max_hops: global.config.max_hops,
+            skip: Arc::clone(&global.skip),
+            blocklist: global.blocklist.clone(),
         })
     }
 
-    pub async fn close_script(mut self) -> EvergardenResult<()> {
-        self.proc_in.close_script().await?;
-        let _ = tokio::time::timeout(Duration::from_millis(100), self.proc.wait()).await;
+    pub async fn close_script(self) -> EvergardenResult<()> {
+        if let Worker::Process {
+            proc, mut proc_in, ..
+        } = self.worker
+        {
+            proc_in.close_script().await?;
+            if let Some(mut proc) = proc {
+                let _ = tokio::time::timeout(Duration::from_millis(100), proc.wait()).await;
+            }
+        }
 
         Ok(())
     }
@@ -184,45 +526,134 @@ impl ScriptInstance {
         url = %data.meta.url,
     ))]
     pub async fn submit(&mut self, data: HttpResponse) -> EvergardenResult<()> {
-        use ClientRequest::*;
+        // in-process engines enforce the timeout themselves, since they can't be interrupted from here
+        let res = match self.script.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run(&data))
+                .await
+                .unwrap_or(Err(EvergardenError::ScriptTimeout(timeout))),
+            None => self.run(&data).await,
+        };
+
+        match res {
+            Err(EvergardenError::ScriptTimeout(timeout)) => {
+                warn!("script timed out after {timeout:?}, restarting it");
+                self.restart().await
+            }
+            res => res,
+        }
+    }
 
-        self.proc_in.submit(&data).await?;
+    async fn restart(&mut self) -> EvergardenResult<()> {
+        let worker = Worker::spawn(&self.script, self.plugin.as_ref()).await?;
+        if let Worker::Process {
+            proc: Some(mut proc),
+            ..
+        } = std::mem::replace(&mut self.worker, worker)
+        {
+            proc.kill().await?;
+        }
 
-        loop {
-            match self.proc_out.read_op().await.unwrap() {
-                Submit { url } => {
-                    let Some(url) = data.meta.url.clone().hop(&url) else {
-                        debug!("script result skipped: invalid url {}", &url);
-                        continue;
-                    };
+        Ok(())
+    }
 
-                    if url.hops > self.max_hops {
-                        debug!(
-                            "script result skipped: url {} exceeded max hops",
-                            url.url.as_str()
-                        );
+    async fn run(&mut self, data: &HttpResponse) -> EvergardenResult<()> {
+        use ClientRequest::*;
 
-                        continue;
+        let (proc_in, proc_out) = match &mut self.worker {
+            Worker::Process {
+                proc_in, proc_out, ..
+            } => (proc_in, proc_out),
+            Worker::Wasm(extractor) => {
+                let (meta, body) = if self.script.decode_body {
+                    let (body, charset) = data.transcoded_body().await?;
+                    (
+                        serde_json::to_vec(&DecodedMeta::new(&data.meta, charset))?,
+                        body,
+                    )
+                } else {
+                    let mut body = data.body.clone();
+                    let mut buf = Vec::new();
+                    while let Some(chunk) = body.try_next().await? {
+                        buf.extend_from_slice(&chunk);
                     }
 
-                    info!(%url, "script yielded url");
+                    (serde_json::to_vec(data.meta.as_ref())?, Bytes::from(buf))
+                };
+
+                let yielded = tokio::task::block_in_place(|| extractor.extract(&meta, &body))?;
+                return self.handle_yielded(data, yielded).await;
+            }
+            Worker::Rhai(extractor) => {
+                let (body, _) = data.transcoded_body().await?;
+
+                let yielded = tokio::task::block_in_place(|| extractor.extract(&data.meta, &body))?;
+                return self.handle_yielded(data, yielded).await;
+            }
+        };
 
-                    let v = self.client.deferred_request(url).await;
-                    tokio::task::spawn(v);
+        proc_in.submit(data).await?;
+
+        loop {
+            match proc_out.read_op().await? {
+                Submit {
+                    url,
+                    priority,
+                    request,
+                } => {
+                    let submitted = Submitted {
+                        url,
+                        priority,
+                        request,
+                        delay: None,
+                    };
+
+                    queue_url(&self.client, self.max_hops, &self.skip, data, submitted).await;
+                }
+                SubmitAfter { url, delay } => {
+                    let submitted = Submitted {
+                        url,
+                        priority: None,
+                        request: None,
+                        delay: Some(delay),
+                    };
+
+                    queue_url(&self.client, self.max_hops, &self.skip, data, submitted).await;
                 }
                 Fetch { url } => {
-                    let Some(url) = data.meta.url.clone().hop(&url) else {
-                        self.proc_in.error_fetch("invalid_url").await?;
+                    let Some(mut url) = data.meta.url.clone().hop(&url) else {
+                        proc_in.error_fetch("invalid_url").await?;
                         continue;
                     };
 
+                    // the script is blocked until this answers, so let it jump the queue
+                    url.priority = UrlInfo::SEED_PRIORITY;
+
                     info!(%url, "fetching url for script");
 
                     match self.client.request(url).await {
-                        Ok(res) => self.proc_in.answer_fetch(&res).await?,
-                        Err(e) => self.proc_in.error_fetch(&e.to_string()).await?,
+                        Ok(res) => proc_in.answer_fetch(&res).await?,
+                        Err(e) => proc_in.error_fetch(&e.to_string()).await?,
                     }
                 }
+                Annotate { annotations } => {
+                    annotate(&self.storage, data, annotations).await?;
+                }
+                Text { text } => {
+                    let annotations = Annotations {
+                        text: Some(text),
+                        ..Annotations::default()
+                    };
+
+                    annotate(&self.storage, data, annotations).await?;
+                }
+                Block { blocked } => {
+                    block(&self.blocklist, data, blocked);
+                }
+                Hello { .. } => {
+                    return Err(EvergardenError::Script(String::from(
+                        "unexpected hello after the handshake",
+                    )));
+                }
                 EndFile => {
                     break;
                 }
@@ -231,6 +662,118 @@ impl ScriptInstance {
 
         Ok(())
     }
+
+    async fn handle_yielded(&self, data: &HttpResponse, yielded: Yielded) -> EvergardenResult<()> {
+        if let Some(annotations) = yielded.annotations {
+            annotate(&self.storage, data, annotations).await?;
+        }
+
+        for blocked in yielded.blocked {
+            block(&self.blocklist, data, blocked);
+        }
+
+        for submitted in yielded.urls {
+            queue_url(&self.client, self.max_hops, &self.skip, data, submitted).await;
+        }
+
+        Ok(())
+    }
+}
+
+// page metadata a script attached to `data`
+async fn annotate(
+    storage: &Mailbox<Blocking<Storage>>,
+    data: &HttpResponse,
+    annotations: Annotations,
+) -> EvergardenResult<()> {
+    debug!(?annotations, "script annotated response");
+
+    storage
+        .request(StorageMessage::Annotate(
+            data.meta.url.key_url(),
+            annotations,
+        ))
+        .await?;
+
+    Ok(())
+}
+
+// urls a script blocked while processing `data`
+fn block(blocklist: &Blocklist, data: &HttpResponse, blocked: Blocked) {
+    match blocked {
+        Blocked::Url(url) => {
+            let Ok(url) = data.meta.url.url.join(&url) else {
+                debug!("script block skipped: invalid url {}", url);
+                return;
+            };
+
+            info!(%url, "script blocked url");
+            blocklist.block_url(url);
+        }
+        Blocked::Pattern(pattern) => {
+            let pattern = match Regex::new(&pattern) {
+                Ok(pattern) => pattern,
+                Err(e) => {
+                    warn!("script block skipped: invalid pattern {pattern:?}: {e}");
+                    return;
+                }
+            };
+
+            info!(%pattern, "script blocked pattern");
+            blocklist.block_pattern(pattern);
+        }
+    }
+}
+
+// a url a script submitted while processing `data`
+async fn queue_url(
+    client: &Mailbox<HttpClient>,
+    max_hops: usize,
+    skip: &SkipConfig,
+    data: &HttpResponse,
+    submitted: Submitted,
+) {
+    if data.meta.robots.is_some_and(|r| r.nofollow) {
+        debug!("script result skipped: {} is nofollow", submitted.url);
+        return;
+    }
+
+    let Some(mut url) = data.meta.url.clone().hop(&submitted.url) else {
+        debug!("script result skipped: invalid url {}", submitted.url);
+        return;
+    };
+
+    if let Some(priority) = submitted.priority {
+        url.priority = url.priority.saturating_add(priority);
+    }
+
+    url.request = submitted.request;
+    url.not_before = submitted
+        .delay
+        .map(|delay| OffsetDateTime::now_utc() + delay);
+
+    if url.hops > max_hops {
+        debug!(
+            "script result skipped: url {} exceeded max hops",
+            url.url.as_str()
+        );
+
+        return;
+    }
+
+    if skip.skips_fetching(&url.url) {
+        debug!(
+            "script result skipped: url {} matches skip list",
+            url.url.as_str()
+        );
+
+        return;
+    }
+
+    info!(%url, "script yielded url");
+
+    let v = client.deferred_request(url).await;
+    tokio::task::spawn(v);
 }
 
 impl Actor for ScriptInstance {
@@ -250,4 +793,12 @@ impl Actor for ScriptInstance {
     fn close<'a>(self) -> Self::CloseFuture<'a> {
         self.close_script().map(|_| ())
     }
+
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.id.name)
+    }
+
+    fn id(&self) -> Option<usize> {
+        Some(self.id.counter)
+    }
 }
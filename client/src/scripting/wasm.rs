@@ -0,0 +1,426 @@
+//! WebAssembly extractors, run in-process instead of as a child process per worker.
+//!
+//! A module exports its `memory`, an `alloc(len: i32) -> i32` function returning a buffer for the crawler to write
+//! into, and `extract(meta_ptr: i32, meta_len: i32, body_ptr: i32, body_len: i32)`, which is called once per response
+//! with its metadata as json and its raw body. While extracting, it can call these imports from the `evergarden`
+//! module:
+//!
+//! - `submit(url_ptr: i32, url_len: i32)` queues a url, like the `Submit` opcode.
+//! - `submit_priority(url_ptr: i32, url_len: i32, priority: i32)` queues a url with a priority adjustment.
+//! - `submit_request(url_ptr: i32, url_len: i32, priority: i32, json_ptr: i32, json_len: i32)` queues a url to be
+//!   requested as described by a json object with `method`, `headers` and `body`.
+//! - `submit_after(url_ptr: i32, url_len: i32, delay_ms: i32)` schedules a url to be (re)fetched later, like the
+//!   `SubmitAfter` opcode.
+//! - `annotate(json_ptr: i32, json_len: i32)` attaches a json object of page metadata, like the `Annotate` opcode.
+//! - `text(text_ptr: i32, text_len: i32)` attaches the page's readable text, like the `Text` opcode.
+//! - `block(url_ptr: i32, url_len: i32)` and `block_pattern(regex_ptr: i32, regex_len: i32)` keep urls out of the
+//!   crawl, like the `Block` opcode.
+//!
+//! If the script has a `config` setting, the module also has to export `configure(json_ptr: i32, json_len: i32)`, which
+//! is called with it as json once per instance, before any response.
+//!
+//! Nothing else is imported, so modules can't reach the filesystem or the network.
+//!
+//! With a timeout, each call is interrupted once it runs past it, with a resolution of [`EPOCH_TICK`].
+
+use std::{path::Path, sync::Arc, thread, time::Duration};
+
+use evergarden_common::{Annotations, EvergardenError, EvergardenResult};
+use wasmtime::{
+    Caller, Config, Engine, EngineWeak, Extern, Instance, Linker, Memory, Module, Store, Trap,
+    TypedFunc,
+};
+
+use super::{Blocked, Submitted, Yielded};
+
+/// How often running modules check whether they're past their timeout.
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+fn wasm_error(e: wasmtime::Error) -> EvergardenError {
+    EvergardenError::Script(format!("{e:#}"))
+}
+
+// advances the engine's epoch until it's dropped, interrupting modules past their deadline
+fn tick_epochs(engine: EngineWeak) {
+    while let Some(engine) = engine.upgrade() {
+        engine.increment_epoch();
+        drop(engine);
+        thread::sleep(EPOCH_TICK);
+    }
+}
+
+/// A compiled module, shared by all of a script's workers.
+#[derive(Clone)]
+pub struct WasmPlugin {
+    engine: Engine,
+    module: Module,
+    timeout: Option<Duration>,
+    config: Option<Arc<[u8]>>,
+}
+
+impl WasmPlugin {
+    pub fn load(
+        path: impl AsRef<Path>,
+        timeout: Option<Duration>,
+        config: Option<&serde_json::Value>,
+    ) -> EvergardenResult<WasmPlugin> {
+        let mut engine_config = Config::new();
+        engine_config.epoch_interruption(timeout.is_some());
+
+        let engine = Engine::new(&engine_config).map_err(wasm_error)?;
+        let module = Module::from_file(&engine, path).map_err(wasm_error)?;
+
+        if timeout.is_some() {
+            let weak = engine.weak();
+            thread::spawn(move || tick_epochs(weak));
+        }
+
+        Ok(WasmPlugin {
+            engine,
+            module,
+            timeout,
+            config: config.map(serde_json::to_vec).transpose()?.map(Arc::from),
+        })
+    }
+
+    pub fn instantiate(&self) -> EvergardenResult<WasmExtractor> {
+        let mut linker = Linker::<Yielded>::new(&self.engine);
+        linker
+            .func_wrap(
+                "evergarden",
+                "submit",
+                |caller: Caller<'_, Yielded>, ptr: i32, len: i32| submit(caller, ptr, len, None),
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "evergarden",
+                "submit_priority",
+                |caller: Caller<'_, Yielded>, ptr: i32, len: i32, priority: i32| {
+                    submit(caller, ptr, len, Some(priority))
+                },
+            )
+            .map_err(wasm_error)?;
+
+        linker
+            .func_wrap(
+                "evergarden",
+                "submit_request",
+                |mut caller: Caller<'_, Yielded>,
+                 ptr: i32,
+                 len: i32,
+                 priority: i32,
+                 json_ptr: i32,
+                 json_len: i32|
+                 -> wasmtime::Result<()> {
+                    let request = serde_json::from_slice(&read(&mut caller, json_ptr, json_len)?)?;
+                    let url = String::from_utf8(read(&mut caller, ptr, len)?)?;
+                    caller.data_mut().urls.push(Submitted {
+                        url,
+                        priority: Some(priority),
+                        request: Some(request),
+                        delay: None,
+                    });
+
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "evergarden",
+                "submit_after",
+                |mut caller: Caller<'_, Yielded>,
+                 ptr: i32,
+                 len: i32,
+                 delay_ms: i32|
+                 -> wasmtime::Result<()> {
+                    let url = String::from_utf8(read(&mut caller, ptr, len)?)?;
+                    caller.data_mut().urls.push(Submitted {
+                        url,
+                        priority: None,
+                        request: None,
+                        delay: Some(Duration::from_millis(delay_ms.max(0) as u64)),
+                    });
+
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "evergarden",
+                "annotate",
+                |mut caller: Caller<'_, Yielded>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+                    let annotations = serde_json::from_slice(&read(&mut caller, ptr, len)?)?;
+                    caller.data_mut().annotate(annotations);
+
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+
+        linker
+            .func_wrap(
+                "evergarden",
+                "text",
+                |mut caller: Caller<'_, Yielded>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+                    let text = String::from_utf8(read(&mut caller, ptr, len)?)?;
+                    caller.data_mut().annotate(Annotations {
+                        text: Some(text),
+                        ..Annotations::default()
+                    });
+
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "evergarden",
+                "block",
+                |mut caller: Caller<'_, Yielded>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+                    let url = String::from_utf8(read(&mut caller, ptr, len)?)?;
+                    caller.data_mut().blocked.push(Blocked::Url(url));
+
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "evergarden",
+                "block_pattern",
+                |mut caller: Caller<'_, Yielded>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+                    let pattern = String::from_utf8(read(&mut caller, ptr, len)?)?;
+                    caller.data_mut().blocked.push(Blocked::Pattern(pattern));
+
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+
+        let mut store = Store::new(&self.engine, Yielded::default());
+        // the epoch only advances with a timeout, so this never triggers without one
+        store.set_epoch_deadline(self.deadline_ticks());
+        let instance = linker
+            .instantiate(&mut store, &self.module)
+            .map_err(wasm_error)?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| EvergardenError::Script(String::from("module exports no memory")))?;
+
+        let configure = self
+            .config
+            .as_ref()
+            .map(|_| typed_func::<(i32, i32), ()>(&instance, &mut store, "configure"))
+            .transpose()?;
+
+        let mut extractor = WasmExtractor {
+            timeout: self.timeout,
+            deadline_ticks: self.deadline_ticks(),
+            alloc: typed_func(&instance, &mut store, "alloc")?,
+            extract: typed_func(&instance, &mut store, "extract")?,
+            memory,
+            store,
+        };
+
+        if let (Some(configure), Some(config)) = (configure, &self.config) {
+            extractor.configure(&configure, config)?;
+        }
+
+        Ok(extractor)
+    }
+}
+
+impl WasmPlugin {
+    fn deadline_ticks(&self) -> u64 {
+        self.timeout.map_or(1, |timeout| {
+            (timeout.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64
+        })
+    }
+}
+
+fn typed_func<P: wasmtime::WasmParams, R: wasmtime::WasmResults>(
+    instance: &Instance,
+    store: &mut Store<Yielded>,
+    name: &str,
+) -> EvergardenResult<TypedFunc<P, R>> {
+    instance
+        .get_typed_func::<P, R>(store, name)
+        .map_err(|e| EvergardenError::Script(format!("module export `{name}`: {e:#}")))
+}
+
+fn read(caller: &mut Caller<'_, Yielded>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return Err(wasmtime::Error::msg("module exports no memory"));
+    };
+
+    let mut buf = vec![0u8; len as u32 as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut buf)?;
+
+    Ok(buf)
+}
+
+fn submit(
+    mut caller: Caller<'_, Yielded>,
+    ptr: i32,
+    len: i32,
+    priority: Option<i32>,
+) -> wasmtime::Result<()> {
+    let url = String::from_utf8(read(&mut caller, ptr, len)?)?;
+    caller.data_mut().urls.push(Submitted {
+        url,
+        priority,
+        request: None,
+        delay: None,
+    });
+
+    Ok(())
+}
+
+/// An instance of a module, owned by a single worker.
+pub struct WasmExtractor {
+    store: Store<Yielded>,
+    timeout: Option<Duration>,
+    deadline_ticks: u64,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    extract: TypedFunc<(i32, i32, i32, i32), ()>,
+}
+
+impl WasmExtractor {
+    /// Runs the module over a response, returning what it submitted.
+    pub fn extract(&mut self, meta: &[u8], body: &[u8]) -> EvergardenResult<Yielded> {
+        self.store.set_epoch_deadline(self.deadline_ticks);
+
+        let (meta_ptr, meta_len) = self.write(meta)?;
+        let (body_ptr, body_len) = self.write(body)?;
+
+        let res = self
+            .extract
+            .call(&mut self.store, (meta_ptr, meta_len, body_ptr, body_len));
+        let yielded = std::mem::take(self.store.data_mut());
+        res.map_err(|e| self.call_error(e))?;
+
+        Ok(yielded)
+    }
+
+    fn call_error(&self, e: wasmtime::Error) -> EvergardenError {
+        match (self.timeout, e.downcast_ref::<Trap>()) {
+            (Some(timeout), Some(Trap::Interrupt)) => EvergardenError::ScriptTimeout(timeout),
+            _ => wasm_error(e),
+        }
+    }
+
+    fn configure(
+        &mut self,
+        configure: &TypedFunc<(i32, i32), ()>,
+        config: &[u8],
+    ) -> EvergardenResult<()> {
+        self.store.set_epoch_deadline(self.deadline_ticks);
+
+        let (ptr, len) = self.write(config)?;
+        let res = configure.call(&mut self.store, (ptr, len));
+        // anything submitted while configuring has no response to belong to
+        std::mem::take(self.store.data_mut());
+        res.map_err(|e| self.call_error(e))
+    }
+
+    fn write(&mut self, data: &[u8]) -> EvergardenResult<(i32, i32)> {
+        let len = i32::try_from(data.len())
+            .map_err(|_| EvergardenError::Script(String::from("input too large for module")))?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|e| self.call_error(e))?;
+
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, data)
+            .map_err(|e| EvergardenError::Script(format!("module memory: {e}")))?;
+
+        Ok((ptr, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use evergarden_common::EvergardenError;
+    use tempfile::NamedTempFile;
+
+    use super::WasmPlugin;
+
+    // a module with a bump allocator, and `extract` as given
+    fn plugin(extract: &str, timeout: Option<Duration>) -> WasmPlugin {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"(module
+                (import "evergarden" "submit" (func $submit (param i32 i32)))
+                (import "evergarden" "submit_priority" (func $submit_priority (param i32 i32 i32)))
+                (import "evergarden" "annotate" (func $annotate (param i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "https://example.com/next")
+                (data (i32.const 32) "{{\"title\":\"hi\"}}")
+                (global $next (mut i32) (i32.const 1024))
+                (func (export "alloc") (param $len i32) (result i32)
+                    global.get $next
+                    global.get $next
+                    local.get $len
+                    i32.add
+                    global.set $next)
+                {extract})"#
+        )
+        .unwrap();
+        WasmPlugin::load(file.path(), timeout, None).unwrap()
+    }
+
+    #[test]
+    fn yields_what_the_module_submits() {
+        let plugin = plugin(
+            r#"(func (export "extract") (param i32 i32 i32 i32)
+                (call $submit (i32.const 0) (i32.const 24))
+                ;; the body's first byte, as the priority
+                (call $submit_priority (i32.const 0) (i32.const 24) (i32.load8_u (local.get 2)))
+                (call $annotate (i32.const 32) (i32.const 14)))"#,
+            None,
+        );
+
+        let yielded = plugin
+            .instantiate()
+            .unwrap()
+            .extract(b"{}", b"\x07")
+            .unwrap();
+        let urls = yielded
+            .urls
+            .iter()
+            .map(|submitted| (submitted.url.as_str(), submitted.priority))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                ("https://example.com/next", None),
+                ("https://example.com/next", Some(7))
+            ]
+        );
+        assert_eq!(yielded.annotations.unwrap().title.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn busy_modules_are_interrupted_at_the_timeout() {
+        let timeout = Duration::from_millis(50);
+        let plugin = plugin(
+            r#"(func (export "extract") (param i32 i32 i32 i32)
+                (loop $forever (br $forever)))"#,
+            Some(timeout),
+        );
+
+        assert!(matches!(
+            plugin.instantiate().unwrap().extract(b"{}", b""),
+            Err(EvergardenError::ScriptTimeout(t)) if t == timeout
+        ));
+    }
+}
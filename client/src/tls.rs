@@ -0,0 +1,146 @@
+use std::{
+    fmt::Write,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use evergarden_common::{CertificateInfo, TlsInfo};
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Uri,
+};
+use hyper_rustls::MaybeHttpsStream;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Wraps an https connector so that every connection carries a [`TlsInfo`] extension describing the negotiated
+/// session, which ends up in the extensions of each response.
+#[derive(Clone, Debug)]
+pub struct TlsInfoConnector<C> {
+    inner: C,
+}
+
+impl<C> TlsInfoConnector<C> {
+    pub fn new(inner: C) -> TlsInfoConnector<C> {
+        TlsInfoConnector { inner }
+    }
+}
+
+impl<C, T> Service<Uri> for TlsInfoConnector<C>
+where
+    C: Service<Uri, Response = MaybeHttpsStream<T>>,
+    C::Future: Send + 'static,
+    T: AsyncRead + AsyncWrite + Connection + Unpin,
+{
+    type Response = TlsInfoStream<T>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let fut = self.inner.call(dst);
+        Box::pin(async move { fut.await.map(TlsInfoStream) })
+    }
+}
+
+pub struct TlsInfoStream<T>(MaybeHttpsStream<T>);
+
+impl<T: AsyncRead + AsyncWrite + Connection + Unpin> Connection for TlsInfoStream<T> {
+    fn connected(&self) -> Connected {
+        let connected = self.0.connected();
+
+        let MaybeHttpsStream::Https(stream) = &self.0 else {
+            return connected;
+        };
+
+        let (_, session) = stream.get_ref();
+
+        connected.extra(TlsInfo {
+            version: session
+                .protocol_version()
+                .map(|v| format!("{v:?}"))
+                .unwrap_or_default(),
+            cipher: session
+                .negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite()))
+                .unwrap_or_default(),
+            certificates: session
+                .peer_certificates()
+                .unwrap_or_default()
+                .iter()
+                .map(|cert| certificate_info(&cert.0))
+                .collect(),
+        })
+    }
+}
+
+fn certificate_info(der: &[u8]) -> CertificateInfo {
+    let mut sha256 = String::with_capacity(64);
+    for byte in Sha256::digest(der) {
+        let _ = write!(sha256, "{byte:02x}");
+    }
+
+    match X509Certificate::from_der(der) {
+        Ok((_, cert)) => CertificateInfo {
+            sha256,
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            not_before: Some(cert.validity().not_before.to_datetime()),
+            not_after: Some(cert.validity().not_after.to_datetime()),
+        },
+        Err(_) => CertificateInfo {
+            sha256,
+            subject: String::new(),
+            issuer: String::new(),
+            not_before: None,
+            not_after: None,
+        },
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsInfoStream<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsInfoStream<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+}
@@ -0,0 +1,175 @@
+use std::{borrow::Cow, io::Read};
+
+use encoding_rs::Encoding;
+use hyper::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE},
+    HeaderMap,
+};
+
+use crate::{EvergardenError, EvergardenResult};
+
+/// A `Content-Encoding` coding we know how to undo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentCoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentCoding {
+    pub fn parse(token: &str) -> EvergardenResult<ContentCoding> {
+        Ok(match token.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => ContentCoding::Identity,
+            "gzip" | "x-gzip" => ContentCoding::Gzip,
+            "deflate" => ContentCoding::Deflate,
+            "br" => ContentCoding::Brotli,
+            "zstd" => ContentCoding::Zstd,
+            other => return Err(EvergardenError::UnsupportedEncoding(other.to_owned())),
+        })
+    }
+
+    fn decode(self, body: &[u8]) -> EvergardenResult<Vec<u8>> {
+        let mut out = Vec::with_capacity(body.len() * 2);
+
+        match self {
+            ContentCoding::Identity => out.extend_from_slice(body),
+            ContentCoding::Gzip => {
+                flate2::read::MultiGzDecoder::new(body).read_to_end(&mut out)?;
+            }
+            // "deflate" is supposed to be zlib-wrapped, but plenty of servers send a raw deflate stream
+            ContentCoding::Deflate if body.first().is_some_and(|b| b & 0x0f == 8) => {
+                flate2::read::ZlibDecoder::new(body).read_to_end(&mut out)?;
+            }
+            ContentCoding::Deflate => {
+                flate2::read::DeflateDecoder::new(body).read_to_end(&mut out)?;
+            }
+            ContentCoding::Brotli => {
+                brotli_decompressor::Decompressor::new(body, 4096).read_to_end(&mut out)?;
+            }
+            ContentCoding::Zstd => {
+                zstd::stream::read::Decoder::new(body)?.read_to_end(&mut out)?;
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// The codings applied to a body, in the order they were applied.
+pub fn content_codings(headers: &HeaderMap) -> EvergardenResult<Vec<ContentCoding>> {
+    let mut codings = Vec::new();
+
+    for header in headers.get_all(CONTENT_ENCODING) {
+        let header = header
+            .to_str()
+            .map_err(|_| EvergardenError::UnsupportedEncoding(String::from("<invalid>")))?;
+
+        for token in header.split(',') {
+            match ContentCoding::parse(token)? {
+                ContentCoding::Identity => {}
+                coding => codings.push(coding),
+            }
+        }
+    }
+
+    Ok(codings)
+}
+
+/// Undoes every `Content-Encoding` listed in `headers`, borrowing the body if it isn't encoded.
+pub fn decode_body<'a>(headers: &HeaderMap, body: &'a [u8]) -> EvergardenResult<Cow<'a, [u8]>> {
+    let mut body = Cow::Borrowed(body);
+
+    for coding in content_codings(headers)?.into_iter().rev() {
+        body = Cow::Owned(coding.decode(&body)?);
+    }
+
+    Ok(body)
+}
+
+/// The charset a `Content-Type` header declares, if it names one we know.
+pub fn declared_charset(headers: &HeaderMap) -> Option<&'static Encoding> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+            .flatten()
+    })
+}
+
+/// Converts a decoded body to utf-8 from the charset its byte order mark or `Content-Type` names, returning the
+/// charset it was in. Bodies with neither are left alone, since they might not be text at all.
+pub fn transcode_body<'a>(
+    headers: &HeaderMap,
+    body: &'a [u8],
+) -> (Cow<'a, [u8]>, Option<&'static Encoding>) {
+    let Some(declared) = Encoding::for_bom(body)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| declared_charset(headers))
+    else {
+        return (Cow::Borrowed(body), None);
+    };
+
+    let (text, encoding, _) = declared.decode(body);
+    let text = match text {
+        Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+        Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+    };
+
+    (text, Some(encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use hyper::{
+        header::{CONTENT_ENCODING, CONTENT_TYPE},
+        HeaderMap,
+    };
+
+    use super::{decode_body, transcode_body};
+
+    #[test]
+    fn stacked_codings() {
+        let body = b"<html>hello</html>";
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(body).unwrap();
+        let encoded = zstd::encode_all(&gz.finish().unwrap()[..], 0).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, "gzip, zstd".parse().unwrap());
+        assert_eq!(&decode_body(&headers, &encoded).unwrap()[..], body);
+
+        assert_eq!(&decode_body(&HeaderMap::new(), body).unwrap()[..], body);
+
+        headers.insert(CONTENT_ENCODING, "compress".parse().unwrap());
+        assert!(decode_body(&headers, body).is_err());
+    }
+
+    #[test]
+    fn transcoded_charsets() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            "text/html; charset=\"ISO-8859-1\"".parse().unwrap(),
+        );
+
+        let (text, charset) = transcode_body(&headers, b"caf\xe9");
+        assert_eq!(&text[..], "café".as_bytes());
+        assert_eq!(charset.unwrap().name(), "windows-1252");
+
+        let (text, charset) = transcode_body(&HeaderMap::new(), b"\xef\xbb\xbfhi");
+        assert_eq!(&text[..], b"hi");
+        assert_eq!(charset.unwrap().name(), "UTF-8");
+
+        let (text, charset) = transcode_body(&HeaderMap::new(), b"\x89PNG");
+        assert_eq!(&text[..], b"\x89PNG");
+        assert!(charset.is_none());
+    }
+}
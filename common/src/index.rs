@@ -0,0 +1,762 @@
+//! A SQLite index of stored responses, kept next to the cache so they can be listed, filtered and sorted without
+//! reading every entry's metadata.
+
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{Type, Value};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use ssri::Integrity;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{EvergardenResult, ResponseMetadata};
+
+/// Bumped whenever the schema changes, so that older indexes are rebuilt from the cache.
+const INDEX_VERSION: i64 = 2;
+
+/// A stored response, as the index knows it.
+#[derive(Clone, Debug)]
+pub struct IndexEntry {
+    pub key: String,
+    pub url: Url,
+    pub host: Option<String>,
+    pub status: u16,
+    /// The content type, without parameters.
+    pub mime: Option<String>,
+    pub fetched_at: OffsetDateTime,
+    pub integrity: Integrity,
+    pub payload_digest: Option<String>,
+}
+
+impl IndexEntry {
+    pub fn new(key: &str, integrity: &Integrity, meta: &ResponseMetadata) -> IndexEntry {
+        IndexEntry {
+            key: key.to_owned(),
+            url: meta.url.url.clone(),
+            host: meta.url.url.host_str().map(str::to_owned),
+            status: meta.status.as_u16(),
+            mime: meta.mime(),
+            fetched_at: meta.fetched_at,
+            integrity: integrity.clone(),
+            payload_digest: meta.payload_digest.clone(),
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<IndexEntry> {
+        let fetched_at = row.get::<_, i64>(5)?;
+
+        Ok(IndexEntry {
+            key: row.get(0)?,
+            url: parse_column(row, 1)?,
+            host: row.get(2)?,
+            status: row.get(3)?,
+            mime: row.get(4)?,
+            fetched_at: OffsetDateTime::from_unix_timestamp_nanos(fetched_at.into()).map_err(
+                |e| rusqlite::Error::FromSqlConversionFailure(5, Type::Integer, Box::new(e)),
+            )?,
+            integrity: parse_column(row, 6)?,
+            payload_digest: row.get(7)?,
+        })
+    }
+}
+
+fn parse_column<T>(row: &Row, idx: usize) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    row.get::<_, String>(idx)?
+        .parse()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e)))
+}
+
+/// Which stored responses [`Storage::query`](crate::Storage::query) returns: those matching every predicate that's
+/// set. An empty filter matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Hosts to include, matched exactly.
+    pub hosts: Vec<String>,
+    /// Status ranges to include, like `200..=299`.
+    pub statuses: Vec<RangeInclusive<u16>>,
+    /// Content types to include, without parameters. `text/*` includes every text type.
+    pub mimes: Vec<String>,
+    /// Only responses fetched at or after this time.
+    pub since: Option<OffsetDateTime>,
+    /// Only responses fetched before this time.
+    pub until: Option<OffsetDateTime>,
+    /// Keys to include, matched exactly.
+    pub keys: Vec<String>,
+    /// Key prefixes to include, like `com,example)/` for everything on example.com.
+    pub key_prefixes: Vec<String>,
+    /// Only responses whose url matches this regex somewhere.
+    pub url_regex: Option<Regex>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+            && self.statuses.is_empty()
+            && self.mimes.is_empty()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.keys.is_empty()
+            && self.key_prefixes.is_empty()
+            && self.url_regex.is_none()
+    }
+
+    /// The filter as a SQL condition over the entries table, along with its parameters.
+    fn to_sql(&self) -> (String, Vec<Value>) {
+        let mut conditions = vec![String::from("1")];
+        let mut values = Vec::new();
+
+        if !self.hosts.is_empty() {
+            conditions.push(format!(
+                "host IN ({})",
+                vec!["?"; self.hosts.len()].join(", ")
+            ));
+            values.extend(self.hosts.iter().map(|host| Value::Text(host.clone())));
+        }
+
+        if !self.statuses.is_empty() {
+            conditions.push(format!(
+                "({})",
+                vec!["status BETWEEN ? AND ?"; self.statuses.len()].join(" OR ")
+            ));
+            for range in &self.statuses {
+                values.push(Value::Integer((*range.start()).into()));
+                values.push(Value::Integer((*range.end()).into()));
+            }
+        }
+
+        if !self.mimes.is_empty() {
+            let mut mimes = Vec::with_capacity(self.mimes.len());
+            for mime in &self.mimes {
+                let mime = mime.trim().to_ascii_lowercase();
+                match mime.strip_suffix('*') {
+                    Some(prefix) => {
+                        mimes.push("mime LIKE ? ESCAPE '\\'");
+                        let prefix = prefix
+                            .replace('\\', "\\\\")
+                            .replace('%', "\\%")
+                            .replace('_', "\\_");
+                        values.push(Value::Text(format!("{prefix}%")));
+                    }
+                    None => {
+                        mimes.push("mime = ?");
+                        values.push(Value::Text(mime));
+                    }
+                }
+            }
+            conditions.push(format!("({})", mimes.join(" OR ")));
+        }
+
+        if let Some(since) = self.since {
+            conditions.push(String::from("fetched_at >= ?"));
+            values.push(Value::Integer(since.unix_timestamp_nanos() as i64));
+        }
+
+        if let Some(until) = self.until {
+            conditions.push(String::from("fetched_at < ?"));
+            values.push(Value::Integer(until.unix_timestamp_nanos() as i64));
+        }
+
+        if !self.keys.is_empty() {
+            conditions.push(format!(
+                "key IN ({})",
+                vec!["?"; self.keys.len()].join(", ")
+            ));
+            values.extend(self.keys.iter().map(|key| Value::Text(key.clone())));
+        }
+
+        // as ranges, rather than LIKE patterns, so that they're case-sensitive and can use the key's index. keys are
+        // urls, which never have the last code point in them, so it bounds everything starting with the prefix.
+        if !self.key_prefixes.is_empty() {
+            conditions.push(format!(
+                "({})",
+                vec!["(key >= ? AND key < ?)"; self.key_prefixes.len()].join(" OR ")
+            ));
+            for prefix in &self.key_prefixes {
+                values.push(Value::Text(prefix.clone()));
+                values.push(Value::Text(format!("{prefix}{}", char::MAX)));
+            }
+        }
+
+        if let Some(regex) = &self.url_regex {
+            conditions.push(String::from("url REGEXP ?"));
+            values.push(Value::Text(regex.as_str().to_owned()));
+        }
+
+        (conditions.join(" AND "), values)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct MetadataIndex {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl MetadataIndex {
+    /// Opens the index in `dir`, and whether it needs to be rebuilt from the cache.
+    pub fn open(dir: &Path) -> EvergardenResult<(MetadataIndex, bool)> {
+        std::fs::create_dir_all(dir)?;
+
+        Self::from_connection(Connection::open(dir.join("index.sqlite3"))?)
+    }
+
+    #[cfg(test)]
+    fn in_memory() -> EvergardenResult<MetadataIndex> {
+        Ok(Self::from_connection(Connection::open_in_memory()?)?.0)
+    }
+
+    fn from_connection(conn: Connection) -> EvergardenResult<(MetadataIndex, bool)> {
+        // exports can read the index while a crawl is writing to it
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;",
+        )?;
+
+        // sqlite leaves REGEXP for applications to define, as regexp(pattern, text)
+        conn.create_scalar_function(
+            "regexp",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let regex = ctx.get_or_create_aux(
+                    0,
+                    |pattern| -> Result<Regex, Box<dyn std::error::Error + Send + Sync>> {
+                        Ok(Regex::new(pattern.as_str()?)?)
+                    },
+                )?;
+                Ok(regex.is_match(ctx.get_raw(1).as_str()?))
+            },
+        )?;
+
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let stale = version != INDEX_VERSION;
+        if stale {
+            conn.execute("DROP TABLE IF EXISTS entries", [])?;
+        }
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS entries (
+                key TEXT PRIMARY KEY NOT NULL,
+                url TEXT NOT NULL,
+                host TEXT,
+                status INTEGER NOT NULL,
+                mime TEXT,
+                fetched_at INTEGER NOT NULL,
+                integrity TEXT NOT NULL,
+                payload_digest TEXT
+            );
+            CREATE INDEX IF NOT EXISTS entries_by_host ON entries (host);
+            CREATE INDEX IF NOT EXISTS entries_by_fetched_at ON entries (fetched_at);
+            CREATE INDEX IF NOT EXISTS entries_by_payload_digest ON entries (payload_digest);",
+        )?;
+
+        Ok((
+            MetadataIndex {
+                conn: Arc::new(Mutex::new(conn)),
+            },
+            stale,
+        ))
+    }
+
+    pub fn insert(&self, entry: &IndexEntry) -> EvergardenResult<()> {
+        Self::insert_with(&self.conn.lock().unwrap(), entry)
+    }
+
+    fn insert_with(conn: &Connection, entry: &IndexEntry) -> EvergardenResult<()> {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO entries
+            (key, url, host, status, mime, fetched_at, integrity, payload_digest)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?
+        .execute(params![
+            entry.key,
+            entry.url.as_str(),
+            entry.host,
+            entry.status,
+            entry.mime,
+            entry.fetched_at.unix_timestamp_nanos() as i64,
+            entry.integrity.to_string(),
+            entry.payload_digest,
+        ])?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) -> EvergardenResult<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("DELETE FROM entries WHERE key = ?1")?
+            .execute([key])?;
+
+        Ok(())
+    }
+
+    /// Every entry, sorted by key.
+    pub fn entries(&self) -> EvergardenResult<Vec<IndexEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare_cached(
+            "SELECT key, url, host, status, mime, fetched_at, integrity, payload_digest
+            FROM entries ORDER BY key",
+        )?;
+
+        let entries = statement
+            .query_map([], IndexEntry::from_row)?
+            .collect::<rusqlite::Result<Vec<IndexEntry>>>()?;
+        Ok(entries)
+    }
+
+    /// Up to `limit` entries matching `filter`, sorted by key, starting after the key `after`.
+    pub fn query(
+        &self,
+        filter: &Filter,
+        after: Option<&str>,
+        limit: usize,
+    ) -> EvergardenResult<Vec<IndexEntry>> {
+        let (condition, mut values) = filter.to_sql();
+        values.push(Value::Text(after.unwrap_or_default().to_owned()));
+        values.push(Value::Integer(limit as i64));
+
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT key, url, host, status, mime, fetched_at, integrity, payload_digest
+            FROM entries WHERE {condition} AND key > ? ORDER BY key LIMIT ?"
+        ))?;
+
+        let entries = statement
+            .query_map(params_from_iter(values), IndexEntry::from_row)?
+            .collect::<rusqlite::Result<Vec<IndexEntry>>>()?;
+        Ok(entries)
+    }
+
+    /// How many entries match `filter`.
+    pub fn count(&self, filter: &Filter) -> EvergardenResult<usize> {
+        let (condition, values) = filter.to_sql();
+
+        let count = self
+            .conn
+            .lock()
+            .unwrap()
+            .prepare_cached(&format!("SELECT COUNT(*) FROM entries WHERE {condition}"))?
+            .query_row(params_from_iter(values), |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// The earliest entry whose payload has the digest `digest`, other than the one under `except`.
+    pub fn find_by_payload_digest(
+        &self,
+        digest: &str,
+        except: &str,
+    ) -> EvergardenResult<Option<IndexEntry>> {
+        let entry = self
+            .conn
+            .lock()
+            .unwrap()
+            .prepare_cached(
+                "SELECT key, url, host, status, mime, fetched_at, integrity, payload_digest
+                FROM entries WHERE payload_digest = ?1 AND key != ?2 ORDER BY fetched_at LIMIT 1",
+            )?
+            .query_row([digest, except], IndexEntry::from_row)
+            .optional()?;
+
+        Ok(entry)
+    }
+
+    /// Replaces the whole index with `entries`.
+    pub fn rebuild(
+        &self,
+        entries: impl Iterator<Item = EvergardenResult<IndexEntry>>,
+    ) -> EvergardenResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM entries", [])?;
+        for entry in entries {
+            Self::insert_with(&tx, &entry?)?;
+        }
+        tx.pragma_update(None, "user_version", INDEX_VERSION)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn entry(
+        key: &str,
+        url: &str,
+        status: u16,
+        mime: &str,
+        fetched_at: OffsetDateTime,
+    ) -> IndexEntry {
+        let url = Url::parse(url).unwrap();
+        IndexEntry {
+            key: key.to_owned(),
+            host: url.host_str().map(str::to_owned),
+            url,
+            status,
+            mime: Some(mime.to_owned()),
+            fetched_at,
+            integrity: Integrity::from(key),
+            payload_digest: None,
+        }
+    }
+
+    const NOON: OffsetDateTime = datetime!(2024-01-01 12:00 UTC);
+    const ONE: OffsetDateTime = datetime!(2024-01-01 13:00 UTC);
+
+    fn index() -> MetadataIndex {
+        let index = MetadataIndex::in_memory().unwrap();
+        for entry in [
+            entry(
+                "com,example)/",
+                "https://example.com/",
+                200,
+                "text/html",
+                NOON,
+            ),
+            entry(
+                "com,example)/a_b",
+                "https://example.com/a_b",
+                404,
+                "text/plain",
+                NOON,
+            ),
+            entry(
+                "com,example)/img",
+                "https://example.com/img",
+                200,
+                "image/png",
+                ONE,
+            ),
+            entry(
+                "com,example)/moved",
+                "https://example.com/moved",
+                301,
+                "text/html",
+                ONE,
+            ),
+            entry(
+                "org,example)/",
+                "https://example.org/",
+                503,
+                "text/html",
+                NOON,
+            ),
+            entry(
+                "org,example)/Feed",
+                "https://example.org/Feed",
+                200,
+                "text_x/atom",
+                ONE,
+            ),
+        ] {
+            index.insert(&entry).unwrap();
+        }
+
+        index
+    }
+
+    fn keys(index: &MetadataIndex, filter: &Filter) -> Vec<String> {
+        let entries = index.query(filter, None, 100).unwrap();
+        assert_eq!(index.count(filter).unwrap(), entries.len());
+        entries.into_iter().map(|entry| entry.key).collect()
+    }
+
+    #[test]
+    fn empty_filters_match_everything() {
+        let filter = Filter::default();
+        assert!(filter.is_empty());
+        assert_eq!(filter.to_sql(), (String::from("1"), Vec::new()));
+        assert_eq!(keys(&index(), &filter).len(), 6);
+    }
+
+    #[test]
+    fn writes_each_filter_as_sql() {
+        let sql = |filter: Filter| {
+            assert!(!filter.is_empty());
+            filter.to_sql()
+        };
+        let text = |s: &str| Value::Text(s.to_owned());
+
+        assert_eq!(
+            sql(Filter {
+                hosts: vec![String::from("a"), String::from("b")],
+                ..Filter::default()
+            }),
+            (
+                String::from("1 AND host IN (?, ?)"),
+                vec![text("a"), text("b")]
+            )
+        );
+        assert_eq!(
+            sql(Filter {
+                statuses: vec![200..=299, 404..=404],
+                ..Filter::default()
+            }),
+            (
+                String::from("1 AND (status BETWEEN ? AND ? OR status BETWEEN ? AND ?)"),
+                vec![
+                    Value::Integer(200),
+                    Value::Integer(299),
+                    Value::Integer(404),
+                    Value::Integer(404)
+                ]
+            )
+        );
+        assert_eq!(
+            sql(Filter {
+                mimes: vec![String::from(" Text/HTML "), String::from("text_x/*")],
+                ..Filter::default()
+            }),
+            (
+                String::from("1 AND (mime = ? OR mime LIKE ? ESCAPE '\\')"),
+                vec![text("text/html"), text("text\\_x/%")]
+            )
+        );
+        assert_eq!(
+            sql(Filter {
+                since: Some(NOON),
+                until: Some(ONE),
+                ..Filter::default()
+            }),
+            (
+                String::from("1 AND fetched_at >= ? AND fetched_at < ?"),
+                vec![
+                    Value::Integer(NOON.unix_timestamp_nanos() as i64),
+                    Value::Integer(ONE.unix_timestamp_nanos() as i64)
+                ]
+            )
+        );
+        assert_eq!(
+            sql(Filter {
+                keys: vec![String::from("k")],
+                ..Filter::default()
+            }),
+            (String::from("1 AND key IN (?)"), vec![text("k")])
+        );
+        assert_eq!(
+            sql(Filter {
+                key_prefixes: vec![String::from("com,")],
+                ..Filter::default()
+            }),
+            (
+                String::from("1 AND ((key >= ? AND key < ?))"),
+                vec![text("com,"), text(&format!("com,{}", char::MAX))]
+            )
+        );
+        assert_eq!(
+            sql(Filter {
+                url_regex: Some(Regex::new("a+").unwrap()),
+                ..Filter::default()
+            }),
+            (String::from("1 AND url REGEXP ?"), vec![text("a+")])
+        );
+    }
+
+    #[test]
+    fn applies_each_filter() {
+        let index = index();
+        let filtered = |filter: Filter| keys(&index, &filter);
+
+        assert_eq!(
+            filtered(Filter {
+                hosts: vec![String::from("example.org")],
+                ..Filter::default()
+            }),
+            ["org,example)/", "org,example)/Feed"]
+        );
+        assert_eq!(
+            filtered(Filter {
+                statuses: vec![300..=399, 500..=599],
+                ..Filter::default()
+            }),
+            ["com,example)/moved", "org,example)/"]
+        );
+        assert_eq!(
+            filtered(Filter {
+                mimes: vec![String::from("image/*")],
+                ..Filter::default()
+            }),
+            ["com,example)/img"]
+        );
+        // the underscore is taken literally, rather than as LIKE's single character wildcard
+        assert_eq!(
+            filtered(Filter {
+                mimes: vec![String::from("text_*")],
+                ..Filter::default()
+            }),
+            ["org,example)/Feed"]
+        );
+        assert_eq!(
+            filtered(Filter {
+                mimes: vec![String::from("TEXT/PLAIN")],
+                ..Filter::default()
+            }),
+            ["com,example)/a_b"]
+        );
+        assert_eq!(
+            filtered(Filter {
+                keys: vec![String::from("com,example)/img"), String::from("missing")],
+                ..Filter::default()
+            }),
+            ["com,example)/img"]
+        );
+        assert_eq!(
+            filtered(Filter {
+                url_regex: Some(Regex::new("/[A-Z]").unwrap()),
+                ..Filter::default()
+            }),
+            ["org,example)/Feed"]
+        );
+    }
+
+    #[test]
+    fn matches_key_prefixes_case_sensitively() {
+        let index = index();
+        let prefixed = |prefixes: &[&str]| {
+            keys(
+                &index,
+                &Filter {
+                    key_prefixes: prefixes.iter().map(|p| String::from(*p)).collect(),
+                    ..Filter::default()
+                },
+            )
+        };
+
+        assert_eq!(prefixed(&["org,example)/F"]), ["org,example)/Feed"]);
+        assert!(prefixed(&["org,example)/f"]).is_empty());
+        // an underscore in a prefix isn't a wildcard either
+        assert_eq!(prefixed(&["com,example)/a_"]), ["com,example)/a_b"]);
+        assert!(prefixed(&["com,example)/ax"]).is_empty());
+        assert_eq!(
+            prefixed(&["com,example)/m", "org,example)/F"]),
+            ["com,example)/moved", "org,example)/Feed"]
+        );
+    }
+
+    #[test]
+    fn bounds_fetch_times() {
+        let index = index();
+        let between = |since, until| {
+            keys(
+                &index,
+                &Filter {
+                    since,
+                    until,
+                    ..Filter::default()
+                },
+            )
+        };
+
+        // since includes its own time, and until doesn't
+        assert_eq!(between(Some(ONE), None).len(), 3);
+        assert_eq!(between(None, Some(ONE)).len(), 3);
+        assert_eq!(between(Some(NOON), Some(ONE)).len(), 3);
+        assert!(between(Some(ONE), Some(ONE)).is_empty());
+        assert_eq!(between(Some(NOON), None).len(), 6);
+    }
+
+    #[test]
+    fn combines_filters() {
+        let index = index();
+
+        let filter = Filter {
+            hosts: vec![String::from("example.com"), String::from("example.org")],
+            statuses: vec![200..=299],
+            mimes: vec![String::from("text/html"), String::from("image/png")],
+            ..Filter::default()
+        };
+        assert_eq!(keys(&index, &filter), ["com,example)/", "com,example)/img"]);
+
+        let filter = Filter {
+            since: Some(ONE),
+            key_prefixes: vec![String::from("com,")],
+            url_regex: Some(Regex::new("o").unwrap()),
+            ..filter
+        };
+        assert_eq!(keys(&index, &filter), ["com,example)/img"]);
+
+        let filter = Filter {
+            statuses: vec![404..=404],
+            ..filter
+        };
+        assert!(keys(&index, &filter).is_empty());
+    }
+
+    #[test]
+    fn pages_by_key() {
+        let index = index();
+        let page = |filter: &Filter, after: Option<&str>| {
+            index
+                .query(filter, after, 2)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.key)
+                .collect::<Vec<_>>()
+        };
+
+        // several entries share a fetch time, which doesn't matter since pages follow on from the last key
+        let everything = Filter::default();
+        let mut seen = Vec::new();
+        let mut after = None;
+        loop {
+            let keys = page(&everything, after.as_deref());
+            if keys.is_empty() {
+                break;
+            }
+            assert!(keys.len() <= 2);
+            after = keys.last().cloned();
+            seen.extend(keys);
+        }
+        assert_eq!(seen.len(), 6);
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // the last page is whatever's left, and there's nothing after it
+        assert_eq!(
+            page(&everything, Some("org,example)/")),
+            ["org,example)/Feed"]
+        );
+        assert!(page(&everything, Some("org,example)/Feed")).is_empty());
+        assert!(page(&everything, Some("zzz")).is_empty());
+
+        // pages of a filter skip over what doesn't match it
+        let html = Filter {
+            mimes: vec![String::from("text/html")],
+            ..Filter::default()
+        };
+        assert_eq!(page(&html, None), ["com,example)/", "com,example)/moved"]);
+        assert_eq!(page(&html, Some("com,example)/moved")), ["org,example)/"]);
+        assert!(page(&html, Some("org,example)/")).is_empty());
+    }
+
+    #[test]
+    fn pages_of_an_empty_index_are_empty() {
+        let index = MetadataIndex::in_memory().unwrap();
+
+        assert!(index
+            .query(&Filter::default(), None, 10)
+            .unwrap()
+            .is_empty());
+        assert!(index
+            .query(&Filter::default(), Some("a"), 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(index.count(&Filter::default()).unwrap(), 0);
+    }
+}
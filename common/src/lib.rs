@@ -1,15 +1,20 @@
-#![feature(impl_trait_in_assoc_type)]
 #![feature(return_position_impl_trait_in_trait)]
 
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
     fmt::{Debug, Display},
     net::SocketAddr,
-    sync::Arc,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
 
+use base64::{prelude::BASE64_STANDARD, Engine};
 use bytes::Bytes;
+use futures_util::TryStreamExt;
 
-use hyper::{http::HeaderValue, HeaderMap, StatusCode, Version};
+use hyper::{header::CONTENT_TYPE, http::HeaderValue, HeaderMap, Method, StatusCode, Version};
 use serde::{Deserialize, Serialize};
 
 use thiserror::Error;
@@ -22,11 +27,19 @@ use thiserror::Error;
 pub mod surt;
 pub use surt::*;
 
+pub mod encoding;
+
+mod index;
+pub use index::{Filter, IndexEntry};
+mod listing;
+pub use listing::{ListingFormat, ListingRecord, ListingSort};
+mod stats;
+pub use stats::{Breakdown, CrawlStats};
 mod storage;
 pub use storage::*;
 
 use time::OffsetDateTime;
-use url::Url;
+use url::{Position, Url};
 use uuid::Uuid;
 
 #[derive(Error, Debug)]
@@ -41,6 +54,26 @@ pub enum EvergardenError {
     Cache(#[from] cacache::Error),
     #[error(transparent)]
     LZ4(#[from] lz4_flex::frame::Error),
+    #[error(transparent)]
+    ObjectStore(#[from] object_store::Error),
+    #[error(transparent)]
+    SQLite(#[from] rusqlite::Error),
+    #[error("url disallowed by robots.txt")]
+    RobotsDisallowed,
+    #[error("skipped after HEAD preflight: {0}")]
+    PreflightRejected(String),
+    #[error("skipped: {0}")]
+    Skipped(String),
+    #[error("the crawl's budget is spent")]
+    BudgetExhausted,
+    #[error("unsupported content encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("browser error: {0}")]
+    Browser(String),
+    #[error("script error: {0}")]
+    Script(String),
+    #[error("script timed out after {0:?}")]
+    ScriptTimeout(Duration),
 }
 
 impl From<BodyReadError> for EvergardenError {
@@ -75,6 +108,89 @@ pub struct UrlInfo {
     pub url: Url,
     pub discovered_in: Url,
     pub hops: usize,
+    /// Higher priorities are fetched first.
+    #[serde(default)]
+    pub priority: i32,
+    /// Inherited from the seed. Leaving the scope counts as a hop, instead of leaving the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+    /// Set for urls that should be requested with something other than a bare GET, like API calls found by scripts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<RequestSpec>,
+    /// Set for urls a script scheduled to be (re)fetched later, which wait in the frontier until then.
+    #[serde(
+        with = "time::serde::rfc3339::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub not_before: Option<OffsetDateTime>,
+}
+
+/// The method, extra headers and body to request a url with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestSpec {
+    #[serde(with = "http_serde::method", default)]
+    pub method: Method,
+    #[serde(with = "http_serde::header_map", default)]
+    pub headers: HeaderMap<HeaderValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// The part of the web a seed's crawl is meant to stay in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Anything on this host (and port).
+    Host(String),
+    /// Anything whose url starts with this prefix.
+    Prefix(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScopeKind {
+    None,
+    Host,
+    Prefix,
+}
+
+impl FromStr for ScopeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ScopeKind::None),
+            "host" => Ok(ScopeKind::Host),
+            "prefix" => Ok(ScopeKind::Prefix),
+            other => Err(format!(
+                "unknown scope {other}, expected none, host or prefix"
+            )),
+        }
+    }
+}
+
+impl Scope {
+    pub fn for_seed(kind: ScopeKind, seed: &Url) -> Option<Scope> {
+        match kind {
+            ScopeKind::None => None,
+            ScopeKind::Host => Some(Scope::Host(
+                seed[Position::BeforeHost..Position::AfterPort].to_owned(),
+            )),
+            ScopeKind::Prefix => {
+                // a seed like /blog/index.html scopes to /blog/
+                let path = &seed[..Position::AfterPath];
+                let dir = &path[..path.rfind('/').map_or(path.len(), |idx| idx + 1)];
+                Some(Scope::Prefix(dir.to_owned()))
+            }
+        }
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        match self {
+            Scope::Host(host) => &url[Position::BeforeHost..Position::AfterPort] == host,
+            Scope::Prefix(prefix) => url.as_str().starts_with(prefix.as_str()),
+        }
+    }
 }
 
 impl Debug for UrlInfo {
@@ -83,6 +199,9 @@ impl Debug for UrlInfo {
             .field("url", &self.url.as_str())
             .field("discovered_in", &self.discovered_in.as_str())
             .field("hops", &self.hops)
+            .field("priority", &self.priority)
+            .field("scope", &self.scope)
+            .field("request", &self.request)
             .finish()
     }
 }
@@ -100,23 +219,71 @@ impl Display for UrlInfo {
 }
 
 impl UrlInfo {
+    pub const SEED_PRIORITY: i32 = 200;
+    pub const SAME_HOST_PRIORITY: i32 = 100;
+    pub const CROSS_HOST_PRIORITY: i32 = 0;
+
     pub fn start(url: &str) -> Option<UrlInfo> {
-        let url = Url::parse(url).ok()?;
-        Some(UrlInfo {
+        Some(UrlInfo::seed(Url::parse(url).ok()?))
+    }
+
+    pub fn seed(url: Url) -> UrlInfo {
+        UrlInfo::scoped_seed(url, ScopeKind::None)
+    }
+
+    pub fn scoped_seed(url: Url, scope: ScopeKind) -> UrlInfo {
+        UrlInfo {
+            scope: Scope::for_seed(scope, &url),
             url: url.clone(),
             discovered_in: url,
             hops: 0,
-        })
+            priority: UrlInfo::SEED_PRIORITY,
+            request: None,
+            not_before: None,
+        }
     }
+
+    /// The url this is stored and deduplicated under. Anything but a bare GET gets its method and body appended to the
+    /// query the way pywb does, so it doesn't collide with the GET of the same url and can still be replayed.
+    pub fn key_url(&self) -> Url {
+        let Some(request) = self
+            .request
+            .as_ref()
+            .filter(|r| r.method != Method::GET || r.body.is_some())
+        else {
+            return self.url.clone();
+        };
+
+        let mut url = self.url.clone();
+        let mut query = url.query_pairs_mut();
+        query.append_pair("__wb_method", &request.method.as_str().to_ascii_lowercase());
+        if let Some(body) = &request.body {
+            query.append_pair("__wb_post_data", &BASE64_STANDARD.encode(body));
+        }
+        drop(query);
+
+        url
+    }
+
     pub fn hop(mut self, new_url: &str) -> Option<UrlInfo> {
         let new_url = self.url.join(new_url).ok()?;
 
-        if new_url.host() != self.url.host() {
+        let leaves_scope = match &self.scope {
+            Some(scope) => !scope.contains(&new_url),
+            None => new_url.host() != self.url.host(),
+        };
+
+        if leaves_scope {
             self.hops += 1;
+            self.priority = UrlInfo::CROSS_HOST_PRIORITY;
+        } else {
+            self.priority = UrlInfo::SAME_HOST_PRIORITY;
         }
 
         self.discovered_in = self.url;
         self.url = new_url;
+        self.request = None;
+        self.not_before = None;
 
         Some(self)
     }
@@ -135,12 +302,299 @@ pub struct ResponseMetadata {
     #[serde(with = "time::serde::rfc3339")]
     pub fetched_at: OffsetDateTime,
     pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<TruncatedReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<RequestMetadata>,
+    /// `sha256:<hex>` digest of the body as received, computed when the response is stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_digest: Option<String>,
+    /// The canonical url advertised by the response, if it differs from the fetched url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<Url>,
+    /// Set when the body is identical to an earlier capture of the same url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revisit: Option<RevisitInfo>,
+    /// Indexing directives that applied to the response, if the crawl honored them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub robots: Option<RobotsDirectives>,
+    /// Set for records made by evergarden itself (like screenshots) rather than received over http. Their body is the
+    /// whole record, and `url` is a `urn:` that doubles as their storage key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<ResourceInfo>,
+    /// Page metadata scripts attached to the response after processing it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+    /// How the stored body is compressed, recorded when the response is stored. Entries from before this was recorded
+    /// are LZ4, the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// The length of the body as received, recorded when the response is stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl ResponseMetadata {
+    /// The content type, without parameters.
+    pub fn mime(&self) -> Option<String> {
+        self.headers
+            .get(CONTENT_TYPE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .filter(|mime| !mime.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResourceInfo {
+    pub content_type: String,
+    /// The response this was made from.
+    pub concurrent_to: Uuid,
+}
+
+/// `noindex`/`nofollow` directives from `X-Robots-Tag` headers and robots meta tags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RobotsDirectives {
+    pub noindex: bool,
+    pub nofollow: bool,
+}
+
+impl RobotsDirectives {
+    /// Adds the directives from a comma separated list like `noindex, nofollow`, where `none` means both.
+    pub fn add(&mut self, list: &str) {
+        for directive in list.split(',') {
+            match directive.trim().to_ascii_lowercase().as_str() {
+                "noindex" => self.noindex = true,
+                "nofollow" => self.nofollow = true,
+                "none" => {
+                    self.noindex = true;
+                    self.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn merge(self, other: RobotsDirectives) -> RobotsDirectives {
+        RobotsDirectives {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow,
+        }
+    }
+}
+
+/// Structured metadata about a page, as found by a script.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The page's readable text, for full-text search.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl Annotations {
+    /// Combines two sets of annotations, with `other`'s values winning.
+    pub fn merge(mut self, other: Annotations) -> Annotations {
+        self.title = other.title.or(self.title);
+        self.description = other.description.or(self.description);
+        self.language = other.language.or(self.language);
+        self.text = other.text.or(self.text);
+        self.extra.extend(other.extra);
+        self
+    }
+}
+
+/// Points a revisit at the capture whose payload it repeats.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevisitInfo {
+    pub id: Uuid,
+    pub url: Url,
+    #[serde(with = "time::serde::rfc3339")]
+    pub fetched_at: OffsetDateTime,
+}
+
+/// The request line and headers that were sent to produce a response.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestMetadata {
+    #[serde(with = "http_serde::method")]
+    pub method: Method,
+    /// Path and query, as sent in the request line.
+    pub target: String,
+    #[serde(with = "http_serde::version")]
+    pub version: Version,
+    #[serde(with = "http_serde::header_map")]
+    pub headers: HeaderMap<HeaderValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "base64_body")]
+    pub body: Option<Bytes>,
+}
+
+/// Request bodies are kept in the metadata json, as base64.
+mod base64_body {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use bytes::Bytes;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &Option<Bytes>, ser: S) -> Result<S::Ok, S::Error> {
+        match body {
+            Some(body) => ser.serialize_some(&BASE64_STANDARD.encode(body)),
+            None => ser.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Option<Bytes>, D::Error> {
+        Option::<String>::deserialize(de)?
+            .map(|body| BASE64_STANDARD.decode(body).map(Bytes::from))
+            .transpose()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// The negotiated TLS session of an https fetch, kept for provenance.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsInfo {
+    pub version: String,
+    pub cipher: String,
+    /// Server certificate chain, leaf first.
+    pub certificates: Vec<CertificateInfo>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    pub sha256: String,
+    pub subject: String,
+    pub issuer: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub not_before: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub not_after: Option<OffsetDateTime>,
+}
+
+/// Why a record's body is incomplete, as expressed by the `WARC-Truncated` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncatedReason {
+    Length,
+    Time,
+    Disconnect,
+    Unspecified,
+}
+
+impl TruncatedReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TruncatedReason::Length => "length",
+            TruncatedReason::Time => "time",
+            TruncatedReason::Disconnect => "disconnect",
+            TruncatedReason::Unspecified => "unspecified",
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct HttpResponse {
     pub meta: Arc<ResponseMetadata>,
     pub body: async_broadcast::Receiver<BodyResult<Bytes>>,
+    /// Set by the body reader once it cuts the body short, since that is only known after `meta` has been shared.
+    pub truncated: Arc<OnceLock<TruncatedReason>>,
+}
+
+impl HttpResponse {
+    /// A resource record derived from the response described by `of`, stored under `urn:<kind>:<url>`.
+    pub fn resource(
+        kind: &str,
+        of: &ResponseMetadata,
+        content_type: &str,
+        body: Bytes,
+    ) -> HttpResponse {
+        let mut url = of.url.clone();
+        url.url = Url::parse(&format!("urn:{kind}:{}", of.url.url))
+            .expect("a urn: prefix keeps urls valid");
+
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(content_type) {
+            headers.insert(CONTENT_TYPE, value);
+        }
+
+        HttpResponse::from_bytes(
+            ResponseMetadata {
+                url,
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers,
+                remote_addr: None,
+                fetched_at: OffsetDateTime::now_utc(),
+                id: Uuid::new_v4(),
+                truncated: None,
+                tls: None,
+                request: None,
+                payload_digest: None,
+                canonical: None,
+                revisit: None,
+                robots: None,
+                resource: Some(ResourceInfo {
+                    content_type: content_type.to_owned(),
+                    concurrent_to: of.id,
+                }),
+                annotations: None,
+                compression: None,
+                size: None,
+            },
+            body,
+        )
+    }
+
+    /// A response whose body is already in memory.
+    pub fn from_bytes(meta: ResponseMetadata, body: Bytes) -> HttpResponse {
+        let (tx, rx) = async_broadcast::broadcast(1);
+        if !body.is_empty() {
+            let _ = tx.try_broadcast(Ok(body));
+        }
+        tx.close();
+
+        HttpResponse {
+            meta: Arc::new(meta),
+            body: rx,
+            truncated: Arc::default(),
+        }
+    }
+
+    /// Reads the whole body and undoes any `Content-Encoding`. The stored body always keeps its original encoding.
+    pub async fn decoded_body(&self) -> EvergardenResult<Bytes> {
+        let mut body = self.body.clone();
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = body.try_next().await? {
+            buf.extend_from_slice(&chunk);
+        }
+
+        Ok(match encoding::decode_body(&self.meta.headers, &buf)? {
+            Cow::Borrowed(_) => Bytes::from(buf),
+            Cow::Owned(decoded) => Bytes::from(decoded),
+        })
+    }
+
+    /// Like [`HttpResponse::decoded_body`], but also converted to utf-8 if it has a known charset, which is returned
+    /// alongside it.
+    pub async fn transcoded_body(
+        &self,
+    ) -> EvergardenResult<(Bytes, Option<&'static encoding_rs::Encoding>)> {
+        let body = self.decoded_body().await?;
+
+        Ok(match encoding::transcode_body(&self.meta.headers, &body) {
+            (Cow::Borrowed(text), charset) => (body.slice_ref(text), charset),
+            (Cow::Owned(text), charset) => (Bytes::from(text), charset),
+        })
+    }
 }
 
 impl Display for HttpResponse {
@@ -150,8 +604,52 @@ impl Display for HttpResponse {
     }
 }
 
+/// A url that could not be archived, persisted so it can be reported on and re-seeded later.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FailedFetch {
+    pub url: UrlInfo,
+    pub class: FailureClass,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub message: String,
+    pub attempts: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_attempt: OffsetDateTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    Timeout,
+    Dns,
+    Connect,
+    ClientError,
+    ServerError,
+    Body,
+    Other,
+}
+
+impl Display for FailureClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FailureClass::Timeout => "timeout",
+            FailureClass::Dns => "dns",
+            FailureClass::Connect => "connect",
+            FailureClass::ClientError => "client_error",
+            FailureClass::ServerError => "server_error",
+            FailureClass::Body => "body",
+            FailureClass::Other => "other",
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CrawlInfo {
+    /// Stays the same when a crawl is resumed.
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     pub config: String,
     pub entry_points: Vec<String>,
+    #[serde(default)]
+    pub seeds: Vec<UrlInfo>,
 }
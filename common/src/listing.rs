@@ -0,0 +1,231 @@
+//! Plain listings of stored responses, for looking over a crawl with a spreadsheet or `jq` rather than WARC tooling.
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::io::Write;
+use std::str::FromStr;
+
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{EvergardenResult, Filter, ResponseMetadata, Storage};
+
+const CSV_HEADER: &str = "url,surt,status,mime,size,fetched_at,digest";
+
+/// How [`Storage::write_listing`] writes records.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListingFormat {
+    /// One json object per line.
+    #[default]
+    Jsonl,
+    /// Comma separated values, with a header row.
+    Csv,
+}
+
+impl FromStr for ListingFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ListingFormat::Jsonl),
+            "csv" => Ok(ListingFormat::Csv),
+            other => Err(format!("unknown format {other}, expected jsonl or csv")),
+        }
+    }
+}
+
+/// What [`Storage::write_listing`] sorts records by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListingSort {
+    /// The SURT key, which keeps each site's records together.
+    #[default]
+    Key,
+    Url,
+    Status,
+    /// The length of the body, largest first.
+    Size,
+    /// When the response was fetched, earliest first.
+    Time,
+}
+
+impl FromStr for ListingSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "key" => Ok(ListingSort::Key),
+            "url" => Ok(ListingSort::Url),
+            "status" => Ok(ListingSort::Status),
+            "size" => Ok(ListingSort::Size),
+            "time" => Ok(ListingSort::Time),
+            other => Err(format!(
+                "unknown sort {other}, expected key, url, status, size or time"
+            )),
+        }
+    }
+}
+
+impl ListingSort {
+    fn sort(self, records: &mut [ListingRecord]) {
+        match self {
+            ListingSort::Key => records.sort_by(|a, b| a.surt.cmp(&b.surt)),
+            ListingSort::Url => records.sort_by(|a, b| a.url.as_str().cmp(b.url.as_str())),
+            ListingSort::Status => records.sort_by_key(|record| record.status),
+            ListingSort::Size => records.sort_by_key(|record| Reverse(record.size)),
+            ListingSort::Time => records.sort_by_key(|record| record.fetched_at),
+        }
+    }
+}
+
+/// A stored response, as listed.
+#[derive(Clone, Debug, Serialize)]
+pub struct ListingRecord {
+    pub url: Url,
+    pub surt: String,
+    pub status: u16,
+    pub mime: Option<String>,
+    /// The length of the body as received, unknown for responses stored before it was recorded.
+    pub size: Option<u64>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub fetched_at: OffsetDateTime,
+    pub digest: Option<String>,
+}
+
+impl ListingRecord {
+    pub fn new(key: &str, meta: &ResponseMetadata) -> ListingRecord {
+        ListingRecord {
+            url: meta.url.url.clone(),
+            surt: key.to_owned(),
+            status: meta.status.as_u16(),
+            mime: meta.mime(),
+            size: meta.size,
+            fetched_at: meta.fetched_at,
+            digest: meta.payload_digest.clone(),
+        }
+    }
+
+    fn write_csv(&self, mut out: impl Write) -> EvergardenResult<()> {
+        let fields = [
+            self.url.to_string(),
+            self.surt.clone(),
+            self.status.to_string(),
+            self.mime.clone().unwrap_or_default(),
+            self.size.map(|size| size.to_string()).unwrap_or_default(),
+            self.fetched_at.format(&Rfc3339).unwrap_or_default(),
+            self.digest.clone().unwrap_or_default(),
+        ];
+
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            out.write_all(csv_field(field).as_bytes())?;
+        }
+        out.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+/// Quotes a field if it has anything in it that csv readers would otherwise split on.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+impl Storage {
+    /// Writes the stored responses matching `filter` to `out`, sorted by `sort`, and returns how many there were.
+    /// Sorting by anything but the key, which the index is already sorted by, reads the whole listing first.
+    pub fn write_listing(
+        &self,
+        filter: Filter,
+        format: ListingFormat,
+        sort: ListingSort,
+        mut out: impl Write,
+    ) -> EvergardenResult<usize> {
+        if format == ListingFormat::Csv {
+            writeln!(out, "{CSV_HEADER}")?;
+        }
+
+        let mut write = |record: &ListingRecord| -> EvergardenResult<()> {
+            match format {
+                ListingFormat::Jsonl => {
+                    serde_json::to_writer(&mut out, record)?;
+                    writeln!(out)?;
+                }
+                ListingFormat::Csv => record.write_csv(&mut out)?,
+            }
+            Ok(())
+        };
+
+        let records = self
+            .query(filter)
+            .map(|record| record.map(|(key, _, meta)| ListingRecord::new(&key, &meta)));
+
+        let mut count = 0;
+        if sort == ListingSort::Key {
+            for record in records {
+                write(&record?)?;
+                count += 1;
+            }
+        } else {
+            let mut records = records.collect::<EvergardenResult<Vec<_>>>()?;
+            sort.sort(&mut records);
+            for record in &records {
+                write(record)?;
+            }
+            count = records.len();
+        }
+
+        out.flush()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+    use url::Url;
+
+    use super::{csv_field, ListingRecord, ListingSort};
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("text/html"), "text/html");
+        assert_eq!(
+            csv_field("http://example.com/?a=1,2"),
+            "\"http://example.com/?a=1,2\""
+        );
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn sorts_largest_first() {
+        let record = |surt: &str, size| ListingRecord {
+            url: Url::parse("http://example.com/").unwrap(),
+            surt: surt.to_owned(),
+            status: 200,
+            mime: None,
+            size,
+            fetched_at: OffsetDateTime::UNIX_EPOCH,
+            digest: None,
+        };
+        let mut records = [
+            record("a", Some(3)),
+            record("b", None),
+            record("c", Some(9)),
+        ];
+
+        ListingSort::Size.sort(&mut records);
+        let order = records
+            .iter()
+            .map(|record| record.surt.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["c", "a", "b"]);
+    }
+}
@@ -0,0 +1,144 @@
+//! Summaries of what a crawl stored, for seeing at a glance what it spent its time and disk on.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{EvergardenResult, Filter, ListingRecord, Storage};
+
+/// How many records, and how many bytes of bodies, fall under a host or content type.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Breakdown {
+    /// The host or content type, if the records have one.
+    pub name: Option<String>,
+    pub records: usize,
+    pub bytes: u64,
+}
+
+/// What [`Storage::stats`] found.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CrawlStats {
+    pub records: usize,
+    /// The total length of the bodies as received, revisits included.
+    pub bytes: u64,
+    /// Records whose body was the same as an earlier capture's, and wasn't stored again.
+    pub revisits: usize,
+    /// Records evergarden made itself, like screenshots.
+    pub resources: usize,
+    /// Records that were cut short, by a size limit or a timeout.
+    pub truncated: usize,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub first_fetched: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_fetched: Option<OffsetDateTime>,
+    /// Sorted by how many records there are of each, most first.
+    pub hosts: Vec<Breakdown>,
+    /// Sorted by how many records there are of each, most first.
+    pub mimes: Vec<Breakdown>,
+    /// How many records there are with each status.
+    pub statuses: BTreeMap<u16, usize>,
+    /// The records with the largest bodies, largest first.
+    pub largest: Vec<ListingRecord>,
+}
+
+/// Adds `record` to `largest`, a list of at most `top` records sorted by size, largest first, if it's large enough.
+fn keep_largest(largest: &mut Vec<ListingRecord>, record: ListingRecord, top: usize) {
+    let size = record.size.unwrap_or_default();
+    let at = largest.partition_point(|kept| kept.size.unwrap_or_default() >= size);
+    if at < top {
+        largest.insert(at, record);
+        largest.truncate(top);
+    }
+}
+
+fn breakdown(tally: HashMap<Option<String>, Breakdown>) -> Vec<Breakdown> {
+    let mut breakdown = tally.into_values().collect::<Vec<_>>();
+    breakdown.sort_by(|a, b| b.records.cmp(&a.records).then_with(|| a.name.cmp(&b.name)));
+    breakdown
+}
+
+impl Storage {
+    /// Summarizes the stored responses matching `filter`, keeping the `top` largest.
+    pub fn stats(&self, filter: Filter, top: usize) -> EvergardenResult<CrawlStats> {
+        let mut stats = CrawlStats::default();
+        let mut hosts = HashMap::<Option<String>, Breakdown>::new();
+        let mut mimes = HashMap::<Option<String>, Breakdown>::new();
+
+        for record in self.query(filter) {
+            let (key, _, meta) = record?;
+            let size = meta.size.unwrap_or_default();
+
+            stats.records += 1;
+            stats.bytes += size;
+            stats.revisits += usize::from(meta.revisit.is_some());
+            stats.resources += usize::from(meta.resource.is_some());
+            stats.truncated += usize::from(meta.truncated.is_some());
+            stats.first_fetched = Some(
+                stats
+                    .first_fetched
+                    .map_or(meta.fetched_at, |first| first.min(meta.fetched_at)),
+            );
+            stats.last_fetched = Some(
+                stats
+                    .last_fetched
+                    .map_or(meta.fetched_at, |last| last.max(meta.fetched_at)),
+            );
+            *stats.statuses.entry(meta.status.as_u16()).or_default() += 1;
+
+            for (tally, name) in [
+                (&mut hosts, meta.url.url.host_str().map(str::to_owned)),
+                (&mut mimes, meta.mime()),
+            ] {
+                let entry = tally.entry(name.clone()).or_insert_with(|| Breakdown {
+                    name,
+                    ..Breakdown::default()
+                });
+                entry.records += 1;
+                entry.bytes += size;
+            }
+
+            keep_largest(&mut stats.largest, ListingRecord::new(&key, &meta), top);
+        }
+
+        stats.hosts = breakdown(hosts);
+        stats.mimes = breakdown(mimes);
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+    use url::Url;
+
+    use super::keep_largest;
+    use crate::ListingRecord;
+
+    fn record(size: u64) -> ListingRecord {
+        ListingRecord {
+            url: Url::parse("http://example.com/").unwrap(),
+            surt: format!("com,example)/{size}"),
+            status: 200,
+            mime: None,
+            size: Some(size),
+            fetched_at: OffsetDateTime::UNIX_EPOCH,
+            digest: None,
+        }
+    }
+
+    #[test]
+    fn keeps_the_largest_records() {
+        let mut largest = Vec::new();
+        for size in [5, 1, 9, 3, 7, 9] {
+            keep_largest(&mut largest, record(size), 3);
+        }
+
+        let sizes = largest
+            .iter()
+            .map(|record| record.size.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [9, 9, 7]);
+    }
+}
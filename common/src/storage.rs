@@ -1,22 +1,43 @@
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{BufReader, Read, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-use actors::Actor;
-use bytes::BytesMut;
+use actors::BlockingActor;
+use bytes::{Bytes, BytesMut};
 use cacache::{Metadata, SyncReader, WriteOpts};
-use futures_util::{Future, TryFutureExt, TryStreamExt};
+use futures_util::{TryFutureExt, TryStreamExt};
+use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use hyper::HeaderMap;
 use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+use neo_mime::{MediaRange, MediaType};
+use object_store::{path::Path as ObjectPath, ObjectStore};
+use regex::Regex;
+use sha2::{Digest, Sha256};
 
-use ssri::Integrity;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ssri::{Integrity, IntegrityOpts};
+use thiserror::Error;
+use time::OffsetDateTime;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::runtime::Handle;
+use tokio::runtime::{Handle, Runtime};
+use tokio_util::io::StreamReader;
 use url::Url;
+use uuid::Uuid;
 
-use crate::{surt, CrawlInfo, EvergardenError, EvergardenResult};
-use crate::{BodyReadError, HttpResponse, ResponseMetadata};
+use crate::index::MetadataIndex;
+use crate::{
+    surt_stripping, CrawlInfo, EvergardenError, EvergardenResult, FailedFetch, Filter, IndexEntry,
+    UrlInfo, SESSION_PARAMS,
+};
+use crate::{Annotations, BodyReadError, BodyResult, HttpResponse, ResponseMetadata, RevisitInfo};
 
 static CRAWL_INFO_KEY: &'static str = "_EVERGARDEN_INTERNAL_CRAWLINFO";
+static INTERNAL_PREFIX: &str = "_EVERGARDEN_INTERNAL";
+static FAILURE_PREFIX: &str = "_EVERGARDEN_INTERNAL_FAILED:";
+static QUEUE_PREFIX: &str = "_EVERGARDEN_INTERNAL_QUEUED:";
+static ANNOTATIONS_PREFIX: &str = "_EVERGARDEN_INTERNAL_ANNOTATIONS:";
 
 struct SyncBridge<T> {
     inner: T,
@@ -24,39 +45,600 @@ struct SyncBridge<T> {
 }
 
 impl<T> SyncBridge<T> {
-    pub fn new(inner: T) -> SyncBridge<T> {
-        SyncBridge {
-            inner,
-            handle: Handle::current(),
-        }
+    pub fn with_handle(inner: T, handle: Handle) -> SyncBridge<T> {
+        SyncBridge { inner, handle }
     }
 }
 
-impl<T> Write for SyncBridge<T>
+impl<T> Read for SyncBridge<T>
 where
-    T: AsyncWrite + Unpin,
+    T: AsyncRead + Unpin,
 {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.handle.block_on(self.inner.read(buf))
+    }
+}
+
+/// The current runtime's handle, or, outside of one, that of a small runtime kept for the purpose. Export reads
+/// bodies synchronously, but object stores are only reachable through tokio.
+fn runtime_handle() -> Handle {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+    Handle::try_current().unwrap_or_else(|_| {
+        RUNTIME
+            .get_or_init(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .enable_all()
+                    .build()
+                    .expect("failed to start a runtime for object store reads")
+            })
+            .handle()
+            .clone()
+    })
+}
+
+/// How response bodies are compressed on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Fast, with modest ratios.
+    #[default]
+    Lz4,
+    /// Slower, with better ratios, tunable with `level`.
+    Zstd,
+    /// Stored as received, for bodies that are compressed already.
+    None,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Every entry records its own compression, so changing this only affects bodies stored from then on.
+    #[serde(default)]
+    pub compression: Compression,
+    /// The zstd compression level, zstd's own default if unset. LZ4 has no levels.
+    #[serde(default)]
+    pub level: Option<i32>,
+    /// Content types stored as received whatever `compression` says, since compressing them again gains next to
+    /// nothing. Defaults to the usual image, audio, video, font and archive formats; set it to `[]` to compress
+    /// everything. Bodies sent with a `Content-Encoding` are always stored as received.
+    #[serde(default = "default_uncompressed")]
+    pub uncompressed: Vec<MediaRange>,
+    #[serde(default)]
+    pub backend: StorageBackend,
+    /// Query and `;` path parameters left out of the keys responses are stored under, so that one page isn't stored
+    /// again for every session it's visited in. Defaults to [`SESSION_PARAMS`]; a trailing `*`
+    /// matches any parameter starting with what's before it.
+    #[serde(default = "default_strip_params")]
+    pub strip_params: Vec<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            compression: Compression::default(),
+            level: None,
+            uncompressed: default_uncompressed(),
+            backend: StorageBackend::default(),
+            strip_params: default_strip_params(),
+        }
+    }
+}
+
+fn default_strip_params() -> Vec<String> {
+    SESSION_PARAMS
+        .iter()
+        .map(|&param| param.to_owned())
+        .collect()
+}
+
+// svg and bmp are images, but compress well, so only the formats that are compressed already are listed
+fn default_uncompressed() -> Vec<MediaRange> {
+    [
+        "image/jpeg",
+        "image/png",
+        "image/gif",
+        "image/webp",
+        "image/avif",
+        "audio/*",
+        "video/*",
+        "font/woff",
+        "font/woff2",
+        "application/zip",
+        "application/gzip",
+        "application/zstd",
+        "application/x-7z-compressed",
+        "application/x-bzip2",
+        "application/x-xz",
+        "application/vnd.rar",
+    ]
+    .into_iter()
+    .map(|range| MediaRange::parse(range).expect("default content types are valid"))
+    .collect()
+}
+
+impl StorageConfig {
+    /// The compression a body with these headers is stored with.
+    pub fn compression_for(&self, headers: &HeaderMap) -> Compression {
+        let encoded = headers
+            .get_all(CONTENT_ENCODING)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .any(|coding| !matches!(coding.trim(), "" | "identity"));
+
+        let compressed_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| MediaType::parse(header).ok())
+            .is_some_and(|mime| self.uncompressed.iter().any(|range| range.matches(&mime)));
+
+        if encoded || compressed_type {
+            Compression::None
+        } else {
+            self.compression
+        }
+    }
+}
+
+/// Where response bodies are kept. The index, the queue and everything else stay in the output directory either way.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// In the output directory, next to the index.
+    #[default]
+    Local,
+    /// In an object store, such as `s3://bucket/prefix`, `gs://bucket/prefix`, `az://container/prefix` or
+    /// `file:///path`. Credentials are read from the usual `AWS_*`, `GOOGLE_*` and `AZURE_*` variables. `options` sets
+    /// anything else, like `aws_region` or `aws_endpoint`; it's saved along with the crawl, so keep secrets out of it.
+    ObjectStore {
+        url: Url,
+        #[serde(default)]
+        options: BTreeMap<String, String>,
+    },
+    /// Straight into rotating `.warc.gz` files and a CDXJ index under `warc/` in the output directory, as responses
+    /// arrive, for crawls that only ever want WARC output. Bodies aren't kept anywhere else, so they can't be exported,
+    /// compacted, or read back by later crawls of the same output directory.
+    Warc,
+}
+
+/// Where responses are handed once they're stored: the only place the [`StorageBackend::Warc`] backend keeps them, or a
+/// copy of what the others keep, like a WACZ package written during the crawl. WARC writing lives with export, in the
+/// cli, so it's provided through [`Storage::with_sink`].
+pub trait RecordSink: Send + Sync {
+    /// Writes out a stored response. `body` reads its payload as received, or is `None` for a revisit, whose payload is
+    /// that of the earlier capture in `meta.revisit`.
+    fn write_record(
+        &self,
+        key: &str,
+        meta: &ResponseMetadata,
+        body: Option<&mut dyn Read>,
+    ) -> EvergardenResult<()>;
+}
+
+/// The opened form of a [`StorageBackend`].
+#[derive(Clone)]
+enum BodyStore {
+    Local,
+    ObjectStore {
+        store: Arc<dyn ObjectStore>,
+        prefix: ObjectPath,
+    },
+    /// Kept only by the sink given with [`Storage::with_sink`].
+    Warc,
+}
+
+impl BodyStore {
+    fn open(backend: &StorageBackend) -> EvergardenResult<BodyStore> {
+        let (url, options) = match backend {
+            StorageBackend::Local => return Ok(BodyStore::Local),
+            StorageBackend::Warc => return Ok(BodyStore::Warc),
+            StorageBackend::ObjectStore { url, options } => (url, options),
+        };
+
+        // keys the store doesn't recognize are ignored, so the environment can be passed along wholesale
+        let credentials = std::env::vars()
+            .filter(|(key, _)| {
+                ["AWS_", "GOOGLE_", "AZURE_"]
+                    .iter()
+                    .any(|p| key.starts_with(p))
+            })
+            .map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, prefix) =
+            object_store::parse_url_opts(url, credentials.chain(options.clone()))?;
+
+        Ok(BodyStore::ObjectStore {
+            store: Arc::from(store),
+            prefix,
+        })
+    }
+
+    /// Bodies are addressed by their integrity, like in the local cache.
+    fn object_path(prefix: &ObjectPath, integrity: &Integrity) -> ObjectPath {
+        prefix.child(integrity.to_hex().1)
+    }
+}
+
+/// Compresses a body into `out`, adding it to the payload digest along the way, and to `hasher` for stores that, unlike
+/// the local cache, don't compute a body's integrity themselves. Returns the body's length.
+async fn encode_body<W: AsyncWrite + Unpin>(
+    config: &StorageConfig,
+    compression: Compression,
+    body: &mut async_broadcast::Receiver<BodyResult<Bytes>>,
+    digest: &mut Sha256,
+    mut hasher: Option<&mut IntegrityOpts>,
+    out: &mut W,
+) -> EvergardenResult<usize> {
+    // bodies are compressed in memory, and whatever the encoder has produced is written out after every chunk
+    let mut encoder = BodyEncoder::new(Vec::new(), compression, config.level)?;
+    let mut len = 0;
+
+    while let Some(chunk) = body.try_next().await? {
+        digest.update(&chunk);
+        encoder.write_all(&chunk)?;
+        len += chunk.len();
+
+        let encoded = encoder.get_mut();
+        if !encoded.is_empty() {
+            write_encoded(out, hasher.as_deref_mut(), encoded).await?;
+            encoded.clear();
+        }
+    }
+
+    let encoded = encoder.finish()?;
+    write_encoded(out, hasher, &encoded).await?;
+
+    Ok(len)
+}
+
+async fn write_encoded<W: AsyncWrite + Unpin>(
+    out: &mut W,
+    hasher: Option<&mut IntegrityOpts>,
+    encoded: &[u8],
+) -> std::io::Result<()> {
+    if let Some(hasher) = hasher {
+        hasher.input(encoded);
+    }
+
+    out.write_all(encoded).await
+}
+
+/// A body that has been written out, but not yet made part of the store, in case it turns out to be a duplicate.
+enum PendingBody {
+    Local(Box<cacache::Writer>),
+    Upload {
+        store: Arc<dyn ObjectStore>,
+        upload: ObjectPath,
+        target: ObjectPath,
+        integrity: Integrity,
+    },
+    /// A body bound for a [`RecordSink`], which is kept in a temporary file until it's written out.
+    Record(Integrity),
+}
+
+impl PendingBody {
+    async fn commit(self) -> EvergardenResult<Integrity> {
+        match self {
+            PendingBody::Local(writer) => Ok(writer.commit().await?),
+            PendingBody::Upload {
+                store,
+                upload,
+                target,
+                integrity,
+            } => {
+                store.rename(&upload, &target).await?;
+                Ok(integrity)
+            }
+            PendingBody::Record(integrity) => Ok(integrity),
+        }
+    }
+
+    async fn discard(self) -> EvergardenResult<()> {
+        match self {
+            // temporary files are removed once they're dropped
+            PendingBody::Local(_) | PendingBody::Record(_) => Ok(()),
+            PendingBody::Upload { store, upload, .. } => {
+                store.delete(&upload).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+enum BodyEncoder<W: Write> {
+    Lz4(FrameEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    None(W),
+}
+
+impl<W: Write> BodyEncoder<W> {
+    fn new(
+        inner: W,
+        compression: Compression,
+        level: Option<i32>,
+    ) -> std::io::Result<BodyEncoder<W>> {
+        Ok(match compression {
+            Compression::Lz4 => BodyEncoder::Lz4(FrameEncoder::new(inner)),
+            Compression::Zstd => BodyEncoder::Zstd(zstd::Encoder::new(
+                inner,
+                level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+            )?),
+            Compression::None => BodyEncoder::None(inner),
+        })
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        match self {
+            BodyEncoder::Lz4(encoder) => encoder.get_mut(),
+            BodyEncoder::Zstd(encoder) => encoder.get_mut(),
+            BodyEncoder::None(inner) => inner,
+        }
+    }
+
+    fn finish(self) -> EvergardenResult<W> {
+        Ok(match self {
+            BodyEncoder::Lz4(encoder) => encoder.finish()?,
+            BodyEncoder::Zstd(encoder) => encoder.finish()?,
+            BodyEncoder::None(inner) => inner,
+        })
+    }
+}
+
+impl<W: Write> Write for BodyEncoder<W> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.handle.block_on(self.inner.write(buf))
+        match self {
+            BodyEncoder::Lz4(encoder) => encoder.write(buf),
+            BodyEncoder::Zstd(encoder) => encoder.write(buf),
+            BodyEncoder::None(inner) => inner.write(buf),
+        }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.handle.block_on(self.inner.flush())
+        match self {
+            BodyEncoder::Lz4(encoder) => encoder.flush(),
+            BodyEncoder::Zstd(encoder) => encoder.flush(),
+            BodyEncoder::None(inner) => inner.flush(),
+        }
     }
 }
 
-impl<T> Read for SyncBridge<T>
-where
-    T: AsyncRead + Unpin,
-{
+/// Reads a stored body back as it was received, undoing whatever compression it was stored with.
+pub enum BodyReader<R: Read> {
+    Lz4(FrameDecoder<R>),
+    Zstd(zstd::Decoder<'static, BufReader<R>>),
+    None(R),
+}
+
+impl<R: Read> BodyReader<R> {
+    pub fn new(inner: R, compression: Compression) -> std::io::Result<BodyReader<R>> {
+        Ok(match compression {
+            Compression::Lz4 => BodyReader::Lz4(FrameDecoder::new(inner)),
+            Compression::Zstd => BodyReader::Zstd(zstd::Decoder::new(inner)?),
+            Compression::None => BodyReader::None(inner),
+        })
+    }
+}
+
+impl<R: Read> Read for BodyReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.handle.block_on(self.inner.read(buf))
+        match self {
+            BodyReader::Lz4(decoder) => decoder.read(buf),
+            BodyReader::Zstd(decoder) => decoder.read(buf),
+            BodyReader::None(inner) => inner.read(buf),
+        }
+    }
+}
+
+/// Which stored responses [`Storage::prune`] removes: those matching every predicate that's set. An empty filter
+/// matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct PruneFilter {
+    pub fetched_before: Option<OffsetDateTime>,
+    pub host: Option<Regex>,
+    /// Only responses with a 4xx or 5xx status.
+    pub failed: bool,
+    pub key: Option<Regex>,
+}
+
+impl PruneFilter {
+    pub fn is_empty(&self) -> bool {
+        self.fetched_before.is_none() && self.host.is_none() && !self.failed && self.key.is_none()
+    }
+
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        self.fetched_before
+            .is_none_or(|before| entry.fetched_at < before)
+            && self.host.as_ref().is_none_or(|host| {
+                entry
+                    .host
+                    .as_deref()
+                    .is_some_and(|entry_host| host.is_match(entry_host))
+            })
+            && (!self.failed || entry.status >= 400)
+            && self.key.as_ref().is_none_or(|key| key.is_match(&entry.key))
+    }
+}
+
+/// Which keys [`Storage::del_by_pattern`] removes.
+#[derive(Clone, Debug)]
+pub enum KeyPattern {
+    Regex(Regex),
+    /// A SURT prefix, like `com,example)/private/`, which covers everything under `example.com/private/`.
+    SurtPrefix(String),
+    /// Exactly these keys.
+    Keys(HashSet<String>),
+}
+
+impl KeyPattern {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            KeyPattern::Regex(regex) => regex.is_match(key),
+            KeyPattern::SurtPrefix(prefix) => key.starts_with(prefix.as_str()),
+            KeyPattern::Keys(keys) => keys.contains(key),
+        }
+    }
+}
+
+/// What [`Storage::prune`] or [`Storage::gc`] removed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PruneStats {
+    pub entries: usize,
+    pub bodies: usize,
+    pub bytes: u64,
+}
+
+/// What [`Storage::compact`] did.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompactStats {
+    pub entries: usize,
+    /// The size of the output directory before and after.
+    pub before: u64,
+    pub after: u64,
+}
+
+fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        size += if meta.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            meta.len()
+        };
     }
+
+    Ok(size)
 }
 
+/// Calls `f` with the path and hex digest of every body in a cacache content directory for one algorithm, which are
+/// spread over two levels of directories named after the first bytes of the digest.
+fn walk_content(
+    dir: &Path,
+    digest: &str,
+    depth: usize,
+    f: &mut impl FnMut(&Path, &str) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let digest = format!("{digest}{}", entry.file_name().to_string_lossy());
+
+        if depth == 0 {
+            f(&entry.path(), &digest)?;
+        } else if entry.file_type()?.is_dir() {
+            walk_content(&entry.path(), &digest, depth - 1, f)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How many entries [`Query`] reads from the index at a time.
+const QUERY_PAGE_SIZE: usize = 512;
+
+/// The index entries matching a [`Filter`], sorted by key, read from the index a page at a time.
+struct IndexPages<'a> {
+    storage: &'a Storage,
+    filter: Filter,
+    page: std::vec::IntoIter<IndexEntry>,
+    /// The last key read from the index, which the next page starts after.
+    after: Option<String>,
+    exhausted: bool,
+}
+
+impl Iterator for IndexPages<'_> {
+    type Item = EvergardenResult<IndexEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(entry) = self.page.next() {
+            return Some(Ok(entry));
+        }
+        if self.exhausted {
+            return None;
+        }
+
+        let page =
+            match self
+                .storage
+                .index
+                .query(&self.filter, self.after.as_deref(), QUERY_PAGE_SIZE)
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    self.exhausted = true;
+                    return Some(Err(e));
+                }
+            };
+
+        self.exhausted = page.len() < QUERY_PAGE_SIZE;
+        self.after = page.last().map(|entry| entry.key.clone());
+        self.page = page.into_iter();
+        self.page.next().map(Ok)
+    }
+}
+
+/// The stored responses matching a [`Filter`], as returned by [`Storage::query`].
+pub struct Query<'a> {
+    entries: IndexPages<'a>,
+}
+
+impl Iterator for Query<'_> {
+    type Item = EvergardenResult<(String, Integrity, ResponseMetadata)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.entries.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+
+            // entries whose metadata has gone missing since they were indexed are left out
+            match self.entries.storage.read_metadata_sync(&entry.key) {
+                Ok(Some(meta)) => return Some(Ok((entry.key, entry.integrity, meta))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// What's wrong with a stored response, as found by [`Storage::verify`].
+#[derive(Debug, Error)]
+pub enum Damage {
+    #[error("its metadata is missing")]
+    MissingMetadata,
+    #[error("its metadata can't be read: {0}")]
+    UnreadableMetadata(EvergardenError),
+    #[error("its body is missing")]
+    MissingBody,
+    #[error("its body can't be read: {0}")]
+    UnreadableBody(EvergardenError),
+    #[error("its body is {actual} bytes, but was stored as {expected}")]
+    WrongSize { expected: u64, actual: u64 },
+    #[error("its body's digest is {actual}, but was stored as {expected}")]
+    WrongDigest { expected: String, actual: String },
+}
+
+/// A payload digest as it's recorded in metadata, `sha256:<hex>`.
+fn payload_digest(digest: Sha256) -> String {
+    let mut payload_digest = String::from("sha256:");
+    for byte in digest.finalize() {
+        let _ = write!(payload_digest, "{byte:02x}");
+    }
+
+    payload_digest
+}
+
+/// A stored body's bytes as kept, before decompression.
+pub type StoredBody = Box<dyn Read + Send>;
+
 #[derive(Clone)]
 pub struct Storage {
     path: PathBuf,
+    config: Arc<StorageConfig>,
+    bodies: BodyStore,
+    sink: Option<Arc<dyn RecordSink>>,
+    index: MetadataIndex,
 }
 
 impl Storage {
@@ -68,7 +650,356 @@ impl Storage {
             cacache::clear_sync(&path)?;
         }
 
-        Ok(Storage { path })
+        let (index, stale) = MetadataIndex::open(&path.join("sqlite"))?;
+        let storage = Storage {
+            path,
+            config: Arc::default(),
+            bodies: BodyStore::Local,
+            sink: None,
+            index,
+        };
+
+        // stores from before the index existed get one the first time they're opened
+        if stale {
+            storage.reindex()?;
+        }
+
+        Ok(storage)
+    }
+
+    /// Opens the archive folder a crawl stored its records in, configured the way the crawl was: bodies are read from
+    /// wherever it stored them, and urls are keyed the way it keyed them.
+    pub fn open_crawl(path: impl AsRef<Path>) -> EvergardenResult<(Storage, StorageConfig)> {
+        let storage = Storage::new(path, false)?;
+        let config = storage.read_info_sync()?.storage_config()?;
+        let storage = storage.with_config(config.clone())?;
+
+        Ok((storage, config))
+    }
+
+    /// Rebuilds the metadata index from the entries in the cache.
+    pub fn reindex(&self) -> EvergardenResult<()> {
+        // cacache only creates its index directory once something is written
+        if !self.path.join("index-v5").exists() {
+            return self.index.rebuild(std::iter::empty());
+        }
+
+        self.index.rebuild(
+            self.list()?.map(|res| {
+                res.map(|(key, integrity, meta)| IndexEntry::new(&key, &integrity, &meta))
+            }),
+        )
+    }
+
+    /// The stored responses matching `filter`, sorted by key. They're looked up in the metadata index as they're
+    /// iterated over, rather than all at once.
+    pub fn query(&self, filter: Filter) -> Query<'_> {
+        Query {
+            entries: self.index_pages(filter),
+        }
+    }
+
+    fn index_pages(&self, filter: Filter) -> IndexPages<'_> {
+        IndexPages {
+            storage: self,
+            filter,
+            page: Vec::new().into_iter(),
+            after: None,
+            exhausted: false,
+        }
+    }
+
+    /// Checks the stored responses matching `filter`, sorted by key: that their metadata can be read, and that their
+    /// bodies are there, decode, and have the length and digest their metadata says. Each comes with what's wrong with
+    /// it, if anything.
+    pub fn verify(
+        &self,
+        filter: Filter,
+    ) -> impl Iterator<Item = EvergardenResult<(IndexEntry, Option<Damage>)>> + '_ {
+        self.index_pages(filter).map(|entry| {
+            let entry = entry?;
+            let damage = self.damage(&entry);
+            Ok((entry, damage))
+        })
+    }
+
+    fn damage(&self, entry: &IndexEntry) -> Option<Damage> {
+        let meta = match self.read_metadata_sync(&entry.key) {
+            Ok(Some(meta)) => meta,
+            Ok(None) => return Some(Damage::MissingMetadata),
+            Err(e) => return Some(Damage::UnreadableMetadata(e)),
+        };
+
+        let mut body = match self.read_body_sync(entry.integrity.clone(), meta.compression) {
+            Ok(Some(body)) => body,
+            Ok(None) => return Some(Damage::MissingBody),
+            Err(e) => return Some(Damage::UnreadableBody(e)),
+        };
+
+        let mut digest = Sha256::new();
+        let size = match std::io::copy(&mut body, &mut digest) {
+            Ok(size) => size,
+            Err(e) => return Some(Damage::UnreadableBody(e.into())),
+        };
+
+        if let Some(expected) = meta.size.filter(|expected| *expected != size) {
+            return Some(Damage::WrongSize {
+                expected,
+                actual: size,
+            });
+        }
+
+        let actual = payload_digest(digest);
+        match meta.payload_digest {
+            Some(expected) if expected != actual => Some(Damage::WrongDigest { expected, actual }),
+            _ => None,
+        }
+    }
+
+    /// How many stored responses match `filter`.
+    pub fn count(&self, filter: &Filter) -> EvergardenResult<usize> {
+        self.index.count(filter)
+    }
+
+    /// Removes the stored responses matching `filter`, then the bodies no longer referred to, see [`Storage::gc`].
+    /// Revisits of a removed capture are kept as full responses, along with the body they shared with it.
+    pub fn prune(&self, filter: &PruneFilter) -> EvergardenResult<PruneStats> {
+        let entries = self
+            .remove_where(false, |entry| filter.matches(entry))?
+            .len();
+
+        Ok(PruneStats {
+            entries,
+            ..self.gc()?
+        })
+    }
+
+    /// Removes the stored responses whose key matches `pattern`, returning them. With `dry_run`, they're only
+    /// returned. Like [`Storage::del_by_key`], this leaves their bodies for [`Storage::gc`] to remove.
+    pub fn del_by_pattern(
+        &self,
+        pattern: &KeyPattern,
+        dry_run: bool,
+    ) -> EvergardenResult<Vec<IndexEntry>> {
+        self.remove_where(dry_run, |entry| pattern.matches(&entry.key))
+    }
+
+    fn remove_where(
+        &self,
+        dry_run: bool,
+        mut predicate: impl FnMut(&IndexEntry) -> bool,
+    ) -> EvergardenResult<Vec<IndexEntry>> {
+        let mut removed = Vec::new();
+        let mut captures = HashSet::new();
+        for entry in self.index.entries()? {
+            if !predicate(&entry) {
+                continue;
+            }
+
+            if !dry_run {
+                if let Some(meta) = self.read_metadata_sync(&entry.key)? {
+                    captures.insert(meta.id);
+                }
+                cacache::remove_sync(&self.path, &entry.key)?;
+                self.index.remove(&entry.key)?;
+            }
+            removed.push(entry);
+        }
+
+        let digests = removed
+            .iter()
+            .filter_map(|entry| entry.payload_digest.as_deref())
+            .collect::<HashSet<_>>();
+        self.detach_revisits(&captures, &digests)?;
+
+        Ok(removed)
+    }
+
+    /// Turns the revisits of the removed `captures` back into full responses. Their bodies are still stored, since
+    /// they refer to them too, so only their metadata changes. Revisits share their capture's payload digest, so only
+    /// entries with one of `digests` are looked at.
+    fn detach_revisits(
+        &self,
+        captures: &HashSet<Uuid>,
+        digests: &HashSet<&str>,
+    ) -> EvergardenResult<()> {
+        if captures.is_empty() {
+            return Ok(());
+        }
+
+        for entry in self.index.entries()? {
+            if !entry
+                .payload_digest
+                .as_deref()
+                .is_some_and(|digest| digests.contains(digest))
+            {
+                continue;
+            }
+
+            let Some(stored) = cacache::metadata_sync(&self.path, &entry.key)? else {
+                continue;
+            };
+            let mut meta: ResponseMetadata = serde_json::from_value(stored.metadata)?;
+            if !meta
+                .revisit
+                .as_ref()
+                .is_some_and(|revisit| captures.contains(&revisit.id))
+            {
+                continue;
+            }
+
+            meta.revisit = None;
+            cacache::index::insert(
+                &self.path,
+                &entry.key,
+                WriteOpts::new()
+                    .integrity(stored.integrity)
+                    .metadata(serde_json::to_value(&meta)?)
+                    .time(stored.time),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes bodies that no entry refers to anymore, like those left behind by removed or overwritten entries.
+    /// Bodies are written before the entries referring to them, so this mustn't run while a crawl is writing to the
+    /// store.
+    pub fn gc(&self) -> EvergardenResult<PruneStats> {
+        let mut stats = PruneStats::default();
+
+        // cacache only creates its index directory once something is written
+        if !self.path.join("index-v5").exists() {
+            return Ok(stats);
+        }
+
+        let live = cacache::list_sync(&self.path)
+            .map(|entry| entry.map(|entry| entry.integrity.to_hex().1))
+            .collect::<Result<HashSet<String>, _>>()?;
+
+        let content = self.path.join("content-v2");
+        if content.exists() {
+            for algorithm in std::fs::read_dir(content)? {
+                walk_content(&algorithm?.path(), "", 2, &mut |path, digest| {
+                    if !live.contains(digest) {
+                        stats.bytes += std::fs::metadata(path)?.len();
+                        stats.bodies += 1;
+                        std::fs::remove_file(path)?;
+                    }
+
+                    Ok(())
+                })?;
+            }
+        }
+
+        if let BodyStore::ObjectStore { store, prefix } = &self.bodies {
+            let handle = runtime_handle();
+            let listing = handle.block_on(store.list_with_delimiter(Some(prefix)))?;
+
+            for object in listing.objects {
+                let Some(name) = object.location.filename() else {
+                    continue;
+                };
+
+                // uploads in progress, and anything else that isn't a body, are left alone
+                let is_body = name.bytes().all(|b| b.is_ascii_hexdigit());
+                if is_body && !live.contains(name) {
+                    handle.block_on(store.delete(&object.location))?;
+                    stats.bytes += object.size as u64;
+                    stats.bodies += 1;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Sets how bodies stored from now on are written, and where bodies are read from.
+    pub fn with_config(mut self, config: StorageConfig) -> EvergardenResult<Storage> {
+        self.bodies = BodyStore::open(&config.backend)?;
+        self.config = Arc::new(config);
+        Ok(self)
+    }
+
+    /// The key `url` is stored under, leaving out the configured [`StorageConfig::strip_params`].
+    pub fn surt(&self, url: Url) -> String {
+        surt_stripping(url, &self.config.strip_params)
+    }
+
+    /// Hands every response stored from now on to `sink` as well. The [`StorageBackend::Warc`] backend needs one, since
+    /// that's where it writes responses to.
+    pub fn with_sink(mut self, sink: Arc<dyn RecordSink>) -> Storage {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Copies every live entry into a fresh output directory, writing bodies with `config`, then swaps it in for the
+    /// current one. Content nothing refers to anymore is left behind, and duplicate bodies are stored once. Nothing
+    /// else may be using the store meanwhile, and clones of it mustn't be used afterwards; use the returned one.
+    ///
+    /// The swap is two renames: should it be interrupted between them, the old directory is left next to the new one,
+    /// with `.old` appended to its name.
+    pub async fn compact(self, config: StorageConfig) -> EvergardenResult<(Storage, CompactStats)> {
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let fresh_path = self.path.with_file_name(format!("{name}.compacting"));
+        let old_path = self.path.with_file_name(format!("{name}.old"));
+
+        if fresh_path.exists() {
+            std::fs::remove_dir_all(&fresh_path)?;
+        }
+
+        let fresh = Storage::new(&fresh_path, false)?.with_config(config.clone())?;
+        let mut stats = CompactStats {
+            before: dir_size(&self.path)?,
+            ..CompactStats::default()
+        };
+
+        // in the order they were fetched, so that duplicates keep pointing at their earliest capture
+        let mut entries = self.with_index(|index| index.entries()).await?;
+        entries.sort_by_key(|entry| entry.fetched_at);
+
+        for entry in entries {
+            if let Some(res) = self.retrieve_by_key(&entry.key).await? {
+                fresh.write_by_key(&entry.key, res).await?;
+                stats.entries += 1;
+            }
+        }
+
+        // the crawl info, queue, failures and pending annotations go in last, so none of those annotations are taken
+        // up by the responses above
+        for entry in cacache::list_sync(&self.path) {
+            let entry = entry?;
+            if entry.key.starts_with(INTERNAL_PREFIX) {
+                let data = cacache::read_hash(&self.path, &entry.integrity).await?;
+                cacache::write(&fresh.path, &entry.key, data).await?;
+            }
+        }
+
+        // along with anything else that's been put in the output directory, like failure reports
+        for file in std::fs::read_dir(&self.path)? {
+            let file = file?;
+            if file.file_type()?.is_file() {
+                std::fs::copy(file.path(), fresh.path.join(file.file_name()))?;
+            }
+        }
+
+        // both indexes are closed before their directories are moved
+        let path = self.path.clone();
+        drop(self);
+        drop(fresh);
+
+        std::fs::rename(&path, &old_path)?;
+        std::fs::rename(&fresh_path, &path)?;
+        std::fs::remove_dir_all(&old_path)?;
+
+        let compacted = Storage::new(&path, false)?.with_config(config)?;
+        stats.after = dir_size(&path)?;
+
+        Ok((compacted, stats))
     }
 
     pub async fn write_info(&self, info: &CrawlInfo) -> EvergardenResult<()> {
@@ -78,84 +1009,436 @@ impl Storage {
 
     pub async fn del_by_key(&self, key: &str) -> EvergardenResult<()> {
         cacache::remove(&self.path, key).await?;
-        Ok(())
+
+        let key = key.to_owned();
+        self.with_index(move |index| index.remove(&key)).await
     }
 
     pub async fn write_res(&self, res: HttpResponse) -> EvergardenResult<()> {
-        let key = surt(res.meta.url.url.clone());
-        self.write_by_key(&key, res).await
+        let key = match res.meta.resource {
+            Some(_) => res.meta.url.url.to_string(),
+            None => self.surt(res.meta.url.key_url()),
+        };
+        let succeeded = !(res.meta.status.is_client_error() || res.meta.status.is_server_error());
+
+        self.write_by_key(&key, res).await?;
+
+        if succeeded {
+            self.clear_failure(&key).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a failed fetch, counting attempts across repeated failures of the same url.
+    pub async fn record_failure(&self, mut failure: FailedFetch) -> EvergardenResult<()> {
+        let key = format!("{FAILURE_PREFIX}{}", self.surt(failure.url.url.clone()));
+
+        if cacache::metadata(&self.path, &key).await?.is_some() {
+            let previous: FailedFetch =
+                serde_json::from_slice(&cacache::read(&self.path, &key).await?)?;
+            failure.attempts += previous.attempts;
+        }
+
+        cacache::write(&self.path, &key, serde_json::to_vec(&failure)?).await?;
+        Ok(())
+    }
+
+    async fn clear_failure(&self, key: &str) -> EvergardenResult<()> {
+        let key = format!("{FAILURE_PREFIX}{key}");
+        if cacache::metadata(&self.path, &key).await?.is_some() {
+            cacache::remove(&self.path, &key).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn list_failures(&self) -> impl Iterator<Item = EvergardenResult<FailedFetch>> + '_ {
+        self.list_internal(FAILURE_PREFIX)
+    }
+
+    /// Attaches annotations to the stored response for `url`. Scripts can finish before the response is written, so
+    /// until it is they're kept aside, and merged in by [`Storage::write_by_key`].
+    pub async fn annotate(&self, url: Url, annotations: Annotations) -> EvergardenResult<()> {
+        let key = self.surt(url);
+
+        if let Some(entry) = cacache::metadata(&self.path, &key).await? {
+            let mut meta: ResponseMetadata = serde_json::from_value(entry.metadata)?;
+            meta.annotations = Some(meta.annotations.unwrap_or_default().merge(annotations));
+
+            cacache::index::insert_async(
+                &self.path,
+                &key,
+                WriteOpts::new()
+                    .integrity(entry.integrity)
+                    .metadata(serde_json::to_value(&meta)?)
+                    .time(entry.time),
+            )
+            .await?;
+
+            return Ok(());
+        }
+
+        let pending_key = format!("{ANNOTATIONS_PREFIX}{key}");
+        let annotations = match cacache::metadata(&self.path, &pending_key).await? {
+            Some(_) => serde_json::from_slice::<Annotations>(
+                &cacache::read(&self.path, &pending_key).await?,
+            )?
+            .merge(annotations),
+            None => annotations,
+        };
+
+        cacache::write(&self.path, &pending_key, serde_json::to_vec(&annotations)?).await?;
+        Ok(())
+    }
+
+    async fn take_pending_annotations(&self, key: &str) -> EvergardenResult<Option<Annotations>> {
+        let pending_key = format!("{ANNOTATIONS_PREFIX}{key}");
+        if cacache::metadata(&self.path, &pending_key).await?.is_none() {
+            return Ok(None);
+        }
+
+        let annotations = serde_json::from_slice(&cacache::read(&self.path, &pending_key).await?)?;
+        cacache::remove(&self.path, &pending_key).await?;
+
+        Ok(Some(annotations))
+    }
+
+    /// Persists a url waiting in the frontier, so an interrupted crawl can pick it back up.
+    pub async fn queue_url(&self, url: &UrlInfo) -> EvergardenResult<()> {
+        let key = format!("{QUEUE_PREFIX}{}", self.surt(url.key_url()));
+        cacache::write(&self.path, key, serde_json::to_vec(url)?).await?;
+        Ok(())
+    }
+
+    pub async fn unqueue_url(&self, url: Url) -> EvergardenResult<()> {
+        let key = format!("{QUEUE_PREFIX}{}", self.surt(url));
+        cacache::remove(&self.path, key).await?;
+        Ok(())
+    }
+
+    pub fn list_queued(&self) -> impl Iterator<Item = EvergardenResult<UrlInfo>> + '_ {
+        self.list_internal(QUEUE_PREFIX)
+    }
+
+    fn list_internal<T: DeserializeOwned>(
+        &self,
+        prefix: &'static str,
+    ) -> impl Iterator<Item = EvergardenResult<T>> + '_ {
+        cacache::list_sync(&self.path).filter_map(move |res| {
+            let res = match res {
+                Ok(v) => v,
+                Err(e) => return Some(Err(EvergardenError::Cache(e))),
+            };
+
+            if !res.key.starts_with(prefix) {
+                return None;
+            }
+
+            Some(
+                cacache::read_hash_sync(&self.path, &res.integrity)
+                    .map_err(EvergardenError::Cache)
+                    .and_then(|bytes| {
+                        serde_json::from_slice(&bytes).map_err(EvergardenError::JSON)
+                    }),
+            )
+        })
     }
 
     pub async fn write_by_key(&self, key: &str, res: HttpResponse) -> EvergardenResult<()> {
-        tokio::task::block_in_place(|| -> EvergardenResult<()> {
-            let handle = Handle::current();
-            let HttpResponse { meta, mut body } = res;
+        let HttpResponse {
+            meta,
+            mut body,
+            truncated,
+        } = res;
 
-            let json_header = serde_json::to_value(meta.as_ref())?;
+        let mut digest = Sha256::new();
+        let compression = self.config.compression_for(&meta.headers);
+        // the warc backend's bodies, which are only written out once it's known whether they're revisits
+        let mut spilled = None;
 
-            let write_opts = WriteOpts::new()
-                .algorithm(cacache::Algorithm::Xxh3)
+        let (pending, len) = match &self.bodies {
+            BodyStore::Local => {
+                let write_opts = WriteOpts::new().algorithm(cacache::Algorithm::Xxh3);
+                let mut writer = write_opts.open_hash(&self.path).await?;
+
+                let len = encode_body(
+                    &self.config,
+                    compression,
+                    &mut body,
+                    &mut digest,
+                    None,
+                    &mut writer,
+                )
+                .await?;
+                writer.flush().await?;
+                (PendingBody::Local(Box::new(writer)), len)
+            }
+            BodyStore::ObjectStore { store, prefix } => {
+                // the body's address is only known once it's written, so it's uploaded under the record's id first
+                let upload = prefix.child(format!("{}.partial", meta.id));
+                let mut writer =
+                    object_store::buffered::BufWriter::new(Arc::clone(store), upload.clone());
+                let mut hasher = IntegrityOpts::new().algorithm(cacache::Algorithm::Xxh3);
+
+                let len = encode_body(
+                    &self.config,
+                    compression,
+                    &mut body,
+                    &mut digest,
+                    Some(&mut hasher),
+                    &mut writer,
+                )
+                .await?;
+                writer.shutdown().await?;
+
+                let integrity = hasher.result();
+                let pending = PendingBody::Upload {
+                    store: Arc::clone(store),
+                    upload,
+                    target: BodyStore::object_path(prefix, &integrity),
+                    integrity,
+                };
+                (pending, len)
+            }
+            BodyStore::Warc => {
+                let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+                let mut hasher = IntegrityOpts::new().algorithm(cacache::Algorithm::Xxh3);
+
+                let len = encode_body(
+                    &self.config,
+                    Compression::None,
+                    &mut body,
+                    &mut digest,
+                    Some(&mut hasher),
+                    &mut file,
+                )
+                .await?;
+                file.flush().await?;
+                spilled = Some(file.into_std().await);
+
+                (PendingBody::Record(hasher.result()), len)
+            }
+        };
+
+        let payload_digest = payload_digest(digest);
+
+        // a body that's already stored under another url becomes a revisit of its earliest capture, pointing at the
+        // existing content instead of adding another copy of it
+        let original = if meta.resource.is_none() && len > 0 {
+            self.find_original(&payload_digest, key).await?
+        } else {
+            None
+        };
+
+        let (integrity, compression, revisit) = match original {
+            Some((integrity, original)) => {
+                pending.discard().await?;
+                let revisit = original.revisit.unwrap_or(RevisitInfo {
+                    id: original.id,
+                    url: original.url.url,
+                    fetched_at: original.fetched_at,
+                });
+                (integrity, original.compression, Some(revisit))
+            }
+            None => (pending.commit().await?, Some(compression), None),
+        };
+
+        let annotations = match self.take_pending_annotations(key).await? {
+            Some(pending) => Some(meta.annotations.clone().unwrap_or_default().merge(pending)),
+            None => meta.annotations.clone(),
+        };
+
+        // the index entry is only written once the body is done, so that truncation and the digest can be recorded in it
+        let stored = ResponseMetadata {
+            truncated: truncated.get().copied(),
+            payload_digest: Some(payload_digest),
+            revisit,
+            annotations,
+            compression,
+            size: Some(len as u64),
+            ..meta.as_ref().clone()
+        };
+        let json_header = serde_json::to_value(&stored)?;
+
+        let entry = IndexEntry::new(key, &integrity, &stored);
+        cacache::index::insert_async(
+            &self.path,
+            key,
+            WriteOpts::new()
+                .integrity(integrity.clone())
                 .metadata(json_header)
-                .time(meta.fetched_at.unix_timestamp_nanos() as u128);
+                .time(meta.fetched_at.unix_timestamp_nanos() as u128),
+        )
+        .await?;
+        self.with_index(move |index| index.insert(&entry)).await?;
 
-            let file = SyncBridge::new(handle.block_on(write_opts.open(&self.path, key))?);
+        let sink = match (&self.sink, &self.bodies) {
+            (Some(sink), _) => Some(Arc::clone(sink)),
+            (None, BodyStore::Warc) => {
+                return Err(std::io::Error::other(
+                    "the warc storage backend has nowhere to write records",
+                )
+                .into())
+            }
+            (None, _) => None,
+        };
+        if let Some(sink) = sink {
+            let (key, storage) = (key.to_owned(), self.clone());
 
-            let mut encoder = FrameEncoder::new(file);
+            tokio::task::spawn_blocking(move || {
+                // bodies the store kept are read back from it, instead of being held in memory for the sink
+                let mut body: Option<Box<dyn Read>> = match spilled {
+                    _ if stored.revisit.is_some() => None,
+                    Some(mut file) => {
+                        file.rewind()?;
+                        Some(Box::new(BufReader::new(file)))
+                    }
+                    None => match storage.read_body_sync(integrity, stored.compression)? {
+                        Some(body) => Some(Box::new(body)),
+                        None => {
+                            return Err(std::io::Error::other(
+                                "the body that was just stored has gone missing",
+                            )
+                            .into())
+                        }
+                    },
+                };
 
-            while let Some(chunk) = handle.block_on(body.try_next())? {
-                encoder.write_all(&chunk)?;
+                sink.write_record(
+                    &key,
+                    &stored,
+                    body.as_mut().map(|body| body as &mut dyn Read),
+                )
+            })
+            .await
+            .map_err(std::io::Error::from)??;
+        }
+
+        Ok(())
+    }
+
+    /// The content and metadata of the earliest stored response with the payload digest `digest`, for a response
+    /// about to be stored under `key`. What's stored under `key` is about to be replaced, so it's never the original,
+    /// and neither is anything that's a revisit of it.
+    async fn find_original(
+        &self,
+        digest: &str,
+        key: &str,
+    ) -> EvergardenResult<Option<(Integrity, ResponseMetadata)>> {
+        let (digest, except) = (digest.to_owned(), key.to_owned());
+        let Some(entry) = self
+            .with_index(move |index| index.find_by_payload_digest(&digest, &except))
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let Some(original) = cacache::metadata(&self.path, &entry.key).await? else {
+            return Ok(None);
+        };
+        let original: ResponseMetadata = serde_json::from_value(original.metadata)?;
+        if original.resource.is_some() {
+            return Ok(None);
+        }
+
+        if let Some(revisit) = &original.revisit {
+            let replaced = match cacache::metadata(&self.path, key).await? {
+                Some(current) => {
+                    serde_json::from_value::<ResponseMetadata>(current.metadata)?.id == revisit.id
+                }
+                None => false,
+            };
+            if replaced {
+                return Ok(None);
             }
+        }
 
-            let mut finished = encoder.finish()?.inner;
-            handle.block_on(finished.flush())?;
-            handle.block_on(finished.commit())?;
+        Ok(Some((entry.integrity, original)))
+    }
 
-            Ok(())
-        })
+    /// Runs `f` against the metadata index on a blocking thread, since SQLite calls block.
+    async fn with_index<T, F>(&self, f: F) -> EvergardenResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&MetadataIndex) -> EvergardenResult<T> + Send + 'static,
+    {
+        let index = self.index.clone();
+        tokio::task::spawn_blocking(move || f(&index))
+            .await
+            .map_err(std::io::Error::from)?
     }
 
     pub async fn retrieve_by_url(&self, url: Url) -> EvergardenResult<Option<HttpResponse>> {
-        let key = surt(url);
+        let key = self.surt(url);
         self.retrieve_by_key(&key).await
     }
 
     pub async fn retrieve_by_key(&self, key: &str) -> EvergardenResult<Option<HttpResponse>> {
-        let Some(metadata) = cacache::metadata(&self.path, key).await? else {
+        let Some(entry) = cacache::metadata(&self.path, key).await? else {
             return Ok(None);
         };
 
-        let metadata: ResponseMetadata = serde_json::from_value(metadata.metadata)?;
+        // responses written to WARC files can't be read back, and are fetched again instead
+        if matches!(self.bodies, BodyStore::Warc) {
+            return Ok(None);
+        }
+
+        let metadata: ResponseMetadata = serde_json::from_value(entry.metadata)?;
 
-        let reader = SyncBridge::new(cacache::Reader::open(&self.path, key).await?);
-        let mut decoder = FrameDecoder::new(reader);
+        // bodies are streamed from where they're stored, and decoded on a blocking thread as they're consumed
+        let stored: Box<dyn AsyncRead + Send + Unpin> = match &self.bodies {
+            BodyStore::Local => {
+                Box::new(cacache::Reader::open_hash(&self.path, entry.integrity).await?)
+            }
+            BodyStore::ObjectStore { store, prefix } => {
+                let object = store
+                    .get(&BodyStore::object_path(prefix, &entry.integrity))
+                    .await?;
+                Box::new(StreamReader::new(object.into_stream()))
+            }
+            BodyStore::Warc => unreachable!("returned early"),
+        };
+        let handle = Handle::current();
+        let compression = metadata.compression.unwrap_or_default();
         let (tx, rx) = async_broadcast::broadcast(1024);
 
         tokio::task::spawn_blocking(move || {
-            let handle = Handle::current();
+            let broadcast = |chunk| handle.block_on(tx.broadcast(chunk)).is_ok();
+
+            let mut decoder =
+                match BodyReader::new(SyncBridge::with_handle(stored, handle.clone()), compression)
+                {
+                    Ok(decoder) => decoder,
+                    Err(e) => {
+                        broadcast(Err(Arc::new(BodyReadError::IOError(e))));
+                        tx.close();
+                        return;
+                    }
+                };
 
             loop {
                 let mut buffer = BytesMut::zeroed(8096);
                 let n = match decoder.read(&mut buffer) {
                     Ok(n) => n,
                     Err(e) => {
-                        let _ =
-                            handle.block_on(tx.broadcast(Err(Arc::new(BodyReadError::IOError(e)))));
+                        broadcast(Err(Arc::new(BodyReadError::IOError(e))));
                         tx.close();
                         return;
                     }
                 };
 
-                if n == 0 {
+                buffer.truncate(n);
+                // stop decoding once nobody's left to read the body
+                if n == 0 || !broadcast(Ok(buffer.freeze())) {
                     tx.close();
                     return;
                 }
-
-                buffer.truncate(n);
-                let _ = handle.block_on(tx.broadcast(Ok(buffer.freeze())));
             }
         });
 
         Ok(Some(HttpResponse {
+            truncated: Arc::new(metadata.truncated.map(OnceLock::from).unwrap_or_default()),
             meta: Arc::new(metadata),
             body: rx,
         }))
@@ -164,14 +1447,46 @@ impl Storage {
     pub fn read_body_sync(
         &self,
         hash: Integrity,
-    ) -> EvergardenResult<Option<FrameDecoder<cacache::SyncReader>>> {
-        if !cacache::exists_sync(&self.path, &hash) {
+        compression: Option<Compression>,
+    ) -> EvergardenResult<Option<BodyReader<StoredBody>>> {
+        let Some(reader) = self.read_stored_body_sync(hash)? else {
             return Ok(None);
-        }
+        };
+
+        Ok(Some(BodyReader::new(
+            reader,
+            compression.unwrap_or_default(),
+        )?))
+    }
+
+    /// Reads a body as it's stored, still compressed.
+    pub fn read_stored_body_sync(&self, hash: Integrity) -> EvergardenResult<Option<StoredBody>> {
+        let reader: StoredBody = match &self.bodies {
+            BodyStore::Local => {
+                if !cacache::exists_sync(&self.path, &hash) {
+                    return Ok(None);
+                }
+
+                Box::new(SyncReader::open_hash(&self.path, hash)?)
+            }
+            BodyStore::ObjectStore { store, prefix } => {
+                let handle = runtime_handle();
+                let object =
+                    match handle.block_on(store.get(&BodyStore::object_path(prefix, &hash))) {
+                        Ok(object) => object,
+                        Err(object_store::Error::NotFound { .. }) => return Ok(None),
+                        Err(e) => return Err(e.into()),
+                    };
+
+                Box::new(SyncBridge::with_handle(
+                    StreamReader::new(object.into_stream()),
+                    handle,
+                ))
+            }
+            BodyStore::Warc => return Ok(None),
+        };
 
-        Ok(Some(FrameDecoder::new(SyncReader::open_hash(
-            &self.path, hash,
-        )?)))
+        Ok(Some(reader))
     }
 
     pub fn list(
@@ -190,7 +1505,7 @@ impl Storage {
                     Err(e) => return Some(Err(EvergardenError::Cache(e))),
                 };
 
-                if res.integrity == crawl_info_hash {
+                if res.integrity == crawl_info_hash || res.key.starts_with(INTERNAL_PREFIX) {
                     return None;
                 }
 
@@ -204,6 +1519,27 @@ impl Storage {
         ))
     }
 
+    pub fn read_metadata_sync(&self, key: &str) -> EvergardenResult<Option<ResponseMetadata>> {
+        match cacache::metadata_sync(&self.path, key)? {
+            Some(entry) => Ok(Some(serde_json::from_value(entry.metadata)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The hash `key`'s body is stored under, along with its metadata.
+    pub fn read_entry_sync(
+        &self,
+        key: &str,
+    ) -> EvergardenResult<Option<(Integrity, ResponseMetadata)>> {
+        match cacache::metadata_sync(&self.path, key)? {
+            Some(entry) => Ok(Some((
+                entry.integrity,
+                serde_json::from_value(entry.metadata)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
     pub fn read_info_sync(&self) -> EvergardenResult<CrawlInfo> {
         let bytes = cacache::read_sync(&self.path, CRAWL_INFO_KEY)?;
         serde_json::from_slice(&bytes).map_err(EvergardenError::JSON)
@@ -221,13 +1557,69 @@ impl Storage {
                     .map_ok(|_| StorageResponse::Stored)
                     .await
             }
+            StorageMessage::Queue(url) => {
+                self.queue_url(&url)
+                    .map_ok(|_| StorageResponse::Stored)
+                    .await
+            }
+            StorageMessage::Unqueue(url) => {
+                self.unqueue_url(url)
+                    .map_ok(|_| StorageResponse::Stored)
+                    .await
+            }
+            StorageMessage::RecordFailure(failure) => {
+                self.record_failure(failure)
+                    .map_ok(|_| StorageResponse::Stored)
+                    .await
+            }
+            StorageMessage::Annotate(url, annotations) => {
+                self.annotate(url, annotations)
+                    .map_ok(|_| StorageResponse::Stored)
+                    .await
+            }
+        }
+    }
+}
+
+impl CrawlInfo {
+    /// The crawl's config as it was saved, brought up to date. Crawls saved before [`StorageConfig::strip_params`]
+    /// existed stored their keys with every parameter kept, so they keep doing so rather than taking up its default.
+    pub fn saved_config(&self) -> EvergardenResult<serde_json::Value> {
+        let mut config: serde_json::Value = serde_json::from_str(&self.config)?;
+        if let Some(config) = config.as_object_mut() {
+            let storage = config
+                .entry("storage")
+                .or_insert_with(|| serde_json::Value::Object(Default::default()));
+            if let Some(storage) = storage.as_object_mut() {
+                storage
+                    .entry("strip_params")
+                    .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The storage section of the crawl's config. Folders that were imported into rather than crawled have only that.
+    pub fn storage_config(&self) -> EvergardenResult<StorageConfig> {
+        #[derive(Deserialize)]
+        struct Saved {
+            storage: StorageConfig,
         }
+
+        let saved: Saved = serde_json::from_value(self.saved_config()?)?;
+        Ok(saved.storage)
     }
 }
 
 pub enum StorageMessage {
     Retrieve(Url),
+    /// Stores a response, along with the request that produced it, kept in its metadata.
     Store(HttpResponse),
+    Queue(UrlInfo),
+    Unqueue(Url),
+    RecordFailure(FailedFetch),
+    Annotate(Url, Annotations),
 }
 
 pub enum StorageResponse {
@@ -235,24 +1627,217 @@ pub enum StorageResponse {
     Stored,
 }
 
-impl Actor for Storage {
+/// Storage compresses and hashes bodies as it writes them, so it answers on a thread of its own rather than holding up
+/// the runtime. It's spawned as a [`Blocking`](actors::Blocking).
+impl BlockingActor for Storage {
     type Input = StorageMessage;
 
     type Output = EvergardenResult<StorageResponse>;
 
-    type Response<'a> = impl Future<Output = Self::Output> + Send + 'a
-    where
-        Self: 'a;
+    fn answer(&mut self, i: Self::Input) -> Self::Output {
+        Handle::current().block_on(self.answer_request(i))
+    }
+}
 
-    type CloseFuture<'a> = futures_util::future::Ready<()>
-    where
-        Self: 'a;
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use futures_util::TryStreamExt;
+    use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE};
+    use hyper::{HeaderMap, StatusCode, Version};
+    use time::OffsetDateTime;
+    use tokio::runtime::Runtime;
+    use uuid::Uuid;
+
+    use super::{Compression, RecordSink, Storage, StorageBackend, StorageConfig};
+    use crate::{CrawlInfo, EvergardenResult, HttpResponse, ResponseMetadata, UrlInfo};
+
+    #[test]
+    fn stores_compressed_media_as_received() {
+        let config = StorageConfig {
+            compression: Compression::Zstd,
+            ..StorageConfig::default()
+        };
+        let headers = |pairs: &[(_, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name, value.parse().unwrap());
+            }
+            headers
+        };
+
+        assert_eq!(
+            config.compression_for(&headers(&[(CONTENT_TYPE, "text/html; charset=utf-8")])),
+            Compression::Zstd
+        );
+        assert_eq!(
+            config.compression_for(&headers(&[(CONTENT_TYPE, "image/svg+xml")])),
+            Compression::Zstd
+        );
+        assert_eq!(
+            config.compression_for(&headers(&[(CONTENT_TYPE, "video/mp4")])),
+            Compression::None
+        );
+        assert_eq!(
+            config.compression_for(&headers(&[
+                (CONTENT_TYPE, "text/html"),
+                (CONTENT_ENCODING, "br")
+            ])),
+            Compression::None
+        );
+    }
 
-    fn close<'a>(self) -> Self::CloseFuture<'a> {
-        futures_util::future::ready(())
+    #[test]
+    fn keeps_parameters_in_keys_of_older_crawls() {
+        let info = |config: &str| CrawlInfo {
+            id: Uuid::new_v4(),
+            config: config.to_owned(),
+            entry_points: Vec::new(),
+            seeds: Vec::new(),
+        };
+
+        let saved = info(r#"{"storage": {"compression": "lz4"}}"#)
+            .storage_config()
+            .unwrap();
+        assert_eq!(saved.compression, Compression::Lz4);
+        assert!(saved.strip_params.is_empty());
+        assert!(info("{}").storage_config().unwrap().strip_params.is_empty());
+
+        let current =
+            serde_json::to_string(&serde_json::json!({ "storage": StorageConfig::default() }))
+                .unwrap();
+        assert_eq!(
+            info(&current).storage_config().unwrap().strip_params,
+            StorageConfig::default().strip_params
+        );
+
+        assert!(info("{not json").storage_config().is_err());
+    }
+
+    fn response(url: &str, body: &[u8]) -> HttpResponse {
+        let meta = ResponseMetadata {
+            url: UrlInfo::start(url).unwrap(),
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            remote_addr: None,
+            fetched_at: OffsetDateTime::now_utc(),
+            id: Uuid::new_v4(),
+            truncated: None,
+            tls: None,
+            request: None,
+            payload_digest: None,
+            canonical: None,
+            revisit: None,
+            robots: None,
+            resource: None,
+            annotations: None,
+            compression: None,
+            size: None,
+        };
+
+        HttpResponse::from_bytes(meta, Bytes::copy_from_slice(body))
+    }
+
+    // what a sink was handed, by url
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<(String, Option<Vec<u8>>)>>);
+
+    impl RecordSink for Recorded {
+        fn write_record(
+            &self,
+            _: &str,
+            meta: &ResponseMetadata,
+            body: Option<&mut dyn Read>,
+        ) -> EvergardenResult<()> {
+            let body = match body {
+                Some(body) => {
+                    let mut read = Vec::new();
+                    body.read_to_end(&mut read)?;
+                    Some(read)
+                }
+                None => None,
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push((meta.url.url.to_string(), body));
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stored_bodies_are_read_back_in_chunks() {
+        // a few megabytes that don't compress down to nothing
+        let body = (0..400_000u32)
+            .flat_map(|i| format!("{:08x}", i.wrapping_mul(2_654_435_761)).into_bytes())
+            .collect::<Vec<_>>();
+
+        for compression in [Compression::Lz4, Compression::Zstd, Compression::None] {
+            let dir = tempfile::tempdir().unwrap();
+            let storage = Storage::new(dir.path(), false)
+                .unwrap()
+                .with_config(StorageConfig {
+                    compression,
+                    ..StorageConfig::default()
+                })
+                .unwrap();
+
+            let chunks = Runtime::new().unwrap().block_on(async {
+                storage
+                    .write_res(response("https://example.com/large", &body))
+                    .await
+                    .unwrap();
+
+                let res = storage
+                    .retrieve_by_key("com,example)/large")
+                    .await
+                    .unwrap()
+                    .unwrap();
+                res.body.try_collect::<Vec<_>>().await.unwrap()
+            });
+
+            assert!(chunks.len() > 1, "{compression:?}");
+            assert_eq!(chunks.concat(), body, "{compression:?}");
+        }
     }
 
-    fn answer(&mut self, i: Self::Input) -> Self::Response<'_> {
-        self.answer_request(i)
+    #[test]
+    fn sinks_are_handed_stored_bodies() {
+        for backend in [StorageBackend::Local, StorageBackend::Warc] {
+            let dir = tempfile::tempdir().unwrap();
+            let recorded = Arc::new(Recorded::default());
+            let storage = Storage::new(dir.path(), false)
+                .unwrap()
+                .with_config(StorageConfig {
+                    compression: Compression::Zstd,
+                    backend,
+                    ..StorageConfig::default()
+                })
+                .unwrap()
+                .with_sink(recorded.clone());
+
+            Runtime::new().unwrap().block_on(async {
+                for url in ["https://example.com/a", "https://example.com/b"] {
+                    storage.write_res(response(url, b"same")).await.unwrap();
+                }
+            });
+
+            // the second capture of the same payload is a revisit, which has no body of its own
+            assert_eq!(
+                *recorded.0.lock().unwrap(),
+                [
+                    (
+                        String::from("https://example.com/a"),
+                        Some(b"same".to_vec())
+                    ),
+                    (String::from("https://example.com/b"), None)
+                ]
+            );
+        }
     }
 }
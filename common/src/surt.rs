@@ -1,7 +1,27 @@
 use lazy_regex::regex;
 use url::{Host, Url};
 
-pub fn surt(mut url: Url) -> String {
+/// Query and path parameters that only track a visitor or their session, left out of keys unless storage is configured
+/// otherwise. A trailing `*` matches any parameter starting with what's before it.
+pub const SESSION_PARAMS: &[&str] = &[
+    "jsessionid",
+    "phpsessid",
+    "aspsessionid*",
+    "cfid",
+    "cftoken",
+    "utm_*",
+    "fbclid",
+    "gclid",
+    "msclkid",
+];
+
+/// The SURT `url` is stored under, without the [`SESSION_PARAMS`].
+pub fn surt(url: Url) -> String {
+    surt_stripping(url, SESSION_PARAMS)
+}
+
+/// The SURT `url` is stored under, leaving out query and `;` path parameters matching `params`.
+pub fn surt_stripping<P: AsRef<str>>(mut url: Url, params: &[P]) -> String {
     if let Some(Host::Domain(s)) = url.host() {
         #[allow(unused_must_use)]
         if let Some(mat) = regex!(r#"^www\d*\."#).find(s) {
@@ -9,8 +29,12 @@ pub fn surt(mut url: Url) -> String {
         }
     }
 
+    // parsing already put internationalized hosts in their lowercase punycode form, however they were written, so the
+    // only thing left is the trailing dot of a fully qualified one
+    let host = url.host_str().map(|host| host.strip_suffix('.').unwrap_or(host));
+
     let mut surt = String::with_capacity(url.as_str().len());
-    let mut part_iter = url.host_str().map_or("".rsplit('.'), |v| v.rsplit('.'));
+    let mut part_iter = host.map_or("".rsplit('.'), |v| v.rsplit('.'));
 
     if let Some(part) = part_iter.next() {
         surt.push_str(part);
@@ -28,11 +52,30 @@ pub fn surt(mut url: Url) -> String {
     }
 
     surt.push(')');
-    surt.push_str(url.path());
+    if url.path().contains(';') {
+        for (i, segment) in url.path().split('/').enumerate() {
+            if i > 0 {
+                surt.push('/');
+            }
+
+            let mut segment_params = segment.split(';');
+            surt.push_str(segment_params.next().unwrap_or_default());
+            for param in segment_params {
+                let name = param.split_once('=').map_or(param, |(name, _)| name);
+                if !is_stripped(&name.to_lowercase(), params) {
+                    surt.push(';');
+                    surt.push_str(param);
+                }
+            }
+        }
+    } else {
+        surt.push_str(url.path());
+    }
 
     let mut sorted_pairs = url
         .query_pairs()
         .map(|(a, b)| (a.into_owned().to_lowercase(), b.into_owned().to_lowercase()))
+        .filter(|(name, _)| !is_stripped(name, params))
         .collect::<Vec<(String, String)>>();
     sorted_pairs.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
     url.query_pairs_mut()
@@ -48,6 +91,15 @@ pub fn surt(mut url: Url) -> String {
     surt
 }
 
+fn is_stripped<P: AsRef<str>>(name: &str, params: &[P]) -> bool {
+    params
+        .iter()
+        .any(|param| match param.as_ref().strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == param.as_ref(),
+        })
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -92,4 +144,58 @@ mod tests {
             "com,example)/some/path?a=b&c=&cc=1&d=e"
         );
     }
+
+    #[test]
+    fn strips_session_params() {
+        macro_rules! test {
+            ($a:literal, $b:literal) => {
+                let url = url::Url::parse($a).unwrap();
+                assert_eq!(super::surt(url).as_str(), $b);
+            };
+        }
+
+        test!(
+            "https://example.com/page?id=4&PHPSESSID=a1b2&utm_source=feed&utm_medium=rss",
+            "com,example)/page?id=4"
+        );
+        test!(
+            "https://example.com/cart;jsessionid=A1B2C3?item=2",
+            "com,example)/cart?item=2"
+        );
+        test!(
+            "https://example.com/a;v=1;JSESSIONID=x/b?utm=kept",
+            "com,example)/a;v=1/b?utm=kept"
+        );
+        test!("https://example.com/?gclid=abc", "com,example)/");
+
+        let url = url::Url::parse("https://example.com/page?ref=x&utm_source=feed").unwrap();
+        assert_eq!(
+            super::surt_stripping(url, &["ref"]),
+            "com,example)/page?utm_source=feed"
+        );
+    }
+
+    #[test]
+    fn idn_hosts() {
+        macro_rules! test {
+            ($a:literal, $b:literal) => {
+                let url = url::Url::parse($a).unwrap();
+                assert_eq!(super::surt(url).as_str(), $b);
+            };
+        }
+
+        // however the host is written, it's keyed in its punycode form
+        test!("https://bücher.de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://xn--bcher-kva.de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://BÜCHER.de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://XN--BCHER-KVA.DE/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://b%C3%BCcher.de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://bücher。de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://www.bücher.de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://www.xn--bcher-kva.de/katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://shop.例え.jp/", "jp,xn--r8jz45g,shop)/");
+        test!("https://ｅｘａｍｐｌｅ.com/", "com,example)/");
+        test!("https://bücher.de./katalog", "de,xn--bcher-kva)/katalog");
+        test!("https://www.example.com./", "com,example)/");
+    }
 }
@@ -3,6 +3,12 @@ import struct
 import uuid
 import json
 import io 
+import os
+import socket
+import threading
+
+PROTOCOL_VERSION = 1
+CAPABILITIES = ["submit_priority", "submit_request", "fetch", "annotate", "block", "text", "submit_after"]
 
 class RpcException(Exception):
     def __init__(self, msg):
@@ -21,9 +27,13 @@ class Scraper:
         self.scrape = func
         self.input = inp
         self.output = out
+        # the script's `config` setting, if it has one
+        self.config = json.loads(os.environ.get("EVERGARDEN_SCRIPT_CONFIG", "null"))
 
 
     def run(self):
+        self.handshake()
+
         while True:
             opcode = self.read_byte()
             if opcode == 2:
@@ -36,6 +46,22 @@ class Scraper:
             else:
                 raise ProtocolError(f"unexpected opcode {opcode}")
 
+    def handshake(self):
+        opcode = self.read_byte()
+        if opcode != 3:
+            raise ProtocolError(f"unexpected opcode {opcode} - expected a hello")
+
+        version = struct.unpack("<H", self.input.read(2))[0]
+        length = struct.unpack("<I", self.input.read(4))[0]
+        self.crawler_capabilities = json.loads(self.input.read(length).decode("utf8"))
+        if version != PROTOCOL_VERSION:
+            raise ProtocolError(f"crawler speaks protocol version {version}, expected {PROTOCOL_VERSION}")
+
+        capabilities = json.dumps(CAPABILITIES).encode()
+        self.output.write(struct.pack("<BHI", 6, PROTOCOL_VERSION, len(capabilities)))
+        self.output.write(capabilities)
+        self.output.flush()
+
     def read_byte(self):
         return ord(self.input.read(1))
 
@@ -60,10 +86,51 @@ class Scraper:
         data = self.input.read(length)
         return data
 
-    def submit(self, url):
-        self.output.write(struct.pack("<B", 0))
+    def submit(self, url, priority=None):
+        if priority is None:
+            self.output.write(struct.pack("<B", 0))
+        else:
+            self.output.write(struct.pack("<Bi", 3, priority))
         self.write_str_with_len(url)
     
+    def submit_request(self, url, method="POST", headers=None, body=None, priority=0):
+        # for api calls and the like, which need more than a bare GET
+        spec = json.dumps({"method": method, "headers": headers or {}, "body": body}).encode()
+        self.output.write(struct.pack("<Bi", 5, priority))
+        self.write_str_with_len(url)
+        self.output.write(struct.pack("<I", len(spec)))
+        self.output.write(spec)
+        self.output.flush()
+
+    def submit_after(self, url, delay):
+        # fetches the url again once `delay` seconds have passed, without holding up this worker
+        self.output.write(struct.pack("<BI", 9, int(delay * 1000)))
+        self.write_str_with_len(url)
+
+    def annotate(self, **fields):
+        # title, description, language, or anything else worth keeping about the page
+        data = json.dumps({k: v for k, v in fields.items() if v is not None}).encode()
+        self.output.write(struct.pack("<BI", 4, len(data)))
+        self.output.write(data)
+        self.output.flush()
+
+    def text(self, text):
+        # the page's readable text, which ends up in pages.jsonl for full-text search
+        data = text.encode()
+        self.output.write(struct.pack("<BI", 8, len(data)))
+        self.output.write(data)
+        self.output.flush()
+
+    def block(self, url):
+        # keeps a url out of the crawl from now on, e.g. logout or delete links
+        self.output.write(struct.pack("<BB", 7, 0))
+        self.write_str_with_len(url)
+
+    def block_pattern(self, pattern):
+        # same, for every url matching a regex
+        self.output.write(struct.pack("<BB", 7, 1))
+        self.write_str_with_len(pattern)
+
     def fetch(self, url): 
         self.output.write(struct.pack("<B", 1))
         self.write_str_with_len(url)
@@ -91,4 +158,25 @@ class Scraper:
 
 def run(func):
     scraper = Scraper(func, sys.stdin.buffer, sys.stdout.buffer)
-    scraper.run()
\ No newline at end of file
+    scraper.run()
+
+def serve(func, address):
+    # runs as a long-lived service instead of a child process, for scripts configured with the unix or tcp engine.
+    # address is a unix socket path, or a (host, port) tuple
+    if isinstance(address, tuple):
+        server = socket.create_server(address)
+    else:
+        if os.path.exists(address):
+            os.unlink(address)
+        server = socket.socket(socket.AF_UNIX)
+        server.bind(address)
+        server.listen()
+
+    while True:
+        conn, _ = server.accept()
+        threading.Thread(target=serve_connection, args=(func, conn), daemon=True).start()
+
+def serve_connection(func, conn):
+    # every crawler worker gets its own connection
+    with conn, conn.makefile("rb") as inp, conn.makefile("wb") as out:
+        Scraper(func, inp, out).run()
@@ -5,15 +5,58 @@ from bs4 import BeautifulSoup
 import re
 
 css_expression = re.compile(r"""url\((?!['"]?(?:data):)['"]?([^'"\)]*)['"]?\)""")
+srcset_candidate = re.compile(r"[\s,]*(\S+)")
+# window.location = "...", location.href = '...', location.replace("...") and friends
+js_location = re.compile(r"""(?<![\w$.])(?:(?:window|document|self|top)\.)?location(?:\.href\s*=(?!=)|\s*=(?!=)|\.(?:replace|assign)\s*\()\s*(['"])(.+?)\1""")
+
+def parse_srcset(value):
+    # candidates are "<url> [descriptor]" separated by commas, but urls can contain commas themselves
+    pos = 0
+    while m := srcset_candidate.match(value, pos):
+        url = m.group(1)
+        pos = m.end()
+
+        if url.endswith(","):
+            url = url.rstrip(",")
+        else:
+            end = value.find(",", pos)
+            pos = len(value) if end == -1 else end + 1
+
+        if url and not url.startswith("data:"):
+            yield url
+
+def meta_refresh(tag):
+    # <meta http-equiv="refresh" content="5; url='/next'">, without a url it just reloads the page
+    if tag.get("http-equiv", "").lower() != "refresh":
+        return
+
+    content = tag.get("content", "")
+    _, sep, target = content.partition(";") if ";" in content else content.partition(",")
+    target = target.strip()
+    if target[:3].lower() == "url":
+        target = target[3:].lstrip().removeprefix("=").strip()
+
+    target = target.strip("'\"")
+    if sep and target:
+        yield target
+
+def js_redirects(tag):
+    return (m.group(2) for m in js_location.finditer(tag.string or ""))
+
+def not_nofollow(tag):
+    return "nofollow" not in (rel.lower() for rel in tag.get("rel", []))
+
+def srcset_of(attr):
+    return lambda tag: parse_srcset(tag.get(attr, ""))
 
 class SimpleScraper:
     def __init__(self, rpc, inp):
         self.rpc = rpc
         self.soup = BeautifulSoup(inp, 'lxml')
     
-    def extract_from_attr(self, tag, attr):
+    def extract_from_attr(self, tag, attr, follow=lambda tag: True):
         for t in self.soup.find_all(tag):
-            if lnk := t.get(attr, None):
+            if follow(t) and (lnk := t.get(attr, None)):
                 self.rpc.submit(lnk)
     
     def extract_with_generator(self, tag, gen):
@@ -21,13 +64,45 @@ class SimpleScraper:
             for lnk in filter(lambda r: r is not None, gen(t)):
                 self.rpc.submit(lnk)
 
+    def annotate(self):
+        title = self.soup.title.string if self.soup.title else None
+        description = self.soup.find("meta", attrs={"name": "description"})
+        html = self.soup.find("html")
+
+        self.rpc.annotate(
+            title=title.strip() if title else None,
+            description=description.get("content") if description else None,
+            language=html.get("lang") if html else None,
+        )
+
+    def text(self):
+        for t in self.soup(["script", "style", "noscript", "template"]):
+            t.decompose()
+
+        body = self.soup.body or self.soup
+        self.rpc.text(body.get_text(" ", strip=True))
+
 def scrape(rpc, header, inp):
     scraper = SimpleScraper(rpc, inp)
+    scraper.annotate()
+    # the crawl only sets robots directives when it honors them
+    follow = not_nofollow if header.get("robots") is not None else (lambda tag: True)
 
-    scraper.extract_from_attr("a", "href")
-    scraper.extract_from_attr("link", "href")
+    scraper.extract_from_attr("a", "href", follow)
+    scraper.extract_from_attr("link", "href", follow)
     scraper.extract_from_attr("img", "src")
     scraper.extract_from_attr("script", "src")
+    # responsive images, <picture> and media sources, and preloaded images (plain preload/prefetch hrefs are covered by link above)
+    scraper.extract_with_generator("img", srcset_of("srcset"))
+    scraper.extract_with_generator("source", srcset_of("srcset"))
+    scraper.extract_from_attr("source", "src")
+    scraper.extract_from_attr("video", "poster")
+    scraper.extract_with_generator("link", srcset_of("imagesrcset"))
+    # legacy redirects that never show up as a 3xx
+    scraper.extract_with_generator("meta", meta_refresh)
+    scraper.extract_with_generator("script", js_redirects)
     scraper.extract_with_generator("style", lambda tag: (link.group(1) for link in css_expression.finditer(tag.string)))
+    # last, since it strips scripts and styles from the tree
+    scraper.text()
 
 run(scrape)
\ No newline at end of file